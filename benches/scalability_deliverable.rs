@@ -33,11 +33,11 @@ fn spawn_server(runtime: &Runtime) -> (Channel, JoinHandle<()>) {
     let (channel, server_future) = runtime.block_on(async {
         let data_switch = DataSwitch::new(HashMap::from([(
             "bench",
-            &TestDataSource {
+            Arc::new(TestDataSource {
                 data_len_single: DATA_LEN_SINGLE,
                 data_len_series: DATA_LEN_SERIES,
                 data_len_spatial: DATA_LEN_SPATIAL,
-            } as &dyn DataConnector,
+            }) as Arc<dyn DataConnector + Send + Sync>,
         )]));
 
         let coordintor_socket = NamedTempFile::new().unwrap();
@@ -1,11 +1,14 @@
 use clap::Parser;
-use met_connectors::Frost;
 use met_connectors::LustreNetatmo;
+use met_connectors::{Frost, FrostAuth, FrostConfig, RateLimit};
 use rove::{
-    data_switch::{DataConnector, DataSwitch},
-    load_pipelines, start_server,
+    data_switch::{DataConnector, DataSwitch, RetryPolicy},
+    load_pipelines,
+    periodic::{load_jobs, run_jobs, LogSink},
+    pipeline_schema, start_server_with_scheduler, Scheduler, ServerConfig, TransportConfig,
 };
-use std::{collections::HashMap, path::Path};
+use std::{collections::HashMap, path::Path, sync::Arc, time::Duration};
+use tonic::transport::{Identity, ServerTlsConfig};
 use tracing::Level;
 
 #[derive(Parser, Debug)]
@@ -17,6 +20,73 @@ struct Args {
     max_trace_level: Level,
     #[arg(short, long, default_value_t = String::from("sample_pipeline/fresh"))]
     pipeline_dir: String,
+    /// Number of fetches to cache in memory, 0 disables caching
+    #[arg(long, default_value_t = 0)]
+    fetch_cache_size: usize,
+    /// Seconds cached fetches are reused for
+    #[arg(long, default_value_t = 300)]
+    fetch_cache_ttl: u64,
+    /// Number of times to attempt fetches that fail with transient errors
+    #[arg(long, default_value_t = 3)]
+    fetch_attempts: u32,
+    /// Seconds a single fetch from frost may take before it is abandoned, 0 disables the timeout
+    #[arg(long, default_value_t = 120)]
+    fetch_timeout: u64,
+    /// URL of the frost observations endpoint to fetch from
+    #[arg(long)]
+    frost_url: Option<String>,
+    /// Maximum number of requests to frost in flight at once
+    #[arg(long, default_value_t = RateLimit::default().max_concurrent)]
+    frost_max_concurrent: usize,
+    /// Sustained rate of requests to frost per second, 0 disables rate limiting
+    #[arg(long, default_value_t = RateLimit::default().requests_per_second)]
+    frost_requests_per_second: f64,
+    /// Provider ids to serve from the lustre files, 3 being Netatmo
+    #[arg(long, value_delimiter = ',', default_values_t = [LustreNetatmo::NETATMO])]
+    lustre_providers: Vec<i32>,
+    /// Highest dqc flag of records to serve from the lustre files, 0 meaning passed QC
+    #[arg(long, default_value_t = 0)]
+    lustre_max_dqc: u32,
+    /// Extra parameters to serve from the lustre files, as name=path_template, where the path
+    /// template is a strftime pattern
+    #[arg(long, value_parser = parse_key_val)]
+    lustre_parameter: Vec<(String, String)>,
+    /// PEM file of the certificate to serve TLS with, serving plaintext if not set
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<String>,
+    /// PEM file of the private key of the TLS certificate
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<String>,
+    /// Largest message in bytes clients may send, unlimited if not set
+    #[arg(long)]
+    max_decoding_message_size: Option<usize>,
+    /// Largest message in bytes the server may send, unlimited if not set
+    #[arg(long)]
+    max_encoding_message_size: Option<usize>,
+    /// Seconds between HTTP/2 keepalive pings, 0 disables them
+    #[arg(long, default_value_t = 0)]
+    http2_keepalive_interval: u64,
+    /// Report the server as not serving while any data source fails its health check
+    #[arg(long)]
+    check_data_source_health: bool,
+    /// TOML file configuring a PostgreSQL data source named `sql`, see met_connectors'
+    /// PostgresConfig
+    #[cfg(feature = "postgres")]
+    #[arg(long)]
+    sql_config: Option<String>,
+    /// TOML file of recurring QC jobs to run, whose results are logged
+    #[arg(long)]
+    jobs_file: Option<String>,
+    /// Print the JSON Schema for pipeline files and exit
+    #[arg(long)]
+    print_pipeline_schema: bool,
+}
+
+fn parse_key_val(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected name=path_template, got `{}`", s))?;
+    Ok((key.to_string(), value.to_string()))
 }
 
 // TODO: use anyhow for error handling?
@@ -24,19 +94,119 @@ struct Args {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
+    if args.print_pipeline_schema {
+        println!("{}", pipeline_schema());
+        return Ok(());
+    }
+
     tracing_subscriber::fmt()
         .with_max_level(args.max_trace_level)
         .init();
 
-    let data_switch = DataSwitch::new(HashMap::from([
-        ("frost", &Frost as &dyn DataConnector),
-        ("lustre_netatmo", &LustreNetatmo as &dyn DataConnector),
-    ]));
-
-    start_server(
-        args.address.parse()?,
-        data_switch,
-        load_pipelines(Path::new(&args.pipeline_dir))?,
-    )
-    .await
+    let mut frost_config = FrostConfig::default();
+    if let Some(frost_url) = args.frost_url {
+        frost_config.url = frost_url;
+    }
+    // read from the environment rather than an argument, so it doesn't show up in process lists
+    if let Ok(id) = std::env::var("FROST_CLIENT_ID") {
+        frost_config.auth = Some(FrostAuth::ClientId {
+            id,
+            secret: std::env::var("FROST_CLIENT_SECRET").ok(),
+        });
+    }
+
+    let lustre_netatmo = args.lustre_parameter.iter().fold(
+        LustreNetatmo::new(&args.lustre_providers, args.lustre_max_dqc),
+        |lustre_netatmo, (parameter, path_template)| {
+            lustre_netatmo.with_parameter(parameter, path_template)
+        },
+    );
+
+    #[allow(unused_mut)]
+    let mut connectors = HashMap::from([
+        (
+            "frost",
+            Arc::new(Frost::new(frost_config)?.with_rate_limit(RateLimit {
+                max_concurrent: args.frost_max_concurrent,
+                requests_per_second: args.frost_requests_per_second,
+                ..Default::default()
+            })?) as Arc<dyn DataConnector + Send + Sync>,
+        ),
+        (
+            "lustre_netatmo",
+            Arc::new(lustre_netatmo) as Arc<dyn DataConnector + Send + Sync>,
+        ),
+    ]);
+    #[cfg(feature = "postgres")]
+    if let Some(sql_config) = args.sql_config {
+        let mut sql_config: met_connectors::PostgresConfig =
+            toml::from_str(&std::fs::read_to_string(sql_config)?)?;
+        // can be read from the environment instead, to keep the password out of the file
+        if let Ok(connection_string) = std::env::var("ROVE_SQL_CONNECTION_STRING") {
+            sql_config.connection_string = connection_string;
+        }
+        connectors.insert(
+            "sql",
+            Arc::new(met_connectors::Sql::postgres(&sql_config)?)
+                as Arc<dyn DataConnector + Send + Sync>,
+        );
+    }
+
+    let mut data_switch = DataSwitch::new(connectors).with_retry(RetryPolicy {
+        max_attempts: args.fetch_attempts,
+        ..Default::default()
+    })?;
+    if args.fetch_timeout > 0 {
+        data_switch = data_switch.with_timeout("frost", Duration::from_secs(args.fetch_timeout));
+    }
+    if args.fetch_cache_size > 0 {
+        data_switch = data_switch.with_cache(
+            args.fetch_cache_size,
+            Duration::from_secs(args.fetch_cache_ttl),
+        );
+    }
+
+    let addr = args.address.parse()?;
+    let pipelines = load_pipelines(Path::new(&args.pipeline_dir))?;
+    // read from the environment rather than an argument, so it doesn't show up in process lists.
+    // This instance both serves the runner service with it, and sends it to other instances'
+    let runner_token = std::env::var("ROVE_RUNNER_TOKEN").ok();
+    // shared by the server and the jobs, so jobs see pipelines registered through the admin API
+    let mut scheduler = Scheduler::new(pipelines, data_switch);
+    if let Some(runner_token) = &runner_token {
+        scheduler = scheduler.with_runner_token(runner_token.clone());
+    }
+
+    if let Some(jobs_file) = args.jobs_file {
+        let jobs = load_jobs(jobs_file)?;
+        let scheduler = scheduler.clone();
+        tokio::spawn(async move {
+            // jobs were validated when they were loaded, so this won't fail
+            let _ = run_jobs(scheduler, jobs, Arc::new(LogSink)).await;
+        });
+    }
+
+    let config = ServerConfig {
+        // read from the environment rather than an argument, so it doesn't show up in process
+        // lists
+        admin_token: std::env::var("ROVE_ADMIN_TOKEN").ok(),
+        runner_token,
+        tls: match (args.tls_cert, args.tls_key) {
+            (Some(cert), Some(key)) => Some(ServerTlsConfig::new().identity(Identity::from_pem(
+                std::fs::read(cert)?,
+                std::fs::read(key)?,
+            ))),
+            _ => None,
+        },
+        transport: TransportConfig {
+            max_decoding_message_size: args.max_decoding_message_size,
+            max_encoding_message_size: args.max_encoding_message_size,
+            http2_keepalive_interval: (args.http2_keepalive_interval > 0)
+                .then(|| Duration::from_secs(args.http2_keepalive_interval)),
+            ..Default::default()
+        },
+        check_data_source_health: args.check_data_source_health,
+        ..Default::default()
+    };
+    start_server_with_scheduler(addr, scheduler, config).await
 }
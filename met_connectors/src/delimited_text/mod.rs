@@ -0,0 +1,611 @@
+use crate::series::{self, Observation};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use chronoutil::RelativeDuration;
+use rayon::prelude::*;
+use rove::{
+    data_switch,
+    data_switch::{DataCache, DataConnector, PolygonFilter, SpaceSpec, StationMetadata, TimeSpec},
+};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+};
+
+// the hourly lustre files are a few MB, so this keeps the number of reads down without holding
+// much memory per file being read
+const READ_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Which columns of a delimited text file hold each field, by header name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnMapping {
+    pub lat: String,
+    pub lon: String,
+    pub elev: String,
+    pub value: String,
+    /// Column of series identifiers
+    ///
+    /// If this is None, series are identified by their position as "(lat,lon)"
+    pub id: Option<String>,
+    /// Column of observation times, parsed with `time_format`
+    ///
+    /// If this is None, all records in a file are taken to be at the time the
+    /// file's path was rendered for
+    pub time: Option<String>,
+    /// strftime pattern for the time column
+    pub time_format: String,
+    /// Column of provider ids, reported as each series' provider
+    pub provider: Option<String>,
+    /// Column of station types, reported as each series' station type
+    pub station_type: Option<String>,
+    /// Column of WIGOS station identifiers, reported as each series' WIGOS id
+    pub wigos_id: Option<String>,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self {
+            lat: "lat".to_string(),
+            lon: "lon".to_string(),
+            elev: "elev".to_string(),
+            value: "value".to_string(),
+            id: None,
+            time: None,
+            time_format: "%Y-%m-%dT%H:%M:%SZ".to_string(),
+            provider: None,
+            station_type: None,
+            wigos_id: None,
+        }
+    }
+}
+
+/// Keep only records whose `column` holds one of `values`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    pub column: String,
+    pub values: Vec<String>,
+}
+
+/// Keep only records whose `column` holds a number no greater than `max`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaxFilter {
+    pub column: String,
+    pub max: f32,
+}
+
+/// Connector for flat files of delimited text, with one record per line and
+/// a header line naming the columns
+///
+/// Files are located by rendering `path_template` as a strftime pattern for
+/// each timestamp in the requested timerange, so a file per timestamp, or a
+/// file per day with a time column, can both be served.
+#[derive(Debug, Clone)]
+pub struct DelimitedText {
+    path_template: String,
+    period: RelativeDuration,
+    delimiter: u8,
+    columns: ColumnMapping,
+    filters: Vec<Filter>,
+    max_filters: Vec<MaxFilter>,
+}
+
+struct ColumnIndices {
+    lat: usize,
+    lon: usize,
+    elev: usize,
+    value: usize,
+    id: Option<usize>,
+    time: Option<usize>,
+    provider: Option<usize>,
+    station_type: Option<usize>,
+    wigos_id: Option<usize>,
+    filters: Vec<(usize, Vec<String>)>,
+    max_filters: Vec<(usize, f32)>,
+}
+
+fn invalid_data(msg: String) -> data_switch::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg).into()
+}
+
+fn parse_field(
+    record: &csv::StringRecord,
+    index: usize,
+    name: &str,
+) -> Result<f32, data_switch::Error> {
+    let field = record.get(index).unwrap_or_default().trim();
+    field
+        .parse()
+        .map_err(|_| invalid_data(format!("invalid {}: `{}`", name, field)))
+}
+
+impl DelimitedText {
+    /// Create a connector for files at `path_template`, containing data at a
+    /// time resolution of `period`
+    ///
+    /// Defaults to comma delimited files, the column names of
+    /// [`ColumnMapping::default`], and no filters.
+    pub fn new(path_template: impl Into<String>, period: RelativeDuration) -> Self {
+        Self {
+            path_template: path_template.into(),
+            period,
+            delimiter: b',',
+            columns: ColumnMapping::default(),
+            filters: Vec::new(),
+            max_filters: Vec::new(),
+        }
+    }
+
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn with_columns(mut self, columns: ColumnMapping) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    pub fn with_filter(mut self, column: impl Into<String>, values: &[&str]) -> Self {
+        self.filters.push(Filter {
+            column: column.into(),
+            values: values.iter().map(|value| value.to_string()).collect(),
+        });
+        self
+    }
+
+    /// Keep only records whose `column` holds a number no greater than `max`,
+    /// e.g. to drop records with a QC flag above some level
+    ///
+    /// Records where the column isn't a number are dropped.
+    pub fn with_max(mut self, column: impl Into<String>, max: f32) -> Self {
+        self.max_filters.push(MaxFilter {
+            column: column.into(),
+            max,
+        });
+        self
+    }
+
+    fn column_indices(
+        &self,
+        headers: &csv::StringRecord,
+    ) -> Result<ColumnIndices, data_switch::Error> {
+        let find = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.trim() == name)
+                .ok_or_else(|| invalid_data(format!("missing column `{}`", name)))
+        };
+
+        Ok(ColumnIndices {
+            lat: find(&self.columns.lat)?,
+            lon: find(&self.columns.lon)?,
+            elev: find(&self.columns.elev)?,
+            value: find(&self.columns.value)?,
+            id: self.columns.id.as_deref().map(find).transpose()?,
+            time: self.columns.time.as_deref().map(find).transpose()?,
+            provider: self.columns.provider.as_deref().map(find).transpose()?,
+            station_type: self.columns.station_type.as_deref().map(find).transpose()?,
+            wigos_id: self.columns.wigos_id.as_deref().map(find).transpose()?,
+            filters: self
+                .filters
+                .iter()
+                .map(|filter| Ok((find(&filter.column)?, filter.values.clone())))
+                .collect::<Result<_, data_switch::Error>>()?,
+            max_filters: self
+                .max_filters
+                .iter()
+                .map(|filter| Ok((find(&filter.column)?, filter.max)))
+                .collect::<Result<_, data_switch::Error>>()?,
+        })
+    }
+
+    pub(crate) fn has_time_column(&self) -> bool {
+        self.columns.time.is_some()
+    }
+
+    /// Read the observations in the file at `path` that pass the filters, and `keep`
+    ///
+    /// Records are taken to be at `file_time` if there is no time column.
+    pub(crate) fn read_observations(
+        &self,
+        path: &Path,
+        file_time: Option<DateTime<Utc>>,
+        keep: impl Fn(&Observation) -> bool,
+    ) -> Result<Vec<Observation>, data_switch::Error> {
+        let file = File::open(path)?;
+        let mut rdr = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .from_reader(BufReader::with_capacity(READ_BUFFER_SIZE, file));
+        let headers = rdr.headers().map_err(|e| invalid_data(e.to_string()))?;
+        let columns = self.column_indices(headers)?;
+
+        // how many records pass the filters isn't known up front, so this isn't sized from the
+        // file, which could hold far more
+        let mut observations = Vec::new();
+        // reused between records, to save an allocation for each
+        let mut record = csv::StringRecord::new();
+        while rdr
+            .read_record(&mut record)
+            .map_err(|e| invalid_data(e.to_string()))?
+        {
+            if !columns.filters.iter().all(|(index, values)| {
+                let field = record.get(*index).unwrap_or_default().trim();
+                values.iter().any(|value| value == field)
+            }) || !columns.max_filters.iter().all(|(index, max)| {
+                record
+                    .get(*index)
+                    .unwrap_or_default()
+                    .trim()
+                    .parse::<f32>()
+                    .is_ok_and(|value| value <= *max)
+            }) {
+                continue;
+            }
+
+            let time = match (columns.time, file_time) {
+                (Some(index), _) => {
+                    let field = record.get(index).unwrap_or_default().trim();
+                    NaiveDateTime::parse_from_str(field, &self.columns.time_format)
+                        .map_err(|e| invalid_data(format!("invalid time `{}`: {}", field, e)))?
+                        .and_utc()
+                }
+                (None, Some(file_time)) => file_time,
+                (None, None) => {
+                    return Err(invalid_data(format!(
+                        "{} has no time column, and no time of its own",
+                        path.display()
+                    )))
+                }
+            };
+
+            let lat = parse_field(&record, columns.lat, "lat")?;
+            let lon = parse_field(&record, columns.lon, "lon")?;
+            // empty fields mean the station's type or id isn't known
+            let text = |index: Option<usize>| {
+                index
+                    .map(|index| record.get(index).unwrap_or_default().trim())
+                    .filter(|field| !field.is_empty())
+                    .map(String::from)
+            };
+            let observation = Observation {
+                id: match columns.id {
+                    Some(index) => record.get(index).unwrap_or_default().trim().to_string(),
+                    None => format!("({},{})", lat, lon),
+                },
+                lat,
+                lon,
+                elev: parse_field(&record, columns.elev, "elev")?,
+                time,
+                value: match record.get(columns.value).unwrap_or_default().trim() {
+                    "" => None,
+                    _ => Some(parse_field(&record, columns.value, "value")?),
+                },
+                metadata: StationMetadata {
+                    provider: columns
+                        .provider
+                        .map(|index| {
+                            let field = record.get(index).unwrap_or_default().trim();
+                            field
+                                .parse()
+                                .map_err(|_| invalid_data(format!("invalid provider: `{}`", field)))
+                        })
+                        .transpose()?,
+                    station_type: text(columns.station_type),
+                    wigos_id: text(columns.wigos_id),
+                    ..Default::default()
+                },
+            };
+            if keep(&observation) {
+                observations.push(observation);
+            }
+        }
+
+        Ok(observations)
+    }
+
+    fn read(
+        &self,
+        only_ids: Option<&HashSet<String>>,
+        polygon: Option<&PolygonFilter>,
+        time_spec: &TimeSpec,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+    ) -> Result<DataCache, data_switch::Error> {
+        let window = series::fetch_window(time_spec, num_leading_points, num_trailing_points);
+
+        // with a time column, one file can hold many timestamps, so we only
+        // want to read each file once
+        let mut paths: Vec<(String, DateTime<Utc>)> = Vec::new();
+        let mut time = window.0;
+        while time <= window.1 {
+            let path = time.format(&self.path_template).to_string();
+            if self.columns.time.is_none() || !paths.iter().any(|(seen, _)| *seen == path) {
+                paths.push((path, time));
+            }
+            time = time + self.period;
+        }
+
+        // observations are filtered as they are parsed, so only the ones we want are held on to
+        let read_file = |(path, file_time): &(String, DateTime<Utc>)| {
+            self.read_observations(Path::new(path), Some(*file_time), |observation| {
+                only_ids.is_none_or(|ids| ids.contains(&observation.id))
+                    && polygon
+                        .is_none_or(|polygon| polygon.contains(observation.lat, observation.lon))
+            })
+        };
+        // files are independent, so are parsed in parallel when there are several
+        let results: Vec<_> = match paths.len() {
+            0 | 1 => paths.iter().map(read_file).collect(),
+            _ => paths.par_iter().map(read_file).collect(),
+        };
+
+        let mut observations = Vec::with_capacity(
+            results
+                .iter()
+                .map(|result| result.as_ref().map_or(0, Vec::len))
+                .sum(),
+        );
+        let mut not_found = None;
+        let mut any_found = false;
+        for result in results {
+            match result {
+                Ok(file_observations) => {
+                    any_found = true;
+                    observations.extend(file_observations);
+                }
+                // a missing file just means missing data, unless all of them are missing
+                Err(data_switch::Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                    not_found = Some(e)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if let (false, Some(e)) = (any_found, not_found) {
+            return Err(e.into());
+        }
+
+        Ok(series::build_cache(
+            observations,
+            window,
+            self.period,
+            num_leading_points,
+            num_trailing_points,
+        ))
+    }
+}
+
+#[async_trait]
+impl DataConnector for DelimitedText {
+    async fn fetch_data(
+        &self,
+        space_spec: &SpaceSpec,
+        time_spec: &TimeSpec,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+        _extra_spec: Option<&str>,
+    ) -> Result<DataCache, data_switch::Error> {
+        if time_spec.time_resolution != self.period {
+            return Err(data_switch::Error::UnimplementedSeries(format!(
+                "this source only has data at a resolution of {}",
+                self.period.format_to_iso8601()
+            )));
+        }
+
+        let (only_ids, polygon) = match space_spec {
+            SpaceSpec::All => (None, None),
+            SpaceSpec::One(id) => (Some(HashSet::from([id.clone()])), None),
+            SpaceSpec::Multiple(ids) => (Some(ids.iter().cloned().collect()), None),
+            SpaceSpec::Polygon(polygon) => (None, Some(PolygonFilter::new(polygon.clone()))),
+        };
+
+        let connector = self.clone();
+        let time_spec = TimeSpec::new(
+            time_spec.timerange.start,
+            time_spec.timerange.end,
+            time_spec.time_resolution,
+        );
+        tokio::task::spawn_blocking(move || {
+            connector.read(
+                only_ids.as_ref(),
+                polygon.as_ref(),
+                &time_spec,
+                num_leading_points,
+                num_trailing_points,
+            )
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rove::data_switch::{GeoPoint, Timestamp};
+    use std::{fs, path::Path};
+
+    fn fetch(
+        connector: &DelimitedText,
+        space_spec: &SpaceSpec,
+        start: i64,
+        end: i64,
+        num_leading_points: u8,
+    ) -> DataCache {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(connector.fetch_data(
+                space_spec,
+                &TimeSpec::new(Timestamp(start), Timestamp(end), RelativeDuration::hours(1)),
+                num_leading_points,
+                0,
+                None,
+            ))
+            .unwrap()
+    }
+
+    fn template(dir: &Path, name: &str) -> String {
+        dir.join(name).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_file_per_timestamp() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("obs_00.txt"),
+            "lat;lon;elev;value;prid\n60;10;100;1.5;3\n61;11;200;2.5;1\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("obs_01.txt"),
+            "lat;lon;elev;value;prid\n60;10;100;;3\n",
+        )
+        .unwrap();
+        // obs_02.txt is missing
+
+        let connector = DelimitedText::new(
+            template(dir.path(), "obs_%H.txt"),
+            RelativeDuration::hours(1),
+        )
+        .with_delimiter(b';')
+        .with_filter("prid", &["3"]);
+        let cache = fetch(&connector, &SpaceSpec::All, 3600, 7200, 1);
+
+        assert_eq!(cache.start_time, Timestamp(0));
+        assert_eq!(cache.num_leading_points, 1);
+        assert_eq!(
+            cache.data,
+            vec![("(60,10)".to_string(), vec![Some(1.5), None, None])]
+        );
+        assert_eq!(cache.rtree.elevs, vec![100.]);
+    }
+
+    #[test]
+    fn test_provider_column() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("obs_00.txt"),
+            "lat;lon;elev;value;prid;dqc;type;wigos\n\
+             60;10;100;1.5;3;0;automatic;0-578-0-18700\n\
+             61;11;200;2.5;5;1;;\n\
+             62;12;300;3.5;9;0;;\n\
+             63;13;400;4.5;5;2;;\n",
+        )
+        .unwrap();
+
+        let connector = DelimitedText::new(
+            template(dir.path(), "obs_%H.txt"),
+            RelativeDuration::hours(1),
+        )
+        .with_delimiter(b';')
+        .with_columns(ColumnMapping {
+            provider: Some("prid".to_string()),
+            station_type: Some("type".to_string()),
+            wigos_id: Some("wigos".to_string()),
+            ..Default::default()
+        })
+        .with_filter("prid", &["3", "5"])
+        .with_max("dqc", 1.);
+        let cache = fetch(&connector, &SpaceSpec::All, 0, 0, 0);
+
+        assert_eq!(
+            cache.data,
+            vec![
+                ("(60,10)".to_string(), vec![Some(1.5)]),
+                ("(61,11)".to_string(), vec![Some(2.5)]),
+            ]
+        );
+        assert_eq!(
+            cache.station_metadata(0),
+            &StationMetadata {
+                provider: Some(3),
+                station_type: Some("automatic".to_string()),
+                wigos_id: Some("0-578-0-18700".to_string()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(cache.station_metadata(1).provider, Some(5));
+        assert_eq!(cache.station_metadata(1).station_type, None);
+    }
+
+    #[test]
+    fn test_time_column() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("obs_19700101.csv"),
+            "station,time,latitude,longitude,altitude,temp\n\
+             a,1970-01-01T00:00:00Z,60,10,100,1\n\
+             b,1970-01-01T00:00:00Z,61,11,200,2\n\
+             a,1970-01-01T01:00:00Z,60,10,100,3\n\
+             a,1970-01-01T05:00:00Z,60,10,100,4\n",
+        )
+        .unwrap();
+
+        let connector = DelimitedText::new(
+            template(dir.path(), "obs_%Y%m%d.csv"),
+            RelativeDuration::hours(1),
+        )
+        .with_columns(ColumnMapping {
+            lat: "latitude".to_string(),
+            lon: "longitude".to_string(),
+            elev: "altitude".to_string(),
+            value: "temp".to_string(),
+            id: Some("station".to_string()),
+            time: Some("time".to_string()),
+            ..Default::default()
+        });
+
+        let cache = fetch(&connector, &SpaceSpec::All, 0, 3600, 0);
+        assert_eq!(
+            cache.data,
+            vec![
+                ("a".to_string(), vec![Some(1.), Some(3.)]),
+                ("b".to_string(), vec![Some(2.), None]),
+            ]
+        );
+
+        let cache = fetch(&connector, &SpaceSpec::One("b".to_string()), 0, 3600, 0);
+        assert_eq!(cache.data, vec![("b".to_string(), vec![Some(2.), None])]);
+
+        let cache = fetch(
+            &connector,
+            &SpaceSpec::Multiple(vec!["a".to_string(), "c".to_string()]),
+            0,
+            3600,
+            0,
+        );
+        assert_eq!(
+            cache.data,
+            vec![("a".to_string(), vec![Some(1.), Some(3.)])]
+        );
+
+        let cache = fetch(
+            &connector,
+            &SpaceSpec::Polygon(vec![
+                GeoPoint {
+                    lat: 59.5,
+                    lon: 9.5,
+                },
+                GeoPoint {
+                    lat: 60.5,
+                    lon: 9.5,
+                },
+                GeoPoint {
+                    lat: 60.5,
+                    lon: 10.5,
+                },
+                GeoPoint {
+                    lat: 59.5,
+                    lon: 10.5,
+                },
+            ]),
+            0,
+            3600,
+            0,
+        );
+        assert_eq!(
+            cache.data,
+            vec![("a".to_string(), vec![Some(1.), Some(3.)])]
+        );
+    }
+}
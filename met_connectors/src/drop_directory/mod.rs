@@ -0,0 +1,298 @@
+use crate::{
+    delimited_text::DelimitedText,
+    series::{self, Observation},
+};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use rove::{
+    data_switch,
+    data_switch::{DataCache, DataConnector, PolygonFilter, SpaceSpec, TimeSpec},
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("files in a drop directory need a time column")]
+    NoTimeColumn,
+}
+
+#[derive(Debug)]
+struct IndexedFile {
+    modified: SystemTime,
+    // None if the file has no observations
+    timerange: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    observations: Vec<Observation>,
+}
+
+/// Connector for feeds that deliver files into a drop directory, e.g. by FTP
+///
+/// Files are parsed as described by a [`DelimitedText`], which must have a
+/// time column, and indexed by the timerange they cover, so fetches only
+/// look at the files that overlap them. The directory is rescanned with
+/// [`scan`](DropDirectory::scan), or periodically by
+/// [`watch`](DropDirectory::watch), picking up new and modified files and
+/// forgetting removed ones. Hidden files are skipped, as many transfer tools
+/// write to those before renaming them into place.
+///
+/// Clones share the same index.
+#[derive(Debug, Clone)]
+pub struct DropDirectory {
+    dir: PathBuf,
+    format: DelimitedText,
+    min_age: Duration,
+    // keyed by path, so fetches see files in a stable order
+    index: Arc<RwLock<BTreeMap<PathBuf, IndexedFile>>>,
+}
+
+impl DropDirectory {
+    /// Create a connector for files in `dir` in the given `format`
+    ///
+    /// The directory isn't scanned until [`scan`](DropDirectory::scan) or
+    /// [`watch`](DropDirectory::watch) is called.
+    ///
+    /// # Errors
+    ///
+    /// If `format` has no time column
+    pub fn new(dir: impl Into<PathBuf>, format: DelimitedText) -> Result<Self, Error> {
+        if !format.has_time_column() {
+            return Err(Error::NoTimeColumn);
+        }
+
+        Ok(Self {
+            dir: dir.into(),
+            format,
+            min_age: Duration::ZERO,
+            index: Arc::new(RwLock::new(BTreeMap::new())),
+        })
+    }
+
+    /// Only index files that haven't been modified for `min_age`, for feeds
+    /// that write files in place rather than renaming them in when complete
+    pub fn with_min_age(mut self, min_age: Duration) -> Self {
+        self.min_age = min_age;
+        self
+    }
+
+    /// Bring the index up to date with the directory, returning the number of
+    /// files that were (re)indexed
+    ///
+    /// Files that fail to parse are skipped, and retried on the next scan if
+    /// they are modified.
+    ///
+    /// # Errors
+    ///
+    /// If the directory couldn't be read
+    pub fn scan(&self) -> Result<usize, data_switch::Error> {
+        let now = SystemTime::now();
+        let mut present = Vec::new();
+        let mut changed = Vec::new();
+
+        {
+            let index = self.index.read().unwrap_or_else(PoisonError::into_inner);
+            for entry in fs::read_dir(&self.dir)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                if !metadata.is_file() || entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                let modified = metadata.modified()?;
+                let path = entry.path();
+                present.push(path.clone());
+
+                let settled = now
+                    .duration_since(modified)
+                    .is_ok_and(|age| age >= self.min_age);
+                if settled
+                    && index
+                        .get(&path)
+                        .is_none_or(|indexed| indexed.modified != modified)
+                {
+                    changed.push((path, modified));
+                }
+            }
+        }
+
+        // parse outside the lock, so fetches aren't held up
+        let parsed: Vec<(PathBuf, IndexedFile)> = changed
+            .into_iter()
+            .map(|(path, modified)| {
+                let observations = match self.format.read_observations(&path, None, |_| true) {
+                    Ok(observations) => observations,
+                    Err(e) => {
+                        tracing::warn!(%e, path = %path.display(), "Failed to parse dropped file.");
+                        Vec::new()
+                    }
+                };
+                let timerange = observations
+                    .iter()
+                    .map(|observation| observation.time)
+                    .fold(
+                        None,
+                        |range: Option<(DateTime<Utc>, DateTime<Utc>)>, time| {
+                            Some(range.map_or((time, time), |(start, end)| {
+                                (start.min(time), end.max(time))
+                            }))
+                        },
+                    );
+                (
+                    path,
+                    IndexedFile {
+                        modified,
+                        timerange,
+                        observations,
+                    },
+                )
+            })
+            .collect();
+
+        let mut index = self.index.write().unwrap_or_else(PoisonError::into_inner);
+        index.retain(|path, _| present.contains(path));
+        let num_indexed = parsed.len();
+        index.extend(parsed);
+
+        Ok(num_indexed)
+    }
+
+    /// Spawn a task on the current tokio runtime that rescans the directory
+    /// every `interval`, until the returned handle is aborted
+    pub fn watch(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let connector = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let scanner = connector.clone();
+                match tokio::task::spawn_blocking(move || scanner.scan()).await {
+                    Ok(Ok(_)) => (),
+                    Ok(Err(e)) => tracing::warn!(%e, "Failed to scan drop directory."),
+                    Err(e) => tracing::warn!(%e, "Drop directory scan panicked."),
+                }
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl DataConnector for DropDirectory {
+    async fn fetch_data(
+        &self,
+        space_spec: &SpaceSpec,
+        time_spec: &TimeSpec,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+        _extra_spec: Option<&str>,
+    ) -> Result<DataCache, data_switch::Error> {
+        let (only_ids, polygon) = match space_spec {
+            SpaceSpec::All => (None, None),
+            SpaceSpec::One(id) => (Some(HashSet::from([id.as_str()])), None),
+            SpaceSpec::Multiple(ids) => (Some(ids.iter().map(String::as_str).collect()), None),
+            SpaceSpec::Polygon(polygon) => (None, Some(PolygonFilter::new(polygon.clone()))),
+        };
+
+        let window = series::fetch_window(time_spec, num_leading_points, num_trailing_points);
+        let observations: Vec<Observation> = {
+            let index = self.index.read().unwrap_or_else(PoisonError::into_inner);
+            index
+                .values()
+                .filter(|file| {
+                    file.timerange
+                        .is_some_and(|(start, end)| start <= window.1 && end >= window.0)
+                })
+                .flat_map(|file| file.observations.iter())
+                .filter(|observation| {
+                    only_ids
+                        .as_ref()
+                        .is_none_or(|ids| ids.contains(observation.id.as_str()))
+                        && polygon.as_ref().is_none_or(|polygon| {
+                            polygon.contains(observation.lat, observation.lon)
+                        })
+                })
+                .cloned()
+                .collect()
+        };
+
+        Ok(series::build_cache(
+            observations,
+            window,
+            time_spec.time_resolution,
+            num_leading_points,
+            num_trailing_points,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delimited_text::ColumnMapping;
+    use chronoutil::RelativeDuration;
+    use rove::data_switch::Timestamp;
+
+    async fn fetch(connector: &DropDirectory) -> Vec<(String, Vec<Option<f32>>)> {
+        connector
+            .fetch_data(
+                &SpaceSpec::All,
+                &TimeSpec::new(Timestamp(3600), Timestamp(7200), RelativeDuration::hours(1)),
+                0,
+                0,
+                None,
+            )
+            .await
+            .unwrap()
+            .data
+    }
+
+    #[tokio::test]
+    async fn test_drop_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let format =
+            DelimitedText::new("", RelativeDuration::hours(1)).with_columns(ColumnMapping {
+                id: Some("id".to_string()),
+                time: Some("time".to_string()),
+                ..Default::default()
+            });
+        assert!(matches!(
+            DropDirectory::new(
+                dir.path(),
+                DelimitedText::new("", RelativeDuration::hours(1))
+            ),
+            Err(Error::NoTimeColumn)
+        ));
+        let connector = DropDirectory::new(dir.path(), format).unwrap();
+        fs::write(
+            dir.path().join("feed_1.csv"),
+            "id,time,lat,lon,elev,value\na,1970-01-01T01:00:00Z,60,10,0,1\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("feed_2.csv"),
+            "id,time,lat,lon,elev,value\na,1970-01-01T02:00:00Z,60,10,0,2\nb,1970-01-01T05:00:00Z,61,11,0,5\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join(".feed_3.csv.part"), "id,ti").unwrap();
+
+        assert_eq!(connector.scan().unwrap(), 2);
+        assert_eq!(
+            fetch(&connector).await,
+            vec![("a".to_string(), vec![Some(1.), Some(2.)])]
+        );
+
+        // nothing changed, so nothing to reindex
+        assert_eq!(connector.scan().unwrap(), 0);
+
+        fs::remove_file(dir.path().join("feed_1.csv")).unwrap();
+        connector.scan().unwrap();
+        assert_eq!(
+            fetch(&connector).await,
+            vec![("a".to_string(), vec![None, Some(2.)])]
+        );
+    }
+}
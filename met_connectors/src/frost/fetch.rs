@@ -1,14 +1,72 @@
-use crate::frost::{util, Error, FrostLatLonElev, FrostObs};
+use crate::frost::{util, Error, Frost, FrostLocation, FrostObs};
 use chrono::{prelude::*, Duration};
 use chronoutil::RelativeDuration;
-use rove::data_switch::{self, DataCache, Polygon, SpaceSpec, TimeSpec, Timestamp};
+use rove::data_switch::{
+    self, Aggregation, DataCache, Polygon, SpaceSpec, StationMetadata, TimeSpec, Timestamp,
+};
+use std::collections::HashMap;
 
+/// Which of a station's timeseries of an element to fetch, where it has several, and how to fit
+/// series at other time resolutions onto the requested one
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct SeriesSelector {
+    level: Option<i32>,
+    sensor: Option<i32>,
+    // series at other time resolutions are dropped if this is None
+    resample: Option<Aggregation>,
+}
+
+impl SeriesSelector {
+    fn matches(&self, (level, sensor): (i32, i32)) -> bool {
+        self.level.is_none_or(|l| l == level) && self.sensor.is_none_or(|s| s == sensor)
+    }
+}
+
+/// Split an extra_spec of the form
+/// `element_id[;level=<level>][;sensor=<sensor>][;resample=<aggregation>]` into the element id
+/// and the series selector
+fn parse_extra_spec(extra_spec: &str) -> Result<(&str, SeriesSelector), Error> {
+    let mut parts = extra_spec.split(';');
+    // split always yields at least one part
+    let element_id = parts.next().unwrap().trim();
+    if element_id.is_empty() {
+        return Err(Error::InvalidElementId(
+            "extra_spec must contain an element id",
+        ));
+    }
+
+    let mut selector = SeriesSelector::default();
+    for part in parts {
+        let invalid = || Error::InvalidSelector(part.to_string());
+        let (key, value) = part.split_once('=').ok_or_else(invalid)?;
+        let value = value.trim();
+        match key.trim() {
+            "level" => selector.level = Some(value.parse().map_err(|_| invalid())?),
+            "sensor" => selector.sensor = Some(value.parse().map_err(|_| invalid())?),
+            "resample" => selector.resample = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok((element_id, selector))
+}
+
+/// Extract the series in a response, each with its own time resolution, if it has one, so
+/// series that need resampling to the requested time resolution can be told apart
 #[allow(clippy::type_complexity)]
 fn extract_data(
     mut resp: serde_json::Value,
-    time: DateTime<Utc>,
     request_time_resolution: RelativeDuration,
-) -> Result<Vec<((String, Vec<FrostObs>), FrostLatLonElev)>, Error> {
+    selector: SeriesSelector,
+) -> Result<
+    Vec<(
+        (String, Vec<FrostObs>),
+        Vec<FrostLocation>,
+        StationMetadata,
+        Option<RelativeDuration>,
+    )>,
+    Error,
+> {
     let ts_portion = resp
         .get_mut("data")
         .ok_or(Error::FindObs(
@@ -29,17 +87,24 @@ fn extract_data(
             ))?;
 
             // TODO: differentiate actual parse errors from missing duration?
-            let ts_time_resolution_result = util::extract_duration(header);
-            if ts_time_resolution_result.is_err()
-                || ts_time_resolution_result.unwrap() != request_time_resolution
-            {
+            let time_resolution = util::extract_duration(header).ok();
+            if time_resolution != Some(request_time_resolution) && selector.resample.is_none() {
+                return Ok(None);
+            }
+
+            let level_sensor = util::extract_level_sensor(header);
+            if !selector.matches(level_sensor) {
                 return Ok(None);
             }
 
             let station_id = util::extract_station_id(header)?;
 
-            // TODO: Should there be a location for each observation?
-            let location = util::extract_location(header, time)?;
+            let locations = util::extract_locations(header)?;
+
+            let metadata = StationMetadata {
+                sensor_height: util::extract_sensor_height(header),
+                ..Default::default()
+            };
 
             let obs: Vec<FrostObs> = serde_json::from_value(
                 ts.get_mut("observations")
@@ -49,12 +114,63 @@ fn extract_data(
                     .take(),
             )?;
 
-            Ok(Some(((station_id, obs), location)))
+            Ok(Some((
+                (station_id, obs),
+                locations,
+                metadata,
+                level_sensor,
+                time_resolution,
+            )))
         })
         .filter_map(Result::transpose)
-        .collect::<Result<Vec<((String, Vec<FrostObs>), FrostLatLonElev)>, Error>>()?;
+        .collect::<Result<Vec<_>, Error>>()?;
 
-    Ok(data)
+    // when resampling, a station can have the same series at several time resolutions. The one
+    // at the requested resolution is used if there is one, and otherwise the first
+    let mut chosen: HashMap<(String, (i32, i32)), usize> = HashMap::new();
+    for (i, ((station_id, _), _, _, level_sensor, time_resolution)) in data.iter().enumerate() {
+        chosen
+            .entry((station_id.clone(), *level_sensor))
+            .and_modify(|chosen| {
+                if data[*chosen].4 != Some(request_time_resolution)
+                    && *time_resolution == Some(request_time_resolution)
+                {
+                    *chosen = i
+                }
+            })
+            .or_insert(i);
+    }
+    let mut data: Vec<_> = data
+        .into_iter()
+        .enumerate()
+        .filter(|(i, ((station_id, _), _, _, level_sensor, _))| {
+            chosen[&(station_id.clone(), *level_sensor)] == *i
+        })
+        .map(|(_, ts)| ts)
+        .collect();
+
+    // a station can have several series of an element, at different levels or from different
+    // sensors. The one from the lowest sensor, then level, keeps the station id, so it's the one
+    // a SpaceSpec::One finds, and the others are identified as `station_id:sensor:level`
+    let mut primary: HashMap<String, (i32, i32)> = HashMap::new();
+    for ((station_id, _), _, _, (level, sensor), _) in &data {
+        primary
+            .entry(station_id.clone())
+            .and_modify(|p| *p = (*p).min((*sensor, *level)))
+            .or_insert((*sensor, *level));
+    }
+    for ((station_id, _), _, _, (level, sensor), _) in &mut data {
+        if primary[station_id.as_str()] != (*sensor, *level) {
+            *station_id = format!("{}:{}:{}", station_id, sensor, level);
+        }
+    }
+
+    Ok(data
+        .into_iter()
+        .map(|(series, locations, metadata, _, resolution)| {
+            (series, locations, metadata, resolution)
+        })
+        .collect())
 }
 
 fn parse_polygon(polygon: &Polygon) -> String {
@@ -74,80 +190,173 @@ fn parse_polygon(polygon: &Polygon) -> String {
     s
 }
 
+/// Place observations already at the requested time resolution into a series starting at
+/// `data_start`, with Nones for gaps
+fn align_obs(
+    obses: Vec<FrostObs>,
+    period: RelativeDuration,
+    data_start: DateTime<Utc>,
+    interval_end: DateTime<Utc>,
+) -> Result<Vec<Option<f32>>, Error> {
+    // TODO: preallocate?
+    // let ts_length = (end_time - first_obs_time) / period;
+    let mut data = Vec::new();
+
+    let mut curr_obs_time = data_start;
+    let first_obs_time = obses
+        .first()
+        .ok_or(Error::MissingObs(
+            "obs array from frost is empty".to_string(),
+        ))?
+        .time;
+
+    // handle misalignment of interval_start with ts, and leading missing values
+    if curr_obs_time != first_obs_time {
+        if first_obs_time < curr_obs_time {
+            return Err(Error::Misalignment(
+                "the first obs returned by frost is outside the time range".to_string(),
+            ));
+        }
+
+        while first_obs_time >= curr_obs_time + period {
+            data.push(None);
+            curr_obs_time = curr_obs_time + period;
+        }
+
+        if first_obs_time != curr_obs_time + period {
+            return Err(Error::Misalignment(
+                "the first obs returned by frost is not aligned with the start time and period"
+                    .to_string(),
+            ));
+        }
+
+        curr_obs_time = first_obs_time;
+    }
+
+    // insert obses into data, with Nones for gaps in the series
+    for obs in obses {
+        while curr_obs_time < obs.time {
+            data.push(None);
+            curr_obs_time = curr_obs_time + period;
+        }
+        if curr_obs_time == obs.time {
+            data.push(Some(obs.body.value));
+            curr_obs_time = curr_obs_time + period;
+        } else {
+            return Err(Error::Misalignment(
+                "obs misaligned with series".to_string(),
+            ));
+        }
+    }
+
+    // handle trailing missing values
+    while curr_obs_time < interval_end {
+        data.push(None);
+        curr_obs_time = curr_obs_time + period;
+    }
+
+    Ok(data)
+}
+
+#[allow(clippy::too_many_arguments)]
 fn json_to_data_cache(
     resp: serde_json::Value,
     period: RelativeDuration,
+    selector: SeriesSelector,
     num_leading_points: u8,
     num_trailing_points: u8,
     interval_start: DateTime<Utc>,
     interval_end: DateTime<Utc>,
 ) -> Result<DataCache, Error> {
-    let ts_vec = extract_data(resp, interval_start, period)?;
+    // every series in a response is of the same element
+    let units = resp
+        .pointer("/data/tseries/0/header/extra/element/unit")
+        .and_then(serde_json::Value::as_str)
+        .map(String::from);
+    let ts_vec = extract_data(resp, period, selector)?;
+    let data_start = interval_start - period * i32::from(num_leading_points);
+    // resampled series cover the whole window, including trailing points
+    let data_end = interval_end + period * i32::from(num_trailing_points);
+    let len = std::iter::successors(Some(data_start), |time| Some(*time + period))
+        .take_while(|time| *time <= data_end)
+        .count();
 
     let processed_ts_vec = ts_vec
         .into_iter()
-        .map(|((station_id, obses), location)| {
-            // TODO: preallocate?
-            // let ts_length = (end_time - first_obs_time) / period;
-            let mut data = Vec::new();
-
-            let mut curr_obs_time = interval_start - period * i32::from(num_leading_points);
-            let first_obs_time = obses
-                .first()
-                .ok_or(Error::MissingObs(
-                    "obs array from frost is empty".to_string(),
-                ))?
-                .time;
-
-            // handle misalignment of interval_start with ts, and leading missing values
-            if curr_obs_time != first_obs_time {
-                if first_obs_time < curr_obs_time {
-                    return Err(Error::Misalignment(
-                        "the first obs returned by frost is outside the time range".to_string(),
-                    ));
+        .map(|((station_id, obses), locations, metadata, resolution)| {
+            let data = match selector.resample {
+                Some(aggregation) if resolution != Some(period) => {
+                    let mut observations: Vec<(Timestamp, Option<f32>)> = obses
+                        .iter()
+                        .map(|obs| (Timestamp(obs.time.timestamp()), Some(obs.body.value)))
+                        .collect();
+                    observations.sort_by_key(|(time, _)| *time);
+                    data_switch::resample(
+                        &observations,
+                        resolution,
+                        Timestamp(data_start.timestamp()),
+                        period,
+                        len,
+                        aggregation,
+                    )
                 }
-
-                while first_obs_time >= curr_obs_time + period {
-                    data.push(None);
-                    curr_obs_time = curr_obs_time + period;
+                // aligned series stop at the end of the interval, so are padded to cover the
+                // trailing points too, like resampled ones
+                _ => {
+                    let mut data = align_obs(obses, period, data_start, interval_end)?;
+                    data.resize(len, None);
+                    data
                 }
+            };
 
-                if first_obs_time != curr_obs_time + period {
-                    return Err(Error::Misalignment(
-                        "the first obs returned by frost is not aligned with the start time and period".to_string(),
-                    ));
-                }
-
-                curr_obs_time = first_obs_time;
-            }
-
-            // insert obses into data, with Nones for gaps in the series
-            for obs in obses {
-                while curr_obs_time < obs.time {
-                    data.push(None);
-                    curr_obs_time = curr_obs_time + period;
-                }
-                if curr_obs_time == obs.time {
-                    data.push(Some(obs.body.value));
-                    curr_obs_time = curr_obs_time + period;
-                } else {
-                    return Err(Error::Misalignment(
-                        "obs misaligned with series".to_string(),
-                    ));
-                }
-            }
-
-            // handle trailing missing values
-            while curr_obs_time < interval_end {
-                data.push(None);
-                curr_obs_time = curr_obs_time + period;
-            }
+            // stations that moved within the window are split into a series per location, so
+            // each value is QCed where it was observed. The series for the location at the start
+            // of the window keeps the station id, and the others are identified by station id
+            // and the time they moved there
+            let location_indices: Vec<usize> = (0..data.len())
+                .map(|i| util::location_at(&locations, data_start + period * i as i32))
+                .collect();
+            let mut used_locations = location_indices.clone();
+            used_locations.sort_unstable();
+            used_locations.dedup();
 
-            Ok(((station_id, data), location))
+            Ok(used_locations
+                .into_iter()
+                .filter_map(|location| {
+                    let values: Vec<Option<f32>> = data
+                        .iter()
+                        .zip(&location_indices)
+                        .map(|(value, i)| if *i == location { *value } else { None })
+                        .collect();
+                    let identifier = if location == location_indices[0] {
+                        station_id.clone()
+                    } else if values.iter().any(Option::is_some) {
+                        format!(
+                            "{}@{}",
+                            station_id,
+                            locations[location]
+                                .from
+                                .to_rfc3339_opts(SecondsFormat::Secs, true)
+                        )
+                    } else {
+                        // nothing was observed there in this window
+                        return None;
+                    };
+                    Some((
+                        (identifier, values),
+                        locations[location].value,
+                        metadata.clone(),
+                    ))
+                })
+                .collect::<Vec<_>>())
         })
-        .collect::<Result<Vec<((String, Vec<Option<f32>>), FrostLatLonElev)>, Error>>()?;
+        .collect::<Result<Vec<_>, Error>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
 
-    Ok(DataCache::new(
+    let metadata = processed_ts_vec.iter().map(|ts| ts.2.clone()).collect();
+    let cache = DataCache::new(
         processed_ts_vec.iter().map(|ts| ts.1.latitude).collect(),
         processed_ts_vec.iter().map(|ts| ts.1.longitude).collect(),
         processed_ts_vec.iter().map(|ts| ts.1.elevation).collect(),
@@ -156,26 +365,165 @@ fn json_to_data_cache(
         num_leading_points,
         num_trailing_points,
         processed_ts_vec.into_iter().map(|ts| ts.0).collect(),
-    ))
+    )
+    .with_metadata(metadata);
+
+    Ok(match units {
+        Some(units) => cache.with_units(units),
+        None => cache,
+    })
+}
+
+// guards against following a loop of links forever
+const MAX_PAGES: usize = 100;
+
+async fn get_json(
+    frost: &Frost,
+    request: reqwest::RequestBuilder,
+) -> Result<serde_json::Value, Error> {
+    // held until the body is read, so slow downloads count towards the concurrency limit
+    let _permit = match &frost.limiter {
+        Some(limiter) => Some(limiter.acquire().await),
+        None => None,
+    };
+    Ok(request
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)?
+        .json()
+        .await?)
+}
+
+/// Resolve a response's link to its next page against the url it was fetched from
+///
+/// The link must be on the same origin as `base`, so a bad response can't send the
+/// connector's credentials to another server.
+fn next_page_url(base: &str, link: &str) -> Result<reqwest::Url, Error> {
+    let base = reqwest::Url::parse(base)
+        .map_err(|e| Error::Pagination(format!("invalid url {}: {}", base, e)))?;
+    let url = base
+        .join(link)
+        .map_err(|e| Error::Pagination(format!("invalid nextLink {}: {}", link, e)))?;
+    if url.origin() != base.origin() {
+        return Err(Error::Pagination(format!(
+            "nextLink {} is not on the same origin as {}",
+            link, base
+        )));
+    }
+    Ok(url)
+}
+
+/// Link to the next page of a paginated response, if there is one
+fn next_link(resp: &serde_json::Value) -> Option<String> {
+    resp.get("nextLink")
+        .or_else(|| resp.pointer("/data/nextLink"))
+        .and_then(serde_json::Value::as_str)
+        .map(String::from)
+}
+
+/// Append the tseries in a later page of a response to those in the first page
+///
+/// A series split across pages has its observations appended to the part of it already seen.
+fn merge_page(resp: &mut serde_json::Value, mut page: serde_json::Value) -> Result<(), Error> {
+    let find_tseries = |resp: &mut serde_json::Value| -> Result<Vec<serde_json::Value>, Error> {
+        match resp
+            .pointer_mut("/data/tseries")
+            .map(serde_json::Value::take)
+        {
+            Some(serde_json::Value::Array(tseries)) => Ok(tseries),
+            _ => Err(Error::FindObs("couldn't get array of tseries".to_string())),
+        }
+    };
+    // the page is checked first, so a bad page leaves the response untouched
+    let new_tseries = find_tseries(&mut page)?;
+    let mut tseries = find_tseries(resp)?;
+
+    // serde_json values can't be hashed, so series are indexed by their serialized id
+    let id = |ts: &serde_json::Value| ts.pointer("/header/id").map(|id| id.to_string());
+    let mut seen: HashMap<String, usize> = tseries
+        .iter()
+        .enumerate()
+        .filter_map(|(i, ts)| Some((id(ts)?, i)))
+        .collect();
+
+    for mut ts in new_tseries {
+        match id(&ts).and_then(|id| seen.get(&id)) {
+            Some(&i) => {
+                if let (
+                    Some(serde_json::Value::Array(seen_obs)),
+                    Some(serde_json::Value::Array(new_obs)),
+                ) = (
+                    tseries[i].get_mut("observations"),
+                    ts.get_mut("observations").map(serde_json::Value::take),
+                ) {
+                    seen_obs.extend(new_obs);
+                }
+            }
+            None => {
+                if let Some(id) = id(&ts) {
+                    seen.insert(id, tseries.len());
+                }
+                tseries.push(ts)
+            }
+        }
+    }
+
+    // find_tseries succeeded, so this exists
+    resp["data"]["tseries"] = serde_json::Value::Array(tseries);
+    Ok(())
+}
+
+/// Fetch a response, following links to any later pages and merging them into it
+///
+/// # Errors
+///
+/// If any page fails to fetch, or there are more than [`MAX_PAGES`] pages, as QCing a partial
+/// set of stations would be misleading, or a link to a page is on another origin than the
+/// configured url
+async fn get_all_pages(
+    frost: &Frost,
+    request: reqwest::RequestBuilder,
+) -> Result<serde_json::Value, Error> {
+    let mut resp = get_json(frost, request).await?;
+
+    let mut next = next_link(&resp);
+    let mut num_pages = 1;
+    while let Some(link) = next {
+        if num_pages == MAX_PAGES {
+            return Err(Error::Pagination(format!(
+                "response has more than {} pages",
+                MAX_PAGES
+            )));
+        }
+        let url = next_page_url(&frost.config.url, &link)?;
+        let page = get_json(frost, frost.config.authorize(frost.client.get(url))).await?;
+        next = next_link(&page);
+        merge_page(&mut resp, page)?;
+        num_pages += 1;
+    }
+
+    Ok(resp)
 }
 
 pub async fn fetch_data_inner(
+    frost: &Frost,
     space_spec: &SpaceSpec,
     time_spec: &TimeSpec,
     num_leading_points: u8,
     num_trailing_points: u8,
     extra_spec: Option<&str>,
 ) -> Result<DataCache, data_switch::Error> {
-    // TODO: figure out how to share the client between rove reqs
-    let client = reqwest::Client::new();
-
-    let element_id = extra_spec.ok_or(data_switch::Error::InvalidExtraSpec {
+    let invalid_extra_spec = |e: Error| data_switch::Error::InvalidExtraSpec {
         data_source: "frost",
         extra_spec: extra_spec.map(|s| s.to_string()),
-        source: Box::new(Error::InvalidElementId(
+        source: Box::new(e),
+    };
+    let (element_id, selector) = parse_extra_spec(extra_spec.ok_or_else(|| {
+        invalid_extra_spec(Error::InvalidElementId(
             "extra_spec must contain an element id",
-        )),
-    })?;
+        ))
+    })?)
+    .map_err(invalid_extra_spec)?;
 
     // TODO: should these maybe just be passed in this way?
     let interval_start = Utc.timestamp_opt(time_spec.timerange.start.0, 0).unwrap();
@@ -183,43 +531,66 @@ pub async fn fetch_data_inner(
 
     let extra_query_param = match space_spec {
         SpaceSpec::One(station_id) => Ok(("stationids", station_id.to_string())),
+        SpaceSpec::Multiple(station_ids) => Ok(("stationids", station_ids.join(","))),
         SpaceSpec::Polygon(polygon) => Ok(("polygon", parse_polygon(polygon))),
         SpaceSpec::All => Err(data_switch::Error::Other(Box::new(
             Error::InvalidSpaceSpec("space_spec for frost cannot be `All`, as frost will time out"),
         ))),
     }?;
 
-    let resp: serde_json::Value = client
-        .get("https://frost-beta.met.no/api/v1/obs/met.no/filter/get")
-        .query(&[
-            extra_query_param,
-            ("elementids", element_id.to_string()),
-            ("incobs", "true".to_string()),
-            (
-                "time",
-                format!(
-                    "{}/{}",
-                    (interval_start - time_spec.time_resolution * i32::from(num_leading_points))
-                        .to_rfc3339_opts(SecondsFormat::Secs, true),
-                    (interval_end
-                        + (time_spec.time_resolution * i32::from(num_trailing_points))
-                        + Duration::seconds(1))
-                    .to_rfc3339_opts(SecondsFormat::Secs, true)
-                ), // .as_str(),
-            ),
-            ("geopostype", "stationary".to_string()),
-        ])
-        .send()
-        .await
-        .map_err(|e| data_switch::Error::Other(Box::new(Error::Request(e))))?
-        .json()
-        .await
-        .map_err(|e| data_switch::Error::Other(Box::new(Error::Request(e))))?;
+    // frost narrows down the series by these, and they're checked again on the response in case
+    // a deployment ignores them
+    let mut selector_query = Vec::new();
+    if let Some(level) = selector.level {
+        selector_query.push(("levels", level.to_string()));
+    }
+    if let Some(sensor) = selector.sensor {
+        selector_query.push(("sensors", sensor.to_string()));
+    }
+
+    // station metadata (locations, time resolution, level) comes in the headers of this same
+    // response, as we ask for incobs=true, so there's no separate metadata request whose result
+    // could be cached across validations.
+    // TODO: if fetching is ever split into an incobs=false metadata request followed by a data
+    // request, cache the metadata by series id with a TTL here, shared between clones of Frost
+    // like the limiter
+    let (client, config) = (&frost.client, &frost.config);
+    let resp = get_all_pages(
+        frost,
+        config.authorize(
+            client
+                .get(&config.url)
+                .query(&config.extra_query)
+                .query(&selector_query)
+                .query(&[
+                    extra_query_param,
+                    ("elementids", element_id.to_string()),
+                    ("incobs", "true".to_string()),
+                    (
+                        "time",
+                        format!(
+                            "{}/{}",
+                            (interval_start
+                                - time_spec.time_resolution * i32::from(num_leading_points))
+                            .to_rfc3339_opts(SecondsFormat::Secs, true),
+                            (interval_end
+                                + (time_spec.time_resolution * i32::from(num_trailing_points))
+                                + Duration::seconds(1))
+                            .to_rfc3339_opts(SecondsFormat::Secs, true)
+                        ), // .as_str(),
+                    ),
+                    ("geopostype", "stationary".to_string()),
+                ]),
+        ),
+    )
+    .await
+    .map_err(|e| data_switch::Error::Other(Box::new(e)))?;
 
     // TODO: send this part to rayon?
     json_to_data_cache(
         resp,
         time_spec.time_resolution,
+        selector,
         num_leading_points,
         num_trailing_points,
         interval_start,
@@ -231,6 +602,7 @@ pub async fn fetch_data_inner(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::frost::{FrostAuth, FrostConfig};
 
     const RESP_SERIES: &str = r#"
 {
@@ -341,6 +713,7 @@ mod tests {
         let series_cache = json_to_data_cache(
             resp,
             RelativeDuration::hours(1),
+            SeriesSelector::default(),
             2,
             0,
             Utc.with_ymd_and_hms(2023, 6, 26, 14, 0, 0).unwrap(),
@@ -358,6 +731,219 @@ mod tests {
             series_cache.data[0].1,
             vec![Some(27.3999996), Some(25.7999992), Some(26.)]
         );
+        assert_eq!(series_cache.station_metadata(0).sensor_height, Some(2.));
+        assert_eq!(series_cache.units.as_deref(), Some("degC"));
+    }
+
+    #[test]
+    fn test_json_to_series_cache_relocated() {
+        let mut resp: serde_json::Value = serde_json::from_str(RESP_SERIES).unwrap();
+        let locations = resp
+            .pointer_mut("/data/tseries/0/header/extra/station/location")
+            .and_then(serde_json::Value::as_array_mut)
+            .unwrap();
+        locations[1]["to"] = "2023-06-26T12:30:00Z".into();
+        locations.push(serde_json::json!({
+            "from": "2023-06-26T12:30:00Z",
+            "to": "9999-01-01T00:00:00Z",
+            "value": {
+                "elevation(masl/hs)": "100",
+                "latitude": "59.950000",
+                "longitude": "10.730000"
+            }
+        }));
+
+        let series_cache = json_to_data_cache(
+            resp,
+            RelativeDuration::hours(1),
+            SeriesSelector::default(),
+            2,
+            0,
+            Utc.with_ymd_and_hms(2023, 6, 26, 14, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2023, 6, 26, 14, 0, 0).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            series_cache.data,
+            vec![
+                ("18700".to_string(), vec![Some(27.3999996), None, None]),
+                (
+                    "18700@2023-06-26T12:30:00Z".to_string(),
+                    vec![None, Some(25.7999992), Some(26.)]
+                ),
+            ]
+        );
+        assert_eq!(series_cache.rtree.elevs, vec![94., 100.]);
+    }
+
+    #[test]
+    fn test_parse_extra_spec() {
+        assert_eq!(
+            parse_extra_spec("air_temperature").unwrap(),
+            ("air_temperature", SeriesSelector::default())
+        );
+        assert_eq!(
+            parse_extra_spec("air_temperature; level=10;sensor=1").unwrap(),
+            (
+                "air_temperature",
+                SeriesSelector {
+                    level: Some(10),
+                    sensor: Some(1),
+                    resample: None,
+                }
+            )
+        );
+        assert_eq!(
+            parse_extra_spec("sum(precipitation_amount PT1H);resample=sum")
+                .unwrap()
+                .1
+                .resample,
+            Some(Aggregation::Sum)
+        );
+        assert!(matches!(
+            parse_extra_spec("air_temperature;height=2"),
+            Err(Error::InvalidSelector(part)) if part == "height=2"
+        ));
+        assert!(matches!(
+            parse_extra_spec(";sensor=1"),
+            Err(Error::InvalidElementId(_))
+        ));
+    }
+
+    #[test]
+    fn test_json_to_series_cache_resampled() {
+        let mut resp: serde_json::Value = serde_json::from_str(RESP_SERIES).unwrap();
+        let ts = resp.pointer_mut("/data/tseries/0").unwrap();
+        ts["header"]["extra"]["timeseries"]["timeresolution"] = "PT30M".into();
+        ts["observations"] = serde_json::json!([
+            { "time": "2023-06-26T12:30:00Z", "body": { "value": "1" } },
+            { "time": "2023-06-26T13:00:00Z", "body": { "value": "2" } },
+            { "time": "2023-06-26T13:30:00Z", "body": { "value": "3" } },
+            { "time": "2023-06-26T14:00:00Z", "body": { "value": "5" } },
+        ]);
+
+        let to_cache = |resample| {
+            json_to_data_cache(
+                resp.clone(),
+                RelativeDuration::hours(1),
+                SeriesSelector {
+                    resample,
+                    ..Default::default()
+                },
+                2,
+                0,
+                Utc.with_ymd_and_hms(2023, 6, 26, 14, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2023, 6, 26, 14, 0, 0).unwrap(),
+            )
+            .unwrap()
+        };
+
+        assert!(to_cache(None).data.is_empty());
+        assert_eq!(
+            to_cache(Some(Aggregation::Mean)).data,
+            vec![("18700".to_string(), vec![None, Some(1.5), Some(4.)])]
+        );
+        assert_eq!(
+            to_cache(Some(Aggregation::Sum)).data,
+            vec![("18700".to_string(), vec![None, Some(3.), Some(8.)])]
+        );
+    }
+
+    #[test]
+    fn test_json_to_series_cache_mixed_resolutions() {
+        let mut resp: serde_json::Value = serde_json::from_str(RESP_SERIES).unwrap();
+        let tseries = resp
+            .pointer_mut("/data/tseries")
+            .and_then(serde_json::Value::as_array_mut)
+            .unwrap();
+        let mut half_hourly = tseries[0].clone();
+        half_hourly["header"]["id"]["sensor"] = 1.into();
+        half_hourly["header"]["extra"]["timeseries"]["timeresolution"] = "PT30M".into();
+        half_hourly["observations"] = serde_json::json!([
+            { "time": "2023-06-26T13:30:00Z", "body": { "value": "3" } },
+            { "time": "2023-06-26T14:00:00Z", "body": { "value": "5" } },
+            { "time": "2023-06-26T14:30:00Z", "body": { "value": "6" } },
+            { "time": "2023-06-26T15:00:00Z", "body": { "value": "8" } },
+        ]);
+        tseries.push(half_hourly);
+
+        let series_cache = json_to_data_cache(
+            resp,
+            RelativeDuration::hours(1),
+            SeriesSelector {
+                resample: Some(Aggregation::Mean),
+                ..Default::default()
+            },
+            2,
+            1,
+            Utc.with_ymd_and_hms(2023, 6, 26, 14, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2023, 6, 26, 14, 0, 0).unwrap(),
+        )
+        .unwrap();
+
+        // both cover the leading and trailing points, though the aligned one has no trailing
+        // observation
+        assert_eq!(
+            series_cache.data,
+            vec![
+                (
+                    "18700".to_string(),
+                    vec![Some(27.3999996), Some(25.7999992), Some(26.), None]
+                ),
+                (
+                    "18700:1:0".to_string(),
+                    vec![None, None, Some(4.), Some(7.)]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_to_series_cache_sensors() {
+        let mut resp: serde_json::Value = serde_json::from_str(RESP_SERIES).unwrap();
+        let tseries = resp
+            .pointer_mut("/data/tseries")
+            .and_then(serde_json::Value::as_array_mut)
+            .unwrap();
+        let mut second_sensor = tseries[0].clone();
+        second_sensor["header"]["id"]["sensor"] = 1.into();
+        second_sensor["observations"][0]["body"]["value"] = "28".into();
+        tseries.insert(0, second_sensor);
+
+        let to_cache = |resp, selector| {
+            json_to_data_cache(
+                resp,
+                RelativeDuration::hours(1),
+                selector,
+                2,
+                0,
+                Utc.with_ymd_and_hms(2023, 6, 26, 14, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2023, 6, 26, 14, 0, 0).unwrap(),
+            )
+            .unwrap()
+        };
+
+        let series_cache = to_cache(resp.clone(), SeriesSelector::default());
+        assert_eq!(
+            series_cache
+                .data
+                .iter()
+                .map(|ts| (ts.0.as_str(), ts.1[0]))
+                .collect::<Vec<_>>(),
+            vec![("18700:1:0", Some(28.)), ("18700", Some(27.3999996))]
+        );
+
+        let series_cache = to_cache(
+            resp,
+            SeriesSelector {
+                sensor: Some(1),
+                ..Default::default()
+            },
+        );
+        assert_eq!(series_cache.data.len(), 1);
+        assert_eq!(series_cache.data[0].0, "18700");
+        assert_eq!(series_cache.data[0].1[0], Some(28.));
     }
 
     const RESP_SPATIAL: &str = r#"
@@ -605,6 +1191,107 @@ mod tests {
     }
 }"#;
 
+    #[test]
+    fn test_merge_page() {
+        let page = |next: Option<&str>, tseries: serde_json::Value| {
+            let mut page = serde_json::json!({ "data": { "tseries": tseries } });
+            if let Some(next) = next {
+                page["nextLink"] = next.into();
+            }
+            page
+        };
+        let ts = |station: i32, times: &[&str]| {
+            serde_json::json!({
+                "header": { "id": { "stationid": station } },
+                "observations": times
+                    .iter()
+                    .map(|time| serde_json::json!({ "time": time }))
+                    .collect::<Vec<_>>(),
+            })
+        };
+
+        let mut resp = page(
+            Some("https://frost-beta.met.no/page/2"),
+            serde_json::json!([ts(1, &["a"]), ts(2, &["a"])]),
+        );
+        assert_eq!(
+            next_link(&resp).as_deref(),
+            Some("https://frost-beta.met.no/page/2")
+        );
+
+        let second = page(None, serde_json::json!([ts(2, &["b"]), ts(3, &["a"])]));
+        assert_eq!(next_link(&second), None);
+        merge_page(&mut resp, second).unwrap();
+
+        assert_eq!(
+            resp["data"]["tseries"],
+            serde_json::json!([ts(1, &["a"]), ts(2, &["a", "b"]), ts(3, &["a"])])
+        );
+
+        assert!(matches!(
+            merge_page(&mut resp, serde_json::json!({})),
+            Err(Error::FindObs(_))
+        ));
+    }
+
+    #[test]
+    fn test_next_page_url() {
+        let base = "https://frost-beta.met.no/api/v1/obs/met.no/filter/get";
+        assert_eq!(
+            next_page_url(base, "https://frost-beta.met.no/page/2")
+                .unwrap()
+                .as_str(),
+            "https://frost-beta.met.no/page/2"
+        );
+        // relative links are resolved against the url
+        assert_eq!(
+            next_page_url(base, "/page/2").unwrap().as_str(),
+            "https://frost-beta.met.no/page/2"
+        );
+        for link in [
+            "https://attacker.example/page/2",
+            "http://frost-beta.met.no/page/2",
+            "https://frost-beta.met.no:8443/page/2",
+            "//attacker.example/page/2",
+        ] {
+            assert!(
+                matches!(next_page_url(base, link), Err(Error::Pagination(_))),
+                "{}",
+                link
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_authorize() {
+        let client = reqwest::Client::new();
+        let config = FrostConfig {
+            url: "https://frost-staging.example/obs".to_string(),
+            auth: Some(FrostAuth::Header {
+                name: "x-api-key".to_string(),
+                value: "hunter2".to_string(),
+            }),
+            extra_query: vec![("qualities".to_string(), "0,1".to_string())],
+        };
+
+        let request = config
+            .authorize(client.get(&config.url).query(&config.extra_query))
+            .build()
+            .unwrap();
+        assert_eq!(
+            request.url().as_str(),
+            "https://frost-staging.example/obs?qualities=0%2C1"
+        );
+        assert_eq!(request.headers()["x-api-key"], "hunter2");
+        assert!(!format!("{:?}", config).contains("hunter2"));
+
+        let request = FrostConfig::default()
+            .authorize(client.get("https://frost-beta.met.no/page/2"))
+            .build()
+            .unwrap();
+        assert!(request.headers().get("authorization").is_none());
+    }
+
     #[test]
     fn test_json_to_spatial_cache() {
         let resp = serde_json::from_str(RESP_SPATIAL).unwrap();
@@ -612,6 +1299,7 @@ mod tests {
         let spatial_cache = json_to_data_cache(
             resp,
             RelativeDuration::hours(1),
+            SeriesSelector::default(),
             0,
             0,
             Utc.with_ymd_and_hms(2023, 8, 13, 18, 0, 0).unwrap(),
@@ -0,0 +1,147 @@
+use std::{
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::{
+    sync::{Semaphore, SemaphorePermit},
+    time::Instant,
+};
+
+/// Limits on the requests a [`Frost`](crate::Frost) connector sends
+///
+/// Requests wait for a slot when `max_concurrent` are already in flight, and are otherwise
+/// spaced out by a token bucket, which allows bursts of up to `burst` requests and refills at
+/// `requests_per_second`. Each page of a paginated response counts as a request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    /// Maximum number of requests in flight at once
+    pub max_concurrent: usize,
+    /// Sustained rate of requests, a rate of 0 or less disables the token bucket
+    pub requests_per_second: f64,
+    /// Number of requests that can be sent at once after a quiet period
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            max_concurrent: 8,
+            requests_per_second: 10.,
+            burst: 10,
+        }
+    }
+}
+
+impl RateLimit {
+    /// Whether the token bucket can be refilled at this rate, which takes a finite rate that's
+    /// either disabled, or fast enough that the wait for a token fits in a [`Duration`]
+    pub fn is_valid(&self) -> bool {
+        let rate = self.requests_per_second;
+        rate.is_finite() && (rate <= 0. || Duration::try_from_secs_f64(1. / rate).is_ok())
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    rate_limit: RateLimit,
+    semaphore: Semaphore,
+    bucket: Mutex<Bucket>,
+}
+
+impl Limiter {
+    pub fn new(rate_limit: RateLimit) -> Self {
+        // a limit of 0 would block every request forever
+        let burst = f64::from(rate_limit.burst.max(1));
+        Limiter {
+            semaphore: Semaphore::new(rate_limit.max_concurrent.max(1)),
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                last_refill: Instant::now(),
+            }),
+            rate_limit,
+        }
+    }
+
+    /// Take a token from the bucket, or return how long to wait until there is one
+    fn take_token(&self) -> Option<Duration> {
+        let rate = self.rate_limit.requests_per_second;
+        if rate <= 0. {
+            return None;
+        }
+        let burst = f64::from(self.rate_limit.burst.max(1));
+
+        let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.last_refill).as_secs_f64() * rate)
+            .min(burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1. {
+            bucket.tokens -= 1.;
+            None
+        } else {
+            // there's less than a token missing, and the rate was validated to wait at most a
+            // Duration for a whole one
+            Some(Duration::from_secs_f64((1. - bucket.tokens) / rate))
+        }
+    }
+
+    /// Wait until a request may be sent, the returned permit holds its slot until dropped
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        // the semaphore is owned by the limiter and never closed
+        let permit = self.semaphore.acquire().await.unwrap();
+        while let Some(wait) = self.take_token() {
+            tokio::time::sleep(wait).await;
+        }
+        permit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limiter() {
+        let limiter = Limiter::new(RateLimit {
+            max_concurrent: 2,
+            requests_per_second: 50.,
+            burst: 2,
+        });
+
+        // the burst goes through straight away, and fills the concurrency limit
+        let start = Instant::now();
+        let first = limiter.acquire().await;
+        let _second = limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert!(limiter.semaphore.try_acquire().is_err());
+
+        // later requests wait for a slot, then a token, which takes 20ms at 50 per second
+        drop(first);
+        let _third = limiter.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(start.elapsed() < Duration::from_millis(25));
+    }
+
+    #[test]
+    fn test_rate_limit_is_valid() {
+        let rate_limit = |requests_per_second| RateLimit {
+            requests_per_second,
+            ..Default::default()
+        };
+
+        for rate in [10., 0., -1., 1e-9] {
+            assert!(rate_limit(rate).is_valid(), "{}", rate);
+        }
+        for rate in [f64::NAN, f64::INFINITY, f64::MIN_POSITIVE, 1e-300] {
+            assert!(!rate_limit(rate).is_valid(), "{}", rate);
+        }
+    }
+}
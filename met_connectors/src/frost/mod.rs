@@ -5,17 +5,27 @@ use rove::{
     data_switch::{DataCache, DataConnector, SpaceSpec, TimeSpec},
 };
 use serde::{Deserialize, Deserializer};
+use std::{sync::Arc, time::Duration};
 use thiserror::Error;
 
 mod duration;
 mod fetch;
+mod limit;
 mod util;
 
+use limit::Limiter;
+pub use limit::RateLimit;
+
 #[derive(Error, Debug)]
 #[non_exhaustive]
 pub enum Error {
     #[error("{0}")]
     InvalidElementId(&'static str),
+    #[error(
+        "invalid series selector `{0}`, expected level=<number>, sensor=<number> or \
+         resample=<nearest|mean|sum>"
+    )]
+    InvalidSelector(String),
     #[error("invalid space_spec: {0}")]
     InvalidSpaceSpec(&'static str),
     #[error("fetching data from frost failed")]
@@ -37,10 +47,134 @@ pub enum Error {
     MissingObs(String),
     #[error("{0}")]
     Misalignment(String),
+    #[error("failed to fetch all pages of the response: {0}")]
+    Pagination(String),
+    #[error("invalid rate limit: {0} requests per second is not finite, or too low to wait for")]
+    InvalidRateLimit(f64),
+}
+
+/// How to authenticate to Frost
+#[derive(Clone, PartialEq, Eq)]
+pub enum FrostAuth {
+    /// HTTP basic auth with a Frost client id, and its secret if it has one
+    ClientId { id: String, secret: Option<String> },
+    /// A header sent with every request, e.g. an API key or bearer token
+    Header { name: String, value: String },
 }
 
-#[derive(Debug)]
-pub struct Frost;
+// hand written, so credentials don't end up in logs
+impl std::fmt::Debug for FrostAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrostAuth::ClientId { id, .. } => f
+                .debug_struct("ClientId")
+                .field("id", id)
+                .field("secret", &"<redacted>")
+                .finish(),
+            FrostAuth::Header { name, .. } => f
+                .debug_struct("Header")
+                .field("name", name)
+                .field("value", &"<redacted>")
+                .finish(),
+        }
+    }
+}
+
+/// Where and how the [`Frost`] connector fetches data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrostConfig {
+    /// URL of the observations endpoint
+    pub url: String,
+    /// Credentials to send with each request, if the deployment needs any
+    pub auth: Option<FrostAuth>,
+    /// Query parameters added to each request, alongside those the connector sets itself
+    pub extra_query: Vec<(String, String)>,
+}
+
+impl Default for FrostConfig {
+    /// The public frost-beta instance, without credentials
+    fn default() -> Self {
+        Self {
+            url: "https://frost-beta.met.no/api/v1/obs/met.no/filter/get".to_string(),
+            auth: None,
+            extra_query: Vec::new(),
+        }
+    }
+}
+
+impl FrostConfig {
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.auth {
+            None => request,
+            Some(FrostAuth::ClientId { id, secret }) => request.basic_auth(id, secret.as_ref()),
+            Some(FrostAuth::Header { name, value }) => request.header(name, value),
+        }
+    }
+}
+
+/// Connector to [Frost](https://frost.met.no)
+///
+/// Holds an HTTP client that is reused across fetches, so connections to Frost are pooled
+///
+/// The extra_spec is the element id to fetch, optionally followed by `;level=<level>` and/or
+/// `;sensor=<sensor>` to pick out one of a station's series of that element. Where a station has
+/// several matching series, the one with the lowest sensor and level is identified by the station
+/// id, and the others by `<station id>:<sensor>:<level>`.
+///
+/// Series at other time resolutions than the one requested are dropped, unless the extra_spec
+/// also has `;resample=<nearest|mean|sum>`, in which case they are
+/// [resampled](rove::data_switch::resample) onto the requested resolution.
+#[derive(Debug, Clone)]
+pub struct Frost {
+    client: reqwest::Client,
+    config: FrostConfig,
+    // shared between clones, so they are limited together
+    limiter: Option<Arc<Limiter>>,
+}
+
+impl Frost {
+    /// Construct a connector fetching according to `config`, with a default client
+    ///
+    /// The client times out requests that take longer than 60 seconds, and picks up proxy
+    /// settings from the environment.
+    ///
+    /// # Errors
+    ///
+    /// If the client could not be initialised, e.g. because the TLS backend failed to load
+    pub fn new(config: FrostConfig) -> Result<Self, Error> {
+        Ok(Self::with_client(
+            reqwest::Client::builder()
+                .connect_timeout(Duration::from_secs(10))
+                .timeout(Duration::from_secs(60))
+                .build()?,
+            config,
+        ))
+    }
+
+    /// Construct a connector fetching according to `config`, that uses `client` for its
+    /// requests, for control over pooling, timeouts and proxies
+    pub fn with_client(client: reqwest::Client, config: FrostConfig) -> Self {
+        Self {
+            client,
+            config,
+            limiter: None,
+        }
+    }
+
+    /// Limit the rate and concurrency of requests to Frost, so bursts of validation requests
+    /// don't trip its abuse protection
+    ///
+    /// # Errors
+    ///
+    /// If `rate_limit` isn't [valid](RateLimit::is_valid)
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Result<Self, Error> {
+        if !rate_limit.is_valid() {
+            return Err(Error::InvalidRateLimit(rate_limit.requests_per_second));
+        }
+        self.limiter = Some(Arc::new(Limiter::new(rate_limit)));
+        Ok(self)
+    }
+}
 
 #[derive(Deserialize, Debug)]
 struct FrostObsBody {
@@ -56,7 +190,7 @@ struct FrostObs {
     time: DateTime<Utc>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, Copy)]
 struct FrostLatLonElev {
     #[serde(rename = "elevation(masl/hs)")]
     #[serde(deserialize_with = "des_value")]
@@ -109,6 +243,7 @@ impl DataConnector for Frost {
         extra_spec: Option<&str>,
     ) -> Result<DataCache, data_switch::Error> {
         fetch::fetch_data_inner(
+            self,
             space_spec,
             time_spec,
             num_leading_points,
@@ -117,4 +252,22 @@ impl DataConnector for Frost {
         )
         .await
     }
+
+    fn is_retryable(&self, error: &data_switch::Error) -> bool {
+        match error {
+            data_switch::Error::Other(source) => match source.downcast_ref::<Error>() {
+                Some(Error::Request(e)) => {
+                    e.is_timeout()
+                        || e.is_connect()
+                        || e.status().is_some_and(|status| {
+                            status.is_server_error()
+                                || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        })
+                }
+                _ => false,
+            },
+            data_switch::Error::Io(_) | data_switch::Error::Timeout(..) => true,
+            _ => false,
+        }
+    }
 }
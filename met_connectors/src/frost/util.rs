@@ -1,4 +1,4 @@
-use crate::frost::{duration, Error, FrostLatLonElev, FrostLocation};
+use crate::frost::{duration, Error, FrostLocation};
 use chrono::prelude::*;
 use chronoutil::RelativeDuration;
 
@@ -27,10 +27,8 @@ pub fn extract_duration(header: &mut serde_json::Value) -> Result<RelativeDurati
     })
 }
 
-pub fn extract_location(
-    header: &mut serde_json::Value,
-    time: DateTime<Utc>,
-) -> Result<FrostLatLonElev, Error> {
+/// The locations a station has had, in chronological order
+pub fn extract_locations(header: &mut serde_json::Value) -> Result<Vec<FrostLocation>, Error> {
     let location = header
         .get_mut("extra")
         .ok_or(Error::FindLocation(
@@ -46,17 +44,24 @@ pub fn extract_location(
         ))?
         .take();
 
-    let loc = serde_json::from_value::<Vec<FrostLocation>>(location)?;
+    let mut locations = serde_json::from_value::<Vec<FrostLocation>>(location)?;
+    if locations.is_empty() {
+        return Err(Error::FindLocation("station has no locations".to_string()));
+    }
+    locations.sort_by_key(|location| location.from);
 
-    let lat_lon_elev = loc
-        .into_iter()
-        .find(|l| time > l.from && time < l.to)
-        .ok_or(Error::FindLocation(
-            "couldn't find relevant location for this observation".to_string(),
-        ))?
-        .value;
+    Ok(locations)
+}
 
-    Ok(lat_lon_elev)
+/// Index of the location a station was at at `time`, out of `locations` in chronological order
+///
+/// This is the latest location starting at or before `time`, so gaps between locations are
+/// bridged by the earlier one, and times before the first location use the first.
+pub fn location_at(locations: &[FrostLocation], time: DateTime<Utc>) -> usize {
+    locations
+        .iter()
+        .rposition(|location| location.from <= time)
+        .unwrap_or(0)
 }
 
 pub fn extract_station_id(header: &mut serde_json::Value) -> Result<String, Error> {
@@ -75,3 +80,26 @@ pub fn extract_station_id(header: &mut serde_json::Value) -> Result<String, Erro
 
     Ok(station_id.to_string())
 }
+
+/// Level and sensor numbers of a timeseries, which tell apart a station's series of an element
+///
+/// Frost leaves these out for some series, in which case they are taken to be 0, the default.
+pub fn extract_level_sensor(header: &serde_json::Value) -> (i32, i32) {
+    let number = |pointer| {
+        header
+            .pointer(pointer)
+            .and_then(serde_json::Value::as_i64)
+            .and_then(|n| i32::try_from(n).ok())
+            .unwrap_or(0)
+    };
+    (number("/id/level"), number("/id/sensor"))
+}
+
+/// Height of the sensor above ground in metres, if the header gives its level in metres
+pub fn extract_sensor_height(header: &serde_json::Value) -> Option<f32> {
+    let level = header.pointer("/extra/timeseries/geometry/level")?;
+    if level.get("unit")?.as_str()? != "m" {
+        return None;
+    }
+    level.get("value")?.as_str()?.parse().ok()
+}
@@ -0,0 +1,619 @@
+//! A minimal GRIB2 decoder
+//!
+//! Only covers what we need from NWP output: regular lat-lon (3.0) and Lambert
+//! conformal (3.30) grids, instantaneous products (4.0 and 4.1), and simple
+//! packing (5.0). Messages with other product templates are skipped, anything
+//! else unsupported is an error.
+
+use chrono::NaiveDate;
+use std::f64::consts::FRAC_PI_4;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("malformed GRIB2 data: {0}")]
+    Malformed(&'static str),
+    #[error("unsupported GRIB2 feature: {0}")]
+    Unsupported(String),
+}
+
+// earth radius assumed by shape of the earth code 6, which most NWP output uses
+const DEFAULT_EARTH_RADIUS: f64 = 6_371_229.;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Projection {
+    LatLon {
+        lat1: f64,
+        lon1: f64,
+        di: f64,
+        dj: f64,
+    },
+    Lambert {
+        lat1: f64,
+        lon1: f64,
+        lov: f64,
+        lad: f64,
+        latin1: f64,
+        latin2: f64,
+        dx: f64,
+        dy: f64,
+        radius: f64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+    ni: usize,
+    nj: usize,
+    scanning_mode: u8,
+    projection: Projection,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Product {
+    category: u8,
+    number: u8,
+    forecast_seconds: i64,
+    surface: Option<(u8, f64)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Packing {
+    num_values: usize,
+    reference_value: f32,
+    binary_scale: i32,
+    decimal_scale: i32,
+    bits_per_value: usize,
+}
+
+/// One field decoded from a GRIB2 message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub discipline: u8,
+    pub category: u8,
+    pub number: u8,
+    /// Type and value of the first fixed surface, if it has one
+    pub surface: Option<(u8, f64)>,
+    /// Unix timestamp of the time the field is valid for
+    pub valid_time: i64,
+    pub grid: Grid,
+    /// Values at each gridpoint, in the order of [`Grid::latlons`]
+    pub values: Vec<Option<f32>>,
+}
+
+fn byte(bytes: &[u8], offset: usize) -> Result<u8, Error> {
+    bytes
+        .get(offset)
+        .copied()
+        .ok_or(Error::Malformed("section shorter than its template"))
+}
+
+fn uint(bytes: &[u8], offset: usize, len: usize) -> Result<u64, Error> {
+    Ok(bytes
+        .get(offset..offset + len)
+        .ok_or(Error::Malformed("section shorter than its template"))?
+        .iter()
+        .fold(0, |acc, b| (acc << 8) | u64::from(*b)))
+}
+
+// GRIB2 stores signed integers as sign and magnitude, not two's complement
+fn int(bytes: &[u8], offset: usize, len: usize) -> Result<i64, Error> {
+    let raw = uint(bytes, offset, len)?;
+    let sign_bit = 1 << (len * 8 - 1);
+    let magnitude = (raw & !sign_bit) as i64;
+    Ok(if raw & sign_bit != 0 {
+        -magnitude
+    } else {
+        magnitude
+    })
+}
+
+impl Grid {
+    fn num_points(&self) -> usize {
+        self.ni * self.nj
+    }
+
+    /// Latitudes and longitudes of the gridpoints, in the order the values are stored
+    pub fn latlons(&self) -> (Vec<f32>, Vec<f32>) {
+        let i_step = if self.scanning_mode & 0x80 != 0 {
+            -1.
+        } else {
+            1.
+        };
+        let j_step = if self.scanning_mode & 0x40 != 0 {
+            1.
+        } else {
+            -1.
+        };
+        let points = (0..self.nj).flat_map(|j| (0..self.ni).map(move |i| (i as f64, j as f64)));
+
+        let (lats, lons) = match &self.projection {
+            Projection::LatLon { lat1, lon1, di, dj } => points
+                .map(|(i, j)| (lat1 + j * dj * j_step, lon1 + i * di * i_step))
+                .unzip::<_, _, Vec<_>, Vec<_>>(),
+            Projection::Lambert {
+                lat1,
+                lon1,
+                lov,
+                lad,
+                latin1,
+                latin2,
+                dx,
+                dy,
+                radius,
+            } => {
+                let lambert = Lambert::new(*latin1, *latin2, *lad, *lov, *radius);
+                let (x1, y1) = lambert.forward(*lat1, *lon1);
+                points
+                    .map(|(i, j)| lambert.inverse(x1 + i * dx * i_step, y1 + j * dy * j_step))
+                    .unzip()
+            }
+        };
+
+        (
+            lats.into_iter().map(|lat| lat as f32).collect(),
+            lons.into_iter()
+                .map(|lon| ((lon + 180.).rem_euclid(360.) - 180.) as f32)
+                .collect(),
+        )
+    }
+}
+
+/// Spherical Lambert conformal conic projection, angles in degrees
+struct Lambert {
+    n: f64,
+    f: f64,
+    rho0: f64,
+    lov: f64,
+    radius: f64,
+}
+
+impl Lambert {
+    fn new(latin1: f64, latin2: f64, lad: f64, lov: f64, radius: f64) -> Self {
+        let t = |lat: f64| (FRAC_PI_4 + lat.to_radians() / 2.).tan();
+        let (phi1, phi2) = (latin1.to_radians(), latin2.to_radians());
+
+        let n = if (latin1 - latin2).abs() < 1e-9 {
+            phi1.sin()
+        } else {
+            (phi1.cos() / phi2.cos()).ln() / (t(latin2) / t(latin1)).ln()
+        };
+        let f = phi1.cos() * t(latin1).powf(n) / n;
+        let rho0 = radius * f / t(lad).powf(n);
+
+        Self {
+            n,
+            f,
+            rho0,
+            lov,
+            radius,
+        }
+    }
+
+    fn rho(&self, lat: f64) -> f64 {
+        self.radius * self.f / (FRAC_PI_4 + lat.to_radians() / 2.).tan().powf(self.n)
+    }
+
+    fn forward(&self, lat: f64, lon: f64) -> (f64, f64) {
+        let dlon = ((lon - self.lov + 180.).rem_euclid(360.) - 180.).to_radians();
+        let theta = self.n * dlon;
+        let rho = self.rho(lat);
+        (rho * theta.sin(), self.rho0 - rho * theta.cos())
+    }
+
+    fn inverse(&self, x: f64, y: f64) -> (f64, f64) {
+        let sign = self.n.signum();
+        let rho = sign * (x.powi(2) + (self.rho0 - y).powi(2)).sqrt();
+        let theta = (sign * x).atan2(sign * (self.rho0 - y));
+        let lat = 2. * (self.radius * self.f / rho).powf(1. / self.n).atan() - 2. * FRAC_PI_4;
+        (lat.to_degrees(), self.lov + (theta / self.n).to_degrees())
+    }
+}
+
+fn parse_reference_time(section: &[u8]) -> Result<i64, Error> {
+    NaiveDate::from_ymd_opt(
+        uint(section, 12, 2)? as i32,
+        byte(section, 14)?.into(),
+        byte(section, 15)?.into(),
+    )
+    .and_then(|date| {
+        date.and_hms_opt(
+            byte(section, 16).ok()?.into(),
+            byte(section, 17).ok()?.into(),
+            byte(section, 18).ok()?.into(),
+        )
+    })
+    .map(|time| time.and_utc().timestamp())
+    .ok_or(Error::Malformed("invalid reference time"))
+}
+
+fn parse_grid(section: &[u8]) -> Result<Grid, Error> {
+    if byte(section, 10)? != 0 {
+        return Err(Error::Unsupported("quasi-regular grids".to_string()));
+    }
+
+    let radius = match byte(section, 14)? {
+        0 => 6_367_470.,
+        1 => uint(section, 16, 4)? as f64 / 10f64.powi(byte(section, 15)?.into()),
+        _ => DEFAULT_EARTH_RADIUS,
+    };
+
+    let (ni, nj, scanning_mode, projection) = match uint(section, 12, 2)? {
+        0 => {
+            let basic_angle = uint(section, 38, 4)?;
+            let subdivisions = uint(section, 42, 4)?;
+            let unit = if basic_angle == 0 || basic_angle == 0xFFFFFFFF {
+                1e-6
+            } else {
+                basic_angle as f64 / subdivisions as f64
+            };
+            (
+                uint(section, 30, 4)?,
+                uint(section, 34, 4)?,
+                byte(section, 71)?,
+                Projection::LatLon {
+                    lat1: int(section, 46, 4)? as f64 * unit,
+                    lon1: uint(section, 50, 4)? as f64 * unit,
+                    di: uint(section, 63, 4)? as f64 * unit,
+                    dj: uint(section, 67, 4)? as f64 * unit,
+                },
+            )
+        }
+        30 => (
+            uint(section, 30, 4)?,
+            uint(section, 34, 4)?,
+            byte(section, 64)?,
+            Projection::Lambert {
+                lat1: int(section, 38, 4)? as f64 * 1e-6,
+                lon1: uint(section, 42, 4)? as f64 * 1e-6,
+                lad: int(section, 47, 4)? as f64 * 1e-6,
+                lov: uint(section, 51, 4)? as f64 * 1e-6,
+                dx: uint(section, 55, 4)? as f64 * 1e-3,
+                dy: uint(section, 59, 4)? as f64 * 1e-3,
+                latin1: int(section, 65, 4)? as f64 * 1e-6,
+                latin2: int(section, 69, 4)? as f64 * 1e-6,
+                radius,
+            },
+        ),
+        template => {
+            return Err(Error::Unsupported(format!(
+                "grid definition template 3.{}",
+                template
+            )))
+        }
+    };
+
+    // the other bits either don't change the order of points, or describe
+    // boustrophedonic or staggered grids
+    if scanning_mode & 0x3F != 0 {
+        return Err(Error::Unsupported(format!(
+            "scanning mode {:#010b}",
+            scanning_mode
+        )));
+    }
+
+    let grid = Grid {
+        ni: ni as usize,
+        nj: nj as usize,
+        scanning_mode,
+        projection,
+    };
+    if grid.num_points() != uint(section, 6, 4)? as usize {
+        return Err(Error::Malformed(
+            "number of gridpoints doesn't match grid size",
+        ));
+    }
+    Ok(grid)
+}
+
+fn parse_product(section: &[u8]) -> Result<Option<Product>, Error> {
+    if !matches!(uint(section, 7, 2)?, 0 | 1) {
+        return Ok(None);
+    }
+
+    let unit_seconds = match byte(section, 17)? {
+        0 => 60,
+        1 => 3600,
+        2 => 86400,
+        10 => 3 * 3600,
+        11 => 6 * 3600,
+        12 => 12 * 3600,
+        13 => 1,
+        unit => return Err(Error::Unsupported(format!("unit of time range {}", unit))),
+    };
+
+    let surface = match byte(section, 22)? {
+        255 => None,
+        surface_type => {
+            let scaled_value = uint(section, 24, 4)?;
+            let value = if scaled_value == 0xFFFFFFFF {
+                0.
+            } else {
+                scaled_value as f64 / 10f64.powi(int(section, 23, 1)? as i32)
+            };
+            Some((surface_type, value))
+        }
+    };
+
+    Ok(Some(Product {
+        category: byte(section, 9)?,
+        number: byte(section, 10)?,
+        forecast_seconds: int(section, 18, 4)? * unit_seconds,
+        surface,
+    }))
+}
+
+fn parse_packing(section: &[u8]) -> Result<Packing, Error> {
+    match uint(section, 9, 2)? {
+        0 => Ok(Packing {
+            num_values: uint(section, 5, 4)? as usize,
+            reference_value: f32::from_bits(uint(section, 11, 4)? as u32),
+            binary_scale: int(section, 15, 2)? as i32,
+            decimal_scale: int(section, 17, 2)? as i32,
+            bits_per_value: byte(section, 19)?.into(),
+        }),
+        template => Err(Error::Unsupported(format!(
+            "data representation template 5.{}",
+            template
+        ))),
+    }
+}
+
+fn unpack(data: &[u8], packing: &Packing) -> Result<Vec<f32>, Error> {
+    let bits = packing.bits_per_value;
+    if bits > 32 {
+        return Err(Error::Unsupported(format!("{} bits per value", bits)));
+    }
+    if data.len() * 8 < bits * packing.num_values {
+        return Err(Error::Malformed("data section shorter than its values"));
+    }
+
+    let binary_factor = 2f64.powi(packing.binary_scale);
+    let decimal_factor = 10f64.powi(-packing.decimal_scale);
+    Ok((0..packing.num_values)
+        .map(|k| {
+            let packed = (k * bits..(k + 1) * bits).fold(0u32, |acc, bit| {
+                (acc << 1) | u32::from((data[bit / 8] >> (7 - bit % 8)) & 1)
+            });
+            ((f64::from(packing.reference_value) + f64::from(packed) * binary_factor)
+                * decimal_factor) as f32
+        })
+        .collect())
+}
+
+fn decode_message(message: &[u8], fields: &mut Vec<Field>) -> Result<(), Error> {
+    let discipline = byte(message, 6)?;
+    let mut reference_time = None;
+    let mut grid = None;
+    let mut product = None;
+    let mut packing = None;
+    let mut bitmap: Option<Vec<bool>> = None;
+
+    // sections 2 to 7 can repeat within a message, each repetition of section
+    // 7 is a field using the latest of the other sections
+    let mut offset = 16;
+    while !message[offset..].starts_with(b"7777") {
+        let len = uint(message, offset, 4)? as usize;
+        let section = message
+            .get(offset..offset + len)
+            .filter(|_| len >= 5)
+            .ok_or(Error::Malformed("section runs past the end of its message"))?;
+
+        match section[4] {
+            1 => reference_time = Some(parse_reference_time(section)?),
+            2 => (),
+            3 => grid = Some(parse_grid(section)?),
+            4 => product = parse_product(section)?,
+            5 => packing = Some(parse_packing(section)?),
+            6 => match byte(section, 5)? {
+                0 => {
+                    bitmap = Some(
+                        (0..(len - 6) * 8)
+                            .map(|bit| section[6 + bit / 8] & (0x80 >> (bit % 8)) != 0)
+                            .collect(),
+                    )
+                }
+                // reuse the previous bitmap
+                254 => (),
+                255 => bitmap = None,
+                indicator => {
+                    return Err(Error::Unsupported(format!(
+                        "predefined bitmap {}",
+                        indicator
+                    )))
+                }
+            },
+            7 => {
+                let (Some(reference_time), Some(grid), Some(packing)) =
+                    (reference_time, &grid, &packing)
+                else {
+                    return Err(Error::Malformed("data section before its definitions"));
+                };
+                // a product we don't support, skip it
+                let Some(product) = &product else {
+                    offset += len;
+                    continue;
+                };
+
+                let mut packed = unpack(&section[5..], packing)?.into_iter();
+                let values = match &bitmap {
+                    Some(bitmap) => bitmap
+                        .iter()
+                        .take(grid.num_points())
+                        .map(|present| present.then(|| packed.next()).flatten())
+                        .collect(),
+                    None => packed.map(Some).collect(),
+                };
+
+                fields.push(Field {
+                    discipline,
+                    category: product.category,
+                    number: product.number,
+                    surface: product.surface,
+                    valid_time: reference_time + product.forecast_seconds,
+                    grid: grid.clone(),
+                    values,
+                });
+            }
+            _ => return Err(Error::Malformed("unknown section number")),
+        }
+
+        offset += len;
+    }
+
+    Ok(())
+}
+
+/// Decode all supported fields in a GRIB2 file
+pub fn decode(bytes: &[u8]) -> Result<Vec<Field>, Error> {
+    let mut fields = Vec::new();
+
+    let mut pos = 0;
+    // anything between messages is padding we can skip
+    while let Some(start) = bytes[pos..]
+        .windows(4)
+        .position(|window| window == b"GRIB")
+        .map(|start| pos + start)
+    {
+        match byte(bytes, start + 7)? {
+            2 => (),
+            edition => {
+                return Err(Error::Unsupported(format!("GRIB edition {}", edition)));
+            }
+        }
+        let len = uint(bytes, start + 8, 8)? as usize;
+        let message = bytes
+            .get(start..start + len)
+            .filter(|message| message.ends_with(b"7777"))
+            .ok_or(Error::Malformed("truncated message"))?;
+
+        decode_message(message, &mut fields)?;
+        pos = start + len;
+    }
+
+    Ok(fields)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn section(number: u8, body: &[u8]) -> Vec<u8> {
+        let mut section = ((body.len() + 5) as u32).to_be_bytes().to_vec();
+        section.push(number);
+        section.extend_from_slice(body);
+        section
+    }
+
+    /// Encode a message with a 2x2 regular lat-lon grid starting at 61N 10E
+    /// with 1 degree spacing, scanning north to south, and the given values
+    /// packed with 8 bits and a decimal scale of 1
+    pub(crate) fn encode_latlon_message(
+        category: u8,
+        number: u8,
+        surface: (u8, u32),
+        forecast_hours: u32,
+        values: [Option<u8>; 4],
+    ) -> Vec<u8> {
+        let mut identification = vec![0; 16];
+        identification[7..9].copy_from_slice(&1970u16.to_be_bytes());
+        identification[9] = 1;
+        identification[10] = 1;
+
+        let mut grid = vec![0; 67];
+        grid[1..5].copy_from_slice(&4u32.to_be_bytes());
+        grid[9] = 6;
+        grid[25..29].copy_from_slice(&2u32.to_be_bytes());
+        grid[29..33].copy_from_slice(&2u32.to_be_bytes());
+        grid[41..45].copy_from_slice(&61_000_000u32.to_be_bytes());
+        grid[45..49].copy_from_slice(&10_000_000u32.to_be_bytes());
+        grid[50..54].copy_from_slice(&60_000_000u32.to_be_bytes());
+        grid[54..58].copy_from_slice(&11_000_000u32.to_be_bytes());
+        grid[58..62].copy_from_slice(&1_000_000u32.to_be_bytes());
+        grid[62..66].copy_from_slice(&1_000_000u32.to_be_bytes());
+
+        let mut product = vec![0; 29];
+        product[4] = category;
+        product[5] = number;
+        product[12] = 1;
+        product[13..17].copy_from_slice(&forecast_hours.to_be_bytes());
+        product[17] = surface.0;
+        product[19..23].copy_from_slice(&surface.1.to_be_bytes());
+        product[23] = 255;
+
+        let present: Vec<u8> = values.iter().flatten().copied().collect();
+        let mut packing = vec![0; 16];
+        packing[0..4].copy_from_slice(&(present.len() as u32).to_be_bytes());
+        packing[12..14].copy_from_slice(&1u16.to_be_bytes());
+        packing[14] = 8;
+
+        let bitmap = values.iter().enumerate().fold(0u8, |acc, (i, value)| {
+            acc | ((value.is_some() as u8) << (7 - i))
+        });
+
+        let mut message = Vec::new();
+        message.extend(section(1, &identification));
+        message.extend(section(3, &grid));
+        message.extend(section(4, &product));
+        message.extend(section(5, &packing));
+        message.extend(section(6, &[0, bitmap]));
+        message.extend(section(7, &present));
+        message.extend(b"7777");
+
+        let mut indicator = b"GRIB\0\0\0\x02".to_vec();
+        indicator.extend(((message.len() + 16) as u64).to_be_bytes());
+        indicator.extend(message);
+        indicator
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut bytes =
+            encode_latlon_message(0, 0, (103, 2), 1, [Some(10), None, Some(25), Some(0)]);
+        bytes.extend(encode_latlon_message(0, 1, (103, 2), 1, [None; 4]));
+
+        let fields = decode(&bytes).unwrap();
+
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].number, 0);
+        assert_eq!(fields[0].surface, Some((103, 2.)));
+        assert_eq!(fields[0].valid_time, 3600);
+        assert_eq!(fields[0].values, vec![Some(1.), None, Some(2.5), Some(0.)]);
+        assert_eq!(fields[1].values, vec![None; 4]);
+        assert_eq!(
+            fields[0].grid.latlons(),
+            (vec![61., 61., 60., 60.], vec![10., 11., 10., 11.])
+        );
+    }
+
+    #[test]
+    fn test_lambert() {
+        // roughly the MEPS domain
+        let grid = Grid {
+            ni: 2,
+            nj: 2,
+            scanning_mode: 0x40,
+            projection: Projection::Lambert {
+                lat1: 50.319616,
+                lon1: 0.278137,
+                lov: 15.,
+                lad: 63.3,
+                latin1: 63.3,
+                latin2: 63.3,
+                dx: 2500.,
+                dy: 2500.,
+                radius: DEFAULT_EARTH_RADIUS,
+            },
+        };
+
+        let (lats, lons) = grid.latlons();
+
+        assert!((lats[0] - 50.319616).abs() < 1e-4);
+        assert!((lons[0] - 0.278137).abs() < 1e-4);
+        // moving along x at the western edge of the domain heads northeast,
+        // moving along y heads north
+        assert!(lats[1] > lats[0] && lons[1] > lons[0]);
+        assert!(lats[2] > lats[0] + 0.02 && lats[2] < lats[0] + 0.03);
+    }
+}
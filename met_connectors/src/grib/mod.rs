@@ -0,0 +1,407 @@
+use async_trait::async_trait;
+use chrono::prelude::*;
+use chronoutil::RelativeDuration;
+use rove::{
+    data_switch,
+    data_switch::{DataCache, DataConnector, Polygon, SpaceSpec, TimeSpec, Timestamp},
+};
+use std::{collections::HashMap, fs, io, str::FromStr};
+
+mod decode;
+
+// how far outside a requested polygon to keep gridpoints, so stations near its
+// edge still have gridpoints around them to interpolate from
+const POLYGON_MARGIN_DEGREES: f32 = 0.5;
+
+/// Selects a field in a GRIB2 file, in the format described on [`Grib`]
+#[derive(Debug, Clone, PartialEq)]
+struct FieldSpec {
+    discipline: u8,
+    category: u8,
+    number: u8,
+    surface: Option<(u8, f64)>,
+}
+
+impl FromStr for FieldSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid field `{}`, expected d.c.n[:surface:value]", s);
+
+        let (parameter, surface) = match s.split_once(':') {
+            Some((parameter, surface)) => {
+                let (surface_type, value) = surface.split_once(':').ok_or_else(invalid)?;
+                (
+                    parameter,
+                    Some((
+                        surface_type.parse().map_err(|_| invalid())?,
+                        value.parse().map_err(|_| invalid())?,
+                    )),
+                )
+            }
+            None => (s, None),
+        };
+        let parameter = parameter
+            .split('.')
+            .map(|part| part.parse().map_err(|_| invalid()))
+            .collect::<Result<Vec<u8>, String>>()?;
+        let [discipline, category, number] = parameter[..] else {
+            return Err(invalid());
+        };
+
+        Ok(FieldSpec {
+            discipline,
+            category,
+            number,
+            surface,
+        })
+    }
+}
+
+impl FieldSpec {
+    fn matches(&self, field: &decode::Field) -> bool {
+        field.discipline == self.discipline
+            && field.category == self.category
+            && field.number == self.number
+            && self.surface.is_none_or(|(surface_type, value)| {
+                field.surface.is_some_and(|(field_type, field_value)| {
+                    field_type == surface_type && (field_value - value).abs() < 1e-6
+                })
+            })
+    }
+}
+
+/// Connector for gridded model fields in GRIB2 files, such as MEPS output, to
+/// serve as the backing source of model_consistency_check and
+/// first_guess_check
+///
+/// Files are located by rendering `path_template` as a strftime pattern for
+/// each timestamp in the requested timerange, and may hold fields for any
+/// number of valid times. The field is picked with the extra_spec, written as
+/// `discipline.category.number`, optionally followed by
+/// `:surface_type:surface_value` to pick a level, e.g. `0.0.0:103:2` for air
+/// temperature 2m above ground. It is returned as a set of gridpoints that the
+/// checks interpolate to the locations of the stations being QCed.
+///
+/// Only regular lat-lon and Lambert conformal grids with simple packing are
+/// supported.
+#[derive(Debug, Clone)]
+pub struct Grib {
+    path_template: String,
+    period: RelativeDuration,
+    orography: Option<FieldSpec>,
+}
+
+impl Grib {
+    /// Create a connector for files at `path_template`, containing fields at
+    /// a time resolution of `period`
+    pub fn new(path_template: impl Into<String>, period: RelativeDuration) -> Self {
+        Self {
+            path_template: path_template.into(),
+            period,
+            orography: None,
+        }
+    }
+
+    /// Take the elevation of the gridpoints, in metres, from `field`, which
+    /// can be valid for any time
+    ///
+    /// This is needed for checks that adjust the model field to the elevation
+    /// of the stations. Without it, all gridpoints are at an elevation of 0.
+    ///
+    /// # Errors
+    ///
+    /// If `field` is not a valid field specifier
+    pub fn with_orography(mut self, field: &str) -> Result<Self, String> {
+        self.orography = Some(field.parse()?);
+        Ok(self)
+    }
+
+    fn read(
+        &self,
+        field_spec: &FieldSpec,
+        polygon: Option<&Polygon>,
+        time_spec: &TimeSpec,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+    ) -> Result<DataCache, data_switch::Error> {
+        // timestamps should be validated before they get here, so it should be safe to unwrap
+        let start_time = Utc.timestamp_opt(time_spec.timerange.start.0, 0).unwrap()
+            - self.period * i32::from(num_leading_points);
+        let end_time = Utc.timestamp_opt(time_spec.timerange.end.0, 0).unwrap()
+            + self.period * i32::from(num_trailing_points);
+
+        let mut times = Vec::new();
+        let mut time = start_time;
+        while time <= end_time {
+            times.push(time);
+            time = time + self.period;
+        }
+        let time_indices: HashMap<i64, usize> = times
+            .iter()
+            .enumerate()
+            .map(|(i, time)| (time.timestamp(), i))
+            .collect();
+
+        let mut paths: Vec<String> = Vec::new();
+        for time in times.iter() {
+            let path = time.format(&self.path_template).to_string();
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+
+        let mut grid: Option<decode::Grid> = None;
+        let mut values: Vec<Vec<Option<f32>>> = Vec::new();
+        let mut elevs: Option<Vec<Option<f32>>> = None;
+        let mut not_found = None;
+        let mut any_found = false;
+        for path in paths {
+            let bytes = match fs::read(&path) {
+                Ok(bytes) => bytes,
+                // a missing file just means missing data, unless all of them are missing
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    not_found = Some(e);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            any_found = true;
+
+            for field in
+                decode::decode(&bytes).map_err(|e| data_switch::Error::Other(Box::new(e)))?
+            {
+                let is_orography = self
+                    .orography
+                    .as_ref()
+                    .is_some_and(|orography| orography.matches(&field));
+                let time_index = time_indices
+                    .get(&field.valid_time)
+                    .filter(|_| field_spec.matches(&field));
+                if !is_orography && time_index.is_none() {
+                    continue;
+                }
+
+                match &grid {
+                    None => {
+                        values = vec![vec![None; times.len()]; field.values.len()];
+                        grid = Some(field.grid.clone());
+                    }
+                    Some(grid) if *grid != field.grid => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("fields in {} are on different grids", path),
+                        )
+                        .into())
+                    }
+                    Some(_) => (),
+                }
+
+                if is_orography && elevs.is_none() {
+                    elevs = Some(field.values.clone());
+                }
+                if let Some(time_index) = time_index {
+                    for (series, value) in values.iter_mut().zip(field.values) {
+                        series[*time_index] = value;
+                    }
+                }
+            }
+        }
+        if let (false, Some(e)) = (any_found, not_found) {
+            return Err(e.into());
+        }
+
+        let (lats, lons) = grid.map(|grid| grid.latlons()).unwrap_or_default();
+        let elevs = match elevs {
+            Some(elevs) => elevs.into_iter().map(|elev| elev.unwrap_or(0.)).collect(),
+            None => vec![0.; lats.len()],
+        };
+
+        // (min_lat, max_lat, min_lon, max_lon)
+        let bounds = polygon.map(|polygon| {
+            polygon.iter().fold(
+                (f32::MAX, f32::MIN, f32::MAX, f32::MIN),
+                |(min_lat, max_lat, min_lon, max_lon), point| {
+                    (
+                        min_lat.min(point.lat - POLYGON_MARGIN_DEGREES),
+                        max_lat.max(point.lat + POLYGON_MARGIN_DEGREES),
+                        min_lon.min(point.lon - POLYGON_MARGIN_DEGREES),
+                        max_lon.max(point.lon + POLYGON_MARGIN_DEGREES),
+                    )
+                },
+            )
+        });
+
+        let mut cache_lats = Vec::new();
+        let mut cache_lons = Vec::new();
+        let mut cache_elevs = Vec::new();
+        let mut data = Vec::new();
+        for (((lat, lon), elev), series) in lats.into_iter().zip(lons).zip(elevs).zip(values) {
+            if bounds.is_some_and(|(min_lat, max_lat, min_lon, max_lon)| {
+                lat < min_lat || lat > max_lat || lon < min_lon || lon > max_lon
+            }) {
+                continue;
+            }
+            cache_lats.push(lat);
+            cache_lons.push(lon);
+            cache_elevs.push(elev);
+            data.push((format!("({},{})", lat, lon), series));
+        }
+
+        Ok(DataCache::new(
+            cache_lats,
+            cache_lons,
+            cache_elevs,
+            Timestamp(start_time.timestamp()),
+            self.period,
+            num_leading_points,
+            num_trailing_points,
+            data,
+        ))
+    }
+}
+
+#[async_trait]
+impl DataConnector for Grib {
+    async fn fetch_data(
+        &self,
+        space_spec: &SpaceSpec,
+        time_spec: &TimeSpec,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+        extra_spec: Option<&str>,
+    ) -> Result<DataCache, data_switch::Error> {
+        if time_spec.time_resolution != self.period {
+            return Err(data_switch::Error::UnimplementedSeries(format!(
+                "this source only has data at a resolution of {}",
+                self.period.format_to_iso8601()
+            )));
+        }
+
+        let field_spec: FieldSpec = extra_spec
+            .ok_or_else(|| "a field must be specified".to_string())
+            .and_then(str::parse)
+            .map_err(|e| data_switch::Error::InvalidExtraSpec {
+                data_source: "grib",
+                extra_spec: extra_spec.map(String::from),
+                source: e.into(),
+            })?;
+
+        // we don't know where a single series is, so all gridpoints are returned
+        let polygon = match space_spec {
+            SpaceSpec::Polygon(polygon) => Some(polygon.clone()),
+            SpaceSpec::One(_) | SpaceSpec::Multiple(_) | SpaceSpec::All => None,
+        };
+
+        let connector = self.clone();
+        let time_spec = TimeSpec::new(
+            time_spec.timerange.start,
+            time_spec.timerange.end,
+            time_spec.time_resolution,
+        );
+        tokio::task::spawn_blocking(move || {
+            connector.read(
+                &field_spec,
+                polygon.as_ref(),
+                &time_spec,
+                num_leading_points,
+                num_trailing_points,
+            )
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use decode::tests::encode_latlon_message;
+    use rove::data_switch::GeoPoint;
+
+    #[test]
+    fn test_field_spec() {
+        assert_eq!(
+            "0.0.0:103:2".parse::<FieldSpec>().unwrap(),
+            FieldSpec {
+                discipline: 0,
+                category: 0,
+                number: 0,
+                surface: Some((103, 2.))
+            }
+        );
+        assert_eq!("0.3.5".parse::<FieldSpec>().unwrap().surface, None);
+        assert!("0.0".parse::<FieldSpec>().is_err());
+        assert!("0.0.0:103".parse::<FieldSpec>().is_err());
+    }
+
+    #[test]
+    fn test_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut bytes = Vec::new();
+        // temperature at 2m at +1h and +2h, and 10m at +1h, which shouldn't be picked up
+        bytes.extend(encode_latlon_message(
+            0,
+            0,
+            (103, 2),
+            1,
+            [Some(1), Some(2), Some(3), Some(4)],
+        ));
+        bytes.extend(encode_latlon_message(
+            0,
+            0,
+            (103, 2),
+            2,
+            [Some(5), None, Some(7), Some(8)],
+        ));
+        bytes.extend(encode_latlon_message(0, 0, (103, 10), 1, [Some(9); 4]));
+        // orography
+        bytes.extend(encode_latlon_message(
+            3,
+            5,
+            (1, 0),
+            0,
+            [Some(100), Some(200), Some(0), Some(50)],
+        ));
+        fs::write(dir.path().join("meps_19700101.grib2"), bytes).unwrap();
+
+        let connector = Grib::new(
+            dir.path().join("meps_%Y%m%d.grib2").to_str().unwrap(),
+            RelativeDuration::hours(1),
+        )
+        .with_orography("0.3.5:1:0")
+        .unwrap();
+        let fetch = |space_spec| {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(connector.fetch_data(
+                    &space_spec,
+                    &TimeSpec::new(Timestamp(7200), Timestamp(7200), RelativeDuration::hours(1)),
+                    1,
+                    0,
+                    Some("0.0.0:103:2"),
+                ))
+                .unwrap()
+        };
+
+        let cache = fetch(SpaceSpec::All);
+        assert_eq!(cache.start_time, Timestamp(3600));
+        assert_eq!(cache.rtree.lats, vec![61., 61., 60., 60.]);
+        assert_eq!(cache.rtree.elevs, vec![10., 20., 0., 5.]);
+        assert_eq!(
+            cache.data[0],
+            ("(61,10)".to_string(), vec![Some(0.1), Some(0.5)])
+        );
+        assert_eq!(cache.data[1].1, vec![Some(0.2), None]);
+
+        let cache = fetch(SpaceSpec::Polygon(vec![
+            GeoPoint { lat: 59., lon: 9. },
+            GeoPoint { lat: 60.2, lon: 9. },
+            GeoPoint {
+                lat: 60.2,
+                lon: 10.2,
+            },
+        ]));
+        assert_eq!(cache.rtree.lats, vec![60.]);
+        assert_eq!(cache.rtree.lons, vec![10.]);
+    }
+}
@@ -0,0 +1,365 @@
+use crate::series::{self, Observation};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rove::{
+    data_switch,
+    data_switch::{DataCache, DataConnector, SpaceSpec, TimeSpec},
+};
+use serde_json::Value;
+use std::time::Duration;
+use thiserror::Error;
+
+mod path;
+
+use path::JsonPath;
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("invalid JSONPath `{0}`: {1}")]
+    InvalidPath(String, &'static str),
+    #[error("unknown placeholder `{{{0}}}` in URL template")]
+    UnknownPlaceholder(String),
+    #[error("fetching data failed")]
+    Request(#[from] reqwest::Error),
+    #[error("record {index} has no valid `{field}`")]
+    MissingField { index: usize, field: &'static str },
+}
+
+/// JSONPath expressions locating observations in a response
+///
+/// `records` selects the list of observations from the response, e.g.
+/// `$.data[*]`. The other paths are evaluated once per record, relative to
+/// the record if they start with `@`, or to the whole response if they start
+/// with `$`, which is useful for metadata given once per response. Only
+/// child (`.key`, `['key']`), index (`[0]`) and wildcard (`.*`, `[*]`)
+/// selectors are supported, and where a path matches more than one value the
+/// first is used.
+///
+/// Times can be RFC 3339 strings or unix timestamps, and numbers can also be
+/// given as strings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPaths {
+    pub records: String,
+    /// Series identifier, series are identified by their position as
+    /// "(lat,lon)" if this is None
+    pub id: Option<String>,
+    pub time: String,
+    pub value: String,
+    pub lat: String,
+    pub lon: String,
+    pub elev: String,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledPaths {
+    records: JsonPath,
+    id: Option<JsonPath>,
+    time: JsonPath,
+    value: JsonPath,
+    lat: JsonPath,
+    lon: JsonPath,
+    elev: JsonPath,
+}
+
+const PLACEHOLDERS: [&str; 4] = ["station_id", "element", "start", "end"];
+
+/// Connector for REST APIs returning observations as JSON, configured with a
+/// URL template and JSONPath expressions instead of code
+///
+/// The URL template can use the placeholders `{station_id}`, `{element}`,
+/// `{start}` and `{end}`, which are percent-encoded before being substituted
+/// in. `{element}` is the extra_spec of the request, and `{start}` and
+/// `{end}` are RFC 3339 timestamps including leading and trailing points. If
+/// the template uses `{station_id}`, only single series can be fetched.
+#[derive(Debug, Clone)]
+pub struct HttpJson {
+    client: reqwest::Client,
+    url_template: String,
+    paths: CompiledPaths,
+}
+
+fn number(value: Option<&Value>) -> Option<f32> {
+    match value? {
+        Value::Number(number) => number.as_f64().map(|number| number as f32),
+        Value::String(string) => string.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn time(value: Option<&Value>) -> Option<DateTime<Utc>> {
+    match value? {
+        Value::Number(number) => Utc.timestamp_opt(number.as_i64()?, 0).single(),
+        Value::String(string) => DateTime::parse_from_rfc3339(string)
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+        _ => None,
+    }
+}
+
+impl HttpJson {
+    /// Construct a connector with a default client, with the same timeouts as
+    /// [`Frost::new`](crate::Frost::new)
+    ///
+    /// # Errors
+    ///
+    /// If a path or the URL template is invalid, or the client could not be
+    /// initialised
+    pub fn new(url_template: impl Into<String>, paths: &JsonPaths) -> Result<Self, Error> {
+        Self::with_client(
+            reqwest::Client::builder()
+                .connect_timeout(Duration::from_secs(10))
+                .timeout(Duration::from_secs(60))
+                .build()?,
+            url_template,
+            paths,
+        )
+    }
+
+    /// Construct a connector that uses `client` for its requests
+    ///
+    /// # Errors
+    ///
+    /// If a path or the URL template is invalid
+    pub fn with_client(
+        client: reqwest::Client,
+        url_template: impl Into<String>,
+        paths: &JsonPaths,
+    ) -> Result<Self, Error> {
+        let url_template = url_template.into();
+        for placeholder in url_template
+            .split('{')
+            .skip(1)
+            .filter_map(|rest| rest.split_once('}'))
+            .map(|(name, _)| name)
+        {
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(Error::UnknownPlaceholder(placeholder.to_string()));
+            }
+        }
+
+        Ok(Self {
+            client,
+            url_template,
+            paths: CompiledPaths {
+                records: paths.records.parse()?,
+                id: paths.id.as_deref().map(str::parse).transpose()?,
+                time: paths.time.parse()?,
+                value: paths.value.parse()?,
+                lat: paths.lat.parse()?,
+                lon: paths.lon.parse()?,
+                elev: paths.elev.parse()?,
+            },
+        })
+    }
+
+    fn url(
+        &self,
+        station_id: Option<&str>,
+        element: Option<&str>,
+        (start_time, end_time): (DateTime<Utc>, DateTime<Utc>),
+    ) -> String {
+        let encode = |value: &str| utf8_percent_encode(value, NON_ALPHANUMERIC).to_string();
+        self.url_template
+            .replace("{station_id}", &encode(station_id.unwrap_or_default()))
+            .replace("{element}", &encode(element.unwrap_or_default()))
+            .replace(
+                "{start}",
+                &encode(&start_time.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            )
+            .replace(
+                "{end}",
+                &encode(&end_time.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            )
+    }
+
+    fn extract(&self, response: &Value) -> Result<Vec<Observation>, Error> {
+        let paths = &self.paths;
+        let mut observations = Vec::new();
+
+        for (index, record) in paths
+            .records
+            .select(response, response)
+            .into_iter()
+            .enumerate()
+        {
+            let first = |path: &JsonPath| path.select(response, record).into_iter().next();
+            let required = |path: &JsonPath, field| {
+                number(first(path)).ok_or(Error::MissingField { index, field })
+            };
+
+            // records without a time can't be placed in a series, so we skip them
+            let Some(time) = time(first(&paths.time)) else {
+                continue;
+            };
+            let lat = required(&paths.lat, "lat")?;
+            let lon = required(&paths.lon, "lon")?;
+            let id = match &paths.id {
+                Some(path) => match first(path) {
+                    Some(Value::String(id)) => id.clone(),
+                    Some(id @ Value::Number(_)) => id.to_string(),
+                    _ => return Err(Error::MissingField { index, field: "id" }),
+                },
+                None => format!("({},{})", lat, lon),
+            };
+
+            observations.push(Observation {
+                id,
+                lat,
+                lon,
+                elev: required(&paths.elev, "elev")?,
+                time,
+                value: number(first(&paths.value)),
+                metadata: Default::default(),
+            });
+        }
+
+        Ok(observations)
+    }
+}
+
+#[async_trait]
+impl DataConnector for HttpJson {
+    async fn fetch_data(
+        &self,
+        space_spec: &SpaceSpec,
+        time_spec: &TimeSpec,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+        extra_spec: Option<&str>,
+    ) -> Result<DataCache, data_switch::Error> {
+        let station_id = match space_spec {
+            SpaceSpec::One(station_id) => Some(station_id.as_str()),
+            SpaceSpec::All if !self.url_template.contains("{station_id}") => None,
+            SpaceSpec::All | SpaceSpec::Multiple(_) => {
+                return Err(data_switch::Error::UnimplementedSpatial(
+                    "this source can only be queried one series at a time".to_string(),
+                ))
+            }
+            SpaceSpec::Polygon(_) => {
+                return Err(data_switch::Error::UnimplementedSpatial(
+                    "this connector cannot filter by a polygon".to_string(),
+                ))
+            }
+        };
+
+        let window = series::fetch_window(time_spec, num_leading_points, num_trailing_points);
+        let response: Value = self
+            .client
+            .get(self.url(station_id, extra_spec, window))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| data_switch::Error::Other(Box::new(Error::Request(e))))?
+            .json()
+            .await
+            .map_err(|e| data_switch::Error::Other(Box::new(Error::Request(e))))?;
+
+        let observations = self
+            .extract(&response)
+            .map_err(|e| data_switch::Error::Other(Box::new(e)))?;
+
+        Ok(series::build_cache(
+            observations,
+            window,
+            time_spec.time_resolution,
+            num_leading_points,
+            num_trailing_points,
+        ))
+    }
+
+    fn is_retryable(&self, error: &data_switch::Error) -> bool {
+        match error {
+            data_switch::Error::Other(source) => match source.downcast_ref::<Error>() {
+                Some(Error::Request(e)) => {
+                    e.is_timeout()
+                        || e.is_connect()
+                        || e.status().is_some_and(|status| status.is_server_error())
+                }
+                _ => false,
+            },
+            data_switch::Error::Io(_) | data_switch::Error::Timeout(..) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const URL_TEMPLATE: &str =
+        "https://obs.example/v1/{station_id}?element={element}&from={start}&to={end}";
+
+    fn paths(id: Option<&str>) -> JsonPaths {
+        JsonPaths {
+            records: "$.observations[*]".to_string(),
+            id: id.map(String::from),
+            time: "@.time".to_string(),
+            value: "@['value']".to_string(),
+            lat: "$.station.location[0]".to_string(),
+            lon: "$.station.location[1]".to_string(),
+            elev: "$.station.elevation".to_string(),
+        }
+    }
+
+    fn connector(id: Option<&str>) -> HttpJson {
+        HttpJson::with_client(reqwest::Client::new(), URL_TEMPLATE, &paths(id)).unwrap()
+    }
+
+    #[test]
+    fn test_url() {
+        assert_eq!(
+            connector(None).url(
+                Some("18700"),
+                Some("air temperature"),
+                (
+                    Utc.timestamp_opt(0, 0).unwrap(),
+                    Utc.timestamp_opt(3600, 0).unwrap()
+                )
+            ),
+            "https://obs.example/v1/18700?element=air%20temperature&from=1970%2D01%2D01T00%3A00%3A00Z&to=1970%2D01%2D01T01%3A00%3A00Z"
+        );
+
+        assert!(matches!(
+            HttpJson::with_client(
+                reqwest::Client::new(),
+                "https://obs.example/{station}",
+                &paths(None)
+            ),
+            Err(Error::UnknownPlaceholder(name)) if name == "station"
+        ));
+    }
+
+    #[test]
+    fn test_extract() {
+        let response = json!({
+            "station": {"id": 18700, "location": [59.94, "10.72"], "elevation": 94},
+            "observations": [
+                {"time": "1970-01-01T00:00:00Z", "value": 1.5},
+                {"time": 3600, "value": null},
+                {"value": 3.0},
+            ]
+        });
+
+        let observations = connector(Some("$.station.id")).extract(&response).unwrap();
+
+        assert_eq!(observations.len(), 2);
+        assert_eq!(observations[0].id, "18700");
+        assert_eq!(observations[0].lon, 10.72);
+        assert_eq!(observations[0].elev, 94.);
+        assert_eq!(observations[0].value, Some(1.5));
+        assert_eq!(observations[1].time, Utc.timestamp_opt(3600, 0).unwrap());
+        assert_eq!(observations[1].value, None);
+
+        assert!(matches!(
+            connector(Some("@.id")).extract(&response),
+            Err(Error::MissingField {
+                index: 0,
+                field: "id"
+            })
+        ));
+    }
+}
@@ -0,0 +1,144 @@
+//! The subset of JSONPath needed to pick observations out of API responses
+
+use super::Error;
+use serde_json::Value;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct JsonPath {
+    // whether the path starts at the current record (`@`) rather than the root (`$`)
+    relative: bool,
+    selectors: Vec<Selector>,
+}
+
+impl FromStr for JsonPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| Error::InvalidPath(s.to_string(), reason);
+
+        let (relative, mut rest) = match s.as_bytes().first() {
+            Some(b'$') => (false, &s[1..]),
+            Some(b'@') => (true, &s[1..]),
+            _ => return Err(invalid("must start with `$` or `@`")),
+        };
+
+        let mut selectors = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after_dot) = rest.strip_prefix('.') {
+                let end = after_dot.find(['.', '[']).unwrap_or(after_dot.len());
+                let key = &after_dot[..end];
+                selectors.push(match key {
+                    "" => return Err(invalid("empty key")),
+                    "*" => Selector::Wildcard,
+                    _ => Selector::Key(key.to_string()),
+                });
+                rest = &after_dot[end..];
+            } else if let Some(after_bracket) = rest.strip_prefix('[') {
+                let end = after_bracket
+                    .find(']')
+                    .ok_or_else(|| invalid("unterminated `[`"))?;
+                let inner = after_bracket[..end].trim();
+                selectors.push(if inner == "*" {
+                    Selector::Wildcard
+                } else if let Some(key) = inner
+                    .strip_prefix('\'')
+                    .and_then(|inner| inner.strip_suffix('\''))
+                    .or_else(|| {
+                        inner
+                            .strip_prefix('"')
+                            .and_then(|inner| inner.strip_suffix('"'))
+                    })
+                {
+                    Selector::Key(key.to_string())
+                } else {
+                    Selector::Index(
+                        inner
+                            .parse()
+                            .map_err(|_| invalid("expected an index, a quoted key or `*`"))?,
+                    )
+                });
+                rest = &after_bracket[end + 1..];
+            } else {
+                return Err(invalid("expected `.` or `[`"));
+            }
+        }
+
+        Ok(JsonPath {
+            relative,
+            selectors,
+        })
+    }
+}
+
+impl JsonPath {
+    /// All values the path matches, starting from `record` if the path is
+    /// relative, or `root` otherwise
+    pub fn select<'a>(&self, root: &'a Value, record: &'a Value) -> Vec<&'a Value> {
+        let mut matches = vec![if self.relative { record } else { root }];
+
+        for selector in self.selectors.iter() {
+            matches = matches
+                .into_iter()
+                .flat_map(|value| -> Vec<&Value> {
+                    match (selector, value) {
+                        (Selector::Key(key), Value::Object(object)) => {
+                            object.get(key).into_iter().collect()
+                        }
+                        (Selector::Index(index), Value::Array(array)) => {
+                            array.get(*index).into_iter().collect()
+                        }
+                        (Selector::Wildcard, Value::Array(array)) => array.iter().collect(),
+                        (Selector::Wildcard, Value::Object(object)) => object.values().collect(),
+                        _ => Vec::new(),
+                    }
+                })
+                .collect();
+        }
+
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_select() {
+        let root = json!({
+            "data": [
+                {"obs": {"value": 1}, "name": "a"},
+                {"obs": {"value": 2}, "name": "b"},
+            ],
+            "meta.data": {"x": true}
+        });
+        let select = |path: &str| {
+            path.parse::<JsonPath>()
+                .unwrap()
+                .select(&root, &root["data"][1])
+                .into_iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(select("$.data[*].obs.value"), vec![json!(1), json!(2)]);
+        assert_eq!(select("$.data[0]['name']"), vec![json!("a")]);
+        assert_eq!(select("$[\"meta.data\"].*"), vec![json!(true)]);
+        assert_eq!(select("@.obs.value"), vec![json!(2)]);
+        assert_eq!(select("@"), vec![root["data"][1].clone()]);
+        assert_eq!(select("$.data[5].name"), Vec::<Value>::new());
+
+        for invalid in ["data", "$.", "$[0", "$[x]", "$x"] {
+            assert!(invalid.parse::<JsonPath>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -1,5 +1,18 @@
+mod delimited_text;
+mod drop_directory;
 mod frost;
+mod grib;
+mod http_json;
 mod lustre_netatmo;
+mod series;
+mod sql;
 
-pub use frost::Frost;
+pub use delimited_text::{ColumnMapping, DelimitedText, Filter, MaxFilter};
+pub use drop_directory::DropDirectory;
+pub use frost::{Frost, FrostAuth, FrostConfig, RateLimit};
+pub use grib::Grib;
+pub use http_json::{HttpJson, JsonPaths};
 pub use lustre_netatmo::LustreNetatmo;
+#[cfg(feature = "postgres")]
+pub use sql::{Postgres, PostgresConfig};
+pub use sql::{QueryTemplates, Sql, SqlExecutor, SqlParam, SqlRow};
@@ -1,76 +1,90 @@
+use crate::delimited_text::{ColumnMapping, DelimitedText};
 use async_trait::async_trait;
 use chrono::prelude::*;
 use chronoutil::RelativeDuration;
 use rove::{
     data_switch,
-    data_switch::{DataCache, DataConnector, SpaceSpec, TimeSpec, Timestamp},
+    data_switch::{DataCache, DataConnector, SpaceSpec, TimeSpec},
 };
-use serde::Deserialize;
-use std::{fs::File, io};
+use std::{collections::HashMap, io};
 
-#[derive(Debug)]
-pub struct LustreNetatmo;
-
-#[derive(Debug, Deserialize)]
-struct Record {
-    lat: f32,
-    lon: f32,
-    elev: f32,
-    value: f32,
-    // Provider ID
-    // 1=WMO stations, 2=MET Non-WMO stations, 3=Netatmo, 4=Foreign WMO, 5=SVV, 6=Bergensværet, 7=FMI, 8=Luftambulansen, 9=Holfuy, 100=Radar precipitation
-    prid: u32,
-    // QC flag
-    // 0 = OK, >=l = fail
-    dqc: u32,
+/// Connector to the hourly observation files on lustre, that include Netatmo
+/// and other third party stations
+///
+/// By default only Netatmo air temperature records that passed QC are served,
+/// see [`new`](LustreNetatmo::new) to serve other providers, and
+/// [`with_parameter`](LustreNetatmo::with_parameter) to serve other
+/// parameters. The parameter to fetch is selected by passing its name as
+/// `extra_spec`, with no `extra_spec` selecting air temperature.
+#[derive(Debug, Clone)]
+pub struct LustreNetatmo {
+    providers: Vec<String>,
+    max_dqc: u32,
+    // keyed by parameter name
+    parameters: HashMap<String, DelimitedText>,
 }
 
-fn read_netatmo(timestamp: Timestamp) -> Result<DataCache, data_switch::Error> {
-    // timestamp should be validated before it gets here, so it should be safe to unwrap
-    let time = Utc.timestamp_opt(timestamp.0, 0).unwrap();
-    // TODO: time resolution might change in the future
-    let period = RelativeDuration::hours(1);
+// The files have columns lat;lon;elev;value;prid;dqc, where
+//
+// prid is the provider ID
+// 1=WMO stations, 2=MET Non-WMO stations, 3=Netatmo, 4=Foreign WMO, 5=SVV, 6=Bergensværet, 7=FMI, 8=Luftambulansen, 9=Holfuy, 100=Radar precipitation
+//
+// and dqc is the QC flag
+// 0 = OK, >=l = fail
+impl LustreNetatmo {
+    /// Provider id of Netatmo stations in the files
+    pub const NETATMO: i32 = 3;
+
+    /// Parameter served when no `extra_spec` is given
+    pub const DEFAULT_PARAMETER: &'static str = "air_temperature";
 
-    if time.minute() != 0 || time.second() != 0 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "timestamps for fetching netatmo data must be on the hour",
+    /// Serve records from the providers in `providers`, with a dqc flag no
+    /// greater than `max_dqc`
+    ///
+    /// Each series reports its provider, so pipeline steps can still be
+    /// restricted to some of them.
+    pub fn new(providers: &[i32], max_dqc: u32) -> Self {
+        Self {
+            providers: providers.iter().map(i32::to_string).collect(),
+            max_dqc,
+            parameters: HashMap::new(),
+        }
+        .with_parameter(
+            Self::DEFAULT_PARAMETER,
+            "/lustre/storeB/immutable/archive/projects/metproduction/yr_short/%Y/%m/%d/obs_ta_%Y%m%dT%HZ.txt",
         )
-        .into());
     }
 
-    let path = format!("{}", time.format("/lustre/storeB/immutable/archive/projects/metproduction/yr_short/%Y/%m/%d/obs_ta_%Y%m%dT%HZ.txt"));
-
-    let file = File::open(path)?;
-
-    // TODO: probably some optimisation potential here?
-    let mut lats = Vec::new();
-    let mut lons = Vec::new();
-    let mut elevs = Vec::new();
-    let mut values = Vec::new();
+    /// Serve `parameter` from the family of files at `path_template`, a
+    /// strftime pattern rendered for each hour, replacing any path template
+    /// already set for it
+    pub fn with_parameter(mut self, parameter: impl Into<String>, path_template: &str) -> Self {
+        // TODO: time resolution might change in the future
+        let files = DelimitedText::new(path_template, RelativeDuration::hours(1))
+            .with_delimiter(b';')
+            .with_columns(ColumnMapping {
+                provider: Some("prid".to_string()),
+                ..Default::default()
+            })
+            .with_filter(
+                "prid",
+                &self
+                    .providers
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>(),
+            )
+            .with_max("dqc", self.max_dqc as f32);
 
-    let mut rdr = csv::ReaderBuilder::new().delimiter(b';').from_reader(file);
-    for result in rdr.deserialize() {
-        let record: Record = result.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-
-        // TODO: should we allow more prids?
-        // prid 3 represents netatmo data, but if we use this as a backing set
-        // I wonder if there's any harm in adding others
-        if record.prid == 3 && record.dqc == 0 {
-            lats.push(record.lat);
-            lons.push(record.lon);
-            elevs.push(record.elev);
-            values.push((
-                // would be nice if we could come up with better identifiers for this
-                format!("({},{})", record.lat, record.lon),
-                vec![Some(record.value)],
-            ));
-        }
+        self.parameters.insert(parameter.into(), files);
+        self
     }
+}
 
-    Ok(DataCache::new(
-        lats, lons, elevs, timestamp, period, 0, 0, values,
-    ))
+impl Default for LustreNetatmo {
+    fn default() -> Self {
+        Self::new(&[Self::NETATMO], 0)
+    }
 }
 
 #[async_trait]
@@ -81,31 +95,84 @@ impl DataConnector for LustreNetatmo {
         time_spec: &TimeSpec,
         num_leading_points: u8,
         num_trailing_points: u8,
-        _extra_spec: Option<&str>,
+        extra_spec: Option<&str>,
     ) -> Result<DataCache, data_switch::Error> {
-        if num_leading_points != 0
-            || num_trailing_points != 0
-            || time_spec.timerange.start != time_spec.timerange.end
-        {
-            return Err(data_switch::Error::UnimplementedSeries(
-                "netatmo files are only in timeslice format".to_string(),
-            ));
+        // timestamp should be validated before it gets here, so it should be safe to unwrap
+        let time = Utc.timestamp_opt(time_spec.timerange.start.0, 0).unwrap();
+        if time.minute() != 0 || time.second() != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "timestamps for fetching netatmo data must be on the hour",
+            )
+            .into());
         }
 
-        match space_spec {
-            SpaceSpec::All => {
-                let start_time = time_spec.timerange.start;
-                tokio::task::spawn_blocking(move || read_netatmo(start_time)).await?
-            }
-            SpaceSpec::One(_) => Err(data_switch::Error::UnimplementedSeries(
-                "netatmo files are only in timeslice format".to_string(),
-            )),
-            // TODO: should we implement this?
-            SpaceSpec::Polygon(_) => Err(data_switch::Error::UnimplementedSpatial(
-                "this connector cannot filter netatmo files by a polygon".to_string(),
-            )),
-        }
+        let files = self
+            .parameters
+            .get(extra_spec.unwrap_or(Self::DEFAULT_PARAMETER))
+            .ok_or_else(|| data_switch::Error::InvalidExtraSpec {
+                data_source: "lustre_netatmo",
+                extra_spec: extra_spec.map(String::from),
+                source: format!(
+                    "unknown parameter, expected one of: {}",
+                    self.parameters
+                        .keys()
+                        .map(String::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+                .into(),
+            })?;
+
+        files
+            .fetch_data(
+                space_spec,
+                time_spec,
+                num_leading_points,
+                num_trailing_points,
+                None,
+            )
+            .await
     }
 }
 
-// TODO: add unit test?
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rove::data_switch::Timestamp;
+    use std::fs;
+
+    #[test]
+    fn test_parameters() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("obs_rh_00.txt"),
+            "lat;lon;elev;value;prid;dqc\n60;10;100;85;3;0\n61;11;200;90;5;0\n",
+        )
+        .unwrap();
+        let connector = LustreNetatmo::default().with_parameter(
+            "relative_humidity",
+            dir.path().join("obs_rh_%H.txt").to_str().unwrap(),
+        );
+
+        let fetch = |extra_spec| {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(connector.fetch_data(
+                    &SpaceSpec::All,
+                    &TimeSpec::new(Timestamp(0), Timestamp(0), RelativeDuration::hours(1)),
+                    0,
+                    0,
+                    extra_spec,
+                ))
+        };
+
+        let cache = fetch(Some("relative_humidity")).unwrap();
+        assert_eq!(cache.data, vec![("(60,10)".to_string(), vec![Some(85.)])]);
+
+        assert!(matches!(
+            fetch(Some("air_pressure")),
+            Err(data_switch::Error::InvalidExtraSpec { .. })
+        ));
+    }
+}
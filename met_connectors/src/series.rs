@@ -0,0 +1,95 @@
+//! Assembly of DataCaches from sources that return loose observations rather
+//! than series
+
+use chrono::prelude::*;
+use chronoutil::RelativeDuration;
+use rove::data_switch::{DataCache, StationMetadata, TimeSpec, Timestamp};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Observation {
+    pub id: String,
+    pub lat: f32,
+    pub lon: f32,
+    pub elev: f32,
+    pub time: DateTime<Utc>,
+    pub value: Option<f32>,
+    pub metadata: StationMetadata,
+}
+
+/// The timerange of a request, widened to include its leading and trailing
+/// points
+pub(crate) fn fetch_window(
+    time_spec: &TimeSpec,
+    num_leading_points: u8,
+    num_trailing_points: u8,
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    let period = time_spec.time_resolution;
+    // timestamps should be validated before they get here, so it should be safe to unwrap
+    (
+        Utc.timestamp_opt(time_spec.timerange.start.0, 0).unwrap()
+            - period * i32::from(num_leading_points),
+        Utc.timestamp_opt(time_spec.timerange.end.0, 0).unwrap()
+            + period * i32::from(num_trailing_points),
+    )
+}
+
+/// Group observations into series by id, placing each value at its
+/// timestamp
+///
+/// Series are ordered by their first observation. Observations outside
+/// `start_time..=end_time`, or not on `period`, are ignored. Each series'
+/// station metadata is taken from its first observation.
+pub(crate) fn build_cache(
+    observations: impl IntoIterator<Item = Observation>,
+    (start_time, end_time): (DateTime<Utc>, DateTime<Utc>),
+    period: RelativeDuration,
+    num_leading_points: u8,
+    num_trailing_points: u8,
+) -> DataCache {
+    let mut time_indices = HashMap::new();
+    let mut time = start_time;
+    while time <= end_time {
+        time_indices.insert(time.timestamp(), time_indices.len());
+        time = time + period;
+    }
+
+    let mut lats = Vec::new();
+    let mut lons = Vec::new();
+    let mut elevs = Vec::new();
+    let mut metadata = Vec::new();
+    let mut data: Vec<(String, Vec<Option<f32>>)> = Vec::new();
+    let mut series_indices: HashMap<String, usize> = HashMap::new();
+    for observation in observations {
+        let Some(time_index) = time_indices.get(&observation.time.timestamp()) else {
+            continue;
+        };
+        let series_index = *series_indices
+            .entry(observation.id.clone())
+            .or_insert_with(|| {
+                lats.push(observation.lat);
+                lons.push(observation.lon);
+                elevs.push(observation.elev);
+                metadata.push(observation.metadata);
+                data.push((observation.id, vec![None; time_indices.len()]));
+                data.len() - 1
+            });
+        data[series_index].1[*time_index] = observation.value;
+    }
+
+    let cache = DataCache::new(
+        lats,
+        lons,
+        elevs,
+        Timestamp(start_time.timestamp()),
+        period,
+        num_leading_points,
+        num_trailing_points,
+        data,
+    );
+    if metadata.iter().any(|metadata| !metadata.is_empty()) {
+        cache.with_metadata(metadata)
+    } else {
+        cache
+    }
+}
@@ -0,0 +1,357 @@
+use crate::series::{self, Observation};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use rove::{
+    data_switch,
+    data_switch::{DataCache, DataConnector, SpaceSpec, TimeSpec},
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "postgres")]
+pub use postgres::{Postgres, PostgresConfig};
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("unknown placeholder `{{{0}}}` in query template")]
+    UnknownPlaceholder(String),
+    #[error("unterminated placeholder in query template")]
+    UnterminatedPlaceholder,
+    #[error("unmatched `}}` in query template, literal braces are written `{{{{` and `}}}}`")]
+    UnmatchedBrace,
+    #[error("invalid connection config: {0}")]
+    Connection(Box<dyn std::error::Error + Send + Sync>),
+    #[error("query failed: {0}")]
+    Query(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// A value bound to a placeholder in a query
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlParam {
+    Text(String),
+    Timestamp(DateTime<Utc>),
+}
+
+/// A row returned by a query
+///
+/// Query templates should select the columns `id, lat, lon, elev, time,
+/// value`, which executors map onto this
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlRow {
+    pub id: String,
+    pub lat: f32,
+    pub lon: f32,
+    pub elev: f32,
+    pub time: DateTime<Utc>,
+    pub value: Option<f32>,
+}
+
+/// Runs queries against a database
+///
+/// This is the only part of [`Sql`] that depends on the database, so
+/// supporting a new database means implementing this on top of its driver,
+/// which holds the connection string or pool.
+#[async_trait]
+pub trait SqlExecutor: std::fmt::Debug + Send + Sync {
+    /// Run `query`, with the placeholders `$1`, `$2`, ... bound to `params`
+    async fn query(
+        &self,
+        query: &str,
+        params: &[SqlParam],
+    ) -> Result<Vec<SqlRow>, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Query templates for an [`Sql`] connector
+///
+/// Templates can use the placeholders `{station_id}`, `{element}`, `{start}`
+/// and `{end}`, which are bound as query parameters rather than pasted into
+/// the query, so they are safe from injection. `{element}` is the extra_spec
+/// of the request, and `{start}` and `{end}` include leading and trailing
+/// points. Literal braces, e.g. in array literals, are written `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct QueryTemplates {
+    /// Query for a single series, used for [`SpaceSpec::One`]
+    pub series: String,
+    /// Query for all series, used for [`SpaceSpec::All`]
+    pub all: String,
+}
+
+const PLACEHOLDERS: [&str; 4] = ["station_id", "element", "start", "end"];
+
+/// A query template with its placeholders replaced by positional parameters
+#[derive(Debug, Clone, PartialEq)]
+struct CompiledQuery {
+    query: String,
+    // names of the placeholders bound to $1, $2, ...
+    params: Vec<&'static str>,
+}
+
+impl CompiledQuery {
+    fn compile(template: &str) -> Result<Self, Error> {
+        let mut query = String::with_capacity(template.len());
+        let mut params: Vec<&'static str> = Vec::new();
+
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            query.push_str(&rest[..start]);
+            let brace = &rest[start..start + 1];
+            if rest[start + 1..].starts_with(brace) {
+                query.push_str(brace);
+                rest = &rest[start + 2..];
+                continue;
+            }
+            if brace == "}" {
+                return Err(Error::UnmatchedBrace);
+            }
+
+            let len = rest[start..]
+                .find('}')
+                .ok_or(Error::UnterminatedPlaceholder)?;
+            let name = &rest[start + 1..start + len];
+            let name = PLACEHOLDERS
+                .into_iter()
+                .find(|placeholder| *placeholder == name)
+                .ok_or_else(|| Error::UnknownPlaceholder(name.to_string()))?;
+
+            // a placeholder used more than once is bound once
+            let position = match params.iter().position(|param| *param == name) {
+                Some(position) => position,
+                None => {
+                    params.push(name);
+                    params.len() - 1
+                }
+            };
+            query.push_str(&format!("${}", position + 1));
+            rest = &rest[start + len + 1..];
+        }
+        query.push_str(rest);
+
+        Ok(Self { query, params })
+    }
+}
+
+/// Connector for relational databases, configured with query templates
+/// instead of code
+///
+/// Series are assembled from the rows the queries return, placing each value
+/// at its timestamp. Rows at timestamps outside the requested timerange, or
+/// not on its time resolution, are ignored.
+#[derive(Debug)]
+pub struct Sql<E> {
+    executor: E,
+    series_query: CompiledQuery,
+    all_query: CompiledQuery,
+}
+
+impl<E: SqlExecutor> Sql<E> {
+    /// Create a connector that runs `templates` with `executor`
+    ///
+    /// # Errors
+    ///
+    /// If a template has an unknown or unterminated placeholder, or an
+    /// unescaped `}`
+    pub fn new(executor: E, templates: &QueryTemplates) -> Result<Self, Error> {
+        Ok(Self {
+            executor,
+            series_query: CompiledQuery::compile(&templates.series)?,
+            all_query: CompiledQuery::compile(&templates.all)?,
+        })
+    }
+}
+
+#[async_trait]
+impl<E: SqlExecutor> DataConnector for Sql<E> {
+    async fn fetch_data(
+        &self,
+        space_spec: &SpaceSpec,
+        time_spec: &TimeSpec,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+        extra_spec: Option<&str>,
+    ) -> Result<DataCache, data_switch::Error> {
+        let (query, station_id) = match space_spec {
+            SpaceSpec::One(station_id) => (&self.series_query, Some(station_id.as_str())),
+            SpaceSpec::All => (&self.all_query, None),
+            // TODO: could be supported by running the series query for each id
+            SpaceSpec::Multiple(_) => {
+                return Err(data_switch::Error::UnimplementedSpatial(
+                    "this connector can only fetch one series or all of them".to_string(),
+                ))
+            }
+            // TODO: could be supported with bounding box placeholders
+            SpaceSpec::Polygon(_) => {
+                return Err(data_switch::Error::UnimplementedSpatial(
+                    "this connector cannot filter by a polygon".to_string(),
+                ))
+            }
+        };
+
+        let window = series::fetch_window(time_spec, num_leading_points, num_trailing_points);
+
+        let params = query
+            .params
+            .iter()
+            .map(|name| match *name {
+                "station_id" => SqlParam::Text(station_id.unwrap_or_default().to_string()),
+                "element" => SqlParam::Text(extra_spec.unwrap_or_default().to_string()),
+                "start" => SqlParam::Timestamp(window.0),
+                "end" => SqlParam::Timestamp(window.1),
+                _ => unreachable!("placeholders are checked when compiling"),
+            })
+            .collect::<Vec<_>>();
+
+        let rows = self
+            .executor
+            .query(&query.query, &params)
+            .await
+            .map_err(|e| data_switch::Error::Other(Box::new(Error::Query(e))))?;
+
+        Ok(series::build_cache(
+            rows.into_iter().map(|row| Observation {
+                id: row.id,
+                lat: row.lat,
+                lon: row.lon,
+                elev: row.elev,
+                time: row.time,
+                value: row.value,
+                metadata: Default::default(),
+            }),
+            window,
+            time_spec.time_resolution,
+            num_leading_points,
+            num_trailing_points,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chronoutil::RelativeDuration;
+    use rove::data_switch::Timestamp;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct MockExecutor {
+        queries: Mutex<Vec<(String, Vec<SqlParam>)>>,
+        rows: Vec<SqlRow>,
+    }
+
+    #[async_trait]
+    impl SqlExecutor for MockExecutor {
+        async fn query(
+            &self,
+            query: &str,
+            params: &[SqlParam],
+        ) -> Result<Vec<SqlRow>, Box<dyn std::error::Error + Send + Sync>> {
+            self.queries
+                .lock()
+                .unwrap()
+                .push((query.to_string(), params.to_vec()));
+            Ok(self.rows.clone())
+        }
+    }
+
+    fn row(id: &str, hour: i64, value: Option<f32>) -> SqlRow {
+        SqlRow {
+            id: id.to_string(),
+            lat: 60.,
+            lon: 10.,
+            elev: 100.,
+            time: Utc.timestamp_opt(hour * 3600, 0).unwrap(),
+            value,
+        }
+    }
+
+    #[test]
+    fn test_compile() {
+        let compiled = CompiledQuery::compile(
+            "SELECT * FROM obs WHERE element = {element} AND time BETWEEN {start} AND {end} AND {start} < now()",
+        )
+        .unwrap();
+        assert_eq!(
+            compiled.query,
+            "SELECT * FROM obs WHERE element = $1 AND time BETWEEN $2 AND $3 AND $2 < now()"
+        );
+        assert_eq!(compiled.params, vec!["element", "start", "end"]);
+
+        assert!(matches!(
+            CompiledQuery::compile("SELECT {stationid}"),
+            Err(Error::UnknownPlaceholder(name)) if name == "stationid"
+        ));
+        assert!(matches!(
+            CompiledQuery::compile("SELECT {station_id"),
+            Err(Error::UnterminatedPlaceholder)
+        ));
+
+        // escaped braces are kept as literals
+        let compiled =
+            CompiledQuery::compile("SELECT '{{1,2}}'::int[], '}}{{' WHERE id = {station_id}")
+                .unwrap();
+        assert_eq!(compiled.query, "SELECT '{1,2}'::int[], '}{' WHERE id = $1");
+        assert_eq!(compiled.params, vec!["station_id"]);
+        assert!(matches!(
+            CompiledQuery::compile("SELECT '{1,2}'"),
+            Err(Error::UnknownPlaceholder(name)) if name == "1,2"
+        ));
+        assert!(matches!(
+            CompiledQuery::compile("SELECT '}'"),
+            Err(Error::UnmatchedBrace)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fetch() {
+        let executor = MockExecutor {
+            rows: vec![
+                row("18700", 1, Some(1.)),
+                row("18700", 3, Some(3.)),
+                row("18700", 2, None),
+                // outside the timerange
+                row("18700", 4, Some(4.)),
+            ],
+            ..Default::default()
+        };
+        let connector = Sql::new(
+            executor,
+            &QueryTemplates {
+                series: "SELECT id, lat, lon, elev, time, value FROM obs WHERE id = {station_id} AND element = {element} AND time BETWEEN {start} AND {end}".to_string(),
+                all: "SELECT id, lat, lon, elev, time, value FROM obs WHERE element = {element}".to_string(),
+            },
+        )
+        .unwrap();
+
+        let cache = connector
+            .fetch_data(
+                &SpaceSpec::One("18700".to_string()),
+                &TimeSpec::new(
+                    Timestamp(7200),
+                    Timestamp(10800),
+                    RelativeDuration::hours(1),
+                ),
+                1,
+                0,
+                Some("air_temperature"),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(cache.start_time, Timestamp(3600));
+        assert_eq!(
+            cache.data,
+            vec![("18700".to_string(), vec![Some(1.), None, Some(3.)])]
+        );
+        assert_eq!(
+            connector.executor.queries.lock().unwrap()[0].1,
+            vec![
+                SqlParam::Text("18700".to_string()),
+                SqlParam::Text("air_temperature".to_string()),
+                SqlParam::Timestamp(Utc.timestamp_opt(3600, 0).unwrap()),
+                SqlParam::Timestamp(Utc.timestamp_opt(10800, 0).unwrap()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use super::{Error, QueryTemplates, Sql, SqlExecutor, SqlParam, SqlRow};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
+use serde::Deserialize;
+use tokio_postgres::{types::ToSql, NoTls, Row};
+
+/// Configuration of an [`Sql`] connector for PostgreSQL, see [`Sql::postgres`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct PostgresConfig {
+    /// Either a libpq-style `key=value` string or a `postgresql://` URL
+    pub connection_string: String,
+    /// Most connections to keep open at once
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    pub templates: QueryTemplates,
+}
+
+fn default_max_connections() -> usize {
+    8
+}
+
+impl Sql<Postgres> {
+    /// Create a connector running `config`'s templates against a PostgreSQL
+    /// database
+    ///
+    /// # Errors
+    ///
+    /// If the connection string can't be parsed, or a template is invalid,
+    /// see [`Sql::new`]
+    pub fn postgres(config: &PostgresConfig) -> Result<Self, Error> {
+        let executor = Postgres::new(&config.connection_string, config.max_connections)
+            .map_err(Error::Connection)?;
+        Sql::new(executor, &config.templates)
+    }
+}
+
+/// [`SqlExecutor`] for PostgreSQL, over a pool of connections
+///
+/// Query templates run with this must select `id` as `text`, `lat`, `lon`,
+/// `elev` and `value` as `real`, and `time` as `timestamptz`, casting in the
+/// query where the schema's types differ, e.g. `SELECT station::text AS id`.
+/// `{start}` and `{end}` are bound as `timestamptz`.
+#[derive(Debug, Clone)]
+pub struct Postgres {
+    pool: Pool,
+}
+
+impl Postgres {
+    /// Create an executor connecting with `connection_string`, either a
+    /// libpq-style `key=value` string or a `postgresql://` URL, keeping at
+    /// most `max_connections` connections open at once
+    ///
+    /// Connections are opened when they are first needed, so this doesn't
+    /// fail if the database is down.
+    ///
+    /// # Errors
+    ///
+    /// If the connection string can't be parsed
+    pub fn new(
+        connection_string: &str,
+        max_connections: usize,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let manager = Manager::from_config(
+            connection_string.parse()?,
+            NoTls,
+            ManagerConfig {
+                recycling_method: RecyclingMethod::Fast,
+            },
+        );
+        Ok(Self {
+            pool: Pool::builder(manager).max_size(max_connections).build()?,
+        })
+    }
+}
+
+fn to_row(row: &Row) -> Result<SqlRow, tokio_postgres::Error> {
+    Ok(SqlRow {
+        id: row.try_get("id")?,
+        lat: row.try_get("lat")?,
+        lon: row.try_get("lon")?,
+        elev: row.try_get("elev")?,
+        time: row.try_get::<_, DateTime<Utc>>("time")?,
+        value: row.try_get("value")?,
+    })
+}
+
+#[async_trait]
+impl SqlExecutor for Postgres {
+    async fn query(
+        &self,
+        query: &str,
+        params: &[SqlParam],
+    ) -> Result<Vec<SqlRow>, Box<dyn std::error::Error + Send + Sync>> {
+        let params: Vec<&(dyn ToSql + Sync)> = params
+            .iter()
+            .map(|param| match param {
+                SqlParam::Text(text) => text as &(dyn ToSql + Sync),
+                SqlParam::Timestamp(timestamp) => timestamp as &(dyn ToSql + Sync),
+            })
+            .collect();
+
+        let client = self.pool.get().await?;
+        // prepared through the connection's cache, since the same few templates run every time
+        let statement = client.prepare_cached(query).await?;
+        let rows = client.query(&statement, &params).await?;
+
+        Ok(rows.iter().map(to_row).collect::<Result<_, _>>()?)
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll},
+    time::Instant,
+};
+#[cfg(feature = "tls")]
+use tonic::transport::server::TlsConnectInfo;
+use tonic::{
+    body::BoxBody,
+    codegen::{
+        http::{HeaderMap, Request, Response},
+        Body, Bytes,
+    },
+    transport::server::TcpConnectInfo,
+    Status,
+};
+use tower::{Layer, Service};
+
+/// Limits on how much each client can ask of the server, so one misbehaving client, like a
+/// runaway batch job, can't starve the others, see
+/// [`ServerConfig::client_limits`](crate::ServerConfig::client_limits)
+///
+/// Requests over the limits are rejected with `RESOURCE_EXHAUSTED`, rather than queued. Health
+/// checks are exempt.
+#[derive(Debug, Clone, Default)]
+pub struct ClientLimits {
+    /// Sustained number of requests per second each client may make, or no limit if None
+    pub requests_per_second: Option<f64>,
+    /// Number of requests a client may make at once after being idle, above
+    /// `requests_per_second`. Treated as 1 if lower
+    pub burst: u32,
+    /// Maximum number of requests each client may have in flight at once, or no limit if None.
+    /// Validation requests are in flight until their last results are sent
+    pub max_concurrent: Option<usize>,
+    /// Metadata header clients are identified by as well as their IP address, e.g.
+    /// `x-api-key`, to separate clients behind the same address, like jobs on a shared batch
+    /// host. Clients aren't authenticated, so a client can't escape the limits of its address
+    /// by varying the header, but only clients that cooperate are separated
+    pub key_header: Option<String>,
+}
+
+impl ClientLimits {
+    fn is_unlimited(&self) -> bool {
+        self.requests_per_second.is_none() && self.max_concurrent.is_none()
+    }
+}
+
+// clients that have been idle long enough to be back at their defaults are forgotten once
+// this many are tracked, and then again each time the number tracked doubles, so sweeping them
+// costs O(1) per request on average
+const MAX_IDLE_CLIENTS: usize = 1024;
+
+#[derive(Debug)]
+struct Client {
+    // for the token bucket
+    tokens: f64,
+    refilled_at: Instant,
+    in_flight: usize,
+}
+
+#[derive(Debug)]
+struct Clients {
+    clients: HashMap<String, Client>,
+    // number of clients tracked at which idle ones are next forgotten
+    sweep_at: usize,
+}
+
+#[derive(Debug)]
+struct Limiter {
+    limits: ClientLimits,
+    clients: Mutex<Clients>,
+}
+
+/// A request in flight from a client, which stops counting against its limit when dropped
+#[derive(Debug)]
+struct Permit {
+    limiter: Arc<Limiter>,
+    key: String,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(client) = self.limiter.clients().clients.get_mut(&self.key) {
+            client.in_flight -= 1;
+        }
+    }
+}
+
+impl Limiter {
+    fn new(limits: ClientLimits) -> Self {
+        Limiter {
+            limits,
+            clients: Mutex::new(Clients {
+                clients: HashMap::new(),
+                sweep_at: MAX_IDLE_CLIENTS,
+            }),
+        }
+    }
+
+    fn clients(&self) -> MutexGuard<'_, Clients> {
+        // clients are never left inconsistent, so poisoning can be ignored
+        self.clients.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn burst(&self) -> f64 {
+        f64::from(self.limits.burst.max(1))
+    }
+
+    /// Count a request from client `key` made at `now` against its limits
+    fn acquire(self: &Arc<Self>, key: &str, now: Instant) -> Result<Permit, Status> {
+        let burst = self.burst();
+        let mut clients = self.clients();
+        let Clients { clients, sweep_at } = &mut *clients;
+        if clients.len() >= *sweep_at {
+            let rate = self.limits.requests_per_second.unwrap_or(f64::INFINITY);
+            clients.retain(|_, client| {
+                client.in_flight > 0
+                    || client.tokens + now.duration_since(client.refilled_at).as_secs_f64() * rate
+                        < burst
+            });
+            *sweep_at = (2 * clients.len()).max(MAX_IDLE_CLIENTS);
+        }
+        let client = clients.entry(key.to_string()).or_insert(Client {
+            tokens: burst,
+            refilled_at: now,
+            in_flight: 0,
+        });
+
+        if let Some(max_concurrent) = self.limits.max_concurrent {
+            if client.in_flight >= max_concurrent {
+                return Err(Status::resource_exhausted(format!(
+                    "client has {} requests in flight, the most it may have at once",
+                    client.in_flight
+                )));
+            }
+        }
+        if let Some(requests_per_second) = self.limits.requests_per_second {
+            client.tokens = (client.tokens
+                + now.duration_since(client.refilled_at).as_secs_f64() * requests_per_second)
+                .min(burst);
+            client.refilled_at = now;
+            if client.tokens < 1. {
+                return Err(Status::resource_exhausted(format!(
+                    "client is over its limit of {} requests per second",
+                    requests_per_second
+                )));
+            }
+            client.tokens -= 1.;
+        }
+
+        client.in_flight += 1;
+        Ok(Permit {
+            limiter: self.clone(),
+            key: key.to_string(),
+        })
+    }
+
+    /// Identify the client a request is from
+    fn key<B>(&self, request: &Request<B>) -> String {
+        let ip = match remote_addr(request) {
+            Some(addr) => format!("ip:{}", addr.ip()),
+            // e.g. unix sockets, which are all local anyway
+            None => "unknown".to_string(),
+        };
+        let header = self
+            .limits
+            .key_header
+            .as_ref()
+            .and_then(|key_header| request.headers().get(key_header))
+            .and_then(|key| key.to_str().ok());
+        match header {
+            Some(key) => format!("{} key:{}", ip, key),
+            None => ip,
+        }
+    }
+}
+
+/// The address a request came from, found the same way as
+/// [`tonic::Request::remote_addr`], which isn't available to a tower layer
+fn remote_addr<B>(request: &Request<B>) -> Option<SocketAddr> {
+    let extensions = request.extensions();
+    let addr = extensions
+        .get::<TcpConnectInfo>()
+        .and_then(TcpConnectInfo::remote_addr);
+    #[cfg(feature = "tls")]
+    let addr = addr.or_else(|| {
+        extensions
+            .get::<TlsConnectInfo<TcpConnectInfo>>()
+            .and_then(|info| info.get_ref().remote_addr())
+    });
+    addr
+}
+
+/// Tower layer enforcing [`ClientLimits`] on a server
+#[derive(Debug, Clone)]
+pub(crate) struct ClientLimitLayer {
+    limiter: Arc<Limiter>,
+}
+
+impl ClientLimitLayer {
+    pub fn new(limits: ClientLimits) -> Self {
+        ClientLimitLayer {
+            limiter: Arc::new(Limiter::new(limits)),
+        }
+    }
+}
+
+impl<S> Layer<S> for ClientLimitLayer {
+    type Service = ClientLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ClientLimit {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+/// See [`ClientLimitLayer`]
+#[derive(Debug, Clone)]
+pub(crate) struct ClientLimit<S> {
+    inner: S,
+    limiter: Arc<Limiter>,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for ClientLimit<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        // so load balancers and orchestrators can always tell whether the server is up
+        if self.limiter.limits.is_unlimited()
+            || request.uri().path().starts_with("/grpc.health.v1.Health/")
+        {
+            let response = self.inner.call(request);
+            return Box::pin(response);
+        }
+
+        let permit = match self
+            .limiter
+            .acquire(&self.limiter.key(&request), Instant::now())
+        {
+            Ok(permit) => permit,
+            Err(status) => {
+                tracing::warn!(%status, "Rejected request over client limits.");
+                return Box::pin(std::future::ready(Ok(status.to_http())));
+            }
+        };
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            // held until the response body is finished, as that's where results are streamed
+            Ok(response.await?.map(|body| {
+                PermitBody {
+                    inner: body,
+                    _permit: permit,
+                }
+                .boxed_unsync()
+            }))
+        })
+    }
+}
+
+/// A response body that holds on to a client's [`Permit`] until it is dropped
+struct PermitBody {
+    inner: BoxBody,
+    _permit: Permit,
+}
+
+impl Body for PermitBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_limiter() {
+        let limiter = Arc::new(Limiter::new(ClientLimits {
+            requests_per_second: Some(2.),
+            burst: 2,
+            max_concurrent: Some(2),
+            key_header: None,
+        }));
+        let start = Instant::now();
+
+        // the burst is available straight away, then the concurrency limit kicks in
+        let first = limiter.acquire("a", start).unwrap();
+        let _second = limiter.acquire("a", start).unwrap();
+        assert!(limiter
+            .acquire("a", start + Duration::from_secs(10))
+            .is_err());
+        // other clients aren't affected
+        let _other = limiter.acquire("b", start).unwrap();
+
+        // once a request finishes, the client is limited by rate
+        drop(first);
+        assert!(limiter.acquire("a", start).is_err());
+        let _third = limiter
+            .acquire("a", start + Duration::from_millis(500))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sweep() {
+        let limiter = Arc::new(Limiter::new(ClientLimits {
+            requests_per_second: Some(1.),
+            burst: 1,
+            max_concurrent: None,
+            key_header: None,
+        }));
+        let start = Instant::now();
+
+        let busy = (0..MAX_IDLE_CLIENTS)
+            .map(|i| limiter.acquire(&i.to_string(), start).unwrap())
+            .collect::<Vec<_>>();
+        // clients in flight aren't forgotten, so the next sweep waits until twice as many
+        limiter.acquire("idle", start).unwrap();
+        assert_eq!(limiter.clients().sweep_at, 2 * MAX_IDLE_CLIENTS);
+        assert_eq!(limiter.clients().clients.len(), MAX_IDLE_CLIENTS + 1);
+
+        // once idle long enough, they are forgotten at that sweep
+        let later = start + Duration::from_secs(10);
+        drop(busy);
+        for i in MAX_IDLE_CLIENTS..2 * MAX_IDLE_CLIENTS - 1 {
+            drop(limiter.acquire(&i.to_string(), later).unwrap());
+        }
+        assert_eq!(limiter.clients().clients.len(), 2 * MAX_IDLE_CLIENTS);
+        limiter
+            .acquire("new", later + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(limiter.clients().clients.len(), 1);
+        assert_eq!(limiter.clients().sweep_at, MAX_IDLE_CLIENTS);
+    }
+
+    #[test]
+    fn test_key() {
+        let limiter = Limiter::new(ClientLimits {
+            key_header: Some("x-api-key".to_string()),
+            ..Default::default()
+        });
+        let request = |key: Option<&str>| {
+            let mut request = Request::builder();
+            if let Some(key) = key {
+                request = request.header("x-api-key", key);
+            }
+            request.body(()).unwrap()
+        };
+
+        // the header separates clients at the same address, rather than replacing it
+        assert_eq!(limiter.key(&request(None)), "unknown");
+        assert_eq!(limiter.key(&request(Some("a"))), "unknown key:a");
+    }
+}
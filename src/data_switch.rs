@@ -8,10 +8,17 @@
 //! mode, or [`Scheduler::new`](crate::Scheduler::new)
 //! otherwise.
 
+use crate::units;
 use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
 use chronoutil::RelativeDuration;
 use olympian::SpatialTree;
-use std::collections::HashMap;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, PoisonError, RwLock, RwLockWriteGuard},
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 
 /// Error type for DataSwitch
@@ -25,6 +32,9 @@ pub enum Error {
     /// The series_id was not in a valid format
     #[error("series id `{0}` could not be parsed")]
     InvalidSeriesId(String),
+    /// The data source has no series with that id
+    #[error("series `{0}` not found")]
+    SeriesNotFound(String),
     /// The no connector was found for that data_source_id in the DataSwitch
     #[error("data source `{0}` not registered")]
     InvalidDataSource(String),
@@ -52,20 +62,36 @@ pub enum Error {
     /// The data source was asked for spatial data but does not offer it
     #[error("this data source does not offer spatial data: {0}")]
     UnimplementedSpatial(String),
+    /// A parameter could not be aligned with the main data in a DataCache
+    #[error("parameter `{0}` could not be aligned with the main data")]
+    MisalignedParam(String),
+    /// A backing source's data could not be aligned with the main data in a
+    /// DataCache
+    #[error("data from backing source `{0}` could not be aligned with the main data")]
+    MisalignedBacking(String),
+    /// Data from a backing source could not be converted to the units of the main data
+    #[error("data from backing source `{0}` could not be converted: {1}")]
+    BackingUnits(String, units::Error),
+    /// The data source took longer than its timeout to respond
+    #[error("fetch from data source `{0}` timed out after {1:?}")]
+    Timeout(String, Duration),
     /// Failure to join a tokio task
     #[error("tokio task failure")]
     Join(#[from] tokio::task::JoinError),
+    /// A [`RetryPolicy`] isn't [valid](RetryPolicy::is_valid)
+    #[error("invalid retry policy: multiplier must be finite and at least 1, got {0}")]
+    InvalidRetryPolicy(f64),
     /// Catchall for any other errors that might occur inside a DataConnector object
     #[error(transparent)]
     Other(Box<dyn std::error::Error + Send + Sync + 'static>),
 }
 
 /// Unix timestamp, inner i64 is seconds since unix epoch
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Timestamp(pub i64);
 
 /// Inclusive range of time, from a start to end [`Timestamp`]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Timerange {
     /// Start of the timerange
     pub start: Timestamp,
@@ -74,11 +100,20 @@ pub struct Timerange {
 }
 
 /// Specifier of which data to fetch from a source by time, and time resolution
+#[derive(Debug, Clone)]
 pub struct TimeSpec {
     /// The range in time of data to fetch
     pub timerange: Timerange,
     /// The time resolution of data that should be fetched
     pub time_resolution: RelativeDuration,
+    /// Scattered times within the timerange to QC, instead of all of it, e.g. only synoptic
+    /// hours
+    ///
+    /// Connectors fetch the whole timerange regardless of this. The
+    /// [`Scheduler`](crate::Scheduler) instead splits a `TimeSpec` with times
+    /// into [`runs`](TimeSpec::runs) of consecutive times, and fetches each
+    /// separately.
+    pub times: Option<Vec<Timestamp>>,
 }
 
 impl TimeSpec {
@@ -88,6 +123,21 @@ impl TimeSpec {
         TimeSpec {
             timerange: Timerange { start, end },
             time_resolution,
+            times: None,
+        }
+    }
+
+    /// Construct a new `TimeSpec` covering only `times`, with a specified time resolution
+    ///
+    /// The timerange spans from the earliest to the latest of `times`, and is
+    /// empty if there are none.
+    pub fn from_times(times: Vec<Timestamp>, time_resolution: RelativeDuration) -> Self {
+        let start = times.iter().min().copied().unwrap_or(Timestamp(0));
+        let end = times.iter().max().copied().unwrap_or(Timestamp(-1));
+        TimeSpec {
+            timerange: Timerange { start, end },
+            time_resolution,
+            times: Some(times),
         }
     }
 
@@ -102,12 +152,239 @@ impl TimeSpec {
             timerange: Timerange { start, end },
             time_resolution: RelativeDuration::parse_from_iso8601(time_resolution)
                 .map_err(|e| e.to_string())?,
+            times: None,
         })
     }
+
+    /// Split the times into runs of consecutive times, each covered by a
+    /// `TimeSpec` without times
+    ///
+    /// Times are consecutive if they are one time resolution apart. Times
+    /// outside the timerange are dropped, so this can be empty. A `TimeSpec`
+    /// without times is a single run.
+    pub fn runs(&self) -> Vec<TimeSpec> {
+        let Some(times) = &self.times else {
+            return vec![TimeSpec::new(
+                self.timerange.start,
+                self.timerange.end,
+                self.time_resolution,
+            )];
+        };
+
+        let mut times: Vec<Timestamp> = times
+            .iter()
+            .filter(|time| self.timerange.start <= **time && **time <= self.timerange.end)
+            .copied()
+            .collect();
+        times.sort_unstable();
+        times.dedup();
+
+        let mut runs: Vec<TimeSpec> = Vec::new();
+        for time in times {
+            match runs.last_mut() {
+                // timestamps should be validated before they get here, so it should be safe to
+                // unwrap
+                Some(run)
+                    if (Utc.timestamp_opt(run.timerange.end.0, 0).unwrap()
+                        + self.time_resolution)
+                        .timestamp()
+                        == time.0 =>
+                {
+                    run.timerange.end = time
+                }
+                _ => runs.push(TimeSpec::new(time, time, self.time_resolution)),
+            }
+        }
+        runs
+    }
+
+    /// Roughly how many points per series the timerange covers
+    ///
+    /// Exact for time resolutions of a fixed length, and otherwise based on the length of the
+    /// first time step, so months are estimated from the first month.
+    pub fn num_points(&self) -> usize {
+        let step = self.first_step();
+        if step <= 0 || self.timerange.end < self.timerange.start {
+            return 0;
+        }
+        ((self.timerange.end.0 - self.timerange.start.0) / step + 1) as usize
+    }
+
+    /// Length in seconds of the first time step of the timerange
+    fn first_step(&self) -> i64 {
+        // timestamps should be validated before they get here, so it should be safe to unwrap
+        let start = Utc.timestamp_opt(self.timerange.start.0, 0).unwrap();
+        (start + self.time_resolution).timestamp() - start.timestamp()
+    }
+
+    /// Whether the time resolution moves forward in time, which it must for the timerange to
+    /// be split into points
+    pub fn has_positive_resolution(&self) -> bool {
+        self.first_step() > 0
+    }
+
+    /// The timerange a connector should fetch to give `num_leading_points` before and
+    /// `num_trailing_points` after the timerange
+    pub fn padded_timerange(&self, num_leading_points: u8, num_trailing_points: u8) -> Timerange {
+        // timestamps should be validated before they get here, so it should be safe to unwrap
+        Timerange {
+            start: Timestamp(
+                (Utc.timestamp_opt(self.timerange.start.0, 0).unwrap()
+                    - self.time_resolution * i32::from(num_leading_points))
+                .timestamp(),
+            ),
+            end: Timestamp(
+                (Utc.timestamp_opt(self.timerange.end.0, 0).unwrap()
+                    + self.time_resolution * i32::from(num_trailing_points))
+                .timestamp(),
+            ),
+        }
+    }
+
+    /// Split the timerange into consecutive timeranges of at most `max_points` points each, with
+    /// the same time resolution
+    ///
+    /// A timerange without a [positive resolution](TimeSpec::has_positive_resolution) can't be
+    /// split, so is returned whole.
+    pub fn chunks(&self, max_points: u32) -> Vec<TimeSpec> {
+        if !self.has_positive_resolution() {
+            return vec![TimeSpec::new(
+                self.timerange.start,
+                self.timerange.end,
+                self.time_resolution,
+            )];
+        }
+        let max_points = max_points.clamp(1, i32::MAX as u32) as i32;
+        // timestamps should be validated before they get here, so it should be safe to unwrap
+        let start = Utc.timestamp_opt(self.timerange.start.0, 0).unwrap();
+
+        let mut chunks = Vec::new();
+        let mut offset: i32 = 0;
+        loop {
+            let chunk_start = (start + self.time_resolution * offset).timestamp();
+            if chunk_start > self.timerange.end.0 {
+                break;
+            }
+            let chunk_end = (start + self.time_resolution * (offset + max_points - 1))
+                .timestamp()
+                .min(self.timerange.end.0);
+            chunks.push(TimeSpec::new(
+                Timestamp(chunk_start),
+                Timestamp(chunk_end),
+                self.time_resolution,
+            ));
+            offset += max_points;
+        }
+        chunks
+    }
+}
+
+/// How to fit a series onto a different time resolution, see [`resample`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    /// The observation closest in time, if it is within half a time step,
+    /// e.g. for instantaneous values reported slightly off the hour
+    Nearest,
+    /// The mean of the observations in each time step
+    Mean,
+    /// The sum of the observations in each time step, for accumulations like
+    /// precipitation. A time step without a value for each of its source time
+    /// steps is missing, as its sum would be too low
+    Sum,
+}
+
+impl std::str::FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Aggregation::Nearest),
+            "mean" => Ok(Aggregation::Mean),
+            "sum" => Ok(Aggregation::Sum),
+            _ => Err(format!(
+                "unknown aggregation `{}`, expected nearest, mean or sum",
+                s
+            )),
+        }
+    }
+}
+
+/// Resample observations onto `len` time steps of `time_resolution`, starting at `start`
+///
+/// `observations` must be sorted by time, and can be at any resolution, or
+/// irregular. For [`Mean`](Aggregation::Mean) and [`Sum`](Aggregation::Sum),
+/// each time step aggregates the observations after the previous time step, up
+/// to and including its own time, following the convention that aggregates are
+/// labelled with the end of their period. Time steps with no observations to
+/// aggregate are missing.
+///
+/// `source_resolution` is the time resolution of `observations`, if they are
+/// regular. [`Sum`](Aggregation::Sum) needs it to tell how many observations
+/// each time step should have, so without it every sum is missing.
+pub fn resample(
+    observations: &[(Timestamp, Option<f32>)],
+    source_resolution: Option<RelativeDuration>,
+    start: Timestamp,
+    time_resolution: RelativeDuration,
+    len: usize,
+    aggregation: Aggregation,
+) -> Vec<Option<f32>> {
+    // timestamps should be validated before they get here, so it should be safe to unwrap
+    let start = Utc.timestamp_opt(start.0, 0).unwrap();
+    // index of the first observation after `time`
+    let after = |time: i64| observations.partition_point(|(t, _)| t.0 <= time);
+
+    (0..len)
+        .map(|i| {
+            let time = (start + time_resolution * i as i32).timestamp();
+            let previous = (start + time_resolution * (i as i32 - 1)).timestamp();
+
+            match aggregation {
+                Aggregation::Nearest => {
+                    let next = observations.partition_point(|(t, _)| t.0 < time);
+                    // ties go to the earlier observation
+                    [next.checked_sub(1), Some(next)]
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|j| observations.get(j))
+                        .filter(|(t, _)| 2 * (t.0 - time).abs() <= time - previous)
+                        .min_by_key(|(t, _)| (t.0 - time).abs())
+                        .and_then(|(_, value)| *value)
+                }
+                Aggregation::Mean | Aggregation::Sum => {
+                    let in_step = &observations[after(previous)..after(time)];
+                    if in_step.is_empty() {
+                        return None;
+                    }
+                    let values: Vec<f32> = in_step.iter().filter_map(|(_, value)| *value).collect();
+                    match aggregation {
+                        Aggregation::Sum => {
+                            // absent observations make a sum as short as missing values do, so
+                            // every source step must be there. Counting stops past the
+                            // observations there are, so a zero resolution can't count forever
+                            let source_resolution = source_resolution?;
+                            let previous = Utc.timestamp_opt(previous, 0).unwrap();
+                            let expected = (1..)
+                                .map(|k| (previous + source_resolution * k).timestamp())
+                                .take_while(|t| *t <= time)
+                                .take(in_step.len() + 1)
+                                .count();
+                            (expected > 0
+                                && expected <= values.len()
+                                && values.len() == in_step.len())
+                            .then(|| values.iter().sum())
+                        }
+                        _ if values.is_empty() => None,
+                        _ => Some(values.iter().sum::<f32>() / values.len() as f32),
+                    }
+                }
+            }
+        })
+        .collect()
 }
 
 /// Specifier of geographic position, by latitude and longitude
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
 pub struct GeoPoint {
     /// latitude, in degrees
     pub lat: f32,
@@ -120,16 +397,288 @@ pub struct GeoPoint {
 /// represented by its vertices as a sequence of lat-lon points
 pub type Polygon = Vec<GeoPoint>;
 
+/// A [`Polygon`] prepared for testing whether points lie inside it
+///
+/// Meant for connectors whose source can only return everything, so they can
+/// honour [`SpaceSpec::Polygon`] by filtering what they read. Points are
+/// checked against the polygon's bounding box before the full test, so
+/// filtering many points that mostly lie outside it is cheap.
+///
+/// Lat-lon is treated as planar, which is plenty accurate for the polygons we
+/// get. Points on the polygon's boundary count as inside, and polygons with
+/// fewer than 3 distinct vertices contain nothing.
+///
+/// ```
+/// use rove::data_switch::{GeoPoint, PolygonFilter};
+///
+/// let filter = PolygonFilter::new(vec![
+///     GeoPoint { lat: 58., lon: 5. },
+///     GeoPoint { lat: 62., lon: 5. },
+///     GeoPoint { lat: 58., lon: 11. },
+/// ]);
+/// assert!(filter.contains(59., 6.));
+/// assert!(!filter.contains(61.5, 9.5));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolygonFilter {
+    polygon: Polygon,
+    min: GeoPoint,
+    max: GeoPoint,
+}
+
+impl PolygonFilter {
+    /// Prepare `polygon` for filtering
+    ///
+    /// The polygon may be open or closed, i.e. its last vertex may or may not
+    /// repeat its first.
+    pub fn new(mut polygon: Polygon) -> Self {
+        polygon.dedup();
+        if polygon.len() > 1 && polygon.first() == polygon.last() {
+            polygon.pop();
+        }
+
+        let (min, max) = polygon.iter().fold(
+            (
+                GeoPoint {
+                    lat: f32::INFINITY,
+                    lon: f32::INFINITY,
+                },
+                GeoPoint {
+                    lat: f32::NEG_INFINITY,
+                    lon: f32::NEG_INFINITY,
+                },
+            ),
+            |(min, max), point| {
+                (
+                    GeoPoint {
+                        lat: min.lat.min(point.lat),
+                        lon: min.lon.min(point.lon),
+                    },
+                    GeoPoint {
+                        lat: max.lat.max(point.lat),
+                        lon: max.lon.max(point.lon),
+                    },
+                )
+            },
+        );
+
+        PolygonFilter { polygon, min, max }
+    }
+
+    /// South-west and north-east corners of the polygon's bounding box, or
+    /// `None` if the polygon has no vertices
+    pub fn bounds(&self) -> Option<(GeoPoint, GeoPoint)> {
+        (!self.polygon.is_empty()).then_some((self.min, self.max))
+    }
+
+    /// Whether the point at `lat`, `lon` lies inside the polygon or on its
+    /// boundary
+    pub fn contains(&self, lat: f32, lon: f32) -> bool {
+        if self.polygon.len() < 3
+            || lat < self.min.lat
+            || lat > self.max.lat
+            || lon < self.min.lon
+            || lon > self.max.lon
+        {
+            return false;
+        }
+
+        // computed in f64, so points near an edge aren't misplaced by rounding
+        let (lat, lon) = (f64::from(lat), f64::from(lon));
+        let mut inside = false;
+        for (i, a) in self.polygon.iter().enumerate() {
+            let b = &self.polygon[(i + 1) % self.polygon.len()];
+            let (a_lat, a_lon) = (f64::from(a.lat), f64::from(a.lon));
+            let (b_lat, b_lon) = (f64::from(b.lat), f64::from(b.lon));
+
+            // zero cross product means the point is in line with the edge
+            let cross = (b_lon - a_lon) * (lat - a_lat) - (b_lat - a_lat) * (lon - a_lon);
+            if cross.abs() < 1e-9
+                && lat >= a_lat.min(b_lat)
+                && lat <= a_lat.max(b_lat)
+                && lon >= a_lon.min(b_lon)
+                && lon <= a_lon.max(b_lon)
+            {
+                return true;
+            }
+
+            // ray casting, counting crossings of a ray heading east from the point
+            if (a_lat > lat) != (b_lat > lat)
+                && lon < a_lon + (lat - a_lat) / (b_lat - a_lat) * (b_lon - a_lon)
+            {
+                inside = !inside;
+            }
+        }
+        inside
+    }
+}
+
 /// Specifier of which data to fetch from a source by location
+///
+/// In config files, like those of [periodic jobs](crate::periodic::Job), this is written as
+/// `"all"`, `{ one = "18700" }`, `{ multiple = ["18700", "18701"] }` or
+/// `{ polygon = [{ lat = 59.9, lon = 10.7 }, ...] }`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SpaceSpec {
     /// One single timeseries, specified with a data_id
     One(String),
+    /// A list of timeseries, specified with data_ids like [`SpaceSpec::One`], fetched together
+    Multiple(Vec<String>),
     /// A Polygon in lat-lon space defining the area from which to fetch data
     Polygon(Polygon),
     /// The whole data set
     All,
 }
 
+/// How to split a [`SpaceSpec::Polygon`] into tiles that are fetched and QCed one at a time,
+/// to bound the memory used by requests covering large areas
+///
+/// Tiles are squares in lat-lon space. Each is fetched with a margin around it, so that spatial
+/// checks near its edges still see their neighbours, but only the series inside the tile itself
+/// are QCed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tiling {
+    /// Width and height of each tile, in degrees. Must be positive
+    pub tile_size: f32,
+    /// Distance around each tile to fetch neighbours from, in degrees. Must not be negative
+    pub margin: f32,
+}
+
+impl Tiling {
+    /// Whether polygons can be split into tiles of this size, which takes a finite, positive
+    /// tile size and a finite margin that isn't negative
+    pub fn is_valid(&self) -> bool {
+        self.tile_size.is_finite()
+            && self.tile_size > 0.
+            && self.margin.is_finite()
+            && self.margin >= 0.
+    }
+}
+
+/// One tile of a [`SpaceSpec::Polygon`], produced by [`SpaceSpec::tiles`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpaceTile {
+    /// The area to fetch for this tile, including its margin
+    pub fetch: Polygon,
+    // the polygon being tiled, and the tile's place in the grid covering it, to decide which
+    // series the tile owns
+    polygon: PolygonFilter,
+    origin: GeoPoint,
+    tile_size: f32,
+    grid_size: (usize, usize),
+    index: (usize, usize),
+}
+
+impl SpaceTile {
+    /// Whether a series at `lat`, `lon` is QCed as part of this tile
+    ///
+    /// Every point inside the tiled polygon is owned by exactly one tile, with points on the
+    /// border between two tiles going to the one further north or east.
+    pub fn owns(&self, lat: f32, lon: f32) -> bool {
+        let cell = |offset: f32, num_cells: usize| {
+            ((offset / self.tile_size).floor().max(0.) as usize).min(num_cells - 1)
+        };
+        (
+            cell(lat - self.origin.lat, self.grid_size.0),
+            cell(lon - self.origin.lon, self.grid_size.1),
+        ) == self.index
+            && self.polygon.contains(lat, lon)
+    }
+}
+
+impl SpaceSpec {
+    /// Split a polygon into tiles according to `tiling`, ordered by row from south-west to
+    /// north-east
+    ///
+    /// Returns `None` for other kinds of space spec, which can't be tiled, or if `tiling` isn't
+    /// [valid](Tiling::is_valid).
+    pub fn tiles(&self, tiling: Tiling) -> Option<Vec<SpaceTile>> {
+        let SpaceSpec::Polygon(polygon) = self else {
+            return None;
+        };
+        if !tiling.is_valid() {
+            return None;
+        }
+        let polygon = PolygonFilter::new(polygon.clone());
+        let Some((origin, max)) = polygon.bounds() else {
+            return Some(Vec::new());
+        };
+        let num_cells = |extent: f32| ((extent / tiling.tile_size).ceil() as usize).max(1);
+        let grid_size = (
+            num_cells(max.lat - origin.lat),
+            num_cells(max.lon - origin.lon),
+        );
+
+        Some(
+            (0..grid_size.0)
+                .flat_map(|row| (0..grid_size.1).map(move |col| (row, col)))
+                .map(|(row, col)| {
+                    let south = origin.lat + tiling.tile_size * row as f32 - tiling.margin;
+                    let north = origin.lat + tiling.tile_size * (row + 1) as f32 + tiling.margin;
+                    let west = origin.lon + tiling.tile_size * col as f32 - tiling.margin;
+                    let east = origin.lon + tiling.tile_size * (col + 1) as f32 + tiling.margin;
+                    SpaceTile {
+                        fetch: vec![
+                            GeoPoint {
+                                lat: south,
+                                lon: west,
+                            },
+                            GeoPoint {
+                                lat: north,
+                                lon: west,
+                            },
+                            GeoPoint {
+                                lat: north,
+                                lon: east,
+                            },
+                            GeoPoint {
+                                lat: south,
+                                lon: east,
+                            },
+                        ],
+                        polygon: polygon.clone(),
+                        origin,
+                        tile_size: tiling.tile_size,
+                        grid_size,
+                        index: (row, col),
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Metadata about the station a series comes from, beyond its location
+///
+/// Every field is optional, as connectors report what their source has.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StationMetadata {
+    /// Provider of the series, e.g. the station network it comes from
+    pub provider: Option<i32>,
+    /// Type of station, e.g. "automatic" or "manual", as the connector
+    /// defines them
+    pub station_type: Option<String>,
+    /// Height of the sensor above ground, in metres
+    pub sensor_height: Option<f32>,
+    /// WIGOS station identifier
+    pub wigos_id: Option<String>,
+}
+
+impl StationMetadata {
+    const EMPTY: StationMetadata = StationMetadata {
+        provider: None,
+        station_type: None,
+        sensor_height: None,
+        wigos_id: None,
+    };
+
+    /// Whether no metadata is known
+    pub fn is_empty(&self) -> bool {
+        self == &Self::EMPTY
+    }
+}
+
 /// Container for metereological data
 ///
 /// a [`new`](DataCache::new) method is provided to
@@ -160,6 +709,33 @@ pub struct DataCache {
     pub num_leading_points: u8,
     /// The number of extra points in the series after the data to be QCed
     pub num_trailing_points: u8,
+    /// Extra parameters for the same stations and times as `data`, keyed by
+    /// parameter name.
+    ///
+    /// Each inner vector is aligned with the series at the same index in
+    /// `data`. These are used by checks that compare the data being QCed
+    /// against other parameters, but are not QCed themselves.
+    pub params: HashMap<String, Vec<Vec<Option<f32>>>>,
+    /// Metadata about the station each series comes from, aligned with
+    /// `data`.
+    ///
+    /// Empty if the connector doesn't report any. Used to restrict steps to
+    /// particular stations, and passed on to clients alongside the results.
+    pub metadata: Vec<StationMetadata>,
+    /// Whether each series, aligned with `data`, comes from a backing source.
+    ///
+    /// Backing series help QC the others, e.g. as extra neighbours in
+    /// spatial checks, but are not flagged themselves. Empty if there are
+    /// none.
+    pub backing: Vec<bool>,
+    /// Units of `data`, if the connector reports them, as understood by
+    /// [`units::conversion`]
+    pub units: Option<String>,
+    /// Units of each of `params`, for those the connector reports units for
+    pub param_units: HashMap<String, String>,
+    /// Name of the data source the data came from, set by the [`DataSwitch`]
+    /// when it was fetched through a fallback chain
+    pub source: Option<String>,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -183,8 +759,259 @@ impl DataCache {
             period,
             num_leading_points,
             num_trailing_points,
+            params: HashMap::new(),
+            metadata: Vec::new(),
+            backing: Vec::new(),
+            units: None,
+            param_units: HashMap::new(),
+            source: None,
         }
     }
+
+    /// Set the units of `data`
+    pub fn with_units(mut self, units: impl Into<String>) -> Self {
+        self.units = Some(units.into());
+        self
+    }
+
+    /// Set the metadata of each series, aligned with `data`
+    pub fn with_metadata(mut self, metadata: Vec<StationMetadata>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Set the provider of each series, aligned with `data`, for connectors
+    /// that report no other metadata
+    pub fn with_providers(self, providers: Vec<Option<i32>>) -> Self {
+        self.with_metadata(
+            providers
+                .into_iter()
+                .map(|provider| StationMetadata {
+                    provider,
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+
+    /// Metadata of the station series `i` comes from, empty if the connector
+    /// didn't report any
+    pub fn station_metadata(&self, i: usize) -> &StationMetadata {
+        self.metadata.get(i).unwrap_or(&StationMetadata::EMPTY)
+    }
+
+    /// Add the data in another DataCache as an extra parameter of this one
+    ///
+    /// Series are matched by identifier, and series in `self` without a match
+    /// in `other` get a series of `None`s for this parameter. Returns an error
+    /// if `other` is not aligned in time with `self`.
+    pub fn add_param(&mut self, name: impl Into<String>, other: DataCache) -> Result<(), Error> {
+        let name = name.into();
+        let series_len = self.data.first().map(|ts| ts.1.len()).unwrap_or(0);
+
+        if other.start_time != self.start_time
+            || other.period != self.period
+            || other.num_leading_points != self.num_leading_points
+            || other.data.iter().any(|ts| ts.1.len() != series_len)
+        {
+            return Err(Error::MisalignedParam(name));
+        }
+
+        let mut other_series: HashMap<String, Vec<Option<f32>>> = other.data.into_iter().collect();
+        let aligned = self
+            .data
+            .iter()
+            .map(|ts| {
+                other_series
+                    .remove(&ts.0)
+                    .unwrap_or_else(|| vec![None; series_len])
+            })
+            .collect();
+
+        if let Some(units) = other.units {
+            self.param_units.insert(name.clone(), units);
+        }
+        self.params.insert(name, aligned);
+        Ok(())
+    }
+
+    /// Add the series in another DataCache, from a backing source, to this one
+    ///
+    /// The added series are marked as backing series, and get series of
+    /// `None`s for any extra parameters. Returns an error if `other` is not
+    /// aligned in time with `self`.
+    /// If both caches report their units, the added series are converted to
+    /// the units of `self`.
+    pub fn add_backing(
+        &mut self,
+        source: impl Into<String>,
+        mut other: DataCache,
+    ) -> Result<(), Error> {
+        let source = source.into();
+        if let (Some(units), Some(other_units)) = (&self.units, &other.units) {
+            let conversion = units::conversion(other_units, units)
+                .map_err(|e| Error::BackingUnits(source.clone(), e))?;
+            convert_series(other.data.iter_mut().map(|ts| &mut ts.1), conversion);
+            other.units = Some(units.clone());
+        }
+
+        let series_len = self.data.first().map(|ts| ts.1.len()).unwrap_or(0);
+
+        if other.start_time != self.start_time
+            || other.period != self.period
+            || other.num_leading_points != self.num_leading_points
+            || other.data.iter().any(|ts| ts.1.len() != series_len)
+        {
+            return Err(Error::MisalignedBacking(source));
+        }
+
+        let num_series = self.data.len();
+        let num_other = other.data.len();
+        if !self.metadata.is_empty() || !other.metadata.is_empty() {
+            self.metadata.resize(num_series, StationMetadata::EMPTY);
+            self.metadata
+                .extend((0..num_other).map(|i| other.station_metadata(i).clone()));
+        }
+        self.backing.resize(num_series, false);
+        self.backing.extend(std::iter::repeat_n(true, num_other));
+        for series in self.params.values_mut() {
+            series.extend(std::iter::repeat_n(vec![None; series_len], num_other));
+        }
+
+        let mut lats = std::mem::take(&mut self.rtree.lats);
+        let mut lons = std::mem::take(&mut self.rtree.lons);
+        let mut elevs = std::mem::take(&mut self.rtree.elevs);
+        lats.extend(other.rtree.lats);
+        lons.extend(other.rtree.lons);
+        elevs.extend(other.rtree.elevs);
+        self.rtree = SpatialTree::from_latlons(lats, lons, elevs);
+        self.data.extend(other.data);
+
+        Ok(())
+    }
+
+    /// Number of points in each series that are QCed, i.e. not leading or trailing context
+    pub(crate) fn num_qc_points(&self) -> usize {
+        self.data.first().map_or(0, |ts| {
+            ts.1.len().saturating_sub(
+                self.num_leading_points as usize + self.num_trailing_points as usize,
+            )
+        })
+    }
+
+    /// Split the cache into consecutive windows of at most `max_points` QCed points each, each
+    /// with the same leading and trailing context as the whole cache
+    pub(crate) fn windows(&self, max_points: usize) -> Vec<DataCache> {
+        let max_points = max_points.max(1);
+        let context = self.num_leading_points as usize + self.num_trailing_points as usize;
+        // timestamps should be validated before they get here, so it should be safe to unwrap
+        let start_time = Utc.timestamp_opt(self.start_time.0, 0).unwrap();
+        let slice = |series: &Vec<Option<f32>>, offset: usize, len: usize| {
+            series[offset..offset + len + context].to_vec()
+        };
+
+        (0..self.num_qc_points())
+            .step_by(max_points)
+            .map(|offset| {
+                let len = max_points.min(self.num_qc_points() - offset);
+                DataCache {
+                    data: self
+                        .data
+                        .iter()
+                        .map(|(identifier, series)| {
+                            (identifier.clone(), slice(series, offset, len))
+                        })
+                        .collect(),
+                    start_time: Timestamp((start_time + self.period * offset as i32).timestamp()),
+                    params: self
+                        .params
+                        .iter()
+                        .map(|(name, param)| {
+                            (
+                                name.clone(),
+                                param
+                                    .iter()
+                                    .map(|series| slice(series, offset, len))
+                                    .collect(),
+                            )
+                        })
+                        .collect(),
+                    ..self.clone_metadata()
+                }
+            })
+            .collect()
+    }
+
+    /// A copy of the cache without any data, for building windows of it
+    fn clone_metadata(&self) -> DataCache {
+        DataCache {
+            data: Vec::new(),
+            start_time: self.start_time,
+            period: self.period,
+            rtree: self.rtree.clone(),
+            num_leading_points: self.num_leading_points,
+            num_trailing_points: self.num_trailing_points,
+            params: HashMap::new(),
+            metadata: self.metadata.clone(),
+            backing: self.backing.clone(),
+            units: self.units.clone(),
+            param_units: self.param_units.clone(),
+            source: self.source.clone(),
+        }
+    }
+
+    /// Whether the series at `index` comes from a backing source
+    pub fn is_backing(&self, index: usize) -> bool {
+        self.backing.get(index).copied().unwrap_or(false)
+    }
+
+    /// Convert the data, and any extra parameters of the same quantity, to `units`
+    ///
+    /// Series without reported units are left as they are. That includes
+    /// [backing series](DataCache::add_backing), which only know their units
+    /// through the data's, so should be converted before they're added to data
+    /// that doesn't report any. Extra parameters of other quantities, like the
+    /// relative humidity alongside a temperature, are also left as they are.
+    ///
+    /// # Errors
+    ///
+    /// If any of the units involved are unknown, or `data` is of a different
+    /// quantity than `units`
+    pub fn convert_units(&mut self, units: &str) -> Result<(), units::Error> {
+        if let Some(from) = &self.units {
+            let conversion = units::conversion(from, units)?;
+            convert_series(self.data.iter_mut().map(|ts| &mut ts.1), conversion);
+            self.units = Some(units.to_string());
+        }
+
+        for (name, series) in self.params.iter_mut() {
+            let Some(from) = self.param_units.get_mut(name) else {
+                continue;
+            };
+            match units::conversion(from, units) {
+                Ok(conversion) => {
+                    convert_series(series.iter_mut(), conversion);
+                    *from = units.to_string();
+                }
+                Err(units::Error::Incompatible(..)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn convert_series<'a>(
+    series: impl Iterator<Item = &'a mut Vec<Option<f32>>>,
+    conversion: units::Conversion,
+) {
+    if conversion.is_identity() {
+        return;
+    }
+    for value in series.flatten().flatten() {
+        *value = conversion.apply(*value);
+    }
 }
 
 /// Trait for pulling data from data sources
@@ -256,47 +1083,431 @@ pub trait DataConnector: Sync + std::fmt::Debug {
         num_trailing_points: u8,
         extra_spec: Option<&str>,
     ) -> Result<DataCache, Error>;
+
+    /// fetch specified data from the data source, along with extra
+    /// parameters for the same stations and times
+    ///
+    /// The default implementation calls
+    /// [`fetch_data`](DataConnector::fetch_data) once for the main data, then
+    /// once for each of `params`, passing the parameter name as `extra_spec`,
+    /// and aligns the results using [`DataCache::add_param`]. Connectors that
+    /// can fetch several parameters more efficiently in one request should
+    /// override this.
+    async fn fetch_data_with_params(
+        &self,
+        space_spec: &SpaceSpec,
+        time_spec: &TimeSpec,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+        extra_spec: Option<&str>,
+        params: &[&str],
+    ) -> Result<DataCache, Error> {
+        let mut cache = self
+            .fetch_data(
+                space_spec,
+                time_spec,
+                num_leading_points,
+                num_trailing_points,
+                extra_spec,
+            )
+            .await?;
+
+        for param in params {
+            let param_cache = self
+                .fetch_data(
+                    space_spec,
+                    time_spec,
+                    num_leading_points,
+                    num_trailing_points,
+                    Some(param),
+                )
+                .await?;
+            cache.add_param(*param, param_cache)?;
+        }
+
+        Ok(cache)
+    }
+
+    /// Whether a fetch that failed with `error` may succeed if tried again,
+    /// e.g. because the source timed out or had a temporary server error
+    ///
+    /// Used by the [`DataSwitch`]'s [`RetryPolicy`]. The default
+    /// implementation only considers IO errors and timeouts retryable.
+    /// Connectors that can tell transient errors apart should override this.
+    fn is_retryable(&self, error: &Error) -> bool {
+        matches!(error, Error::Io(_) | Error::Timeout(..))
+    }
+
+    /// Check whether the data source can currently be fetched from, e.g. by
+    /// pinging its database
+    ///
+    /// Used by the server's health checking service, which reports the
+    /// server as not ready to serve while any source is unhealthy. The
+    /// default implementation always reports the source as healthy.
+    async fn check_health(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Policy for retrying failed fetches from data sources
+///
+/// Fetches are retried after a backoff that starts at `initial_backoff` and is
+/// multiplied by `multiplier` after each attempt, up to `max_backoff`. Only
+/// errors the connector considers retryable (see
+/// [`DataConnector::is_retryable`]) are retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first
+    pub max_attempts: u32,
+    /// Time to wait before the first retry
+    pub initial_backoff: Duration,
+    /// Longest time to wait between attempts
+    pub max_backoff: Duration,
+    /// Factor the backoff grows by after each attempt
+    pub multiplier: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether backoffs can be computed with this policy, which takes a finite multiplier of at
+    /// least 1, so the backoff never shrinks
+    pub fn is_valid(&self) -> bool {
+        self.multiplier.is_finite() && self.multiplier >= 1.
+    }
 }
 
 // TODO: this needs updating when we update the proto
 /// Data routing utility for ROVE
 ///
-/// This contains a map of &str to [`DataConnector`]s and is used by ROVE to
-/// pull data for QC tests the &str keys in the hashmap correspond to the data
+/// This contains a map of names to [`DataConnector`]s and is used by ROVE to
+/// pull data for QC tests the keys in the hashmap correspond to the data
 /// source name you would use in the call to validate_series or validate_spatial.
 /// So in the example below, you would contruct a spatial_id like "test:single"
 /// to direct ROVE to fetch data from `TestDataSource` and pass it the data_id
 /// "single"
 ///
+/// The DataSwitch shares ownership of its connectors, so they can be
+/// constructed at runtime and hold owned state, like connection pools.
+///
 /// ```
 /// use rove::{
 ///     data_switch::{DataConnector, DataSwitch},
 ///     dev_utils::TestDataSource,
 /// };
-/// use std::collections::HashMap;
+/// use std::{collections::HashMap, sync::Arc};
 ///
 /// let data_switch = DataSwitch::new(HashMap::from([
-///     ("test", &TestDataSource{
+///     ("test", Arc::new(TestDataSource {
 ///         data_len_single: 3,
 ///         data_len_series: 1000,
 ///         data_len_spatial: 1000,
-///     } as &dyn DataConnector),
+///     }) as Arc<dyn DataConnector + Send + Sync>),
 /// ]));
 /// ```
 #[derive(Debug, Clone)]
-pub struct DataSwitch<'ds> {
-    sources: HashMap<&'ds str, &'ds dyn DataConnector>,
+pub struct DataSwitch {
+    sources: HashMap<String, Arc<dyn DataConnector + Send + Sync>>,
+    // shared between clones, so e.g. the server's services all use the same cache
+    cache: Option<Arc<Mutex<FetchCache>>>,
+    // fetches are only attempted once if this is None
+    retry: Option<RetryPolicy>,
+    // keyed by data source, sources without a timeout can take as long as they like
+    timeouts: HashMap<String, Duration>,
+    // keyed by data source, for sources that don't report units themselves
+    units: HashMap<String, String>,
+    // ordered data sources to try, keyed by the name they're fetched through
+    fallbacks: HashMap<String, Vec<String>>,
+}
+
+/// A request the [`DataSwitch`] would make of one of its connectors, see
+/// [`Scheduler::fetch_plan`](crate::Scheduler::fetch_plan)
+#[derive(Debug, Clone)]
+pub struct ConnectorRequest {
+    /// Name the connector is registered under
+    pub data_source: String,
+    /// Where to fetch data from
+    pub space_spec: SpaceSpec,
+    /// The timerange to QC
+    pub timerange: Timerange,
+    /// Time resolution of the data
+    pub time_resolution: RelativeDuration,
+    /// Number of points to fetch before the timerange
+    pub num_leading_points: u8,
+    /// Number of points to fetch after the timerange
+    pub num_trailing_points: u8,
+    /// The timerange the connector should fetch, including the leading and trailing points
+    pub fetch_timerange: Timerange,
+    /// Extra info passed to the connector, to further specify what data to fetch
+    pub extra_spec: Option<String>,
+    /// Extra parameters fetched alongside the data, see
+    /// [`DataConnector::fetch_data_with_params`]
+    pub params: Vec<String>,
+}
+
+/// Space spec in a form that can be hashed, for keying the fetch cache
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum SpaceKey {
+    One(String),
+    Multiple(Vec<String>),
+    // lat-lon bit patterns, as floats aren't hashable
+    Polygon(Vec<(u32, u32)>),
+    All,
+}
+
+impl From<&SpaceSpec> for SpaceKey {
+    fn from(item: &SpaceSpec) -> Self {
+        match item {
+            SpaceSpec::One(data_id) => SpaceKey::One(data_id.clone()),
+            SpaceSpec::Multiple(data_ids) => SpaceKey::Multiple(data_ids.clone()),
+            SpaceSpec::Polygon(polygon) => SpaceKey::Polygon(
+                polygon
+                    .iter()
+                    .map(|point| (point.lat.to_bits(), point.lon.to_bits()))
+                    .collect(),
+            ),
+            SpaceSpec::All => SpaceKey::All,
+        }
+    }
+}
+
+/// Everything that identifies a fetch from a data source
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FetchKey {
+    data_source: String,
+    space: SpaceKey,
+    timerange: Timerange,
+    time_resolution: RelativeDuration,
+    num_leading_points: u8,
+    num_trailing_points: u8,
+    extra_spec: Option<String>,
+    params: Vec<String>,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    data: DataCache,
+    fetched: Instant,
+    // value of the cache's clock when the entry was last used, for evicting the least recently
+    // used entry
+    last_used: u64,
+}
+
+/// Cache of fetched data, with entries expiring after `ttl`, and the least recently used entry
+/// evicted when more than `capacity` are stored
+#[derive(Debug)]
+struct FetchCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<FetchKey, CacheEntry>,
+    clock: u64,
+}
+
+impl FetchCache {
+    fn get(&mut self, key: &FetchKey) -> Option<DataCache> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        if entry.fetched.elapsed() > self.ttl {
+            self.entries.remove(key);
+            return None;
+        }
+        entry.last_used = self.clock;
+        Some(entry.data.clone())
+    }
+
+    fn insert(&mut self, key: FetchKey, data: DataCache) {
+        self.clock += 1;
+        self.entries
+            .retain(|_, entry| entry.fetched.elapsed() <= self.ttl);
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            if let Some(lru_key) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            {
+                self.entries.remove(&lru_key);
+            }
+        }
+        if self.capacity > 0 {
+            self.entries.insert(
+                key,
+                CacheEntry {
+                    data,
+                    fetched: Instant::now(),
+                    last_used: self.clock,
+                },
+            );
+        }
+    }
 }
 
-impl<'ds> DataSwitch<'ds> {
+impl DataSwitch {
     /// Instantiate a new DataSwitch
     ///
     /// See the DataSwitch struct documentation for more info
-    pub fn new(sources: HashMap<&'ds str, &'ds dyn DataConnector>) -> Self {
-        Self { sources }
+    pub fn new(
+        sources: impl IntoIterator<Item = (impl Into<String>, Arc<dyn DataConnector + Send + Sync>)>,
+    ) -> Self {
+        Self {
+            sources: sources
+                .into_iter()
+                .map(|(name, connector)| (name.into(), connector))
+                .collect(),
+            cache: None,
+            retry: None,
+            timeouts: HashMap::new(),
+            units: HashMap::new(),
+            fallbacks: HashMap::new(),
+        }
+    }
+
+    /// Fail fetches from `data_source` with [`Error::Timeout`] if they take longer than
+    /// `timeout`
+    ///
+    /// With a [`RetryPolicy`], the timeout applies to each attempt separately.
+    pub fn with_timeout(mut self, data_source: impl Into<String>, timeout: Duration) -> Self {
+        self.timeouts.insert(data_source.into(), timeout);
+        self
+    }
+
+    /// Treat data from `data_source` as being in `units`, where its connector doesn't report
+    /// units itself
+    ///
+    /// Units are used to convert data to the units a pipeline expects, see
+    /// [`Pipeline::units`](crate::Pipeline::units).
+    pub fn with_units(mut self, data_source: impl Into<String>, units: impl Into<String>) -> Self {
+        self.units.insert(data_source.into(), units.into());
+        self
+    }
+
+    /// Fetch data for `name` from the first of `sources` that has any
+    ///
+    /// Sources are tried in order, moving on to the next if one fails or
+    /// returns no series. The error or empty result of the last source is
+    /// returned if none of them have data. The name of the source the data
+    /// came from is recorded in [`DataCache::source`]. Each source keeps its
+    /// own timeout, retries and caching, and the sources must be registered
+    /// connectors, not other fallback chains.
+    pub fn with_fallbacks(
+        mut self,
+        name: impl Into<String>,
+        sources: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.fallbacks
+            .insert(name.into(), sources.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Retry fetches that fail with transient errors, according to `policy`
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidRetryPolicy`] if `policy` isn't [valid](RetryPolicy::is_valid)
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Result<Self, Error> {
+        if !policy.is_valid() {
+            return Err(Error::InvalidRetryPolicy(policy.multiplier));
+        }
+        self.retry = Some(policy);
+        Ok(self)
+    }
+
+    /// Cache fetched data in memory, so repeated fetches of the same data (e.g. by several
+    /// pipelines QCing the same window) don't hit the data source again
+    ///
+    /// Fetches are identified by data source, space and time spec, extra_spec, number of leading
+    /// and trailing points, and extra parameters. Cached data is refetched once it is older than
+    /// `ttl`, and the least recently used data is dropped to keep at most `capacity` fetches
+    /// cached. Failed fetches are not cached.
+    pub fn with_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.cache = Some(Arc::new(Mutex::new(FetchCache {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            clock: 0,
+        })));
+        self
+    }
+
+    /// Check the health of every connector, returning the sources that are unhealthy, and why
+    ///
+    /// Sources are checked concurrently, each subject to its timeout.
+    pub(crate) async fn check_health(&self) -> Vec<(String, Error)> {
+        let checks = self.sources.iter().map(|(name, connector)| async move {
+            let check = connector.check_health();
+            let result = match self.timeouts.get(name) {
+                Some(timeout) => tokio::time::timeout(*timeout, check)
+                    .await
+                    .unwrap_or_else(|_| Err(Error::Timeout(name.clone(), *timeout))),
+                None => check.await,
+            };
+            result.err().map(|e| (name.clone(), e))
+        });
+        futures::future::join_all(checks)
+            .await
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// The requests [`fetch_data`](DataSwitch::fetch_data) would make of connectors, without
+    /// making them
+    ///
+    /// Fallback chains give a request to each source in the chain, in the order they would be
+    /// tried, though later ones are only made if the earlier ones fail or have no data.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidDataSource`] if `data_source_id`, or a source in its fallback chain, has
+    /// no connector
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn plan_fetch(
+        &self,
+        data_source_id: &str,
+        space_spec: &SpaceSpec,
+        time_spec: &TimeSpec,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+        extra_spec: Option<&str>,
+        params: &[&str],
+    ) -> Result<Vec<ConnectorRequest>, Error> {
+        let chain = match self.fallbacks.get(data_source_id) {
+            Some(chain) => chain.clone(),
+            None => vec![data_source_id.to_string()],
+        };
+        chain
+            .into_iter()
+            .map(|source| {
+                if !self.sources.contains_key(&source) {
+                    return Err(Error::InvalidDataSource(source));
+                }
+                Ok(ConnectorRequest {
+                    data_source: source,
+                    space_spec: space_spec.clone(),
+                    timerange: time_spec.timerange,
+                    time_resolution: time_spec.time_resolution,
+                    num_leading_points,
+                    num_trailing_points,
+                    fetch_timerange: time_spec
+                        .padded_timerange(num_leading_points, num_trailing_points),
+                    extra_spec: extra_spec.map(String::from),
+                    params: params.iter().map(|param| param.to_string()).collect(),
+                })
+            })
+            .collect()
     }
 
     // TODO: handle backing sources
+    #[allow(clippy::too_many_arguments)]
     pub(crate) async fn fetch_data(
         &self,
         data_source_id: &str,
@@ -305,20 +1516,1108 @@ impl<'ds> DataSwitch<'ds> {
         num_leading_points: u8,
         num_trailing_points: u8,
         extra_spec: Option<&str>,
+        params: &[&str],
+    ) -> Result<DataCache, Error> {
+        let Some(chain) = self.fallbacks.get(data_source_id) else {
+            return self
+                .fetch_source(
+                    data_source_id,
+                    space_spec,
+                    time_spec,
+                    num_leading_points,
+                    num_trailing_points,
+                    extra_spec,
+                    params,
+                )
+                .await;
+        };
+
+        let mut result = Err(Error::InvalidDataSource(data_source_id.to_string()));
+        for (i, source) in chain.iter().enumerate() {
+            result = self
+                .fetch_source(
+                    source,
+                    space_spec,
+                    time_spec,
+                    num_leading_points,
+                    num_trailing_points,
+                    extra_spec,
+                    params,
+                )
+                .await
+                .map(|mut data| {
+                    data.source = Some(source.clone());
+                    data
+                });
+            let is_last = i + 1 == chain.len();
+            match &result {
+                Ok(data) if data.data.is_empty() && !is_last => {
+                    tracing::warn!(%source, "Fallback source had no data, trying the next.");
+                }
+                Err(e) if !is_last => {
+                    tracing::warn!(%source, %e, "Fallback source failed, trying the next.");
+                }
+                _ => break,
+            }
+        }
+        result
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn fetch_source(
+        &self,
+        data_source_id: &str,
+        space_spec: &SpaceSpec,
+        time_spec: &TimeSpec,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+        extra_spec: Option<&str>,
+        params: &[&str],
     ) -> Result<DataCache, Error> {
         let data_source = self
             .sources
             .get(data_source_id)
             .ok_or_else(|| Error::InvalidDataSource(data_source_id.to_string()))?;
 
-        data_source
-            .fetch_data(
+        let Some(cache) = &self.cache else {
+            return self
+                .fetch_with_retry(
+                    data_source_id,
+                    data_source.as_ref(),
+                    space_spec,
+                    time_spec,
+                    num_leading_points,
+                    num_trailing_points,
+                    extra_spec,
+                    params,
+                )
+                .await;
+        };
+
+        let key = FetchKey {
+            data_source: data_source_id.to_string(),
+            space: space_spec.into(),
+            timerange: time_spec.timerange,
+            time_resolution: time_spec.time_resolution,
+            num_leading_points,
+            num_trailing_points,
+            extra_spec: extra_spec.map(String::from),
+            params: params.iter().map(|param| param.to_string()).collect(),
+        };
+        // the lock is never held across anything that can panic, so poisoning can be ignored
+        if let Some(data) = cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+        {
+            return Ok(data);
+        }
+
+        let data = self
+            .fetch_with_retry(
+                data_source_id,
+                data_source.as_ref(),
                 space_spec,
                 time_spec,
                 num_leading_points,
                 num_trailing_points,
                 extra_spec,
+                params,
             )
+            .await?;
+        cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key, data.clone());
+
+        Ok(data)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn fetch_with_retry(
+        &self,
+        data_source_id: &str,
+        data_source: &(dyn DataConnector + Send + Sync),
+        space_spec: &SpaceSpec,
+        time_spec: &TimeSpec,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+        extra_spec: Option<&str>,
+        params: &[&str],
+    ) -> Result<DataCache, Error> {
+        let mut attempt = 1;
+        let mut backoff = self
+            .retry
+            .as_ref()
+            .map_or(Duration::ZERO, |retry| retry.initial_backoff);
+        let timeout = self.timeouts.get(data_source_id).copied();
+        loop {
+            let fetch = data_source.fetch_data_with_params(
+                space_spec,
+                time_spec,
+                num_leading_points,
+                num_trailing_points,
+                extra_spec,
+                params,
+            );
+            let result = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, fetch)
+                    .await
+                    .unwrap_or_else(|_| Err(Error::Timeout(data_source_id.to_string(), timeout))),
+                None => fetch.await,
+            };
+
+            match (result, &self.retry) {
+                (Err(e), Some(retry))
+                    if attempt < retry.max_attempts && data_source.is_retryable(&e) =>
+                {
+                    tracing::warn!(%e, attempt, "Fetch failed, retrying.");
+                    tokio::time::sleep(backoff).await;
+                    // a backoff too long for a Duration is well past the longest allowed
+                    backoff = Duration::try_from_secs_f64(backoff.as_secs_f64() * retry.multiplier)
+                        .map_or(retry.max_backoff, |backoff| backoff.min(retry.max_backoff));
+                    attempt += 1;
+                }
+                (result, _) => {
+                    return result.map(|mut data| {
+                        if data.units.is_none() {
+                            data.units = self.units.get(data_source_id).cloned();
+                        }
+                        data
+                    })
+                }
+            }
+        }
+    }
+}
+
+/// An observation to push into a [`MemoryConnector`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    /// Identifier of the series the observation belongs to
+    pub series_id: String,
+    /// latitude, in degrees
+    pub lat: f32,
+    /// longitude, in degrees
+    pub lon: f32,
+    /// elevation, in metres
+    pub elev: f32,
+    /// time of the observation
+    pub time: Timestamp,
+    /// observed value, if any
+    pub value: Option<f32>,
+}
+
+#[derive(Debug, Clone)]
+struct MemorySeries {
+    lat: f32,
+    lon: f32,
+    elev: f32,
+    metadata: StationMetadata,
+    // keyed by unix timestamp
+    values: BTreeMap<i64, Option<f32>>,
+}
+
+/// Ready-made [`DataConnector`] serving data the host application already
+/// holds in memory
+///
+/// Data is pushed in with [`insert_cache`](MemoryConnector::insert_cache) or
+/// [`insert_observations`](MemoryConnector::insert_observations) before calling
+/// [`Scheduler::validate_direct`](crate::Scheduler::validate_direct). Clones
+/// share the same data, so the application can keep a clone to push into while
+/// another sits in the [`DataSwitch`].
+///
+/// Requests are served at whatever time resolution they ask for, with points
+/// that were never pushed in left missing. The extra_spec is ignored, so data
+/// sets that need telling apart should go in separate connectors.
+///
+/// # Example
+///
+/// ```
+/// use rove::data_switch::{DataConnector, DataSwitch, MemoryConnector, Observation, Timestamp};
+/// use std::sync::Arc;
+///
+/// let memory = MemoryConnector::new();
+/// let data_switch = DataSwitch::new([(
+///     "memory",
+///     Arc::new(memory.clone()) as Arc<dyn DataConnector + Send + Sync>,
+/// )]);
+///
+/// memory.insert_observations([Observation {
+///     series_id: "18700".to_string(),
+///     lat: 59.94,
+///     lon: 10.72,
+///     elev: 94.,
+///     time: Timestamp(1_700_000_000),
+///     value: Some(4.2),
+/// }]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryConnector {
+    series: Arc<RwLock<HashMap<String, MemorySeries>>>,
+}
+
+impl MemoryConnector {
+    /// Create an empty connector
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn series(&self) -> RwLockWriteGuard<'_, HashMap<String, MemorySeries>> {
+        // the map is never left in an inconsistent state, so poisoning can be ignored
+        self.series.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Insert every point of every series in `cache`, overwriting any values
+    /// already held at the same times
+    ///
+    /// The location of series already held is replaced with the one in `cache`.
+    pub fn insert_cache(&self, cache: &DataCache) {
+        let mut series = self.series();
+        // timestamps in a cache should be valid, so it should be safe to unwrap
+        let start_time = Utc.timestamp_opt(cache.start_time.0, 0).unwrap();
+
+        for (i, (series_id, values)) in cache.data.iter().enumerate() {
+            let entry = series
+                .entry(series_id.clone())
+                .or_insert_with(|| MemorySeries {
+                    lat: 0.,
+                    lon: 0.,
+                    elev: 0.,
+                    metadata: StationMetadata::default(),
+                    values: BTreeMap::new(),
+                });
+            entry.lat = cache.rtree.lats[i];
+            entry.lon = cache.rtree.lons[i];
+            entry.elev = cache.rtree.elevs[i];
+            entry.metadata = cache.station_metadata(i).clone();
+            for (j, value) in values.iter().enumerate() {
+                let time = start_time + cache.period * j as i32;
+                entry.values.insert(time.timestamp(), *value);
+            }
+        }
+    }
+
+    /// Insert observations, overwriting any values already held for the same
+    /// series and time
+    ///
+    /// The location of each series is taken from its latest observation.
+    pub fn insert_observations(&self, observations: impl IntoIterator<Item = Observation>) {
+        let mut series = self.series();
+
+        for observation in observations {
+            let entry = series
+                .entry(observation.series_id)
+                .or_insert_with(|| MemorySeries {
+                    lat: observation.lat,
+                    lon: observation.lon,
+                    elev: observation.elev,
+                    metadata: StationMetadata::default(),
+                    values: BTreeMap::new(),
+                });
+            entry.lat = observation.lat;
+            entry.lon = observation.lon;
+            entry.elev = observation.elev;
+            entry.values.insert(observation.time.0, observation.value);
+        }
+    }
+
+    /// Drop all data held for a series, returning whether there was any
+    pub fn remove_series(&self, series_id: &str) -> bool {
+        self.series().remove(series_id).is_some()
+    }
+
+    /// Drop all data held
+    pub fn clear(&self) {
+        self.series().clear();
+    }
+}
+
+#[async_trait]
+impl DataConnector for MemoryConnector {
+    async fn fetch_data(
+        &self,
+        space_spec: &SpaceSpec,
+        time_spec: &TimeSpec,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+        _extra_spec: Option<&str>,
+    ) -> Result<DataCache, Error> {
+        let period = time_spec.time_resolution;
+        // timestamps should be validated before they get here, so it should be safe to unwrap
+        let start_time = Utc.timestamp_opt(time_spec.timerange.start.0, 0).unwrap()
+            - period * i32::from(num_leading_points);
+        let end_time = Utc.timestamp_opt(time_spec.timerange.end.0, 0).unwrap()
+            + period * i32::from(num_trailing_points);
+        let mut times = Vec::new();
+        let mut time = start_time;
+        while time <= end_time {
+            times.push(time.timestamp());
+            time = time + period;
+        }
+
+        let all_series = self.series.read().unwrap_or_else(PoisonError::into_inner);
+        let mut selected: Vec<(&String, &MemorySeries)> = match space_spec {
+            SpaceSpec::One(series_id) => vec![all_series
+                .get_key_value(series_id)
+                .ok_or_else(|| Error::SeriesNotFound(series_id.clone()))?],
+            SpaceSpec::Multiple(series_ids) => series_ids
+                .iter()
+                .map(|series_id| {
+                    all_series
+                        .get_key_value(series_id)
+                        .ok_or_else(|| Error::SeriesNotFound(series_id.clone()))
+                })
+                .collect::<Result<_, _>>()?,
+            SpaceSpec::Polygon(polygon) => {
+                let polygon = PolygonFilter::new(polygon.clone());
+                all_series
+                    .iter()
+                    .filter(|(_, series)| polygon.contains(series.lat, series.lon))
+                    .collect()
+            }
+            SpaceSpec::All => all_series.iter().collect(),
+        };
+        // so responses don't depend on the order of the hashmap
+        selected.sort_by_key(|(series_id, _)| *series_id);
+        selected.dedup_by_key(|(series_id, _)| *series_id);
+
+        Ok(DataCache::new(
+            selected.iter().map(|(_, series)| series.lat).collect(),
+            selected.iter().map(|(_, series)| series.lon).collect(),
+            selected.iter().map(|(_, series)| series.elev).collect(),
+            Timestamp(start_time.timestamp()),
+            period,
+            num_leading_points,
+            num_trailing_points,
+            selected
+                .iter()
+                .map(|(series_id, series)| {
+                    (
+                        series_id.to_string(),
+                        times
+                            .iter()
+                            .map(|time| series.values.get(time).copied().flatten())
+                            .collect(),
+                    )
+                })
+                .collect(),
+        )
+        .with_metadata(
+            selected
+                .iter()
+                .map(|(_, series)| series.metadata.clone())
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Default)]
+    struct CountingSource {
+        fetches: AtomicUsize,
+        // number of fetches that fail before they start succeeding
+        failures: usize,
+    }
+
+    #[async_trait]
+    impl DataConnector for CountingSource {
+        async fn fetch_data(
+            &self,
+            _space_spec: &SpaceSpec,
+            _time_spec: &TimeSpec,
+            num_leading_points: u8,
+            num_trailing_points: u8,
+            _extra_spec: Option<&str>,
+        ) -> Result<DataCache, Error> {
+            if self.fetches.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(Error::Io(std::io::Error::from(
+                    std::io::ErrorKind::ConnectionReset,
+                )));
+            }
+            Ok(DataCache::new(
+                vec![60.],
+                vec![10.],
+                vec![0.],
+                Timestamp(0),
+                RelativeDuration::hours(1),
+                num_leading_points,
+                num_trailing_points,
+                vec![("a".to_string(), vec![Some(1.)])],
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_cache() {
+        let source = Arc::new(CountingSource::default());
+        let data_switch = DataSwitch::new([(
+            "counting",
+            source.clone() as Arc<dyn DataConnector + Send + Sync>,
+        )])
+        .with_cache(1, Duration::from_secs(60));
+        let time_spec = TimeSpec::new(Timestamp(0), Timestamp(3600), RelativeDuration::hours(1));
+        let fetch = |data_id: &str, num_leading_points: u8| {
+            let data_switch = data_switch.clone();
+            let space_spec = SpaceSpec::One(data_id.to_string());
+            let time_spec = &time_spec;
+            async move {
+                data_switch
+                    .fetch_data(
+                        "counting",
+                        &space_spec,
+                        time_spec,
+                        num_leading_points,
+                        0,
+                        None,
+                        &[],
+                    )
+                    .await
+                    .unwrap()
+            }
+        };
+
+        fetch("a", 0).await;
+        let cached = fetch("a", 0).await;
+        assert_eq!(source.fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(cached.data, vec![("a".to_string(), vec![Some(1.)])]);
+
+        // different fetches aren't served from the cache, and evict the older entry
+        fetch("a", 1).await;
+        assert_eq!(source.fetches.load(Ordering::SeqCst), 2);
+        fetch("a", 0).await;
+        assert_eq!(source.fetches.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_fallbacks() {
+        let broken = Arc::new(CountingSource {
+            failures: usize::MAX,
+            ..Default::default()
+        });
+        let working = Arc::new(CountingSource::default());
+        let data_switch = DataSwitch::new([
+            (
+                "broken",
+                broken.clone() as Arc<dyn DataConnector + Send + Sync>,
+            ),
+            (
+                "empty",
+                Arc::new(MemoryConnector::new()) as Arc<dyn DataConnector + Send + Sync>,
+            ),
+            (
+                "working",
+                working.clone() as Arc<dyn DataConnector + Send + Sync>,
+            ),
+        ])
+        .with_fallbacks("obs", ["broken", "empty", "working"])
+        .with_fallbacks("no_obs", ["broken", "empty"])
+        .with_fallbacks("failing", ["empty", "broken"]);
+        let time_spec = TimeSpec::new(Timestamp(0), Timestamp(3600), RelativeDuration::hours(1));
+        let fetch = |name: &'static str| {
+            let data_switch = data_switch.clone();
+            let time_spec = &time_spec;
+            async move {
+                data_switch
+                    .fetch_data(name, &SpaceSpec::All, time_spec, 0, 0, None, &[])
+                    .await
+            }
+        };
+
+        let data = fetch("obs").await.unwrap();
+        assert_eq!(data.source.as_deref(), Some("working"));
+        assert_eq!(data.data, vec![("a".to_string(), vec![Some(1.)])]);
+        assert_eq!(broken.fetches.load(Ordering::SeqCst), 1);
+
+        let data = fetch("no_obs").await.unwrap();
+        assert_eq!(data.source.as_deref(), Some("empty"));
+        assert!(data.data.is_empty());
+
+        assert!(matches!(fetch("failing").await, Err(Error::Io(_))));
+
+        // plain sources don't record where their data came from
+        assert_eq!(fetch("working").await.unwrap().source, None);
+
+        // plans list the whole chain, as it isn't known which sources will have data
+        let plan = data_switch
+            .plan_fetch("obs", &SpaceSpec::All, &time_spec, 1, 2, None, &[])
+            .unwrap();
+        assert_eq!(
+            plan.iter()
+                .map(|request| request.data_source.as_str())
+                .collect::<Vec<_>>(),
+            vec!["broken", "empty", "working"]
+        );
+        assert_eq!(
+            plan[0].fetch_timerange,
+            Timerange {
+                start: Timestamp(-3600),
+                end: Timestamp(3 * 3600),
+            }
+        );
+        assert!(matches!(
+            data_switch.plan_fetch("missing", &SpaceSpec::All, &time_spec, 0, 0, None, &[]),
+            Err(Error::InvalidDataSource(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            multiplier: 2.,
+        };
+        let time_spec = TimeSpec::new(Timestamp(0), Timestamp(3600), RelativeDuration::hours(1));
+        let fetch = |failures: usize| {
+            let source = Arc::new(CountingSource {
+                failures,
+                ..Default::default()
+            });
+            let data_switch = DataSwitch::new([(
+                "counting",
+                source.clone() as Arc<dyn DataConnector + Send + Sync>,
+            )])
+            .with_retry(policy.clone())
+            .unwrap();
+            let time_spec = &time_spec;
+            async move {
+                let result = data_switch
+                    .fetch_data("counting", &SpaceSpec::All, time_spec, 0, 0, None, &[])
+                    .await;
+                (result, source.fetches.load(Ordering::SeqCst))
+            }
+        };
+
+        let (result, fetches) = fetch(2).await;
+        assert!(result.is_ok());
+        assert_eq!(fetches, 3);
+
+        let (result, fetches) = fetch(3).await;
+        assert!(matches!(result, Err(Error::Io(_))));
+        assert_eq!(fetches, 3);
+
+        for multiplier in [0.5, -1., f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                DataSwitch::new(HashMap::<String, _>::new()).with_retry(RetryPolicy {
+                    multiplier,
+                    ..policy.clone()
+                }),
+                Err(Error::InvalidRetryPolicy(_))
+            ));
+        }
+        // backoffs too long for a Duration are capped rather than panicking
+        let data_switch = DataSwitch::new([(
+            "counting",
+            Arc::new(CountingSource {
+                failures: 2,
+                ..Default::default()
+            }) as Arc<dyn DataConnector + Send + Sync>,
+        )])
+        .with_retry(RetryPolicy {
+            multiplier: f64::MAX,
+            ..policy
+        })
+        .unwrap();
+        assert!(data_switch
+            .fetch_data("counting", &SpaceSpec::All, &time_spec, 0, 0, None, &[])
             .await
+            .is_ok());
+    }
+
+    #[derive(Debug)]
+    struct HungSource;
+
+    #[async_trait]
+    impl DataConnector for HungSource {
+        async fn fetch_data(
+            &self,
+            _space_spec: &SpaceSpec,
+            _time_spec: &TimeSpec,
+            _num_leading_points: u8,
+            _num_trailing_points: u8,
+            _extra_spec: Option<&str>,
+        ) -> Result<DataCache, Error> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let data_switch = DataSwitch::new([(
+            "hung",
+            Arc::new(HungSource) as Arc<dyn DataConnector + Send + Sync>,
+        )])
+        .with_timeout("hung", Duration::from_millis(10));
+
+        let result = data_switch
+            .fetch_data(
+                "hung",
+                &SpaceSpec::All,
+                &TimeSpec::new(Timestamp(0), Timestamp(3600), RelativeDuration::hours(1)),
+                0,
+                0,
+                None,
+                &[],
+            )
+            .await;
+
+        assert!(matches!(result, Err(Error::Timeout(source, _)) if source == "hung"));
+    }
+
+    #[tokio::test]
+    async fn test_memory_connector() {
+        let memory = MemoryConnector::new();
+        memory.insert_cache(
+            &DataCache::new(
+                vec![60., 70.],
+                vec![10., 20.],
+                vec![0., 0.],
+                Timestamp(0),
+                RelativeDuration::hours(1),
+                0,
+                0,
+                vec![
+                    ("a".to_string(), vec![Some(1.), Some(2.)]),
+                    ("b".to_string(), vec![Some(3.), None]),
+                ],
+            )
+            .with_providers(vec![Some(1), None]),
+        );
+        memory.insert_observations([Observation {
+            series_id: "a".to_string(),
+            lat: 60.,
+            lon: 10.,
+            elev: 5.,
+            time: Timestamp(7200),
+            value: Some(4.),
+        }]);
+        let fetch = |space_spec| {
+            let memory = memory.clone();
+            async move {
+                memory
+                    .fetch_data(
+                        &space_spec,
+                        &TimeSpec::new(
+                            Timestamp(3600),
+                            Timestamp(10800),
+                            RelativeDuration::hours(1),
+                        ),
+                        1,
+                        0,
+                        None,
+                    )
+                    .await
+            }
+        };
+
+        let cache = fetch(SpaceSpec::All).await.unwrap();
+        assert_eq!(
+            cache.data,
+            vec![
+                ("a".to_string(), vec![Some(1.), Some(2.), Some(4.), None]),
+                ("b".to_string(), vec![Some(3.), None, None, None]),
+            ]
+        );
+        assert_eq!(cache.rtree.elevs, vec![5., 0.]);
+        assert_eq!(cache.station_metadata(0).provider, Some(1));
+        assert!(cache.station_metadata(1).is_empty());
+
+        let cache = fetch(SpaceSpec::Polygon(vec![
+            GeoPoint { lat: 65., lon: 15. },
+            GeoPoint { lat: 75., lon: 15. },
+            GeoPoint { lat: 75., lon: 25. },
+            GeoPoint { lat: 65., lon: 25. },
+        ]))
+        .await
+        .unwrap();
+        assert_eq!(cache.data[0].0, "b");
+        assert_eq!(cache.data.len(), 1);
+
+        let cache = fetch(SpaceSpec::Multiple(vec![
+            "b".to_string(),
+            "a".to_string(),
+            "b".to_string(),
+        ]))
+        .await
+        .unwrap();
+        assert_eq!(
+            cache
+                .data
+                .iter()
+                .map(|ts| ts.0.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+
+        assert!(memory.remove_series("b"));
+        assert!(matches!(
+            fetch(SpaceSpec::One("b".to_string())).await,
+            Err(Error::SeriesNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_convert_units() {
+        let cache = |units: &str, values: Vec<Option<f32>>| {
+            DataCache::new(
+                vec![60.],
+                vec![10.],
+                vec![0.],
+                Timestamp(0),
+                RelativeDuration::hours(1),
+                0,
+                0,
+                vec![("a".to_string(), values)],
+            )
+            .with_units(units)
+        };
+
+        let mut data = cache("K", vec![Some(273.15), None]);
+        data.add_param("relative_humidity", cache("percent", vec![Some(50.), None]))
+            .unwrap();
+        data.add_param("dew_point", cache("0.1degC", vec![Some(-15.), None]))
+            .unwrap();
+        data.add_backing("other", cache("degF", vec![Some(32.), Some(212.)]))
+            .unwrap();
+        assert!((data.data[1].1[1].unwrap() - 373.15).abs() < 1e-3);
+
+        data.convert_units("degC").unwrap();
+        assert_eq!(data.units.as_deref(), Some("degC"));
+        assert!(data.data[0].1[0].unwrap().abs() < 1e-4);
+        assert!((data.data[1].1[1].unwrap() - 100.).abs() < 1e-3);
+        assert_eq!(data.params["dew_point"][0], vec![Some(-1.5), None]);
+        assert_eq!(data.param_units["dew_point"], "degC");
+        assert_eq!(data.params["relative_humidity"][0], vec![Some(50.), None]);
+
+        assert!(matches!(
+            data.convert_units("mm"),
+            Err(units::Error::Incompatible(..))
+        ));
+        assert!(matches!(
+            data.add_backing("rain", cache("mm", vec![Some(1.), None])),
+            Err(Error::BackingUnits(..))
+        ));
+    }
+
+    #[test]
+    fn test_windows() {
+        let series = |offset: f32| (0..6).map(|i| Some(i as f32 + offset)).collect();
+        let mut data = DataCache::new(
+            vec![60.],
+            vec![10.],
+            vec![0.],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            1,
+            1,
+            vec![("a".to_string(), series(0.))],
+        );
+        data.params.insert("b".to_string(), vec![series(10.)]);
+
+        // 4 QCed points, with one point of context on either side
+        let windows = data.windows(3);
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[0].start_time, Timestamp(0));
+        assert_eq!(
+            windows[0].data[0].1,
+            vec![Some(0.), Some(1.), Some(2.), Some(3.), Some(4.)]
+        );
+        assert_eq!(windows[1].start_time, Timestamp(3 * 3600));
+        assert_eq!(windows[1].data[0].1, vec![Some(3.), Some(4.), Some(5.)]);
+        assert_eq!(
+            windows[1].params["b"][0],
+            vec![Some(13.), Some(14.), Some(15.)]
+        );
+        assert_eq!(windows[1].num_qc_points(), 1);
+    }
+
+    #[test]
+    fn test_time_spec_chunks() {
+        let time_spec = TimeSpec::new(
+            Timestamp(0),
+            Timestamp(9 * 3600),
+            RelativeDuration::hours(1),
+        );
+
+        let chunks = time_spec.chunks(4);
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|chunk| chunk.timerange)
+                .collect::<Vec<_>>(),
+            vec![
+                Timerange {
+                    start: Timestamp(0),
+                    end: Timestamp(3 * 3600)
+                },
+                Timerange {
+                    start: Timestamp(4 * 3600),
+                    end: Timestamp(7 * 3600)
+                },
+                Timerange {
+                    start: Timestamp(8 * 3600),
+                    end: Timestamp(9 * 3600)
+                },
+            ]
+        );
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.time_resolution == RelativeDuration::hours(1)));
+
+        assert_eq!(time_spec.chunks(10).len(), 1);
+        assert_eq!(time_spec.chunks(1).len(), 10);
+        assert_eq!(time_spec.num_points(), 10);
+        assert_eq!(chunks[2].num_points(), 2);
+
+        // a time resolution that doesn't move forward can't be split
+        for time_resolution in [RelativeDuration::zero(), RelativeDuration::hours(-1)] {
+            let time_spec = TimeSpec::new(Timestamp(0), Timestamp(9 * 3600), time_resolution);
+            assert!(!time_spec.has_positive_resolution());
+            assert_eq!(time_spec.chunks(4).len(), 1);
+            assert_eq!(time_spec.num_points(), 0);
+        }
+    }
+
+    #[test]
+    fn test_time_spec_runs() {
+        let hours = |hours: &[i64]| {
+            hours
+                .iter()
+                .map(|hour| Timestamp(hour * 3600))
+                .collect::<Vec<_>>()
+        };
+
+        let mut time_spec =
+            TimeSpec::from_times(hours(&[12, 0, 6, 7, 18, 6]), RelativeDuration::hours(1));
+        assert_eq!(time_spec.timerange.start, Timestamp(0));
+        assert_eq!(time_spec.timerange.end, Timestamp(18 * 3600));
+        assert_eq!(
+            time_spec
+                .runs()
+                .iter()
+                .map(|run| (run.timerange.start, run.timerange.end))
+                .collect::<Vec<_>>(),
+            vec![
+                (Timestamp(0), Timestamp(0)),
+                (Timestamp(6 * 3600), Timestamp(7 * 3600)),
+                (Timestamp(12 * 3600), Timestamp(12 * 3600)),
+                (Timestamp(18 * 3600), Timestamp(18 * 3600)),
+            ]
+        );
+        assert!(time_spec.runs().iter().all(|run| run.times.is_none()));
+
+        time_spec.timerange.end = Timestamp(10 * 3600);
+        assert_eq!(time_spec.runs().len(), 2);
+        time_spec.times = Some(Vec::new());
+        assert!(time_spec.runs().is_empty());
+
+        assert_eq!(
+            TimeSpec::new(Timestamp(0), Timestamp(3600), RelativeDuration::hours(1))
+                .runs()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_resample() {
+        let minutes = |observations: &[(i64, Option<f32>)]| {
+            observations
+                .iter()
+                .map(|(minute, value)| (Timestamp(minute * 60), *value))
+                .collect::<Vec<_>>()
+        };
+        let hourly = |observations, aggregation| {
+            resample(
+                observations,
+                Some(RelativeDuration::minutes(10)),
+                Timestamp(3600),
+                RelativeDuration::hours(1),
+                3,
+                aggregation,
+            )
+        };
+
+        // ten minute observations, with one missing value, and none in the last hour
+        let ten_minute = minutes(&[
+            (10, Some(1.)),
+            (20, Some(2.)),
+            (30, None),
+            (40, Some(3.)),
+            (50, Some(4.)),
+            (60, Some(5.)),
+            (70, Some(1.)),
+            (120, Some(2.)),
+        ]);
+        assert_eq!(
+            hourly(&ten_minute, Aggregation::Mean),
+            vec![Some(3.), Some(1.5), None]
+        );
+        // the second hour has only two of its six values, so its sum would be too low
+        assert_eq!(
+            hourly(&ten_minute, Aggregation::Sum),
+            vec![None, None, None]
+        );
+        let complete = minutes(&[
+            (70, Some(1.)),
+            (80, Some(2.)),
+            (90, Some(0.)),
+            (100, Some(3.)),
+            (110, Some(1.)),
+            (120, Some(2.)),
+        ]);
+        assert_eq!(
+            hourly(&complete, Aggregation::Sum),
+            vec![None, Some(9.), None]
+        );
+        // without a source resolution, it can't be told whether a sum is complete
+        assert_eq!(
+            resample(
+                &complete,
+                None,
+                Timestamp(3600),
+                RelativeDuration::hours(1),
+                3,
+                Aggregation::Sum,
+            ),
+            vec![None, None, None]
+        );
+
+        // instantaneous values reported off the hour
+        let off_hour = minutes(&[(58, Some(1.)), (85, Some(2.)), (181, Some(3.))]);
+        assert_eq!(
+            hourly(&off_hour, Aggregation::Nearest),
+            vec![Some(1.), None, Some(3.)]
+        );
+
+        // upsampling
+        assert_eq!(
+            resample(
+                &minutes(&[(60, Some(1.)), (120, Some(2.))]),
+                None,
+                Timestamp(3600),
+                RelativeDuration::minutes(30),
+                3,
+                Aggregation::Nearest,
+            ),
+            vec![Some(1.), None, Some(2.)]
+        );
+
+        assert_eq!("sum".parse(), Ok(Aggregation::Sum));
+        assert!("median".parse::<Aggregation>().is_err());
+    }
+
+    #[test]
+    fn test_polygon_filter() {
+        let square = vec![
+            GeoPoint { lat: 60., lon: 10. },
+            GeoPoint { lat: 61., lon: 10. },
+            GeoPoint { lat: 61., lon: 11. },
+            GeoPoint { lat: 60., lon: 11. },
+        ];
+        let filter = PolygonFilter::new(square.clone());
+        assert!(filter.contains(60.5, 10.5));
+        assert!(!filter.contains(59.5, 10.5));
+        assert!(!filter.contains(60.5, 11.5));
+        // boundary points, including vertices
+        assert!(filter.contains(60., 10.5));
+        assert!(filter.contains(60.5, 11.));
+        assert!(filter.contains(61., 11.));
+        assert_eq!(
+            filter.bounds(),
+            Some((
+                GeoPoint { lat: 60., lon: 10. },
+                GeoPoint { lat: 61., lon: 11. }
+            ))
+        );
+
+        // closing the polygon doesn't change anything
+        let mut closed = square.clone();
+        closed.push(square[0]);
+        assert_eq!(PolygonFilter::new(closed), filter);
+
+        // concave, so its bounding box contains points it doesn't
+        let notched = PolygonFilter::new(vec![
+            GeoPoint { lat: 60., lon: 10. },
+            GeoPoint { lat: 62., lon: 10. },
+            GeoPoint { lat: 61., lon: 11. },
+            GeoPoint { lat: 62., lon: 12. },
+            GeoPoint { lat: 60., lon: 12. },
+        ]);
+        assert!(notched.contains(60.5, 11.));
+        assert!(!notched.contains(61.8, 11.));
+
+        assert!(!PolygonFilter::new(square[..2].to_vec()).contains(60., 10.));
+        assert!(!PolygonFilter::new(Vec::new()).contains(60., 10.));
+        assert_eq!(PolygonFilter::new(Vec::new()).bounds(), None);
+    }
+
+    #[test]
+    fn test_tiles() {
+        // a triangle, so some tiles only partly overlap it
+        let space_spec = SpaceSpec::Polygon(vec![
+            GeoPoint { lat: 58., lon: 5. },
+            GeoPoint { lat: 62., lon: 5. },
+            GeoPoint { lat: 58., lon: 11. },
+        ]);
+        let tiles = space_spec
+            .tiles(Tiling {
+                tile_size: 2.,
+                margin: 0.5,
+            })
+            .unwrap();
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(
+            tiles[0].fetch,
+            vec![
+                GeoPoint {
+                    lat: 57.5,
+                    lon: 4.5
+                },
+                GeoPoint {
+                    lat: 60.5,
+                    lon: 4.5
+                },
+                GeoPoint {
+                    lat: 60.5,
+                    lon: 7.5
+                },
+                GeoPoint {
+                    lat: 57.5,
+                    lon: 7.5
+                },
+            ]
+        );
+
+        // points inside the polygon belong to exactly one tile, including those on tile borders
+        for (lat, lon) in [(58.5, 5.5), (60., 7.), (59., 9.), (61., 5.2)] {
+            assert_eq!(tiles.iter().filter(|tile| tile.owns(lat, lon)).count(), 1);
+        }
+        assert!(tiles[0].owns(59., 6.));
+        assert!(tiles[4].owns(60., 7.));
+        // in the margin of the first tile, but owned by the one east of it
+        assert!(!tiles[0].owns(59., 7.2));
+        // inside a tile's square, but outside the polygon
+        assert!(!tiles.iter().any(|tile| tile.owns(61.5, 9.5)));
+
+        assert!(SpaceSpec::All
+            .tiles(Tiling {
+                tile_size: 2.,
+                margin: 0.5,
+            })
+            .is_none());
+
+        // a polygon can't be covered by tiles with no size
+        for (tile_size, margin) in [(0., 0.5), (-2., 0.5), (f32::NAN, 0.5), (2., -0.5)] {
+            let tiling = Tiling { tile_size, margin };
+            assert!(!tiling.is_valid());
+            assert!(space_spec.tiles(tiling).is_none());
+        }
     }
 }
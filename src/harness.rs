@@ -1,16 +1,40 @@
 use crate::{
     data_switch::DataCache,
     pb::{Flag, TestResult, ValidateResponse},
-    pipeline::{CheckConf, PipelineStep},
+    pipeline::{
+        BuddyEventCheckConf, CheckConf, ClimatologyCheckConf, CombiConf, FlagName, FlagOverride,
+        PipelineStep, SctConf, SctResistantConf, SeasonalBand,
+    },
 };
 use chrono::prelude::*;
 use chronoutil::DateRule;
+use olympian::SpatialTree;
+use rayon::prelude::*;
+use rstar::{primitives::GeomWithData, RTree};
+use std::collections::{HashMap, HashSet};
 use thiserror::Error;
 
 pub const SPIKE_LEADING_PER_RUN: u8 = 1;
 pub const SPIKE_TRAILING_PER_RUN: u8 = 1;
 pub const STEP_LEADING_PER_RUN: u8 = 1;
 pub const STEP_TRAILING_PER_RUN: u8 = 0;
+pub const RANGE_DYNAMIC_MIN_SPEC: &str = "min";
+pub const RANGE_DYNAMIC_MAX_SPEC: &str = "max";
+/// Name given to the response holding a pipeline's combined flags
+pub const COMBI_TEST_NAME: &str = "combi";
+/// Number of gridpoints used when interpolating gridded data to a station
+const INTERPOLATION_NEIGHBOURS: usize = 4;
+const RADIUS_EARTH: f32 = 6371.;
+/// Solar irradiance at the top of the atmosphere, in W/m²
+const SOLAR_CONSTANT: f32 = 1361.;
+
+/// Data fetched from sources other than the one being QCed, for checks that compare against
+/// reference data. Keyed by data source name and the extra_spec it was fetched with
+pub type BackingData = HashMap<(String, Option<String>), DataCache>;
+
+/// A flag, along with an extra value for the same point, such as a score quantifying how far the
+/// value was from what the check expected, or a proposed correction
+type FlagWithExtra = (Flag, Option<f32>);
 
 #[derive(Error, Debug, Clone)]
 #[non_exhaustive]
@@ -21,150 +45,1645 @@ pub enum Error {
     FailedTest(#[from] olympian::Error),
     #[error("unknown olympian flag: {0}")]
     UnknownFlag(String),
+    #[error("parameter {0} was not fetched")]
+    MissingParam(String),
+    #[error("results of step {0}, needed to decide what to QC, are missing")]
+    MissingConditionResults(String),
+    #[error("backing data from source {0} was not fetched")]
+    MissingBackingData(String),
+    #[error("backing data from source {0} is not aligned with the data being QCed")]
+    MisalignedBackingData(String),
+    #[error("remote runner at {0} failed: {1}")]
+    Remote(String, String),
+    #[error("step gave invalid results: {0}")]
+    InvalidResults(String),
+    #[error(
+        "step {0} needs {1} leading and {2} trailing points, but the data was fetched with {3} and {4}"
+    )]
+    InsufficientContext(String, u8, u8, u8, u8),
+}
+
+/// Flags a window as failing if every value in it is identical
+///
+/// The window is expected to be `max + 1` points long, so a failure means the series has stayed
+/// flat for more than `max` consecutive steps
+fn flatline_check(window: &[Option<f32>]) -> Flag {
+    if window.contains(&None) {
+        return Flag::DataMissing;
+    }
+
+    if window.windows(2).all(|pair| pair[0] == pair[1]) {
+        Flag::Fail
+    } else {
+        Flag::Pass
+    }
+}
+
+/// Flags the last point in a window as missing if it is `None`, otherwise flags it as failing if
+/// the fraction of the window that is missing exceeds `max_missing_fraction`
+fn completeness_check(window: &[Option<f32>], max_missing_fraction: Option<f32>) -> Flag {
+    if window.last().copied().flatten().is_none() {
+        return Flag::DataMissing;
+    }
+
+    let missing_fraction =
+        window.iter().filter(|value| value.is_none()).count() as f32 / window.len() as f32;
+    match max_missing_fraction {
+        Some(max_missing_fraction) if missing_fraction > max_missing_fraction => Flag::Fail,
+        _ => Flag::Pass,
+    }
+}
+
+/// Flags the centre of a window as failing if it is more than `k` median absolute deviations
+/// (but at least `k * min_mad`) from the median of the rest of the window
+///
+/// Also returns the centre's deviation from the median in units of MAD, where it could be computed
+fn mad_check(window: &[Option<f32>], k: f32, min_mad: f32) -> FlagWithExtra {
+    let centre = window.len() / 2;
+    let Some(value) = window[centre] else {
+        return (Flag::DataMissing, None);
+    };
+
+    let mut others: Vec<f32> = window
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != centre)
+        .filter_map(|(_, value)| *value)
+        .collect();
+    // a median and MAD from fewer points than this aren't meaningful
+    if others.len() < 3 {
+        return (Flag::Inconclusive, None);
+    }
+
+    let Some(window_median) = median(&mut others) else {
+        return (Flag::Inconclusive, None);
+    };
+    let mad = median(
+        &mut others
+            .iter()
+            .map(|other| (other - window_median).abs())
+            .collect::<Vec<f32>>(),
+    )
+    .unwrap_or(0.)
+    .max(min_mad);
+
+    let score = (value - window_median) / mad;
+    let flag = if score.abs() > k {
+        Flag::Fail
+    } else {
+        Flag::Pass
+    };
+    (flag, Some(score))
+}
+
+/// Flags a window as failing if its standard deviation is below `min_std`
+fn persistence_check(window: &[Option<f32>], min_std: f32) -> Flag {
+    if window.contains(&None) {
+        return Flag::DataMissing;
+    }
+
+    let n = window.len() as f32;
+    let mean = window.iter().flatten().sum::<f32>() / n;
+    let variance = window
+        .iter()
+        .flatten()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f32>()
+        / n;
+
+    if variance.sqrt() < min_std {
+        Flag::Fail
+    } else {
+        Flag::Pass
+    }
+}
+
+/// Cosine of the solar zenith angle at a location and time
+///
+/// Uses the NOAA approximations for solar declination and the equation of time
+fn cos_solar_zenith(lat: f32, lon: f32, time: DateTime<Utc>) -> f32 {
+    let hour = time.hour() as f32 + time.minute() as f32 / 60. + time.second() as f32 / 3600.;
+    // fractional year, in radians
+    let gamma = 2. * std::f32::consts::PI / 365. * (time.ordinal0() as f32 + (hour - 12.) / 24.);
+
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2. * gamma).cos()
+        + 0.000907 * (2. * gamma).sin()
+        - 0.002697 * (3. * gamma).cos()
+        + 0.00148 * (3. * gamma).sin();
+    // in minutes
+    let equation_of_time = 229.18
+        * (0.000075 + 0.001868 * gamma.cos()
+            - 0.032077 * gamma.sin()
+            - 0.014615 * (2. * gamma).cos()
+            - 0.040849 * (2. * gamma).sin());
+
+    let true_solar_time = hour * 60. + equation_of_time + 4. * lon;
+    let hour_angle = (true_solar_time / 4. - 180.).to_radians();
+
+    let lat = lat.to_radians();
+    lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos()
+}
+
+/// Flags global radiation that is too high for the position of the sun
+///
+/// At night, values above `night_max` fail. During the day, values above the BSRN physically
+/// possible limit fail
+fn radiation_check(value: Option<f32>, cos_zenith: f32, night_max: f32) -> Flag {
+    match value {
+        None => Flag::DataMissing,
+        Some(value) if cos_zenith <= 0. => {
+            if value > night_max {
+                Flag::Fail
+            } else {
+                Flag::Pass
+            }
+        }
+        Some(value) => {
+            if value > SOLAR_CONSTANT * 1.5 * cos_zenith.powf(1.2) + 100. {
+                Flag::Fail
+            } else {
+                Flag::Pass
+            }
+        }
+    }
+}
+
+/// Flags a value as invalid if it matches any of the special values, proposing `substitute` as
+/// its replacement
+///
+/// Special values are sentinels like -999 or 6999 that data sources use to encode missing or
+/// broken data. Since they often pass through float conversions before reaching us, they are
+/// matched with a tolerance relative to their magnitude rather than exactly
+fn special_value_check(
+    value: Option<f32>,
+    special_values: &[f32],
+    substitute: Option<f32>,
+) -> FlagWithExtra {
+    match value {
+        None => (Flag::DataMissing, None),
+        Some(value)
+            if special_values
+                .iter()
+                .any(|special| (value - special).abs() <= f32::EPSILON * special.abs().max(1.)) =>
+        {
+            (Flag::Invalid, substitute)
+        }
+        Some(_) => (Flag::Pass, None),
+    }
+}
+
+/// Flags a value as failing if it falls outside the inclusive range `[min, max]`
+fn range_check(value: Option<f32>, min: f32, max: f32) -> Flag {
+    match value {
+        None => Flag::DataMissing,
+        Some(value) if value < min || value > max => Flag::Fail,
+        Some(_) => Flag::Pass,
+    }
+}
+
+/// Finds the limits of the first band covering `time`
+fn seasonal_limits(bands: &[SeasonalBand], time: &DateTime<Utc>) -> Option<(f32, f32)> {
+    bands.iter().find_map(|band| match band {
+        SeasonalBand::Months { months, min, max } => {
+            months.contains(&time.month()).then_some((*min, *max))
+        }
+        SeasonalBand::DayOfYear {
+            start_day,
+            end_day,
+            min,
+            max,
+        } => {
+            let day = time.ordinal();
+            let covered = if start_day <= end_day {
+                (*start_day..=*end_day).contains(&day)
+            } else {
+                day >= *start_day || day <= *end_day
+            };
+            covered.then_some((*min, *max))
+        }
+    })
+}
+
+/// Splits per-point results of a flag and some extra value, such as a score or a corrected
+/// value, into flags, storing the extra values in `extras`
+fn split_flags(
+    results: Vec<(String, Vec<FlagWithExtra>)>,
+    extras: &mut Option<Vec<Vec<Option<f32>>>>,
+) -> Vec<(String, Vec<Flag>)> {
+    let (flags, series_extras) = results
+        .into_iter()
+        .map(|(identifier, series)| {
+            let (flags, extras) = series.into_iter().unzip();
+            ((identifier, flags), extras)
+        })
+        .unzip();
+    *extras = Some(series_extras);
+    flags
+}
+
+/// Whether any value in the cache, including its extra parameters, is NaN or infinite
+fn has_non_finite(cache: &DataCache) -> bool {
+    cache
+        .data
+        .iter()
+        .map(|ts| &ts.1)
+        .chain(cache.params.values().flatten())
+        .flatten()
+        .flatten()
+        .any(|value| !value.is_finite())
+}
+
+/// Copies the cache with NaN and infinite values replaced by gaps
+fn sanitize(cache: &DataCache) -> DataCache {
+    let mut sanitized = cache.clone();
+    for series in sanitized
+        .data
+        .iter_mut()
+        .map(|ts| &mut ts.1)
+        .chain(sanitized.params.values_mut().flatten())
+    {
+        for value in series.iter_mut() {
+            if value.is_some_and(|value| !value.is_finite()) {
+                *value = None;
+            }
+        }
+    }
+    sanitized
+}
+
+/// Runs a spatial check on each QCed timestep concurrently, collecting the per-station results
+/// back into series
+///
+/// `check` is called with the values of all stations at one timestep
+fn per_timestep<T, F>(cache: &DataCache, check: F) -> Result<Vec<(String, Vec<T>)>, Error>
+where
+    T: Send,
+    F: Fn(&[Option<f32>]) -> Result<Vec<T>, Error> + Sync,
+{
+    let series_len = cache.data[0].1.len();
+
+    let timestep_results = ((cache.num_leading_points as usize)
+        ..(series_len - cache.num_trailing_points as usize))
+        .into_par_iter()
+        .map(|i| {
+            let inner: Vec<Option<f32>> = cache.data.iter().map(|v| v.1[i]).collect();
+            check(&inner)
+        })
+        .collect::<Result<Vec<Vec<T>>, Error>>()?;
+
+    let mut result_vec: Vec<(String, Vec<T>)> = cache
+        .data
+        .iter()
+        .map(|ts| (ts.0.clone(), Vec::with_capacity(timestep_results.len())))
+        .collect();
+    for timestep_result in timestep_results {
+        for (i, result) in timestep_result.into_iter().enumerate() {
+            result_vec[i].1.push(result);
+        }
+    }
+    Ok(result_vec)
+}
+
+/// Runs an olympian spatial check for one timestep on only the stations with data, flagging the
+/// rest as missing
+///
+/// `check` is called with a tree of the stations with data, their values, and their indices into
+/// the cache's stations, so per-station parameters can be matched up
+fn spatial_check_present<F>(
+    cache: &DataCache,
+    values: &[Option<f32>],
+    check: F,
+) -> Result<Vec<Flag>, Error>
+where
+    F: FnOnce(&SpatialTree, &[f32], &[usize]) -> Result<Vec<olympian::Flag>, olympian::Error>,
+{
+    let present: Vec<usize> = (0..values.len()).filter(|i| values[*i].is_some()).collect();
+    let mut flags = vec![Flag::DataMissing; values.len()];
+    if present.is_empty() {
+        return Ok(flags);
+    }
+
+    let present_values: Vec<f32> = values.iter().flatten().copied().collect();
+    let spatial_result = if present.len() == values.len() {
+        check(&cache.rtree, &present_values, &present)?
+    } else {
+        let tree = SpatialTree::from_latlons(
+            select_present(&cache.rtree.lats, &present),
+            select_present(&cache.rtree.lons, &present),
+            select_present(&cache.rtree.elevs, &present),
+        );
+        check(&tree, &present_values, &present)?
+    };
+
+    for (i, flag) in present.into_iter().zip(spatial_result) {
+        flags[i] = flag.try_into().map_err(Error::UnknownFlag)?;
+    }
+    Ok(flags)
+}
+
+/// Resolves SCT's pos, neg and eps2 for each station in the cache, from the class it belongs to if
+/// any, otherwise from the defaults
+fn sct_station_params(cache: &DataCache, conf: &SctConf) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+    let mut pos = Vec::with_capacity(cache.data.len());
+    let mut neg = Vec::with_capacity(cache.data.len());
+    let mut eps2 = Vec::with_capacity(cache.data.len());
+    for ts in cache.data.iter() {
+        match conf
+            .station_classes
+            .iter()
+            .find(|class| ts.0.starts_with(&class.identifier_prefix))
+        {
+            Some(class) => {
+                pos.push(class.pos);
+                neg.push(class.neg);
+                eps2.push(class.eps2);
+            }
+            None => {
+                pos.push(conf.pos[0]);
+                neg.push(conf.neg[0]);
+                eps2.push(conf.eps2[0]);
+            }
+        }
+    }
+    (pos, neg, eps2)
+}
+
+/// Selects the elements of a per-station vector belonging to the `present` stations
+///
+/// Vectors with a single element apply to all stations, so are returned unchanged
+fn select_present<T: Copy>(values: &[T], present: &[usize]) -> Vec<T> {
+    if values.len() == 1 {
+        values.to_vec()
+    } else {
+        present.iter().map(|i| values[*i]).collect()
+    }
+}
+
+/// Looks up a cache in the backing data
+fn lookup_backing_cache<'a>(
+    backing_data: &'a BackingData,
+    source: &str,
+    extra_spec: Option<&str>,
+) -> Result<&'a DataCache, Error> {
+    backing_data
+        .get(&(source.to_string(), extra_spec.map(String::from)))
+        .ok_or_else(|| Error::MissingBackingData(source.to_string()))
+}
+
+/// Looks up a cache in the backing data, making sure its series line up in time with the data
+/// being QCed
+fn get_backing_cache<'a>(
+    backing_data: &'a BackingData,
+    cache: &DataCache,
+    source: &str,
+    extra_spec: Option<&str>,
+) -> Result<&'a DataCache, Error> {
+    let backing_cache = lookup_backing_cache(backing_data, source, extra_spec)?;
+
+    if backing_cache.start_time != cache.start_time
+        || backing_cache.period != cache.period
+        || backing_cache.num_leading_points != cache.num_leading_points
+        || backing_cache
+            .data
+            .iter()
+            .any(|ts| ts.1.len() != cache.data[0].1.len())
+    {
+        return Err(Error::MisalignedBackingData(source.to_string()));
+    }
+
+    Ok(backing_cache)
+}
+
+/// Looks up an extra parameter in a cache
+fn get_param<'a>(cache: &'a DataCache, name: &str) -> Result<&'a [Vec<Option<f32>>], Error> {
+    cache
+        .params
+        .get(name)
+        .map(Vec::as_slice)
+        .ok_or_else(|| Error::MissingParam(name.to_string()))
+}
+
+/// Indexes the series in a cache by their identifiers
+fn index_series(cache: &DataCache) -> HashMap<&str, &[Option<f32>]> {
+    cache
+        .data
+        .iter()
+        .map(|ts| (ts.0.as_str(), ts.1.as_slice()))
+        .collect()
+}
+
+fn to_cartesian(lat: f32, lon: f32) -> [f32; 3] {
+    [
+        lat.to_radians().cos() * lon.to_radians().cos() * RADIUS_EARTH,
+        lat.to_radians().cos() * lon.to_radians().sin() * RADIUS_EARTH,
+        lat.to_radians().sin() * RADIUS_EARTH,
+    ]
+}
+
+/// Builds an R*-tree of the locations of the series in a cache, in cartesian coordinates in km,
+/// tagged with the series' indices
+fn build_tree(cache: &DataCache) -> RTree<GeomWithData<[f32; 3], usize>> {
+    RTree::bulk_load(
+        cache
+            .rtree
+            .lats
+            .iter()
+            .zip(cache.rtree.lons.iter())
+            .enumerate()
+            .map(|(i, (lat, lon))| GeomWithData::new(to_cartesian(*lat, *lon), i))
+            .collect(),
+    )
+}
+
+/// Finds the indices of the neighbours of each station in a cache, within `radius` metres and
+/// optionally within `max_elev_diff` metres of elevation, nearest first. Stations are not their
+/// own neighbours
+fn find_neighbours(cache: &DataCache, radius: f32, max_elev_diff: Option<f32>) -> Vec<Vec<usize>> {
+    let tree = build_tree(cache);
+    // the tree is in km, and takes a squared distance
+    let radius_squared = (radius / 1000.).powi(2);
+
+    (0..cache.data.len())
+        .map(|station| {
+            let point = to_cartesian(cache.rtree.lats[station], cache.rtree.lons[station]);
+            tree.nearest_neighbor_iter_with_distance_2(&point)
+                .take_while(|(_, distance_2)| *distance_2 <= radius_squared)
+                .map(|(neighbour, _)| neighbour.data)
+                .filter(|neighbour| {
+                    *neighbour != station
+                        && max_elev_diff.is_none_or(|max_elev_diff| {
+                            (cache.rtree.elevs[*neighbour] - cache.rtree.elevs[station]).abs()
+                                <= max_elev_diff
+                        })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Compares whether an event (value >= `event_threshold`) happened at each station with whether
+/// it happened at its neighbours, for one timestep
+///
+/// Stations where less than `threshold` of their neighbours agree with them are flagged as
+/// failing, and no longer considered as neighbours in later iterations. Within an iteration,
+/// every station is judged against the flags from the iteration before, so the order of the
+/// stations doesn't matter
+fn buddy_event_check(
+    values: &[Option<f32>],
+    neighbours: &[Vec<usize>],
+    conf: &BuddyEventCheckConf,
+) -> Vec<Flag> {
+    let mut flags: Vec<Flag> = values
+        .iter()
+        .map(|value| match value {
+            Some(_) => Flag::Pass,
+            None => Flag::DataMissing,
+        })
+        .collect();
+
+    for _ in 0..conf.num_iterations {
+        let previous = flags.clone();
+        let mut num_flagged = 0;
+
+        for i in 0..values.len() {
+            let Some(value) = values[i] else {
+                continue;
+            };
+            if previous[i] == Flag::Fail {
+                continue;
+            }
+
+            let is_event = value >= conf.event_threshold;
+            let buddies: Vec<bool> = neighbours[i]
+                .iter()
+                .filter(|neighbour| previous[**neighbour] == Flag::Pass)
+                .filter_map(|neighbour| values[*neighbour])
+                .map(|neighbour_value| neighbour_value >= conf.event_threshold)
+                .collect();
+
+            if buddies.len() < conf.num_min as usize {
+                flags[i] = Flag::Isolated;
+                continue;
+            }
+
+            let fraction_agreeing = buddies
+                .iter()
+                .filter(|buddy_is_event| **buddy_is_event == is_event)
+                .count() as f32
+                / buddies.len() as f32;
+            if fraction_agreeing < conf.threshold {
+                flags[i] = Flag::Fail;
+                num_flagged += 1;
+            } else {
+                flags[i] = Flag::Pass;
+            }
+        }
+
+        if num_flagged == 0 {
+            break;
+        }
+    }
+
+    flags
+}
+
+/// Median of `values`, or None if there are none
+fn median(values: &mut [f32]) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable_by(f32::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        Some((values[mid - 1] + values[mid]) / 2.)
+    } else {
+        Some(values[mid])
+    }
+}
+
+/// Whether `b` is a copy of `a` shifted later by `lag` steps, to within `tolerance`
+///
+/// At least `min_overlap` points must be present in both series to compare, and they must not be
+/// constant, since e.g. two dry rain gauges would otherwise look like copies of each other
+fn is_shifted_copy(
+    a: &[Option<f32>],
+    b: &[Option<f32>],
+    lag: usize,
+    tolerance: f32,
+    min_overlap: usize,
+) -> bool {
+    if lag >= a.len() {
+        return false;
+    }
+
+    let pairs: Vec<(f32, f32)> = a[..a.len() - lag]
+        .iter()
+        .zip(b[lag..].iter())
+        .filter_map(|(a, b)| a.zip(*b))
+        .collect();
+
+    pairs.len() >= min_overlap.max(2)
+        && pairs.iter().any(|(a, _)| *a != pairs[0].0)
+        && pairs.iter().all(|(a, b)| (a - b).abs() <= tolerance)
+}
+
+/// Pearson correlation of `series[t + lag]` against `reference[t]`
+///
+/// Returns None if fewer than `min_overlap` points are present in both, or either side is
+/// constant over them
+fn lagged_correlation(
+    series: &[Option<f32>],
+    reference: &[Option<f32>],
+    lag: isize,
+    min_overlap: usize,
+) -> Option<f32> {
+    let pairs: Vec<(f32, f32)> = reference
+        .iter()
+        .enumerate()
+        .filter_map(|(t, reference_value)| {
+            let series_value = series.get(usize::try_from(t as isize + lag).ok()?)?;
+            series_value.zip(*reference_value)
+        })
+        .collect();
+    if pairs.len() < min_overlap.max(2) {
+        return None;
+    }
+
+    let n = pairs.len() as f32;
+    let mean_a = pairs.iter().map(|(a, _)| a).sum::<f32>() / n;
+    let mean_b = pairs.iter().map(|(_, b)| b).sum::<f32>() / n;
+    let (cov, var_a, var_b) = pairs
+        .iter()
+        .fold((0., 0., 0.), |(cov, var_a, var_b), (a, b)| {
+            let (da, db) = (a - mean_a, b - mean_b);
+            (cov + da * db, var_a + da * da, var_b + db * db)
+        });
+    if var_a == 0. || var_b == 0. {
+        return None;
+    }
+
+    Some(cov / (var_a * var_b).sqrt())
+}
+
+/// Estimate how many steps `series` lags behind `reference`, by the lag within `max_lag` that
+/// maximises their correlation
+///
+/// A nonzero lag is only reported if its correlation beats the correlation at no lag by more than
+/// `min_improvement`. Returns None if no correlation could be computed at no lag
+fn estimate_time_shift(
+    series: &[Option<f32>],
+    reference: &[Option<f32>],
+    max_lag: u8,
+    min_overlap: usize,
+    min_improvement: f32,
+) -> Option<i32> {
+    let unshifted = lagged_correlation(series, reference, 0, min_overlap)?;
+
+    let best = (-(max_lag as isize)..=max_lag as isize)
+        .filter_map(|lag| {
+            lagged_correlation(series, reference, lag, min_overlap).map(|corr| (lag, corr))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+
+    match best {
+        Some((lag, corr)) if corr - unshifted > min_improvement => Some(lag as i32),
+        _ => Some(0),
+    }
 }
 
-pub fn run_test(step: &PipelineStep, cache: &DataCache) -> Result<ValidateResponse, Error> {
+/// Theil-Sen fit of value against elevation to `buddies`, as (elevation, value) pairs, returning
+/// the slope, intercept and median absolute deviation of the buddies from the fit
+///
+/// The slope is 0 if the buddies span less than `min_elev_diff` of elevation. Returns None if
+/// there are no buddies
+fn resistant_fit(buddies: &[(f32, f32)], min_elev_diff: f32) -> Option<(f32, f32, f32)> {
+    let (min_elev, max_elev) = buddies.iter().fold((f32::MAX, f32::MIN), |acc, (elev, _)| {
+        (acc.0.min(*elev), acc.1.max(*elev))
+    });
+    let slope = if max_elev - min_elev >= min_elev_diff {
+        let mut slopes = Vec::with_capacity(buddies.len() * (buddies.len() - 1) / 2);
+        for (j, (elev_a, value_a)) in buddies.iter().enumerate() {
+            for (elev_b, value_b) in buddies[j + 1..].iter() {
+                if elev_a != elev_b {
+                    slopes.push((value_b - value_a) / (elev_b - elev_a));
+                }
+            }
+        }
+        // with min_elev_diff = 0, the buddies may all be at the same elevation
+        median(&mut slopes).unwrap_or(0.)
+    } else {
+        0.
+    };
+    let intercept = median(
+        &mut buddies
+            .iter()
+            .map(|(elev, value)| value - slope * elev)
+            .collect::<Vec<f32>>(),
+    )?;
+
+    let mut residuals: Vec<f32> = buddies
+        .iter()
+        .map(|(elev, value)| value - (intercept + slope * elev))
+        .collect();
+    let residual_median = median(&mut residuals)?;
+    let mad = median(
+        &mut residuals
+            .iter()
+            .map(|residual| (residual - residual_median).abs())
+            .collect::<Vec<f32>>(),
+    )?;
+
+    Some((slope, intercept, mad))
+}
+
+/// Resistant variant of SCT for one timestep
+///
+/// For each station, a background is estimated from its nearest neighbours using a Theil-Sen fit
+/// of value against elevation (or their median, if the neighbours don't span enough elevation),
+/// and the station's deviation from it is scaled by the median absolute deviation of the
+/// neighbours from the fit. Using medians throughout means a few bad neighbours can't drag the
+/// background or spread towards themselves, which matters in dense crowdsourced networks
+fn sct_resistant(
+    cache: &DataCache,
+    values: &[Option<f32>],
+    neighbours: &[Vec<usize>],
+    conf: &SctResistantConf,
+) -> Vec<FlagWithExtra> {
+    let elevs = &cache.rtree.elevs;
+    let mut deviations: Vec<Option<f32>> = vec![None; values.len()];
+    let mut flags: Vec<Flag> = values
+        .iter()
+        .map(|value| match value {
+            Some(_) => Flag::Pass,
+            None => Flag::DataMissing,
+        })
+        .collect();
+
+    for _ in 0..conf.num_iterations {
+        let mut num_flagged = 0;
+
+        for i in 0..values.len() {
+            let Some(value) = values[i] else {
+                continue;
+            };
+            if flags[i] == Flag::Fail {
+                continue;
+            }
+
+            let buddies: Vec<(f32, f32)> = neighbours[i]
+                .iter()
+                .filter(|neighbour| flags[**neighbour] != Flag::Fail)
+                .filter_map(|neighbour| values[*neighbour].map(|v| (elevs[*neighbour], v)))
+                .take(conf.num_max)
+                .collect();
+
+            let fit = if buddies.len() < conf.num_min {
+                None
+            } else {
+                resistant_fit(&buddies, conf.min_elev_diff)
+            };
+            let Some((slope, intercept, mad)) = fit else {
+                flags[i] = Flag::Isolated;
+                deviations[i] = None;
+                continue;
+            };
+            let spread = (1.4826 * mad).max(conf.min_std);
+
+            let deviation = (value - (intercept + slope * elevs[i])) / spread;
+            deviations[i] = Some(deviation);
+            if deviation > conf.pos || -deviation > conf.neg {
+                flags[i] = Flag::Fail;
+                num_flagged += 1;
+            } else {
+                flags[i] = Flag::Pass;
+            }
+        }
+
+        if num_flagged == 0 {
+            break;
+        }
+    }
+
+    flags.into_iter().zip(deviations).collect()
+}
+
+/// Interpolates the series in a gridded cache to the locations of the series in `cache`
+///
+/// Uses inverse distance weighting of the nearest gridpoints that have data for each timestep.
+/// Gridpoint values are adjusted to the elevation of the station using `elev_gradient` (in units
+/// per metre) before weighting. Returns, for each series in `cache`, the interpolated series and
+/// the difference between the station's elevation and the interpolated elevation of the grid
+fn interpolate_to_stations(
+    grid: &DataCache,
+    cache: &DataCache,
+    elev_gradient: f32,
+) -> Vec<(Vec<Option<f32>>, f32)> {
+    let grid_tree = build_tree(grid);
+    let series_len = cache.data[0].1.len();
+
+    // inverse distance weighted mean of the values at the neighbours that have them
+    let weighted_mean = |neighbours: &[(usize, f32)], value_at: &dyn Fn(usize) -> Option<f32>| {
+        let mut weighted_sum = 0.;
+        let mut weight_total = 0.;
+        for (grid_index, distance) in neighbours.iter() {
+            if let Some(value) = value_at(*grid_index) {
+                // a gridpoint right on top of the station is used directly
+                if *distance == 0. {
+                    return Some(value);
+                }
+                weighted_sum += value / distance;
+                weight_total += 1. / distance;
+            }
+        }
+        (weight_total > 0.).then(|| weighted_sum / weight_total)
+    };
+
+    (0..cache.data.len())
+        .map(|station| {
+            let (lat, lon, elev) = (
+                cache.rtree.lats[station],
+                cache.rtree.lons[station],
+                cache.rtree.elevs[station],
+            );
+            let station_point = to_cartesian(lat, lon);
+            let neighbours: Vec<(usize, f32)> = grid_tree
+                .nearest_neighbor_iter(&station_point)
+                .take(INTERPOLATION_NEIGHBOURS)
+                .map(|point| {
+                    let geom = point.geom();
+                    let distance = ((geom[0] - station_point[0]).powi(2)
+                        + (geom[1] - station_point[1]).powi(2)
+                        + (geom[2] - station_point[2]).powi(2))
+                    .sqrt();
+                    (point.data, distance)
+                })
+                .collect();
+
+            let grid_elev =
+                weighted_mean(&neighbours, &|j| Some(grid.rtree.elevs[j])).unwrap_or(elev);
+
+            let series = (0..series_len)
+                .map(|i| {
+                    weighted_mean(&neighbours, &|j| {
+                        grid.data[j].1[i]
+                            .map(|value| value + elev_gradient * (elev - grid.rtree.elevs[j]))
+                    })
+                })
+                .collect();
+
+            (series, elev - grid_elev)
+        })
+        .collect()
+}
+
+/// The times of the points to be QCed in a cache, i.e. excluding leading and trailing points
+fn qc_times(cache: &DataCache) -> Vec<DateTime<Utc>> {
+    let series_len = cache.data[0].1.len();
+
+    DateRule::new(
+        Utc.timestamp_opt(cache.start_time.0, 0).unwrap(),
+        cache.period,
+    )
+    .take(series_len - cache.num_leading_points as usize - cache.num_trailing_points as usize)
+    .collect()
+}
+
+pub fn run_test(
+    step: &PipelineStep,
+    cache: &DataCache,
+    backing_data: &BackingData,
+) -> Result<ValidateResponse, Error> {
     let step_name = step.name.to_string();
 
-    let flags: Vec<(String, Vec<Flag>)> = match &step.check {
-        CheckConf::SpikeCheck(conf) => {
-            const LEADING_PER_RUN: u8 = SPIKE_LEADING_PER_RUN;
-            const TRAILING_PER_RUN: u8 = SPIKE_TRAILING_PER_RUN;
+    // the index math below assumes the cache has at least the context the check needs, so
+    // catch caches fetched with too little here rather than underflowing
+    let (leading_required, trailing_required) = step.check.get_num_leading_trailing();
+    if cache.num_leading_points < leading_required || cache.num_trailing_points < trailing_required
+    {
+        return Err(Error::InsufficientContext(
+            step_name,
+            leading_required,
+            trailing_required,
+            cache.num_leading_points,
+            cache.num_trailing_points,
+        ));
+    }
 
-            // TODO: use par_iter?
+    // non-finite values are hidden from the checks as gaps, and flagged invalid afterwards
+    let raw_cache = cache;
+    let sanitized_cache;
+    let cache = if has_non_finite(cache) {
+        sanitized_cache = sanitize(cache);
+        &sanitized_cache
+    } else {
+        cache
+    };
+    // and in backing data, where they are only hidden, as backing data isn't flagged
+    let sanitized_backing_data: BackingData;
+    let backing_data = if backing_data.values().any(has_non_finite) {
+        sanitized_backing_data = backing_data
+            .iter()
+            .map(|(key, backing_cache)| {
+                let backing_cache = match has_non_finite(backing_cache) {
+                    true => sanitize(backing_cache),
+                    false => backing_cache.clone(),
+                };
+                (key.clone(), backing_cache)
+            })
+            .collect();
+        &sanitized_backing_data
+    } else {
+        backing_data
+    };
 
-            let mut result_vec = Vec::with_capacity(cache.data.len());
+    // per series, only set by checks that detect clock offsets
+    let mut estimated_shifts: Option<Vec<Option<i32>>> = None;
+    // per point, only set by checks that can quantify how far a value is from what was expected
+    let mut scores: Option<Vec<Vec<Option<f32>>>> = None;
+    // per point, only set by checks that can propose a replacement for values they flag
+    let mut corrected_values: Option<Vec<Vec<Option<f32>>>> = None;
 
+    let mut flags: Vec<(String, Vec<Flag>)> = match &step.check {
+        CheckConf::SpecialValueCheck(conf) => {
             let series_len = cache.data[0].1.len();
 
-            for i in 0..cache.data.len() {
-                result_vec.push((
-                    cache.data[i].0.clone(),
-                    cache.data[i].1[(cache.num_leading_points - LEADING_PER_RUN).into()
-                        ..(series_len - (cache.num_trailing_points - TRAILING_PER_RUN) as usize)]
-                        .windows((LEADING_PER_RUN + 1 + TRAILING_PER_RUN).into())
-                        .map(|window| {
-                            // TODO: the "high" param is hardcoded for now, but should be removed
-                            // from olympian
-                            olympian::dip_check(window, 2., conf.max)?
-                                .try_into()
-                                .map_err(Error::UnknownFlag)
+            let results = cache
+                .data
+                .iter()
+                .map(|ts| {
+                    (
+                        ts.0.clone(),
+                        ts.1[(cache.num_leading_points as usize)
+                            ..(series_len - cache.num_trailing_points as usize)]
+                            .iter()
+                            .map(|value| {
+                                special_value_check(*value, &conf.special_values, conf.substitute)
+                            })
+                            .collect(),
+                    )
+                })
+                .collect();
+            split_flags(results, &mut corrected_values)
+        }
+        CheckConf::RangeCheck(conf) => {
+            let series_len = cache.data[0].1.len();
+
+            cache
+                .data
+                .iter()
+                .map(|ts| {
+                    (
+                        ts.0.clone(),
+                        ts.1[(cache.num_leading_points as usize)
+                            ..(series_len - cache.num_trailing_points as usize)]
+                            .iter()
+                            .map(|value| range_check(*value, conf.min, conf.max))
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+        CheckConf::RangeCheckDynamic(conf) => {
+            let series_len = cache.data[0].1.len();
+
+            let mins = index_series(get_backing_cache(
+                backing_data,
+                cache,
+                &conf.source,
+                Some(RANGE_DYNAMIC_MIN_SPEC),
+            )?);
+            let maxes = index_series(get_backing_cache(
+                backing_data,
+                cache,
+                &conf.source,
+                Some(RANGE_DYNAMIC_MAX_SPEC),
+            )?);
+
+            cache
+                .data
+                .iter()
+                .map(|ts| {
+                    let (min_series, max_series) =
+                        match (mins.get(ts.0.as_str()), maxes.get(ts.0.as_str())) {
+                            (Some(min_series), Some(max_series)) => (*min_series, *max_series),
+                            // without limits for this series there's nothing to check against
+                            _ => {
+                                return (
+                                    ts.0.clone(),
+                                    vec![
+                                        Flag::Inconclusive;
+                                        series_len
+                                            - cache.num_leading_points as usize
+                                            - cache.num_trailing_points as usize
+                                    ],
+                                )
+                            }
+                        };
+
+                    (
+                        ts.0.clone(),
+                        ((cache.num_leading_points as usize)
+                            ..(series_len - cache.num_trailing_points as usize))
+                            .map(|i| match (ts.1[i], min_series[i], max_series[i]) {
+                                (None, _, _) => Flag::DataMissing,
+                                (value, Some(min), Some(max)) => range_check(value, min, max),
+                                _ => Flag::Inconclusive,
+                            })
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+        CheckConf::ModelConsistencyCheck(conf) => {
+            let series_len = cache.data[0].1.len();
+
+            let model = interpolate_to_stations(
+                get_backing_cache(
+                    backing_data,
+                    cache,
+                    &conf.model_source,
+                    Some(&conf.model_args),
+                )?,
+                cache,
+                0.,
+            );
+
+            cache
+                .data
+                .iter()
+                .zip(model)
+                .map(|(ts, (model_series, _))| {
+                    (
+                        ts.0.clone(),
+                        ((cache.num_leading_points as usize)
+                            ..(series_len - cache.num_trailing_points as usize))
+                            .map(|i| match (ts.1[i], model_series[i]) {
+                                (None, _) => Flag::DataMissing,
+                                (_, None) => Flag::Inconclusive,
+                                (Some(value), Some(model_value)) => {
+                                    if (value - model_value).abs() > conf.threshold {
+                                        Flag::Fail
+                                    } else {
+                                        Flag::Pass
+                                    }
+                                }
+                            })
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+        CheckConf::ClimatologyCheck(conf) => {
+            let times = qc_times(cache);
+            let leading = cache.num_leading_points as usize;
+
+            match conf {
+                ClimatologyCheckConf::Table { min, max } => cache
+                    .data
+                    .iter()
+                    .map(|ts| {
+                        (
+                            ts.0.clone(),
+                            times
+                                .iter()
+                                .enumerate()
+                                .map(|(i, time)| {
+                                    let month = time.month0() as usize;
+                                    range_check(ts.1[leading + i], min[month], max[month])
+                                })
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+                ClimatologyCheckConf::Source { source } => {
+                    let mins = index_series(lookup_backing_cache(
+                        backing_data,
+                        source,
+                        Some(RANGE_DYNAMIC_MIN_SPEC),
+                    )?);
+                    let maxes = index_series(lookup_backing_cache(
+                        backing_data,
+                        source,
+                        Some(RANGE_DYNAMIC_MAX_SPEC),
+                    )?);
+
+                    cache
+                        .data
+                        .iter()
+                        .map(|ts| {
+                            let tables = match (mins.get(ts.0.as_str()), maxes.get(ts.0.as_str())) {
+                                (Some(min_table), Some(max_table))
+                                    if min_table.len() == 12 && max_table.len() == 12 =>
+                                {
+                                    Some((*min_table, *max_table))
+                                }
+                                (None, _) | (_, None) => None,
+                                _ => return Err(Error::MisalignedBackingData(source.clone())),
+                            };
+
+                            Ok((
+                                ts.0.clone(),
+                                times
+                                    .iter()
+                                    .enumerate()
+                                    .map(|(i, time)| {
+                                        let month = time.month0() as usize;
+                                        match (ts.1[leading + i], tables) {
+                                            (None, _) => Flag::DataMissing,
+                                            (value, Some((min_table, max_table))) => {
+                                                match (min_table[month], max_table[month]) {
+                                                    (Some(min), Some(max)) => {
+                                                        range_check(value, min, max)
+                                                    }
+                                                    _ => Flag::Inconclusive,
+                                                }
+                                            }
+                                            // no limits for this station
+                                            (_, None) => Flag::Inconclusive,
+                                        }
+                                    })
+                                    .collect(),
+                            ))
                         })
-                        .collect::<Result<Vec<Flag>, Error>>()?,
-                ))
+                        .collect::<Result<Vec<(String, Vec<Flag>)>, Error>>()?
+                }
             }
-            result_vec
+        }
+        CheckConf::SeasonalRangeCheck(conf) => {
+            let times = qc_times(cache);
+            let leading = cache.num_leading_points as usize;
+
+            cache
+                .data
+                .iter()
+                .map(|ts| {
+                    (
+                        ts.0.clone(),
+                        times
+                            .iter()
+                            .enumerate()
+                            .map(|(i, time)| {
+                                match (ts.1[leading + i], seasonal_limits(&conf.bands, time)) {
+                                    (None, _) => Flag::DataMissing,
+                                    (_, None) => Flag::Inconclusive,
+                                    (value, Some((min, max))) => range_check(value, min, max),
+                                }
+                            })
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+        CheckConf::RadiationCheck(conf) => {
+            let times = qc_times(cache);
+            let leading = cache.num_leading_points as usize;
+
+            cache
+                .data
+                .iter()
+                .enumerate()
+                .map(|(station, ts)| {
+                    let (lat, lon) = (cache.rtree.lats[station], cache.rtree.lons[station]);
+                    (
+                        ts.0.clone(),
+                        times
+                            .iter()
+                            .enumerate()
+                            .map(|(i, time)| {
+                                radiation_check(
+                                    ts.1[leading + i],
+                                    cos_solar_zenith(lat, lon, *time),
+                                    conf.night_max,
+                                )
+                            })
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+        CheckConf::DewPointCheck(conf) => {
+            let series_len = cache.data[0].1.len();
+
+            let air_temperatures = get_param(cache, &conf.air_temperature_param)?;
+
+            cache
+                .data
+                .iter()
+                .zip(air_temperatures)
+                .map(|(ts, air_temperature_series)| {
+                    (
+                        ts.0.clone(),
+                        ((cache.num_leading_points as usize)
+                            ..(series_len - cache.num_trailing_points as usize))
+                            .map(|i| match (ts.1[i], air_temperature_series[i]) {
+                                (None, _) => Flag::DataMissing,
+                                (_, None) => Flag::Inconclusive,
+                                (Some(dew_point), Some(air_temperature)) => {
+                                    if dew_point > air_temperature + conf.tolerance {
+                                        Flag::Fail
+                                    } else {
+                                        Flag::Pass
+                                    }
+                                }
+                            })
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+        CheckConf::FirstGuessCheck(conf) => {
+            let series_len = cache.data[0].1.len();
+
+            let first_guess = interpolate_to_stations(
+                get_backing_cache(backing_data, cache, &conf.source, Some(&conf.field))?,
+                cache,
+                conf.elev_gradient,
+            );
+
+            let results = cache
+                .data
+                .iter()
+                .zip(first_guess)
+                .map(|(ts, (first_guess_series, elev_diff))| {
+                    // the further the station is vertically from the grid, the less we trust the
+                    // elevation correction
+                    let extra_tolerance = elev_diff.abs() * conf.elev_diff_tolerance;
+                    (
+                        ts.0.clone(),
+                        ((cache.num_leading_points as usize)
+                            ..(series_len - cache.num_trailing_points as usize))
+                            .map(|i| match (ts.1[i], first_guess_series[i]) {
+                                (None, _) => (Flag::DataMissing, None),
+                                (_, None) => (Flag::Inconclusive, None),
+                                (Some(value), Some(first_guess)) => {
+                                    let deviation = value - first_guess;
+                                    let flag = if deviation > conf.pos + extra_tolerance
+                                        || -deviation > conf.neg + extra_tolerance
+                                    {
+                                        Flag::Fail
+                                    } else {
+                                        Flag::Pass
+                                    };
+                                    (flag, Some(deviation))
+                                }
+                            })
+                            .collect(),
+                    )
+                })
+                .collect();
+            split_flags(results, &mut scores)
+        }
+        CheckConf::MetadataCheck(conf) => {
+            let series_len = cache.data[0].1.len();
+            let num_qc_points =
+                series_len - cache.num_leading_points as usize - cache.num_trailing_points as usize;
+
+            cache
+                .data
+                .iter()
+                .enumerate()
+                .map(|(station, ts)| {
+                    let (lat, lon, elev) = (
+                        cache.rtree.lats[station],
+                        cache.rtree.lons[station],
+                        cache.rtree.elevs[station],
+                    );
+                    // NaNs fail all of these comparisons, so are also caught here
+                    let plausible = (-90. ..=90.).contains(&lat)
+                        && (-180. ..=180.).contains(&lon)
+                        && conf.min_elev.is_none_or(|min_elev| elev >= min_elev)
+                        && conf.max_elev.is_none_or(|max_elev| elev <= max_elev)
+                        && elev.is_finite();
+
+                    let flag = if plausible { Flag::Pass } else { Flag::Invalid };
+                    (ts.0.clone(), vec![flag; num_qc_points])
+                })
+                .collect()
+        }
+        CheckConf::SpikeCheck(conf) => {
+            const LEADING_PER_RUN: u8 = SPIKE_LEADING_PER_RUN;
+            const TRAILING_PER_RUN: u8 = SPIKE_TRAILING_PER_RUN;
+
+            let series_len = cache.data[0].1.len();
+
+            let result_vec = cache
+                .data
+                .par_iter()
+                .map(|ts| {
+                    Ok((
+                        ts.0.clone(),
+                        ts.1[(cache.num_leading_points - LEADING_PER_RUN).into()
+                            ..(series_len
+                                - (cache.num_trailing_points - TRAILING_PER_RUN) as usize)]
+                            .windows((LEADING_PER_RUN + 1 + TRAILING_PER_RUN).into())
+                            .map(|window| {
+                                // TODO: the "high" param is hardcoded for now, but should be removed
+                                // from olympian
+                                let flag: Flag = olympian::dip_check(window, 2., conf.max)?
+                                    .try_into()
+                                    .map_err(Error::UnknownFlag)?;
+                                // propose interpolating over spikes
+                                let corrected_value = match (flag, window[0], window[2]) {
+                                    (Flag::Fail, Some(before), Some(after)) => {
+                                        Some((before + after) / 2.)
+                                    }
+                                    _ => None,
+                                };
+                                Ok((flag, corrected_value))
+                            })
+                            .collect::<Result<Vec<FlagWithExtra>, Error>>()?,
+                    ))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            split_flags(result_vec, &mut corrected_values)
         }
         CheckConf::StepCheck(conf) => {
             const LEADING_PER_RUN: u8 = STEP_LEADING_PER_RUN;
             const TRAILING_PER_RUN: u8 = STEP_TRAILING_PER_RUN;
 
-            let mut result_vec = Vec::with_capacity(cache.data.len());
-
             // NOTE: Does data in each series have the same len?
             let series_len = cache.data[0].1.len();
 
-            for i in 0..cache.data.len() {
-                result_vec.push((
-                    cache.data[i].0.clone(),
-                    cache.data[i].1[(cache.num_leading_points - LEADING_PER_RUN).into()
-                        ..(series_len - (cache.num_trailing_points - TRAILING_PER_RUN) as usize)]
-                        .windows((LEADING_PER_RUN + 1).into())
-                        .map(|window| {
-                            // TODO: the "high" param is hardcoded for now, but should be removed
-                            // from olympian
-                            olympian::step_check(window, 2., conf.max)?
-                                .try_into()
-                                .map_err(Error::UnknownFlag)
-                        })
-                        .collect::<Result<Vec<Flag>, Error>>()?,
-                ))
-            }
-            result_vec
+            cache
+                .data
+                .par_iter()
+                .map(|ts| {
+                    Ok((
+                        ts.0.clone(),
+                        ts.1[(cache.num_leading_points - LEADING_PER_RUN).into()
+                            ..(series_len
+                                - (cache.num_trailing_points - TRAILING_PER_RUN) as usize)]
+                            .windows((LEADING_PER_RUN + 1).into())
+                            .map(|window| {
+                                // TODO: the "high" param is hardcoded for now, but should be
+                                // removed from olympian
+                                olympian::step_check(window, 2., conf.max)?
+                                    .try_into()
+                                    .map_err(Error::UnknownFlag)
+                            })
+                            .collect::<Result<Vec<Flag>, Error>>()?,
+                    ))
+                })
+                .collect::<Result<Vec<_>, Error>>()?
         }
-        CheckConf::BuddyCheck(conf) => {
-            let n = cache.data.len();
+        CheckConf::FlatlineCheck(conf) => {
+            // the number of leading points needed is determined by the conf, so unlike the other
+            // timeseries checks this can't be a const
+            let leading_per_run = conf.max;
 
             let series_len = cache.data[0].1.len();
 
-            let mut result_vec: Vec<(String, Vec<Flag>)> = cache
+            cache
+                .data
+                .par_iter()
+                .map(|ts| {
+                    (
+                        ts.0.clone(),
+                        ts.1[(cache.num_leading_points - leading_per_run).into()
+                            ..(series_len - cache.num_trailing_points as usize)]
+                            .windows(usize::from(leading_per_run) + 1)
+                            .map(flatline_check)
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+        CheckConf::PersistenceCheck(conf) => {
+            let leading_per_run = conf.window;
+
+            let series_len = cache.data[0].1.len();
+
+            cache
                 .data
                 .iter()
-                .map(|ts| (ts.0.clone(), Vec::with_capacity(series_len)))
-                .collect();
+                .map(|ts| {
+                    (
+                        ts.0.clone(),
+                        ts.1[(cache.num_leading_points - leading_per_run).into()
+                            ..(series_len - cache.num_trailing_points as usize)]
+                            .windows(usize::from(leading_per_run) + 1)
+                            .map(|window| persistence_check(window, conf.min_std))
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+        CheckConf::IsolationCheck(conf) => {
+            let series_len = cache.data[0].1.len();
+            let neighbours = find_neighbours(cache, conf.radius, conf.max_elev_diff);
 
-            for i in (cache.num_leading_points as usize)
-                ..(series_len - cache.num_trailing_points as usize)
-            {
-                // TODO: change `buddy_check` to accept Option<f32>?
-                let inner: Vec<f32> = cache.data.iter().map(|v| v.1[i].unwrap()).collect();
-
-                let spatial_result = olympian::buddy_check(
-                    &cache.rtree,
-                    &inner,
-                    &conf.radii,         // &vec![5000.; n],
-                    &conf.nums_min,      // &vec![2; n],
-                    conf.threshold,      // 2.,
-                    conf.max_elev_diff,  // 200.,
-                    conf.elev_gradient,  // 0.,
-                    conf.min_std,        // 1.,
-                    conf.num_iterations, // 2,
-                    // TODO: should we be setting this dynamically? from where?
-                    &vec![true; n],
-                )?;
+            cache
+                .data
+                .iter()
+                .zip(neighbours)
+                .map(|(ts, station_neighbours)| {
+                    (
+                        ts.0.clone(),
+                        ((cache.num_leading_points as usize)
+                            ..(series_len - cache.num_trailing_points as usize))
+                            .map(|i| {
+                                if ts.1[i].is_none() {
+                                    return Flag::DataMissing;
+                                }
+                                // only neighbours with data at this timestep can help spatial
+                                // checks
+                                let num_neighbours = station_neighbours
+                                    .iter()
+                                    .filter(|neighbour| cache.data[**neighbour].1[i].is_some())
+                                    .count();
+                                if num_neighbours < conf.num_min as usize {
+                                    Flag::Isolated
+                                } else {
+                                    Flag::Pass
+                                }
+                            })
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+        CheckConf::BuddyEventCheck(conf) => {
+            let neighbours = find_neighbours(cache, conf.radius, conf.max_elev_diff);
+
+            per_timestep(cache, |inner| {
+                Ok(buddy_event_check(inner, &neighbours, conf))
+            })?
+        }
+        CheckConf::SctResistant(conf) => {
+            let neighbours = find_neighbours(cache, conf.outer_radius, None);
 
-                for (i, flag) in spatial_result.into_iter().map(Flag::try_from).enumerate() {
-                    result_vec[i].1.push(flag.map_err(Error::UnknownFlag)?);
+            let result_vec = per_timestep(cache, |inner| {
+                Ok(sct_resistant(cache, inner, &neighbours, conf))
+            })?;
+            split_flags(result_vec, &mut scores)
+        }
+        CheckConf::RocCheck(conf) => {
+            let leading_per_run = conf.window;
+
+            let series_len = cache.data[0].1.len();
+
+            let start = Utc.timestamp_opt(cache.start_time.0, 0).unwrap();
+            // RelativeDuration doesn't have a fixed length if it includes months, so this is the
+            // length of the first period in the cache
+            let window_hours =
+                (start + cache.period - start).num_seconds() as f32 / 3600. * conf.window as f32;
+
+            cache
+                .data
+                .iter()
+                .map(|ts| {
+                    (
+                        ts.0.clone(),
+                        ts.1[(cache.num_leading_points - leading_per_run).into()
+                            ..(series_len - cache.num_trailing_points as usize)]
+                            .windows(usize::from(leading_per_run) + 1)
+                            .map(|window| {
+                                match (
+                                    window.first().copied().flatten(),
+                                    window.last().copied().flatten(),
+                                ) {
+                                    (Some(first), Some(last)) => {
+                                        if (last - first).abs() / window_hours > conf.max_per_hour {
+                                            Flag::Fail
+                                        } else {
+                                            Flag::Pass
+                                        }
+                                    }
+                                    _ => Flag::DataMissing,
+                                }
+                            })
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+        CheckConf::CompletenessCheck(conf) => {
+            let leading_per_run = conf.window;
+
+            let series_len = cache.data[0].1.len();
+
+            cache
+                .data
+                .iter()
+                .map(|ts| {
+                    (
+                        ts.0.clone(),
+                        ts.1[(cache.num_leading_points - leading_per_run).into()
+                            ..(series_len - cache.num_trailing_points as usize)]
+                            .windows(usize::from(leading_per_run) + 1)
+                            .map(|window| completeness_check(window, conf.max_missing_fraction))
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+        CheckConf::DuplicateCheck(conf) => {
+            let series_len = cache.data[0].1.len();
+            let qc_range = (cache.num_leading_points as usize)
+                ..(series_len - cache.num_trailing_points as usize);
+
+            let candidates = find_neighbours(cache, conf.radius, None);
+
+            let mut is_duplicate = vec![false; cache.data.len()];
+            for (i, station_candidates) in candidates.iter().enumerate() {
+                for j in station_candidates.iter().filter(|j| **j > i) {
+                    let a = &cache.data[i].1[qc_range.clone()];
+                    let b = &cache.data[*j].1[qc_range.clone()];
+                    if (0..=conf.max_lag as usize).any(|lag| {
+                        is_shifted_copy(a, b, lag, conf.tolerance, conf.min_overlap)
+                            || is_shifted_copy(b, a, lag, conf.tolerance, conf.min_overlap)
+                    }) {
+                        is_duplicate[i] = true;
+                        is_duplicate[*j] = true;
+                    }
                 }
             }
-            result_vec
+
+            cache
+                .data
+                .iter()
+                .zip(is_duplicate)
+                .map(|(ts, is_duplicate)| {
+                    (
+                        ts.0.clone(),
+                        ts.1[qc_range.clone()]
+                            .iter()
+                            .map(|value| match (value, is_duplicate) {
+                                (None, _) => Flag::DataMissing,
+                                (Some(_), true) => Flag::Warn,
+                                (Some(_), false) => Flag::Pass,
+                            })
+                            .collect(),
+                    )
+                })
+                .collect()
         }
-        CheckConf::Sct(conf) => {
-            // TODO: evaluate whether we will need this to extend param vectors from conf
-            // if the checks accept single values (which they should) then we don't need this.
-            // anyway I think if we have dynamic values for these we can match them to the data
-            // when fetching them.
-            let n = cache.data.len();
+        CheckConf::MadCheck(conf) => {
+            let leading_per_run = conf.window;
+            let trailing_per_run = conf.window;
 
             let series_len = cache.data[0].1.len();
 
-            let mut result_vec: Vec<(String, Vec<Flag>)> = cache
+            let results = cache
                 .data
                 .iter()
-                .map(|ts| (ts.0.clone(), Vec::with_capacity(series_len)))
+                .map(|ts| {
+                    (
+                        ts.0.clone(),
+                        ts.1[(cache.num_leading_points - leading_per_run).into()
+                            ..(series_len
+                                - (cache.num_trailing_points - trailing_per_run) as usize)]
+                            .windows(
+                                usize::from(leading_per_run) + 1 + usize::from(trailing_per_run),
+                            )
+                            .map(|window| mad_check(window, conf.k, conf.min_mad))
+                            .collect(),
+                    )
+                })
                 .collect();
+            split_flags(results, &mut scores)
+        }
+        CheckConf::BuddyCheck(conf) => per_timestep(cache, |inner| {
+            spatial_check_present(cache, inner, |tree, values, present| {
+                olympian::buddy_check(
+                    tree,
+                    values,
+                    &select_present(&conf.radii, present), // &vec![5000.; n],
+                    &select_present(&conf.nums_min, present), // &vec![2; n],
+                    conf.threshold,                        // 2.,
+                    conf.max_elev_diff,                    // 200.,
+                    conf.elev_gradient,                    // 0.,
+                    conf.min_std,                          // 1.,
+                    conf.num_iterations,                   // 2,
+                    // TODO: should we be setting this dynamically? from where?
+                    &vec![true; values.len()],
+                )
+            })
+        })?,
+        CheckConf::TimeShiftCheck(conf) => {
+            let series_len = cache.data[0].1.len();
+            let neighbours = find_neighbours(cache, conf.radius, None);
 
-            for i in (cache.num_leading_points as usize)
-                ..(series_len - cache.num_trailing_points as usize)
-            {
-                // TODO: change `sct` to accept Option<f32>?
-                let inner: Vec<f32> = cache.data.iter().map(|v| v.1[i].unwrap()).collect();
-                // TODO: make it so olympian can accept the conf as one param?
-                let spatial_result = olympian::sct(
-                    &cache.rtree,
-                    &inner,
-                    conf.num_min,              // 5,
-                    conf.num_max,              // 100,
-                    conf.inner_radius,         // 50000.,
-                    conf.outer_radius,         // 150000.,
-                    conf.num_iterations,       // 5,
-                    conf.num_min_prof,         // 20,
-                    conf.min_elev_diff,        // 200.,
-                    conf.min_horizontal_scale, // 10000.,
-                    conf.vertical_scale,       // 200.,
-                    // TODO: we shouldn't need to extend these vectors, it should be handled
-                    // better in olympian
-                    &vec![conf.pos[0]; n],  // &vec![4.; n],
-                    &vec![conf.neg[0]; n],  // &vec![8.; n],
-                    &vec![conf.eps2[0]; n], // &vec![0.5; n],
-                    None,
-                )?;
-
-                for (i, flag) in spatial_result.into_iter().map(Flag::try_from).enumerate() {
-                    result_vec[i].1.push(flag.map_err(Error::UnknownFlag)?);
-                }
-            }
-            result_vec
+            let shifts: Vec<Option<i32>> = cache
+                .data
+                .iter()
+                .zip(neighbours)
+                .map(|(ts, station_neighbours)| {
+                    // mean of the neighbours with data at each timestep
+                    let reference: Vec<Option<f32>> = (0..series_len)
+                        .map(|t| {
+                            let values: Vec<f32> = station_neighbours
+                                .iter()
+                                .filter_map(|j| cache.data[*j].1[t])
+                                .collect();
+                            (!values.is_empty())
+                                .then(|| values.iter().sum::<f32>() / values.len() as f32)
+                        })
+                        .collect();
+
+                    estimate_time_shift(
+                        &ts.1,
+                        &reference,
+                        conf.max_lag,
+                        conf.min_overlap,
+                        conf.min_improvement,
+                    )
+                })
+                .collect();
+
+            let flags = cache
+                .data
+                .iter()
+                .zip(shifts.iter())
+                .map(|(ts, shift)| {
+                    (
+                        ts.0.clone(),
+                        ts.1[(cache.num_leading_points as usize)
+                            ..(series_len - cache.num_trailing_points as usize)]
+                            .iter()
+                            .map(|value| match (value, shift) {
+                                (None, _) => Flag::DataMissing,
+                                (Some(_), None) => Flag::Inconclusive,
+                                (Some(_), Some(0)) => Flag::Pass,
+                                (Some(_), Some(_)) => Flag::Warn,
+                            })
+                            .collect(),
+                    )
+                })
+                .collect();
+            estimated_shifts = Some(shifts);
+            flags
+        }
+        CheckConf::Sct(conf) => {
+            // TODO: evaluate whether we will need this to extend param vectors from conf
+            // if the checks accept single values (which they should) then we don't need this.
+            // anyway I think if we have dynamic values for these we can match them to the data
+            // when fetching them.
+            let (pos, neg, eps2) = sct_station_params(cache, conf);
+
+            per_timestep(cache, |inner| {
+                spatial_check_present(cache, inner, |tree, values, present| {
+                    // TODO: make it so olympian can accept the conf as one param?
+                    olympian::sct(
+                        tree,
+                        values,
+                        conf.num_min,                    // 5,
+                        conf.num_max,                    // 100,
+                        conf.inner_radius,               // 50000.,
+                        conf.outer_radius,               // 150000.,
+                        conf.num_iterations,             // 5,
+                        conf.num_min_prof,               // 20,
+                        conf.min_elev_diff,              // 200.,
+                        conf.min_horizontal_scale,       // 10000.,
+                        conf.vertical_scale,             // 200.,
+                        &select_present(&pos, present),  // &vec![4.; n],
+                        &select_present(&neg, present),  // &vec![8.; n],
+                        &select_present(&eps2, present), // &vec![0.5; n],
+                        None,
+                    )
+                })
+            })?
         }
         _ => {
             // used for integration testing
@@ -176,6 +1695,33 @@ pub fn run_test(step: &PipelineStep, cache: &DataCache) -> Result<ValidateRespon
         }
     };
 
+    if !std::ptr::eq(cache, raw_cache) {
+        let leading = raw_cache.num_leading_points as usize;
+        for (flag_series, raw_series) in flags.iter_mut().zip(raw_cache.data.iter()) {
+            for (flag, raw_value) in flag_series.1.iter_mut().zip(&raw_series.1[leading..]) {
+                if raw_value.is_some_and(|value| !value.is_finite()) {
+                    *flag = Flag::Invalid;
+                }
+            }
+        }
+    }
+
+    if let Some(on_fail) = step.on_fail {
+        let replacement = match on_fail {
+            FlagOverride::Pass => Flag::Pass,
+            FlagOverride::Warn => Flag::Warn,
+            FlagOverride::Inconclusive => Flag::Inconclusive,
+        };
+        for flag in flags
+            .iter_mut()
+            .flat_map(|flag_series| flag_series.1.iter_mut())
+        {
+            if *flag == Flag::Fail {
+                *flag = replacement;
+            }
+        }
+    }
+
     let date_rule = DateRule::new(
         // TODO: make sure this start time is actually correct
         Utc.timestamp_opt(cache.start_time.0, 0).unwrap(),
@@ -183,25 +1729,1457 @@ pub fn run_test(step: &PipelineStep, cache: &DataCache) -> Result<ValidateRespon
     );
     let results = flags
         .into_iter()
-        .flat_map(|flag_series| {
+        .enumerate()
+        .flat_map(|(i, (identifier, flag_series))| {
+            let estimated_shift = estimated_shifts.as_ref().and_then(|shifts| shifts[i]);
+            let series_scores = scores.as_ref().map(|scores| &scores[i]);
+            let series_corrected_values = corrected_values.as_ref().map(|values| &values[i]);
             flag_series
-                .1
                 .into_iter()
                 .zip(date_rule)
-                .zip(std::iter::repeat(flag_series.0))
-        })
-        .map(|((flag, time), identifier)| TestResult {
-            time: Some(prost_types::Timestamp {
-                seconds: time.timestamp(),
-                nanos: 0,
-            }),
-            identifier,
-            flag: flag.into(),
+                .enumerate()
+                .map(move |(j, (flag, time))| TestResult {
+                    time: Some(prost_types::Timestamp {
+                        seconds: time.timestamp(),
+                        nanos: 0,
+                    }),
+                    identifier: identifier.clone(),
+                    flag: flag.into(),
+                    estimated_shift,
+                    score: series_scores.and_then(|scores| scores[j]),
+                    corrected_value: series_corrected_values.and_then(|values| values[j]),
+                })
         })
         .collect();
 
     Ok(ValidateResponse {
         test: step_name,
         results,
+        ..Default::default()
     })
 }
+
+/// Runs a step with a `run_if` condition or station filter, given the results of the step the
+/// condition refers to
+///
+/// Observations that didn't get the flag the condition requires, or come from stations the step
+/// doesn't apply to, are hidden from the check as gaps, and flagged Inconclusive. Steps without a
+/// condition or filter are run as normal. The check itself is run by `run`, which is [`run_test`]
+/// for checks run locally.
+///
+/// Series from backing sources are never masked by the condition, and their results are left out
+/// of the response
+pub fn run_test_if(
+    step: &PipelineStep,
+    cache: &DataCache,
+    backing_data: &BackingData,
+    condition_results: Option<&ValidateResponse>,
+    run: impl Fn(&PipelineStep, &DataCache, &BackingData) -> Result<ValidateResponse, Error>,
+) -> Result<ValidateResponse, Error> {
+    if step.run_if.is_none() && step.filter.is_empty() {
+        let mut response = run(step, cache, backing_data)?;
+        drop_backing_results(&mut response, cache);
+        return Ok(response);
+    }
+
+    let selected: Option<HashSet<(&str, Option<i64>)>> = match &step.run_if {
+        Some(run_if) => {
+            let condition_results = condition_results
+                .ok_or_else(|| Error::MissingConditionResults(run_if.step.clone()))?;
+            let required_flag = Flag::from(run_if.flag) as i32;
+            Some(
+                condition_results
+                    .results
+                    .iter()
+                    .filter(|result| result.flag == required_flag)
+                    .map(|result| {
+                        (
+                            result.identifier.as_str(),
+                            result.time.as_ref().map(|time| time.seconds),
+                        )
+                    })
+                    .collect(),
+            )
+        }
+        None => None,
+    };
+    let applicable: Vec<bool> = (0..cache.data.len())
+        .map(|i| {
+            step.filter
+                .applies_to(cache.station_metadata(i), cache.rtree.elevs[i])
+        })
+        .collect();
+
+    // only the QCed points are masked, leading and trailing points are left as context
+    let times = qc_times(cache);
+    let leading = cache.num_leading_points as usize;
+    let mut masked_cache = cache.clone();
+    let mut skipped = vec![vec![false; times.len()]; cache.data.len()];
+    for (series_index, ((ts, series_skipped), applicable)) in masked_cache
+        .data
+        .iter_mut()
+        .zip(skipped.iter_mut())
+        .zip(applicable)
+        .enumerate()
+    {
+        // backing series have no results for the condition to select from
+        let conditional = !cache.is_backing(series_index);
+        for (i, time) in times.iter().enumerate() {
+            if ts.1[leading + i].is_some()
+                && (!applicable
+                    || conditional
+                        && selected.as_ref().is_some_and(|selected| {
+                            !selected.contains(&(ts.0.as_str(), Some(time.timestamp())))
+                        }))
+            {
+                ts.1[leading + i] = None;
+                series_skipped[i] = true;
+            }
+        }
+    }
+
+    let mut response = run(step, &masked_cache, backing_data)?;
+    // results are in series order, with one per QCed point
+    for (result, skipped) in response
+        .results
+        .iter_mut()
+        .zip(skipped.into_iter().flatten())
+    {
+        if skipped {
+            result.flag = Flag::Inconclusive.into();
+        }
+    }
+    drop_backing_results(&mut response, cache);
+    Ok(response)
+}
+
+/// Removes the results for series from backing sources, which aren't to be flagged
+fn drop_backing_results(response: &mut ValidateResponse, cache: &DataCache) {
+    if !cache.backing.contains(&true) || response.results.is_empty() {
+        return;
+    }
+
+    // results are in series order, with the same number for each series
+    let per_series = response.results.len() / cache.data.len();
+    let mut index = 0;
+    response.results.retain(|_| {
+        let keep = !cache.is_backing(index / per_series);
+        index += 1;
+        keep
+    });
+}
+
+impl From<FlagName> for Flag {
+    fn from(item: FlagName) -> Self {
+        match item {
+            FlagName::Pass => Flag::Pass,
+            FlagName::Fail => Flag::Fail,
+            FlagName::Warn => Flag::Warn,
+            FlagName::Inconclusive => Flag::Inconclusive,
+            FlagName::Invalid => Flag::Invalid,
+            FlagName::DataMissing => Flag::DataMissing,
+            FlagName::Isolated => Flag::Isolated,
+        }
+    }
+}
+
+/// Combines the results of all steps in a pipeline into one flag per observation, according to
+/// the precedence in `conf`
+pub fn combine(responses: &[ValidateResponse], conf: &CombiConf) -> ValidateResponse {
+    let rank = |flag: i32| {
+        conf.precedence
+            .iter()
+            .position(|name| Flag::from(*name) as i32 == flag)
+            .unwrap_or(conf.precedence.len())
+    };
+
+    // keyed by identifier and time, keeping the order observations were first seen in
+    let mut index: HashMap<(String, Option<i64>), usize> = HashMap::new();
+    let mut results: Vec<TestResult> = Vec::new();
+    for result in responses
+        .iter()
+        .flat_map(|response| response.results.iter())
+    {
+        let key = (
+            result.identifier.clone(),
+            result.time.as_ref().map(|time| time.seconds),
+        );
+        match index.get(&key) {
+            Some(i) => {
+                if rank(result.flag) < rank(results[*i].flag) {
+                    results[*i].flag = result.flag;
+                }
+            }
+            None => {
+                index.insert(key, results.len());
+                results.push(TestResult {
+                    time: result.time.clone(),
+                    identifier: result.identifier.clone(),
+                    flag: result.flag,
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    ValidateResponse {
+        test: COMBI_TEST_NAME.to_string(),
+        results,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data_switch::Timestamp,
+        pipeline::{
+            BuddyCheckConf, CheckConf, CompletenessCheckConf, DewPointCheckConf,
+            DuplicateCheckConf, FirstGuessCheckConf, FlatlineCheckConf, IsolationCheckConf,
+            MadCheckConf, MetadataCheckConf, ModelConsistencyCheckConf, PersistenceCheckConf,
+            RadiationCheckConf, RangeCheckConf, RangeCheckDynamicConf, RocCheckConf, RunIf,
+            SctStationClass, SeasonalRangeCheckConf, SpecialValueCheckConf, StepCheckConf,
+            StepFilter, TimeShiftCheckConf,
+        },
+    };
+    use chronoutil::RelativeDuration;
+
+    fn series_cache(
+        values: Vec<Option<f32>>,
+        num_leading_points: u8,
+        num_trailing_points: u8,
+    ) -> DataCache {
+        DataCache::new(
+            vec![60.],
+            vec![10.],
+            vec![0.],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            num_leading_points,
+            num_trailing_points,
+            vec![("test".to_string(), values)],
+        )
+    }
+
+    fn flags(response: &ValidateResponse) -> Vec<i32> {
+        response.results.iter().map(|res| res.flag).collect()
+    }
+
+    #[test]
+    fn test_flatline_check() {
+        let step = PipelineStep::new(
+            "flatline_check",
+            CheckConf::FlatlineCheck(FlatlineCheckConf { max: 2 }),
+        );
+        let cache = series_cache(
+            vec![
+                Some(1.),
+                Some(2.),
+                Some(2.),
+                Some(2.),
+                Some(3.),
+                None,
+                Some(3.),
+            ],
+            2,
+            0,
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Fail as i32,
+                Flag::Pass as i32,
+                Flag::DataMissing as i32,
+                Flag::DataMissing as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_insufficient_context() {
+        let step = PipelineStep::new(
+            "flatline_check",
+            CheckConf::FlatlineCheck(FlatlineCheckConf { max: 2 }),
+        );
+        let cache = series_cache(vec![Some(1.), Some(2.), Some(2.)], 1, 1);
+
+        assert!(matches!(
+            run_test(&step, &cache, &BackingData::new()),
+            Err(Error::InsufficientContext(_, 2, 0, 1, 1))
+        ));
+    }
+
+    #[test]
+    fn test_range_check() {
+        let step = PipelineStep::new(
+            "range_check",
+            CheckConf::RangeCheck(RangeCheckConf { min: -1., max: 1. }),
+        );
+        // the leading and trailing points are outside the range, but shouldn't be flagged
+        let cache = series_cache(
+            vec![Some(5.), Some(-1.), Some(0.5), None, Some(1.5), Some(-5.)],
+            1,
+            1,
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Pass as i32,
+                Flag::DataMissing as i32,
+                Flag::Fail as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_special_value_check() {
+        let step = PipelineStep::new(
+            "special_value_check",
+            CheckConf::SpecialValueCheck(SpecialValueCheckConf {
+                special_values: vec![-999., -99.9, 6999.],
+                substitute: Some(0.),
+            }),
+        );
+        let cache = series_cache(
+            vec![Some(-999.), Some(-99.9), Some(-99.8), None, Some(6999.)],
+            0,
+            0,
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Invalid as i32,
+                Flag::Invalid as i32,
+                Flag::Pass as i32,
+                Flag::DataMissing as i32,
+                Flag::Invalid as i32,
+            ]
+        );
+        let corrected_values: Vec<Option<f32>> = response
+            .results
+            .iter()
+            .map(|result| result.corrected_value)
+            .collect();
+        assert_eq!(
+            corrected_values,
+            vec![Some(0.), Some(0.), None, None, Some(0.)]
+        );
+    }
+
+    #[test]
+    fn test_range_check_dynamic() {
+        let step = PipelineStep::new(
+            "climate_range_check",
+            CheckConf::RangeCheckDynamic(RangeCheckDynamicConf {
+                source: "climatology".to_string(),
+            }),
+        );
+        let cache = series_cache(vec![Some(0.), Some(3.), Some(0.), None], 0, 0);
+        // a non-finite limit is as good as a missing one
+        for missing_min in [None, Some(f32::NAN)] {
+            let backing_data = BackingData::from([
+                (
+                    ("climatology".to_string(), Some("min".to_string())),
+                    series_cache(vec![Some(-1.), Some(-1.), missing_min, Some(-1.)], 0, 0),
+                ),
+                (
+                    ("climatology".to_string(), Some("max".to_string())),
+                    series_cache(vec![Some(1.), Some(2.), Some(1.), Some(1.)], 0, 0),
+                ),
+            ]);
+
+            let response = run_test(&step, &cache, &backing_data).unwrap();
+
+            assert_eq!(
+                flags(&response),
+                vec![
+                    Flag::Pass as i32,
+                    Flag::Fail as i32,
+                    Flag::Inconclusive as i32,
+                    Flag::DataMissing as i32,
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_model_consistency_check() {
+        let step = PipelineStep::new(
+            "model_consistency_check",
+            CheckConf::ModelConsistencyCheck(ModelConsistencyCheckConf {
+                model_source: "model".to_string(),
+                model_args: "air_temperature".to_string(),
+                threshold: 3.,
+            }),
+        );
+        let cache = series_cache(vec![Some(10.), Some(10.), Some(10.), None], 0, 0);
+        // gridpoints equidistant from the station, so the interpolated value is their mean
+        let model = DataCache::new(
+            vec![60.1, 59.9],
+            vec![10., 10.],
+            vec![0., 0.],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            vec![
+                ("0".to_string(), vec![Some(9.), Some(13.), None, Some(10.)]),
+                ("1".to_string(), vec![Some(11.), Some(15.), None, Some(10.)]),
+            ],
+        );
+        let backing_data = BackingData::from([(
+            ("model".to_string(), Some("air_temperature".to_string())),
+            model,
+        )]);
+
+        let response = run_test(&step, &cache, &backing_data).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Fail as i32,
+                Flag::Inconclusive as i32,
+                Flag::DataMissing as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_climatology_check() {
+        let mut min = [-10.; 12];
+        let mut max = [10.; 12];
+        // tighter limits in february
+        min[1] = -1.;
+        max[1] = 1.;
+        let step = PipelineStep::new(
+            "climatology_check",
+            CheckConf::ClimatologyCheck(ClimatologyCheckConf::Table { min, max }),
+        );
+        let cache = DataCache::new(
+            vec![60.],
+            vec![10.],
+            vec![0.],
+            Timestamp(
+                Utc.with_ymd_and_hms(2023, 1, 31, 0, 0, 0)
+                    .unwrap()
+                    .timestamp(),
+            ),
+            RelativeDuration::days(1),
+            0,
+            0,
+            vec![("test".to_string(), vec![Some(5.), Some(5.), Some(0.), None])],
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Fail as i32,
+                Flag::Pass as i32,
+                Flag::DataMissing as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_persistence_check() {
+        let step = PipelineStep::new(
+            "persistence_check",
+            CheckConf::PersistenceCheck(PersistenceCheckConf {
+                window: 2,
+                min_std: 0.1,
+            }),
+        );
+        let cache = series_cache(vec![Some(1.), Some(1.05), Some(1.), Some(3.), None], 2, 0);
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Fail as i32,
+                Flag::Pass as i32,
+                Flag::DataMissing as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_radiation_check() {
+        let step = PipelineStep::new(
+            "radiation_check",
+            CheckConf::RadiationCheck(RadiationCheckConf { night_max: 10. }),
+        );
+        // Oslo, so the sun is up at noon and down at midnight in june
+        let cache = DataCache::new(
+            vec![59.94],
+            vec![10.72],
+            vec![94.],
+            Timestamp(
+                Utc.with_ymd_and_hms(2023, 6, 21, 0, 0, 0)
+                    .unwrap()
+                    .timestamp(),
+            ),
+            RelativeDuration::hours(12),
+            0,
+            0,
+            vec![(
+                "test".to_string(),
+                vec![Some(0.), Some(600.), Some(50.), Some(2000.)],
+            )],
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Pass as i32,
+                Flag::Fail as i32,
+                Flag::Fail as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dew_point_check() {
+        let step = PipelineStep::new(
+            "dew_point_check",
+            CheckConf::DewPointCheck(DewPointCheckConf {
+                air_temperature_param: "air_temperature".to_string(),
+                tolerance: 0.5,
+            }),
+        );
+        let mut cache = series_cache(vec![Some(5.), Some(10.3), Some(11.), Some(5.), None], 0, 0);
+        cache
+            .add_param(
+                "air_temperature",
+                series_cache(vec![Some(10.), Some(10.), Some(10.), None, Some(10.)], 0, 0),
+            )
+            .unwrap();
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Pass as i32,
+                Flag::Fail as i32,
+                Flag::Inconclusive as i32,
+                Flag::DataMissing as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_first_guess_check() {
+        let step = PipelineStep::new(
+            "first_guess_check",
+            CheckConf::FirstGuessCheck(FirstGuessCheckConf {
+                source: "analysis".to_string(),
+                field: "air_temperature".to_string(),
+                elev_gradient: -0.0065,
+                pos: 2.,
+                neg: 2.,
+                elev_diff_tolerance: 0.,
+            }),
+        );
+        // station 200m above the gridpoint, so the first guess should be adjusted down by 1.3
+        let cache = DataCache::new(
+            vec![60.],
+            vec![10.],
+            vec![200.],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            vec![(
+                "test".to_string(),
+                vec![Some(8.7), Some(10.), Some(6.), None],
+            )],
+        );
+        let analysis = DataCache::new(
+            vec![60.],
+            vec![10.],
+            vec![0.],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            vec![(
+                "0".to_string(),
+                vec![Some(10.), Some(10.), Some(10.), Some(10.)],
+            )],
+        );
+        let backing_data = BackingData::from([(
+            ("analysis".to_string(), Some("air_temperature".to_string())),
+            analysis,
+        )]);
+
+        let response = run_test(&step, &cache, &backing_data).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Pass as i32,
+                Flag::Fail as i32,
+                Flag::DataMissing as i32,
+            ]
+        );
+        let scores: Vec<Option<f32>> = response
+            .results
+            .iter()
+            .map(|result| result.score.map(|score| (score * 10.).round() / 10.))
+            .collect();
+        assert_eq!(scores, vec![Some(0.), Some(1.3), Some(-2.7), None]);
+    }
+
+    #[test]
+    fn test_isolation_check() {
+        let step = PipelineStep::new(
+            "isolation_check",
+            CheckConf::IsolationCheck(IsolationCheckConf {
+                radius: 5000.,
+                num_min: 1,
+                max_elev_diff: Some(100.),
+            }),
+        );
+        // three stations close together, though one is much higher, and one far away
+        let cache = DataCache::new(
+            vec![60., 60.01, 60.02, 62.],
+            vec![10., 10., 10., 10.],
+            vec![0., 50., 500., 0.],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            vec![
+                ("a".to_string(), vec![Some(1.), Some(1.)]),
+                ("b".to_string(), vec![Some(1.), None]),
+                ("c".to_string(), vec![Some(1.), Some(1.)]),
+                ("d".to_string(), vec![Some(1.), Some(1.)]),
+            ],
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        // results are grouped by series, then ordered by time
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Isolated as i32,
+                Flag::Pass as i32,
+                Flag::DataMissing as i32,
+                Flag::Isolated as i32,
+                Flag::Isolated as i32,
+                Flag::Isolated as i32,
+                Flag::Isolated as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_buddy_event_check() {
+        let step = PipelineStep::new(
+            "buddy_event_check",
+            CheckConf::BuddyEventCheck(BuddyEventCheckConf {
+                radius: 10000.,
+                num_min: 2,
+                event_threshold: 0.1,
+                threshold: 0.5,
+                max_elev_diff: None,
+                num_iterations: 2,
+            }),
+        );
+        // it's raining at all the nearby stations but one, and one station is far from the others
+        let cache = DataCache::new(
+            vec![60., 60.01, 60.02, 60.03, 60.04, 62.],
+            vec![10.; 6],
+            vec![0.; 6],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            vec![
+                ("a".to_string(), vec![Some(1.)]),
+                ("b".to_string(), vec![Some(2.)]),
+                ("c".to_string(), vec![Some(0.)]),
+                ("d".to_string(), vec![Some(3.)]),
+                ("e".to_string(), vec![None]),
+                ("f".to_string(), vec![Some(0.)]),
+            ],
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Pass as i32,
+                Flag::Fail as i32,
+                Flag::Pass as i32,
+                Flag::DataMissing as i32,
+                Flag::Isolated as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_buddy_event_check_order() {
+        let conf = BuddyEventCheckConf {
+            radius: 10000.,
+            num_min: 1,
+            event_threshold: 0.1,
+            threshold: 0.6,
+            max_elev_diff: None,
+            num_iterations: 1,
+        };
+        // every station is a neighbour of every other
+        let neighbours: Vec<Vec<usize>> = (0..4)
+            .map(|i| (0..4).filter(|j| *j != i).collect())
+            .collect();
+        let values = vec![Some(1.), Some(2.), Some(0.), Some(0.)];
+
+        // no station has enough neighbours agreeing with it, whichever is looked at first
+        let flags = buddy_event_check(&values, &neighbours, &conf);
+        assert_eq!(flags, vec![Flag::Fail; 4]);
+        let reversed: Vec<Option<f32>> = values.iter().rev().copied().collect();
+        let mut reversed_flags = buddy_event_check(&reversed, &neighbours, &conf);
+        reversed_flags.reverse();
+        assert_eq!(reversed_flags, flags);
+    }
+
+    #[test]
+    fn test_sct_resistant() {
+        let step = PipelineStep::new(
+            "sct_resistant",
+            CheckConf::SctResistant(SctResistantConf {
+                num_min: 3,
+                num_max: 10,
+                outer_radius: 20000.,
+                num_iterations: 2,
+                min_elev_diff: 100.,
+                min_std: 0.5,
+                pos: 4.,
+                neg: 4.,
+            }),
+        );
+        // temperature dropping with elevation, with one outlier and one isolated station
+        let cache = DataCache::new(
+            vec![60., 60.01, 60.02, 60.03, 60.04, 60.05, 62.],
+            vec![10.; 7],
+            vec![0., 100., 200., 300., 400., 500., 0.],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            vec![
+                ("a".to_string(), vec![Some(10.)]),
+                ("b".to_string(), vec![Some(9.4)]),
+                ("c".to_string(), vec![Some(25.)]),
+                ("d".to_string(), vec![Some(8.)]),
+                ("e".to_string(), vec![Some(7.4)]),
+                ("f".to_string(), vec![Some(6.7)]),
+                ("g".to_string(), vec![Some(10.)]),
+            ],
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Pass as i32,
+                Flag::Fail as i32,
+                Flag::Pass as i32,
+                Flag::Pass as i32,
+                Flag::Pass as i32,
+                Flag::Isolated as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resistant_fit() {
+        assert_eq!(resistant_fit(&[], 0.), None);
+        // all at the same elevation, so there are no slopes to take the median of
+        assert_eq!(
+            resistant_fit(&[(100., 1.), (100., 2.), (100., 3.)], 0.),
+            Some((0., 2., 1.))
+        );
+    }
+
+    #[test]
+    fn test_metadata_check() {
+        let step = PipelineStep::new(
+            "metadata_check",
+            CheckConf::MetadataCheck(MetadataCheckConf {
+                min_elev: Some(-10.),
+                max_elev: Some(2500.),
+            }),
+        );
+        let cache = DataCache::new(
+            vec![60., 95., 60., 60.],
+            vec![10., 10., 10., f32::NAN],
+            vec![100., 100., 8000., 100.],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            vec![
+                ("a".to_string(), vec![Some(1.), Some(1.)]),
+                ("b".to_string(), vec![Some(1.), Some(1.)]),
+                ("c".to_string(), vec![Some(1.), None]),
+                ("d".to_string(), vec![Some(1.), Some(1.)]),
+            ],
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            [vec![Flag::Pass as i32; 2], vec![Flag::Invalid as i32; 6]].concat()
+        );
+    }
+
+    #[test]
+    fn test_roc_check() {
+        let step = PipelineStep::new(
+            "roc_check",
+            CheckConf::RocCheck(RocCheckConf {
+                window: 3,
+                max_per_hour: 1.,
+            }),
+        );
+        let cache = series_cache(
+            vec![
+                Some(0.),
+                Some(10.),
+                Some(-10.),
+                Some(2.),
+                Some(4.),
+                None,
+                Some(5.),
+            ],
+            3,
+            0,
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        // only the ends of each window matter, not the spike in the middle
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Fail as i32,
+                Flag::DataMissing as i32,
+                Flag::Pass as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_completeness_check() {
+        let step = PipelineStep::new(
+            "completeness_check",
+            CheckConf::CompletenessCheck(CompletenessCheckConf {
+                window: 3,
+                max_missing_fraction: Some(0.25),
+            }),
+        );
+        let cache = series_cache(
+            vec![Some(1.), None, Some(1.), Some(1.), Some(1.), None, Some(1.)],
+            3,
+            0,
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Pass as i32,
+                Flag::DataMissing as i32,
+                Flag::Pass as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_check() {
+        let step = PipelineStep::new(
+            "duplicate_check",
+            CheckConf::DuplicateCheck(DuplicateCheckConf {
+                max_lag: 1,
+                tolerance: 0.05,
+                min_overlap: 3,
+                radius: 500000.,
+            }),
+        );
+        let cache = DataCache::new(
+            vec![60., 61., 62., 63., 64.],
+            vec![10.; 5],
+            vec![0.; 5],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            vec![
+                (
+                    "a".to_string(),
+                    vec![Some(1.), Some(2.), Some(3.), Some(4.)],
+                ),
+                // a copy of a, an hour late
+                ("b".to_string(), vec![None, Some(1.), Some(2.01), Some(3.)]),
+                (
+                    "c".to_string(),
+                    vec![Some(1.), Some(5.), Some(2.), Some(4.)],
+                ),
+                // constant series shouldn't count as copies of each other
+                ("d".to_string(), vec![Some(0.); 4]),
+                ("e".to_string(), vec![Some(0.); 4]),
+            ],
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            [
+                vec![Flag::Warn as i32; 4],
+                vec![Flag::DataMissing as i32],
+                vec![Flag::Warn as i32; 3],
+                vec![Flag::Pass as i32; 12],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_widest_windows() {
+        let step = |check| PipelineStep::new("wide", check);
+        // the window plus the point being checked doesn't fit in a u8
+        let leading = series_cache(vec![Some(1.); 256], u8::MAX, 0);
+        let both = series_cache(vec![Some(1.); 511], u8::MAX, u8::MAX);
+
+        for (check, cache) in [
+            (
+                CheckConf::PersistenceCheck(PersistenceCheckConf {
+                    window: u8::MAX,
+                    min_std: 0.1,
+                }),
+                &leading,
+            ),
+            (
+                CheckConf::RocCheck(RocCheckConf {
+                    window: u8::MAX,
+                    max_per_hour: 1.,
+                }),
+                &leading,
+            ),
+            (
+                CheckConf::CompletenessCheck(CompletenessCheckConf {
+                    window: u8::MAX,
+                    max_missing_fraction: Some(0.5),
+                }),
+                &leading,
+            ),
+            (
+                CheckConf::MadCheck(MadCheckConf {
+                    window: u8::MAX,
+                    k: 5.,
+                    min_mad: 0.1,
+                }),
+                &both,
+            ),
+        ] {
+            let response = run_test(&step(check), cache, &BackingData::new()).unwrap();
+            assert_eq!(response.results.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_mad_check() {
+        let step = PipelineStep::new(
+            "mad_check",
+            CheckConf::MadCheck(MadCheckConf {
+                window: 2,
+                k: 5.,
+                min_mad: 0.1,
+            }),
+        );
+        let cache = series_cache(
+            vec![
+                Some(1.),
+                Some(1.2),
+                Some(0.9),
+                Some(5.),
+                Some(1.1),
+                Some(1.),
+                None,
+                None,
+            ],
+            2,
+            2,
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Fail as i32,
+                Flag::Pass as i32,
+                Flag::Inconclusive as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_time_shift_check() {
+        let step = PipelineStep::new(
+            "time_shift_check",
+            CheckConf::TimeShiftCheck(TimeShiftCheckConf {
+                radius: 50000.,
+                max_lag: 2,
+                min_overlap: 4,
+                min_improvement: 0.2,
+            }),
+        );
+        let signal = [1., 3., 2., 6., 4., 5., 9., 7., 8.];
+        let cache = DataCache::new(
+            vec![60., 60.1, 60.2],
+            vec![10.; 3],
+            vec![0.; 3],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            vec![
+                ("a".to_string(), signal.iter().map(|v| Some(*v)).collect()),
+                (
+                    "b".to_string(),
+                    signal.iter().map(|v| Some(v + 0.5)).collect(),
+                ),
+                // the same signal, an hour late
+                (
+                    "c".to_string(),
+                    std::iter::once(None)
+                        .chain(signal[..8].iter().map(|v| Some(*v)))
+                        .collect(),
+                ),
+            ],
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            [
+                vec![Flag::Pass as i32; 18],
+                vec![Flag::DataMissing as i32],
+                vec![Flag::Warn as i32; 8],
+            ]
+            .concat()
+        );
+        let shifts: Vec<Option<i32>> = response
+            .results
+            .iter()
+            .step_by(9)
+            .map(|result| result.estimated_shift)
+            .collect();
+        assert_eq!(shifts, vec![Some(0), Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn test_seasonal_range_check() {
+        let step = PipelineStep::new(
+            "seasonal_range_check",
+            CheckConf::SeasonalRangeCheck(SeasonalRangeCheckConf {
+                bands: vec![
+                    SeasonalBand::Months {
+                        months: vec![12, 1],
+                        min: -30.,
+                        max: 5.,
+                    },
+                    // 1st to 10th of february
+                    SeasonalBand::DayOfYear {
+                        start_day: 32,
+                        end_day: 41,
+                        min: -20.,
+                        max: 10.,
+                    },
+                ],
+            }),
+        );
+        let cache = DataCache::new(
+            vec![60.],
+            vec![10.],
+            vec![0.],
+            Timestamp(
+                Utc.with_ymd_and_hms(2023, 1, 31, 0, 0, 0)
+                    .unwrap()
+                    .timestamp(),
+            ),
+            RelativeDuration::days(10),
+            0,
+            0,
+            vec![("test".to_string(), vec![Some(8.), Some(8.), Some(8.), None])],
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Fail as i32,
+                Flag::Pass as i32,
+                Flag::Inconclusive as i32,
+                Flag::DataMissing as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_non_finite_values() {
+        let step = PipelineStep::new(
+            "step_check",
+            CheckConf::StepCheck(StepCheckConf { max: 3. }),
+        );
+        let cache = series_cache(
+            vec![
+                Some(1.),
+                Some(f32::NAN),
+                Some(2.),
+                Some(f32::INFINITY),
+                Some(3.),
+            ],
+            1,
+            0,
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Invalid as i32,
+                Flag::DataMissing as i32,
+                Flag::Invalid as i32,
+                Flag::DataMissing as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_buddy_check_missing_values() {
+        let step = PipelineStep::new(
+            "buddy_check",
+            CheckConf::BuddyCheck(BuddyCheckConf {
+                radii: vec![50000.],
+                nums_min: vec![2],
+                threshold: 2.,
+                max_elev_diff: 200.,
+                elev_gradient: 0.,
+                min_std: 1.,
+                num_iterations: 2,
+            }),
+        );
+        let cache = DataCache::new(
+            vec![60., 60.01, 60.02, 60.03],
+            vec![10.; 4],
+            vec![0.; 4],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            vec![
+                ("a".to_string(), vec![Some(1.), None]),
+                ("b".to_string(), vec![Some(1.), Some(1.)]),
+                ("c".to_string(), vec![None, Some(1.)]),
+                ("d".to_string(), vec![Some(20.), Some(1.)]),
+            ],
+        );
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::DataMissing as i32,
+                Flag::Pass as i32,
+                Flag::Pass as i32,
+                Flag::DataMissing as i32,
+                Flag::Pass as i32,
+                Flag::Fail as i32,
+                Flag::Pass as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sct_station_params() {
+        let conf = SctConf {
+            num_min: 5,
+            num_max: 100,
+            inner_radius: 50000.,
+            outer_radius: 150000.,
+            num_iterations: 5,
+            num_min_prof: 20,
+            min_elev_diff: 200.,
+            min_horizontal_scale: 10000.,
+            vertical_scale: 200.,
+            pos: vec![4.],
+            neg: vec![8.],
+            eps2: vec![0.5],
+            obs_to_check: None,
+            station_classes: vec![SctStationClass {
+                identifier_prefix: "netatmo:".to_string(),
+                pos: 2.,
+                neg: 3.,
+                eps2: 1.,
+            }],
+        };
+        let cache = DataCache::new(
+            vec![60., 61.],
+            vec![10.; 2],
+            vec![0.; 2],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            vec![
+                ("18700".to_string(), vec![Some(1.)]),
+                ("netatmo:70:ee:50".to_string(), vec![Some(1.)]),
+            ],
+        );
+
+        assert_eq!(
+            sct_station_params(&cache, &conf),
+            (vec![4., 2.], vec![8., 3.], vec![0.5, 1.])
+        );
+    }
+
+    #[test]
+    fn test_on_fail_override() {
+        let step = PipelineStep {
+            on_fail: Some(FlagOverride::Warn),
+            ..PipelineStep::new(
+                "range_check",
+                CheckConf::RangeCheck(RangeCheckConf { min: 0., max: 1. }),
+            )
+        };
+        let cache = series_cache(vec![Some(0.5), Some(2.), None], 0, 0);
+
+        let response = run_test(&step, &cache, &BackingData::new()).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Pass as i32,
+                Flag::Warn as i32,
+                Flag::DataMissing as i32
+            ]
+        );
+    }
+
+    #[test]
+    fn test_combine() {
+        let response = |test: &str, flags: [Flag; 2]| ValidateResponse {
+            test: test.to_string(),
+            results: flags
+                .into_iter()
+                .enumerate()
+                .map(|(i, flag)| TestResult {
+                    time: Some(prost_types::Timestamp {
+                        seconds: i as i64 * 3600,
+                        nanos: 0,
+                    }),
+                    identifier: "test".to_string(),
+                    flag: flag.into(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let responses = [
+            response("range_check", [Flag::Pass, Flag::Warn]),
+            response("step_check", [Flag::Inconclusive, Flag::Fail]),
+        ];
+
+        let combined = combine(
+            &responses,
+            &CombiConf {
+                precedence: vec![FlagName::Fail, FlagName::Warn, FlagName::Inconclusive],
+            },
+        );
+
+        assert_eq!(combined.test, COMBI_TEST_NAME);
+        assert_eq!(
+            flags(&combined),
+            vec![Flag::Inconclusive as i32, Flag::Fail as i32]
+        );
+    }
+
+    #[test]
+    fn test_run_if() {
+        let step = PipelineStep {
+            run_if: Some(RunIf {
+                step: "range_check".to_string(),
+                flag: FlagName::Pass,
+            }),
+            ..PipelineStep::new(
+                "special_value_check",
+                CheckConf::SpecialValueCheck(SpecialValueCheckConf {
+                    special_values: vec![-999.],
+                    substitute: None,
+                }),
+            )
+        };
+        let cache = series_cache(vec![Some(-999.), Some(-999.), Some(1.), None], 0, 0);
+        let range_step = PipelineStep::new(
+            "range_check",
+            CheckConf::RangeCheck(RangeCheckConf {
+                min: -1000.,
+                max: 0.,
+            }),
+        );
+        let condition_results = run_test(&range_step, &cache, &BackingData::new()).unwrap();
+
+        let response = run_test_if(
+            &step,
+            &cache,
+            &BackingData::new(),
+            Some(&condition_results),
+            run_test,
+        )
+        .unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Invalid as i32,
+                Flag::Invalid as i32,
+                Flag::Inconclusive as i32,
+                Flag::DataMissing as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_step_filter() {
+        let step = PipelineStep {
+            filter: StepFilter {
+                only_providers: Some(vec![1, 2]),
+                max_elevation: Some(1000.),
+                ..Default::default()
+            },
+            ..PipelineStep::new(
+                "range_check",
+                CheckConf::RangeCheck(RangeCheckConf { min: 0., max: 1. }),
+            )
+        };
+        // a mountain station, a ship, a station with no provider, and one that passes the filter
+        let cache = DataCache::new(
+            vec![60., 61., 62., 63.],
+            vec![10., 11., 12., 13.],
+            vec![2000., 0., 0., 100.],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            (0..4).map(|i| (i.to_string(), vec![Some(5.)])).collect(),
+        )
+        .with_providers(vec![Some(1), Some(3), None, Some(2)]);
+
+        let response = run_test_if(&step, &cache, &BackingData::new(), None, run_test).unwrap();
+
+        assert_eq!(
+            flags(&response),
+            vec![
+                Flag::Inconclusive as i32,
+                Flag::Inconclusive as i32,
+                Flag::Inconclusive as i32,
+                Flag::Fail as i32,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backing_series() {
+        let step = PipelineStep::new(
+            "buddy_check",
+            CheckConf::BuddyCheck(BuddyCheckConf {
+                radii: vec![50000.],
+                nums_min: vec![2],
+                threshold: 2.,
+                max_elev_diff: 200.,
+                elev_gradient: 0.,
+                min_std: 1.,
+                num_iterations: 2,
+            }),
+        );
+        let station = || {
+            DataCache::new(
+                vec![60.],
+                vec![10.],
+                vec![0.],
+                Timestamp(0),
+                RelativeDuration::hours(1),
+                0,
+                0,
+                vec![("a".to_string(), vec![Some(20.)])],
+            )
+        };
+        let backing = DataCache::new(
+            vec![60.01, 60.02, 60.03],
+            vec![10.; 3],
+            vec![0.; 3],
+            Timestamp(0),
+            RelativeDuration::hours(1),
+            0,
+            0,
+            vec![
+                ("b".to_string(), vec![Some(1.)]),
+                ("c".to_string(), vec![Some(1.)]),
+                ("d".to_string(), vec![Some(1.)]),
+            ],
+        );
+
+        // on its own the station has no buddies to fail against
+        let response = run_test_if(&step, &station(), &BackingData::new(), None, run_test).unwrap();
+        assert_eq!(flags(&response), vec![Flag::Pass as i32]);
+
+        let mut cache = station();
+        cache.add_backing("netatmo", backing).unwrap();
+        let response = run_test_if(&step, &cache, &BackingData::new(), None, run_test).unwrap();
+
+        assert_eq!(flags(&response), vec![Flag::Fail as i32]);
+        assert_eq!(response.results[0].identifier, "a");
+    }
+}
@@ -0,0 +1,65 @@
+use crate::{results::QcResult, runs::RunStatus};
+use std::{fmt, sync::Arc};
+
+/// Callbacks into the scheduler's runs, for auditing runs, recording metrics about them, or
+/// persisting their results, see [`Scheduler::with_hooks`](crate::Scheduler::with_hooks)
+///
+/// Every method does nothing by default, so implementations only need to override the ones they
+/// care about.
+///
+/// Hooks are called inline, some of them on tokio's async workers, so they should return
+/// quickly. Slow work, like writing to a database, should be handed off, e.g. over a channel to
+/// a task of its own.
+pub trait RunHooks: Send + Sync {
+    /// Called when a run is started, before it is queued or fetches any data
+    fn on_run_start(&self, _status: &RunStatus) {}
+
+    /// Called with the results of each step, and the combined results of all of them, just
+    /// before they are sent to the run's receiver
+    ///
+    /// Runs split into chunks call this once per step per chunk. Steps that fail are only
+    /// passed here if the run [continues on error](crate::RunOptions::continue_on_error), with
+    /// their [`error`](QcResult::error) set, otherwise the run fails, which is reported to
+    /// [`on_run_complete`](RunHooks::on_run_complete).
+    fn on_step_complete(&self, _result: &QcResult) {}
+
+    /// Called once a run finishes, whether it was done, failed or was cancelled
+    ///
+    /// Runs forgotten by the scheduler's [run history](crate::Scheduler::with_run_history)
+    /// before they finish aren't reported.
+    fn on_run_complete(&self, _status: &RunStatus) {}
+}
+
+/// The hooks registered on a scheduler, called in the order they were registered
+#[derive(Clone, Default)]
+pub(crate) struct Hooks(Vec<Arc<dyn RunHooks>>);
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hooks({} registered)", self.0.len())
+    }
+}
+
+impl Hooks {
+    pub fn push(&mut self, hooks: Arc<dyn RunHooks>) {
+        self.0.push(hooks);
+    }
+
+    pub fn run_start(&self, status: &RunStatus) {
+        for hooks in &self.0 {
+            hooks.on_run_start(status);
+        }
+    }
+
+    pub fn step_complete(&self, result: &QcResult) {
+        for hooks in &self.0 {
+            hooks.on_step_complete(result);
+        }
+    }
+
+    pub fn run_complete(&self, status: &RunStatus) {
+        for hooks in &self.0 {
+            hooks.on_run_complete(status);
+        }
+    }
+}
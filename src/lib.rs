@@ -11,16 +11,16 @@
 //!     data_switch::{DataSwitch, DataConnector},
 //!     dev_utils::{TestDataSource, construct_hardcoded_pipeline},
 //! };
-//! use std::collections::HashMap;
+//! use std::{collections::HashMap, sync::Arc};
 //!
 //! #[tokio::main]
 //! async fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!     let data_switch = DataSwitch::new(HashMap::from([
-//!         ("test", &TestDataSource{
+//!         ("test", Arc::new(TestDataSource {
 //!             data_len_single: 3,
 //!             data_len_series: 1000,
 //!             data_len_spatial: 1000,
-//!         } as &dyn DataConnector),
+//!         }) as Arc<dyn DataConnector + Send + Sync>),
 //!     ]));
 //!
 //!     start_server(
@@ -39,18 +39,18 @@
 //!     data_switch::{DataSwitch, DataConnector, Timestamp, Timerange, TimeSpec, SpaceSpec},
 //!     dev_utils::{TestDataSource, construct_hardcoded_pipeline},
 //! };
-//! use std::collections::HashMap;
+//! use std::{collections::HashMap, sync::Arc};
 //! use chrono::{Utc, TimeZone};
 //! use chronoutil::RelativeDuration;
 //!
 //! #[tokio::main]
 //! async fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!     let data_switch = DataSwitch::new(HashMap::from([
-//!         ("test", &TestDataSource{
+//!         ("test", Arc::new(TestDataSource {
 //!             data_len_single: 3,
 //!             data_len_series: 1000,
 //!             data_len_spatial: 1000,
-//!         } as &dyn DataConnector),
+//!         }) as Arc<dyn DataConnector + Send + Sync>),
 //!     ]));
 //!
 //!     let rove_scheduler = Scheduler::new(construct_hardcoded_pipeline(), data_switch);
@@ -81,8 +81,8 @@
 //!             Ok(inner) => {
 //!                 println!("\ntest name: {}\n", inner.test);
 //!                 for result in inner.results {
-//!                     println!("timestamp: {}", result.time.unwrap().seconds);
-//!                     println!("flag: {}", result.flag);
+//!                     println!("timestamp: {}", result.time.0);
+//!                     println!("flag: {:?}", result.flag);
 //!                 }
 //!             }
 //!             Err(e) => println!("uh oh, got an error: {}", e),
@@ -92,20 +92,60 @@
 //!     Ok(())
 //! }
 //! ```
+//!
+//! With the `metrics` feature, the scheduler records metrics through the
+//! [metrics](https://docs.rs/metrics) facade, for whichever recorder the
+//! application installs:
+//! - `rove_runs_started_total`, and `rove_runs_finished_total` with the
+//!   `state` the run finished in, counted by `pipeline`
+//! - `rove_run_duration_seconds`, by `pipeline` and `state`
+//! - `rove_step_duration_seconds` and `rove_step_failures_total`, by
+//!   `pipeline` and `step`
+//! - `rove_fetched_series` and `rove_fetched_points`, the size of the data
+//!   fetched for each run or chunk, by `pipeline`
+//! - `rove_flags_total`, the flags sent, by `pipeline`, `step` and `flag`
 
 #![warn(missing_docs)]
 
+mod client_limits;
 pub mod data_switch;
 mod harness;
+mod hooks;
+mod monitoring;
+pub mod periodic;
 mod pipeline;
+mod replay;
+mod results;
+mod run_queue;
+mod runner;
+mod runs;
 mod scheduler;
 mod server;
+mod shutdown;
+mod transport;
+pub mod units;
+
+pub use pipeline::{
+    load_pipelines, load_pipelines_with_vars, load_routes, pipeline_schema, Pipeline,
+    PipelineBuilder, PipelineRoutes,
+};
+
+pub use client_limits::ClientLimits;
+
+pub use hooks::RunHooks;
 
-pub use pipeline::{load_pipelines, Pipeline};
+pub use results::{Flag, PipelineMetadata, QcResult, TestResult};
 
-pub use scheduler::Scheduler;
+pub use runs::{RunId, RunState, RunStatus};
 
-pub use server::start_server;
+pub use scheduler::{Priority, RunLimits, RunOptions, Scheduler, ValidateSpec};
+
+pub use server::{
+    start_server, start_server_with_admin, start_server_with_config, start_server_with_scheduler,
+    ServerConfig,
+};
+
+pub use transport::TransportConfig;
 
 #[doc(hidden)]
 pub use server::start_server_unix_listener;
@@ -131,20 +171,45 @@ pub(crate) mod pb {
     }
 }
 
-#[doc(hidden)]
+/// Helpers for testing code built on rove, and pipelines
+///
+/// [`TestDataSource`](dev_utils::TestDataSource) and
+/// [`construct_hardcoded_pipeline`](dev_utils::construct_hardcoded_pipeline) give a data source
+/// and pipeline to exercise a server or scheduler with, as in the examples above.
+/// [`assert_fixture`](dev_utils::assert_fixture) regression tests a pipeline against fixed input
+/// data and the flags it is expected to give.
 pub mod dev_utils {
     use crate::{
-        data_switch::{self, DataCache, DataConnector, SpaceSpec, TimeSpec, Timestamp},
-        pipeline::{derive_num_leading_trailing, Pipeline},
+        data_switch::{
+            self, DataCache, DataConnector, DataSwitch, SpaceSpec, StationMetadata, TimeSpec,
+            Timestamp,
+        },
+        pipeline::{derive_num_leading_trailing, FlagName, Pipeline},
+        results::Flag,
+        scheduler::{self, Scheduler},
     };
     use async_trait::async_trait;
+    use chrono::{DateTime, TimeZone, Utc};
     use chronoutil::RelativeDuration;
-    use std::{collections::HashMap, hint::black_box};
+    use serde::Deserialize;
+    use std::{
+        collections::{BTreeMap, HashMap},
+        hint::black_box,
+        sync::Arc,
+    };
 
+    /// Data source serving constant data of configurable size, for tests and benchmarks
+    ///
+    /// [`SpaceSpec::One`] serves one series, of `data_len_single` points for the id `single`, or
+    /// `data_len_series` points for the id `series`. [`SpaceSpec::All`] serves
+    /// `data_len_spatial` series spread over a small area. Other space specs aren't supported.
     #[derive(Debug)]
     pub struct TestDataSource {
+        /// Number of points in the series with the id `single`
         pub data_len_single: usize,
+        /// Number of points in the series with the id `series`
         pub data_len_series: usize,
+        /// Number of series served for [`SpaceSpec::All`]
         pub data_len_spatial: usize,
     }
 
@@ -207,12 +272,13 @@ pub mod dev_utils {
                         self.data_len_spatial
                     ],
                 ))),
-                SpaceSpec::Polygon(_) => unimplemented!(),
+                SpaceSpec::Polygon(_) | SpaceSpec::Multiple(_) => unimplemented!(),
             }
         }
     }
 
     // TODO: replace this by just loading a sample pipeline toml?
+    /// A pipeline of a few timeseries and spatial checks, keyed by its name, `hardcoded`
     pub fn construct_hardcoded_pipeline() -> HashMap<String, Pipeline> {
         let mut pipeline = toml::from_str(
             r#"
@@ -263,4 +329,316 @@ pub mod dev_utils {
 
         HashMap::from([(String::from("hardcoded"), pipeline)])
     }
+
+    /// Input data for testing a pipeline, see [`assert_fixture`]
+    ///
+    /// In JSON, this looks like:
+    ///
+    /// ```json
+    /// {
+    ///     "start_time": 1700000000,
+    ///     "period": "PT1H",
+    ///     "num_leading_points": 1,
+    ///     "series": [
+    ///         {"identifier": "18700", "lat": 59.9, "lon": 10.7, "elev": 94.0,
+    ///          "values": [1.0, 1.2, null, 1.4]}
+    ///     ]
+    /// }
+    /// ```
+    #[derive(Debug, Deserialize, Clone)]
+    pub struct Fixture {
+        /// Unix timestamp of the first QCed point, i.e. the first point after the leading ones
+        pub start_time: i64,
+        /// ISO 8601 duration between successive points
+        pub period: String,
+        /// Number of points at the start of each series that are context rather than QCed
+        #[serde(default)]
+        pub num_leading_points: u8,
+        /// Number of points at the end of each series that are context rather than QCed
+        #[serde(default)]
+        pub num_trailing_points: u8,
+        /// The series, all the same length, which must be at least the number of leading and
+        /// trailing points
+        pub series: Vec<FixtureSeries>,
+    }
+
+    /// One series of a [`Fixture`]
+    #[derive(Debug, Deserialize, Clone)]
+    pub struct FixtureSeries {
+        /// Identifier of the series, which its flags are keyed by
+        pub identifier: String,
+        /// Latitude of the station
+        pub lat: f32,
+        /// Longitude of the station
+        pub lon: f32,
+        /// Elevation of the station, in metres
+        #[serde(default)]
+        pub elev: f32,
+        /// Provider of the series, for steps that filter by provider
+        #[serde(default)]
+        pub provider: Option<i32>,
+        /// Type of the station, for steps that filter by station type
+        #[serde(default)]
+        pub station_type: Option<String>,
+        /// `null`s represent gaps
+        pub values: Vec<Option<f32>>,
+        /// Extra parameters for checks that use them, aligned with `values`
+        #[serde(default)]
+        pub params: HashMap<String, Vec<Option<f32>>>,
+    }
+
+    /// Flags from a pipeline run, keyed by step name, then series identifier
+    pub type FixtureFlags = BTreeMap<String, BTreeMap<String, Vec<FlagName>>>;
+
+    impl Fixture {
+        /// Parse a fixture from JSON
+        pub fn from_json(s: &str) -> Result<Self, serde_json::Error> {
+            serde_json::from_str(s)
+        }
+
+        /// Check that every series, and extra parameter, has the same number of points, and at
+        /// least the fixture's leading and trailing points
+        fn check_lengths(&self) -> Result<(), data_switch::Error> {
+            let context = self.num_leading_points as usize + self.num_trailing_points as usize;
+            let Some(len) = self.series.first().map(|series| series.values.len()) else {
+                return Ok(());
+            };
+            if len < context {
+                return Err(data_switch::Error::Other(
+                    format!(
+                        "fixture series have {} points, fewer than their {} leading and trailing \
+                        points",
+                        len, context
+                    )
+                    .into(),
+                ));
+            }
+            for series in &self.series {
+                let mut lens = std::iter::once(series.values.len())
+                    .chain(series.params.values().map(Vec::len));
+                if lens.any(|other| other != len) {
+                    return Err(data_switch::Error::Other(
+                        format!(
+                            "fixture series {} or one of its parameters doesn't have the {} points \
+                            the first series has",
+                            series.identifier, len
+                        )
+                        .into(),
+                    ));
+                }
+            }
+            Ok(())
+        }
+
+        fn period(&self) -> Result<RelativeDuration, data_switch::Error> {
+            RelativeDuration::parse_from_iso8601(&self.period)
+                .map_err(|e| data_switch::Error::Other(e.to_string().into()))
+        }
+
+        /// Fit a fixture series to the number of leading and trailing points requested, by
+        /// dropping context points or padding with gaps
+        ///
+        /// The series must have passed [`check_lengths`](Fixture::check_lengths).
+        fn fit(&self, values: &[Option<f32>], leading: u8, trailing: u8) -> Vec<Option<f32>> {
+            let (fixture_leading, fixture_trailing) = (
+                self.num_leading_points as usize,
+                self.num_trailing_points as usize,
+            );
+            let (leading, trailing) = (leading as usize, trailing as usize);
+            let qc_end = values.len() - fixture_trailing;
+
+            std::iter::repeat_n(None, leading.saturating_sub(fixture_leading))
+                .chain(
+                    values[fixture_leading.saturating_sub(leading)..qc_end]
+                        .iter()
+                        .copied(),
+                )
+                .chain(
+                    values[qc_end..qc_end + fixture_trailing.min(trailing)]
+                        .iter()
+                        .copied(),
+                )
+                .chain(std::iter::repeat_n(
+                    None,
+                    trailing.saturating_sub(fixture_trailing),
+                ))
+                .collect()
+        }
+
+        /// Time of the `i`th QCed point
+        fn time(&self, i: usize) -> Result<DateTime<Utc>, data_switch::Error> {
+            Ok(Utc
+                .timestamp_opt(self.start_time, 0)
+                .single()
+                .ok_or_else(|| {
+                    data_switch::Error::Other("fixture start_time out of range".into())
+                })?
+                + self.period()? * i as i32)
+        }
+
+        fn num_qc_points(&self) -> usize {
+            self.series.first().map_or(0, |series| {
+                series.values.len().saturating_sub(
+                    self.num_leading_points as usize + self.num_trailing_points as usize,
+                )
+            })
+        }
+    }
+
+    /// Serves a fixture's data regardless of the space and time spec
+    ///
+    /// Extra parameters are fetched by passing their name as `extra_spec`
+    #[async_trait]
+    impl DataConnector for Fixture {
+        async fn fetch_data(
+            &self,
+            _space_spec: &SpaceSpec,
+            _time_spec: &TimeSpec,
+            num_leading_points: u8,
+            num_trailing_points: u8,
+            extra_spec: Option<&str>,
+        ) -> Result<DataCache, data_switch::Error> {
+            self.check_lengths()?;
+            let period = self.period()?;
+            let start_time = self.time(0)? + period * -i32::from(num_leading_points);
+
+            let data = self
+                .series
+                .iter()
+                .map(|series| {
+                    let values = match extra_spec {
+                        None => Some(&series.values),
+                        Some(param) => series.params.get(param),
+                    };
+                    (
+                        series.identifier.clone(),
+                        match values {
+                            Some(values) => {
+                                self.fit(values, num_leading_points, num_trailing_points)
+                            }
+                            None => vec![
+                                None;
+                                self.num_qc_points()
+                                    + num_leading_points as usize
+                                    + num_trailing_points as usize
+                            ],
+                        },
+                    )
+                })
+                .collect();
+
+            Ok(DataCache::new(
+                self.series.iter().map(|series| series.lat).collect(),
+                self.series.iter().map(|series| series.lon).collect(),
+                self.series.iter().map(|series| series.elev).collect(),
+                Timestamp(start_time.timestamp()),
+                period,
+                num_leading_points,
+                num_trailing_points,
+                data,
+            )
+            .with_metadata(
+                self.series
+                    .iter()
+                    .map(|series| StationMetadata {
+                        provider: series.provider,
+                        station_type: series.station_type.clone(),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ))
+        }
+    }
+
+    fn flag_name(flag: Flag) -> FlagName {
+        match flag {
+            Flag::Pass => FlagName::Pass,
+            Flag::Fail => FlagName::Fail,
+            Flag::Warn => FlagName::Warn,
+            Flag::Inconclusive => FlagName::Inconclusive,
+            Flag::Invalid => FlagName::Invalid,
+            Flag::DataMissing => FlagName::DataMissing,
+            Flag::Isolated => FlagName::Isolated,
+        }
+    }
+
+    /// Run a pipeline on a fixture's data, through a [`Scheduler`], without a gRPC server
+    ///
+    /// Checks that need backing data from other data sources are not supported.
+    pub async fn run_fixture(
+        pipeline: &Pipeline,
+        fixture: &Fixture,
+    ) -> Result<FixtureFlags, scheduler::Error> {
+        fixture.check_lengths()?;
+        let time_spec = TimeSpec::new(
+            Timestamp(fixture.start_time),
+            Timestamp(
+                fixture
+                    .time(fixture.num_qc_points().saturating_sub(1))?
+                    .timestamp(),
+            ),
+            fixture.period()?,
+        );
+
+        let scheduler = Scheduler::new(
+            HashMap::from([(String::from("fixture"), pipeline.clone())]),
+            DataSwitch::new(HashMap::from([(
+                "fixture",
+                Arc::new(fixture.clone()) as Arc<dyn DataConnector + Send + Sync>,
+            )])),
+        );
+        let mut rx = scheduler
+            .validate_direct(
+                "fixture",
+                &[] as &[&str],
+                &time_spec,
+                &SpaceSpec::All,
+                "fixture",
+                None,
+            )
+            .await?;
+
+        let mut flags = FixtureFlags::new();
+        while let Some(response) = rx.recv().await {
+            let response = response?;
+            let step_flags = flags.entry(response.test).or_default();
+            for result in response.results {
+                step_flags
+                    .entry(result.identifier)
+                    .or_default()
+                    .push(flag_name(result.flag));
+            }
+        }
+
+        Ok(flags)
+    }
+
+    /// Assert that running a pipeline on some data gives the expected flags, for regression
+    /// testing pipelines
+    ///
+    /// `pipeline` is a pipeline in TOML, as in pipeline files, `fixture` is the input data in
+    /// JSON (see [`Fixture`]), and `expected` maps step names to series identifiers to flags in
+    /// JSON, e.g. `{"range_check": {"18700": ["pass", "fail", "data_missing", "pass"]}}`. Steps
+    /// missing from `expected` are not checked.
+    ///
+    /// # Panics
+    ///
+    /// If any of the inputs are invalid, the pipeline fails to run, or the flags don't match
+    pub async fn assert_fixture(pipeline: &str, fixture: &str, expected: &str) {
+        let pipeline = Pipeline::from_toml(pipeline).expect("pipeline should be valid");
+        let fixture = Fixture::from_json(fixture).expect("fixture should be valid");
+        let expected: FixtureFlags =
+            serde_json::from_str(expected).expect("expected flags should be valid");
+
+        let flags = run_fixture(&pipeline, &fixture)
+            .await
+            .expect("pipeline should run on the fixture");
+
+        for (step, expected_step_flags) in expected {
+            let step_flags = flags
+                .get(&step)
+                .unwrap_or_else(|| panic!("step {} did not produce any flags", step));
+            assert_eq!(step_flags, &expected_step_flags, "flags of step {}", step);
+        }
+    }
 }
@@ -0,0 +1,106 @@
+//! Metrics about what the scheduler is doing, recorded through the
+//! [`metrics`](https://docs.rs/metrics) facade if the `metrics` feature is enabled, and
+//! otherwise not at all
+
+// without the feature, these functions are all no-ops
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+
+use crate::{data_switch::DataCache, results::QcResult, runs::RunStatus};
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+use crate::{results::Flag, runs::RunState};
+#[cfg(feature = "metrics")]
+use metrics::{counter, histogram};
+
+#[cfg(feature = "metrics")]
+fn flag_label(flag: Flag) -> &'static str {
+    match flag {
+        Flag::Pass => "pass",
+        Flag::Fail => "fail",
+        Flag::Warn => "warn",
+        Flag::Inconclusive => "inconclusive",
+        Flag::Invalid => "invalid",
+        Flag::DataMissing => "data_missing",
+        Flag::Isolated => "isolated",
+    }
+}
+
+pub(crate) fn run_started(pipeline: &str) {
+    #[cfg(feature = "metrics")]
+    counter!("rove_runs_started_total", "pipeline" => pipeline.to_string()).increment(1);
+}
+
+/// Record a run that just reached a finished state
+pub(crate) fn run_finished(status: &RunStatus) {
+    #[cfg(feature = "metrics")]
+    {
+        let state = match status.state {
+            RunState::Done => "done",
+            RunState::Failed => "failed",
+            RunState::Cancelled => "cancelled",
+            RunState::Queued | RunState::Fetching | RunState::Running(_) => return,
+        };
+        let labels = [
+            ("pipeline", status.pipeline.clone()),
+            ("state", state.to_string()),
+        ];
+        counter!("rove_runs_finished_total", &labels).increment(1);
+        histogram!("rove_run_duration_seconds", &labels).record(
+            // negative if the clock was turned back during the run
+            (status.updated_at - status.started_at)
+                .to_std()
+                .unwrap_or_default()
+                .as_secs_f64(),
+        );
+    }
+}
+
+/// Record the size of the data fetched for a run, or for one chunk of it
+pub(crate) fn data_fetched(pipeline: &str, data: &DataCache) {
+    #[cfg(feature = "metrics")]
+    {
+        let num_series = (0..data.data.len())
+            .filter(|i| !data.is_backing(*i))
+            .count();
+        let num_points: usize = data.data.iter().map(|(_, series)| series.len()).sum();
+        histogram!("rove_fetched_series", "pipeline" => pipeline.to_string())
+            .record(num_series as f64);
+        histogram!("rove_fetched_points", "pipeline" => pipeline.to_string())
+            .record(num_points as f64);
+    }
+}
+
+pub(crate) fn step_finished(pipeline: &str, step: &str, duration: Duration, failed: bool) {
+    #[cfg(feature = "metrics")]
+    {
+        let labels = [
+            ("pipeline", pipeline.to_string()),
+            ("step", step.to_string()),
+        ];
+        histogram!("rove_step_duration_seconds", &labels).record(duration.as_secs_f64());
+        if failed {
+            counter!("rove_step_failures_total", &labels).increment(1);
+        }
+    }
+}
+
+/// Tally the flags in results about to be sent, by pipeline, step and flag
+pub(crate) fn flags_sent(result: &QcResult) {
+    #[cfg(feature = "metrics")]
+    {
+        let mut tallies: std::collections::HashMap<Flag, u64> = std::collections::HashMap::new();
+        for test_result in &result.results {
+            *tallies.entry(test_result.flag).or_default() += 1;
+        }
+        for (flag, count) in tallies {
+            counter!(
+                "rove_flags_total",
+                "pipeline" => result.pipeline.name.clone(),
+                "step" => result.test.clone(),
+                "flag" => flag_label(flag)
+            )
+            .increment(count);
+        }
+    }
+}
@@ -0,0 +1,495 @@
+//! Recurring QC jobs, run by the [`Scheduler`] on a cron schedule
+//!
+//! This lets a service QC fresh data as it arrives, without an external cron wrapper sending it
+//! requests. Jobs are usually loaded from a TOML file with [`load_jobs`], like:
+//!
+//! ```toml
+//! [[job]]
+//! name = "ta_hourly"
+//! # five minutes past every hour
+//! schedule = "5 * * * *"
+//! data_source = "frost"
+//! extra_spec = "air_temperature"
+//! pipeline = "TA_PT1H"
+//! space_spec = "all"
+//! time_resolution = "PT1H"
+//! # QC the last three hours, in case late data has come in since the last run
+//! window = "PT3H"
+//! # give data five minutes to arrive, so this run QCs up to the top of the hour
+//! delay = "PT5M"
+//! ```
+//!
+//! and run with [`run_jobs`], which sends the results of every run to a [`ResultSink`].
+
+use crate::{
+    data_switch::{SpaceSpec, TimeSpec, Timestamp},
+    results::QcResult,
+    scheduler::{self, Scheduler},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chronoutil::RelativeDuration;
+use croner::Cron;
+use serde::Deserialize;
+use std::{path::Path, str::FromStr, sync::Arc};
+use thiserror::Error;
+use tokio::sync::mpsc::Sender;
+
+/// Error type for periodic jobs
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Generic IO error
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    /// TOML deserialize error
+    #[error("failed to deserialize toml: {0}")]
+    TomlDeserialize(#[from] toml::de::Error),
+    /// A cron expression could not be parsed
+    #[error("invalid schedule `{0}`: {1}")]
+    InvalidSchedule(String, String),
+    /// An ISO 8601 duration could not be parsed
+    #[error("invalid duration `{0}`")]
+    InvalidDuration(String),
+    /// Two jobs have the same name
+    #[error("job name `{0}` is used more than once")]
+    DuplicateJob(String),
+}
+
+/// A cron schedule, in UTC
+///
+/// Parsed from the usual five fields: minute, hour, day of month, month and day of week, where
+/// Sunday is either 0 or 7. Fields can be `*`, a value, a range like `1-5`, a step like `*/15` or
+/// `0-30/10`, or a comma separated list of those. Months and days of the week can also be named,
+/// like `JAN` or `MON`, and nicknames like `@hourly` stand in for all five fields. As in most
+/// crons, if both the day of month and day of week are restricted, a day matching either of them
+/// matches.
+#[derive(Debug, Clone)]
+pub struct Schedule(Cron);
+
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Cron::new(s)
+            .parse()
+            .map(Schedule)
+            .map_err(|e| Error::InvalidSchedule(s.to_string(), e.to_string()))
+    }
+}
+
+impl Schedule {
+    /// The first time strictly after `time` that matches the schedule
+    ///
+    /// Returns `None` if nothing matches, as for `0 0 30 2 *`.
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.0.find_next_occurrence(&time, false).ok()
+    }
+}
+
+fn parse_duration(s: &str) -> Result<RelativeDuration, Error> {
+    RelativeDuration::parse_from_iso8601(s).map_err(|_| Error::InvalidDuration(s.to_string()))
+}
+
+fn default_space_spec() -> SpaceSpec {
+    SpaceSpec::All
+}
+
+/// A recurring QC job, see the [module docs](self)
+#[derive(Debug, Clone, Deserialize)]
+pub struct Job {
+    /// Name of the job, for telling its results apart from other jobs'
+    pub name: String,
+    /// When to run the job, as a cron expression, see [`Schedule`]
+    pub schedule: String,
+    /// Data source to QC data from, as in
+    /// [`Scheduler::validate_direct`](crate::Scheduler::validate_direct)
+    pub data_source: String,
+    /// Data sources whose series are only used to QC the data source's
+    #[serde(default)]
+    pub backing_sources: Vec<String>,
+    /// Extra identifier passed to the data source's connector
+    pub extra_spec: Option<String>,
+    /// Name of the pipeline to run
+    pub pipeline: String,
+    /// Which series to QC, defaults to all of them
+    #[serde(default = "default_space_spec")]
+    pub space_spec: SpaceSpec,
+    /// Time resolution of the data to QC, as an ISO 8601 duration
+    pub time_resolution: String,
+    /// How far back to QC from the end of each run's timerange, as an ISO 8601 duration, defaults
+    /// to one time step
+    pub window: Option<String>,
+    /// How long before the scheduled time each run's timerange ends, as an ISO 8601 duration, to
+    /// give data time to arrive. Defaults to no delay
+    ///
+    /// The scheduled time minus the delay should land on a time step of the data, as it isn't
+    /// rounded to one.
+    pub delay: Option<String>,
+}
+
+impl Job {
+    /// The cron schedule of the job
+    ///
+    /// # Errors
+    ///
+    /// If the schedule isn't a valid cron expression
+    pub fn schedule(&self) -> Result<Schedule, Error> {
+        self.schedule.parse()
+    }
+
+    /// What to QC in the run scheduled at `scheduled_time`
+    ///
+    /// This is the last `window` of data before `scheduled_time - delay`, e.g. with a window of
+    /// `PT3H` and a time resolution of `PT1H`, a run at 12:00 QCs 10:00, 11:00 and 12:00.
+    ///
+    /// # Errors
+    ///
+    /// If any of the job's durations can't be parsed
+    pub fn time_spec(&self, scheduled_time: DateTime<Utc>) -> Result<TimeSpec, Error> {
+        let time_resolution = parse_duration(&self.time_resolution)?;
+        let window = match &self.window {
+            Some(window) => parse_duration(window)?,
+            None => time_resolution,
+        };
+        let delay = match &self.delay {
+            Some(delay) => parse_duration(delay)?,
+            None => RelativeDuration::zero(),
+        };
+
+        let end = scheduled_time - delay;
+        // the timerange is inclusive at both ends
+        let start = end - window + time_resolution;
+        Ok(TimeSpec::new(
+            Timestamp(start.timestamp()),
+            Timestamp(end.timestamp()),
+            time_resolution,
+        ))
+    }
+
+    /// Check the job's schedule and durations can be parsed
+    ///
+    /// # Errors
+    ///
+    /// If they can't
+    pub fn validate(&self) -> Result<(), Error> {
+        self.schedule()?;
+        self.time_spec(Utc::now())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Jobs {
+    #[serde(rename = "job", default)]
+    jobs: Vec<Job>,
+}
+
+/// Load recurring jobs from a TOML file, see the [module docs](self)
+///
+/// # Errors
+///
+/// If the file can't be read or deserialized, or any of the jobs are invalid
+pub fn load_jobs(path: impl AsRef<Path>) -> Result<Vec<Job>, Error> {
+    let Jobs { jobs } = toml::from_str(&std::fs::read_to_string(path)?)?;
+    for (i, job) in jobs.iter().enumerate() {
+        job.validate()?;
+        if jobs[..i].iter().any(|other| other.name == job.name) {
+            return Err(Error::DuplicateJob(job.name.clone()));
+        }
+    }
+    Ok(jobs)
+}
+
+/// A result of a run of a periodic job, passed to a [`ResultSink`]
+#[derive(Debug)]
+pub struct JobOutput {
+    /// Name of the job
+    pub job: String,
+    /// When the run was scheduled, which may be a little before it actually started
+    pub scheduled_time: Timestamp,
+    /// One of the results of the run, as would be received from
+    /// [`Scheduler::validate_direct`](crate::Scheduler::validate_direct), or the error that
+    /// stopped the run from starting
+    pub result: Result<QcResult, scheduler::Error>,
+}
+
+/// Somewhere to send the results of periodic jobs
+#[async_trait]
+pub trait ResultSink {
+    /// Handle one result of a run
+    ///
+    /// Runs wait for this to return before sending their next result, so slow sinks slow down
+    /// the runs feeding them.
+    async fn publish(&self, output: JobOutput);
+}
+
+/// Sends results down a channel, dropping them if the receiver has been dropped
+#[async_trait]
+impl ResultSink for Sender<JobOutput> {
+    async fn publish(&self, output: JobOutput) {
+        // if this fails the receiver was dropped, and there's nobody left to tell
+        let _ = self.send(output).await;
+    }
+}
+
+/// Logs a summary of each result, for trying out jobs before they have somewhere to go
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogSink;
+
+#[async_trait]
+impl ResultSink for LogSink {
+    async fn publish(&self, output: JobOutput) {
+        match output.result {
+            Ok(response) => tracing::info!(
+                job = %output.job,
+                scheduled_time = output.scheduled_time.0,
+                test = %response.test,
+                num_results = response.results.len(),
+                "Periodic job step finished."
+            ),
+            Err(e) => tracing::error!(
+                job = %output.job,
+                scheduled_time = output.scheduled_time.0,
+                %e,
+                "Periodic job failed."
+            ),
+        }
+    }
+}
+
+/// Run one scheduled run of a job, publishing its results as they come in
+async fn run_job_once(
+    scheduler: &Scheduler,
+    job: &Job,
+    scheduled_time: DateTime<Utc>,
+    sink: &(dyn ResultSink + Send + Sync),
+) {
+    let output = |result| JobOutput {
+        job: job.name.clone(),
+        scheduled_time: Timestamp(scheduled_time.timestamp()),
+        result,
+    };
+
+    let time_spec = match job.time_spec(scheduled_time) {
+        Ok(time_spec) => time_spec,
+        Err(e) => {
+            // jobs are validated before they're run, so this shouldn't happen
+            tracing::error!(job = %job.name, %e, "Invalid periodic job.");
+            return;
+        }
+    };
+
+    match scheduler
+        .validate_direct(
+            &job.data_source,
+            &job.backing_sources,
+            &time_spec,
+            &job.space_spec,
+            &job.pipeline,
+            job.extra_spec.as_deref(),
+        )
+        .await
+    {
+        Ok(mut rx) => {
+            while let Some(result) = rx.recv().await {
+                sink.publish(output(result)).await;
+            }
+        }
+        Err(e) => sink.publish(output(Err(e))).await,
+    }
+}
+
+async fn run_job(scheduler: &Scheduler, job: &Job, sink: &(dyn ResultSink + Send + Sync)) {
+    let schedule = match job.schedule() {
+        Ok(schedule) => schedule,
+        Err(e) => {
+            // jobs are validated before they're run, so this shouldn't happen
+            tracing::error!(job = %job.name, %e, "Invalid periodic job.");
+            return;
+        }
+    };
+
+    // runs of a job don't overlap, if one overruns the next scheduled time, that time is skipped
+    let mut last_run = Utc::now();
+    while let Some(next_run) = schedule.next_after(last_run.max(Utc::now())) {
+        tokio::time::sleep((next_run - Utc::now()).to_std().unwrap_or_default()).await;
+        run_job_once(scheduler, job, next_run, sink).await;
+        last_run = next_run;
+    }
+
+    tracing::warn!(job = %job.name, "Schedule has no more runs, stopping the job.");
+}
+
+/// Run `jobs` on their schedules with `scheduler`, sending their results to `sink`
+///
+/// Each job's runs happen one after the other, but different jobs run concurrently. This only
+/// returns once none of the jobs' schedules have any more runs, which for most schedules is
+/// never, so it's usually spawned alongside the server.
+///
+/// # Errors
+///
+/// If any of the jobs are invalid, before anything is run
+pub async fn run_jobs(
+    scheduler: Scheduler,
+    jobs: Vec<Job>,
+    sink: Arc<dyn ResultSink + Send + Sync>,
+) -> Result<(), Error> {
+    // so a typo doesn't only show up when the job is first due
+    for job in &jobs {
+        job.validate()?;
+    }
+
+    futures::future::join_all(
+        jobs.iter()
+            .map(|job| run_job(&scheduler, job, sink.as_ref())),
+    )
+    .await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data_switch::{DataConnector, DataSwitch, MemoryConnector, Observation},
+        pipeline::Pipeline,
+    };
+    use chrono::{Datelike, TimeZone};
+    use std::collections::HashMap;
+    use tokio::sync::mpsc::channel;
+
+    fn time(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_schedule() {
+        let next = |schedule: &str, after| schedule.parse::<Schedule>().unwrap().next_after(after);
+
+        assert_eq!(next("* * * * *", time(1, 1, 0, 0)), Some(time(1, 1, 0, 1)));
+        assert_eq!(next("5 * * * *", time(1, 1, 0, 5)), Some(time(1, 1, 1, 5)));
+        assert_eq!(
+            next("*/15 6-8 * * *", time(1, 1, 8, 50)),
+            Some(time(1, 2, 6, 0))
+        );
+        assert_eq!(next("0 0 1 3 *", time(1, 1, 0, 0)), Some(time(3, 1, 0, 0)));
+        assert_eq!(next("0 0 29 2 *", time(3, 1, 0, 0)).unwrap().year(), 2028);
+        // 2024-01-01 is a monday, sunday can be written as 0 or 7
+        assert_eq!(
+            next("0 12 * * 7", time(1, 1, 0, 0)),
+            Some(time(1, 7, 12, 0))
+        );
+        // either day field can match when both are restricted
+        assert_eq!(next("0 0 15 * 3", time(1, 1, 0, 0)), Some(time(1, 3, 0, 0)));
+        assert_eq!(next("0 0 30 2 *", time(1, 1, 0, 0)), None);
+        // names and nicknames
+        assert_eq!(
+            next("0 9 * JAN-MAR MON", time(1, 1, 9, 0)),
+            Some(time(1, 8, 9, 0))
+        );
+        assert_eq!(next("@daily", time(1, 1, 0, 0)), Some(time(1, 2, 0, 0)));
+        // strictly after, even partway through a minute
+        assert_eq!(
+            next(
+                "* * * * *",
+                time(1, 1, 0, 0) + chrono::Duration::seconds(30)
+            ),
+            Some(time(1, 1, 0, 1))
+        );
+
+        for invalid in [
+            "* * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "a * * * *",
+            "5-1 * * * *",
+            // seconds aren't supported
+            "0 * * * * *",
+        ] {
+            assert!(matches!(
+                invalid.parse::<Schedule>(),
+                Err(Error::InvalidSchedule(..))
+            ));
+        }
+    }
+
+    fn job() -> Job {
+        toml::from_str(
+            r#"
+            name = "test"
+            schedule = "5 * * * *"
+            data_source = "memory"
+            pipeline = "range"
+            time_resolution = "PT1H"
+            window = "PT3H"
+            delay = "PT5M"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_time_spec() {
+        let time_spec = job().time_spec(time(1, 1, 12, 5)).unwrap();
+
+        assert_eq!(
+            time_spec.timerange.start,
+            Timestamp(time(1, 1, 10, 0).timestamp())
+        );
+        assert_eq!(
+            time_spec.timerange.end,
+            Timestamp(time(1, 1, 12, 0).timestamp())
+        );
+        assert_eq!(time_spec.time_resolution, RelativeDuration::hours(1));
+    }
+
+    #[tokio::test]
+    async fn test_run_job_once() {
+        let memory = MemoryConnector::new();
+        memory.insert_observations((10..=12).map(|hour| Observation {
+            series_id: "18700".to_string(),
+            lat: 59.94,
+            lon: 10.72,
+            elev: 94.,
+            time: Timestamp(time(1, 1, hour, 0).timestamp()),
+            value: Some(hour as f32),
+        }));
+        let scheduler = Scheduler::new(
+            HashMap::from([(
+                "range".to_string(),
+                Pipeline::from_toml(
+                    r#"
+                    [[step]]
+                    name = "range_check"
+                    [step.range_check]
+                    min = 0.0
+                    max = 11.5
+                    "#,
+                )
+                .unwrap(),
+            )]),
+            DataSwitch::new([(
+                "memory",
+                Arc::new(memory) as Arc<dyn DataConnector + Send + Sync>,
+            )]),
+        );
+        let (tx, mut rx) = channel(10);
+
+        run_job_once(&scheduler, &job(), time(1, 1, 12, 5), &tx).await;
+        drop(tx);
+
+        let output = rx.recv().await.unwrap();
+        assert_eq!(output.job, "test");
+        assert_eq!(
+            output.scheduled_time,
+            Timestamp(time(1, 1, 12, 5).timestamp())
+        );
+        let response = output.result.unwrap();
+        assert_eq!(response.test, "range_check");
+        assert_eq!(response.results.len(), 3);
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -1,16 +1,37 @@
-use crate::harness::{
-    SPIKE_LEADING_PER_RUN, SPIKE_TRAILING_PER_RUN, STEP_LEADING_PER_RUN, STEP_TRAILING_PER_RUN,
+use crate::{
+    data_switch::StationMetadata,
+    harness::{
+        RANGE_DYNAMIC_MAX_SPEC, RANGE_DYNAMIC_MIN_SPEC, SPIKE_LEADING_PER_RUN,
+        SPIKE_TRAILING_PER_RUN, STEP_LEADING_PER_RUN, STEP_TRAILING_PER_RUN,
+    },
+    units,
+};
+use chronoutil::RelativeDuration;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
 };
-use serde::Deserialize;
-use std::{collections::HashMap, path::Path};
 use thiserror::Error;
 
 /// Data structure defining a pipeline of checks, with parameters built in
 ///
 /// Rather than constructing these manually, a convenience function `load_pipelines` is provided
-/// to deserialize a set of pipelines from a directory containing TOML files defining them.
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+/// to deserialize a set of pipelines from a directory containing TOML or JSON files defining them.
+/// Pipelines can also be parsed from strings with [`from_toml`](Pipeline::from_toml) and
+/// [`from_json`](Pipeline::from_json), or built in code with [`builder`](Pipeline::builder).
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
 pub struct Pipeline {
+    /// Version of the pipeline's configuration, to trace flags back to it
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Human readable description of what the pipeline is for
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Who is responsible for the pipeline
+    #[serde(default)]
+    pub author: Option<String>,
     /// Sequence of steps in the pipeline
     #[serde(rename = "step")]
     pub steps: Vec<PipelineStep>,
@@ -20,16 +41,301 @@ pub struct Pipeline {
     /// Number of trailing points required by the checks in this pipeline
     #[serde(skip)]
     pub num_trailing_required: u8,
+    /// If set, the flags from all steps are combined into one flag per observation, emitted after
+    /// the steps' results
+    #[serde(default)]
+    pub combi: Option<CombiConf>,
+    /// Groups of independent steps, run in order
+    ///
+    /// Each step in a group implicitly depends on every step in the group before it. Steps not
+    /// in any group only wait for their own dependencies
+    #[serde(rename = "group", default)]
+    pub groups: Vec<StepGroup>,
+    /// Units the steps' thresholds are in, e.g. `degC`
+    ///
+    /// If set, data is converted to these units before the checks run, where
+    /// its data source reports its units or they are configured on the
+    /// [`DataSwitch`](crate::data_switch::DataSwitch). See
+    /// [`units`](crate::units) for the units that are understood.
+    #[serde(default)]
+    pub units: Option<String>,
+    /// If set, a step that fails to run is reported with an error response, and the rest of the
+    /// pipeline still runs, rather than the error ending the run's results. Requests can also
+    /// ask for this, see [`RunOptions`](crate::RunOptions)
+    #[serde(default)]
+    pub continue_on_error: bool,
+}
+
+/// A set of steps that don't depend on each other, so can all run concurrently
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct StepGroup {
+    /// Names of the steps in the group, of which there must be at least one
+    pub steps: Vec<String>,
+}
+
+/// Configuration for combining the flags from all steps in a pipeline
+///
+/// Each observation gets the flag that comes first in `precedence` out of those any step gave
+/// it. Flags missing from `precedence` lose to all those in it
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct CombiConf {
+    #[serde(default = "default_precedence")]
+    pub precedence: Vec<FlagName>,
+}
+
+fn default_precedence() -> Vec<FlagName> {
+    vec![
+        FlagName::Fail,
+        FlagName::Invalid,
+        FlagName::Warn,
+        FlagName::Isolated,
+        FlagName::Inconclusive,
+        FlagName::DataMissing,
+        FlagName::Pass,
+    ]
+}
+
+/// Names of flags, for use in pipeline definitions
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum FlagName {
+    Pass,
+    Fail,
+    Warn,
+    Inconclusive,
+    Invalid,
+    DataMissing,
+    Isolated,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
 pub struct PipelineStep {
     pub name: String,
     #[serde(flatten)]
     pub check: CheckConf,
+    /// Flag to emit in place of Fail, so e.g. `on_fail = "warn"` makes a check advisory in this
+    /// pipeline
+    #[serde(default)]
+    pub on_fail: Option<FlagOverride>,
+    /// Names of steps that must finish before this one starts
+    ///
+    /// Steps that don't depend on each other, directly or indirectly, may run concurrently
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Only QC observations that got a particular flag from an earlier step, giving the rest
+    /// Inconclusive. Implies a dependency on that step
+    #[serde(default)]
+    pub run_if: Option<RunIf>,
+    /// Restricts which stations the step applies to
+    #[serde(flatten)]
+    pub filter: StepFilter,
+    /// Where the step's check is run
+    #[serde(flatten)]
+    pub runner: RunnerConf,
+}
+
+/// Where a step's check is run
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// In this process
+    #[default]
+    Local,
+    /// On a remote runner service, reached over gRPC
+    Grpc,
+}
+
+/// Configuration of where a step's check is run
+///
+/// With `backend = "grpc"`, the data the check needs is sent to the runner service at `endpoint`
+/// (e.g. another rove server), which runs the check and sends back the flags. This lets heavy
+/// checks, like SCT over a large domain, be scaled out
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct RunnerConf {
+    #[serde(default)]
+    pub backend: Backend,
+    /// URI of the runner service, required for the grpc backend
+    #[serde(default)]
+    pub endpoint: Option<String>,
+}
+
+impl RunnerConf {
+    /// The endpoint to send the step to, if it is run remotely
+    pub fn remote_endpoint(&self) -> Option<&str> {
+        match self.backend {
+            Backend::Local => None,
+            Backend::Grpc => self.endpoint.as_deref(),
+        }
+    }
+}
+
+/// Filters on station metadata restricting which stations a step applies to
+///
+/// Observations from other stations are hidden from the check, so they are neither QCed nor used
+/// as neighbours, and are flagged Inconclusive. An empty filter applies to every station
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct StepFilter {
+    /// Only apply to series from these providers. Series whose connector doesn't report a
+    /// provider are excluded
+    #[serde(default)]
+    pub only_providers: Option<Vec<i32>>,
+    /// Only apply to stations of these types. Stations whose connector doesn't report a type are
+    /// excluded
+    #[serde(default)]
+    pub only_station_types: Option<Vec<String>>,
+    /// Only apply to stations at or above this elevation, in metres
+    #[serde(default)]
+    pub min_elevation: Option<f32>,
+    /// Only apply to stations at or below this elevation, in metres
+    #[serde(default)]
+    pub max_elevation: Option<f32>,
+}
+
+impl StepFilter {
+    /// Whether a station with the given metadata and elevation passes the filter
+    pub fn applies_to(&self, metadata: &StationMetadata, elevation: f32) -> bool {
+        self.only_providers.as_ref().is_none_or(|providers| {
+            metadata
+                .provider
+                .is_some_and(|provider| providers.contains(&provider))
+        }) && self.only_station_types.as_ref().is_none_or(|types| {
+            metadata
+                .station_type
+                .as_ref()
+                .is_some_and(|station_type| types.contains(station_type))
+        }) && self.min_elevation.is_none_or(|min| elevation >= min)
+            && self.max_elevation.is_none_or(|max| elevation <= max)
+    }
+
+    /// Whether the filter applies to every station
+    pub fn is_empty(&self) -> bool {
+        self == &StepFilter::default()
+    }
+}
+
+/// Condition on the results of an earlier step, for conditionally running a step
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct RunIf {
+    /// Name of the earlier step
+    pub step: String,
+    /// Flag the earlier step must have given an observation for it to be QCed
+    pub flag: FlagName,
+}
+
+impl PipelineStep {
+    /// Create a step running `check` locally, with no flag override, dependencies, conditions or
+    /// filters
+    pub fn new(name: impl Into<String>, check: CheckConf) -> Self {
+        PipelineStep {
+            name: name.into(),
+            check,
+            on_fail: None,
+            depends_on: Vec::new(),
+            run_if: None,
+            filter: StepFilter::default(),
+            runner: RunnerConf::default(),
+        }
+    }
+}
+
+/// Builder for constructing a [`Pipeline`] in code
+#[derive(Debug, Default, Clone)]
+pub struct PipelineBuilder {
+    version: Option<String>,
+    description: Option<String>,
+    author: Option<String>,
+    steps: Vec<PipelineStep>,
+    combi: Option<CombiConf>,
+    groups: Vec<StepGroup>,
+    units: Option<String>,
+    continue_on_error: bool,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+impl PipelineBuilder {
+    /// Set the pipeline's version
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Set the pipeline's description
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Set the pipeline's author
+    pub fn author(mut self, author: impl Into<String>) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    /// Append a step to the pipeline
+    pub fn step(mut self, step: PipelineStep) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Append a group of steps, named by `steps`, that run after the previous group, see
+    /// [`StepGroup`]
+    pub fn group(mut self, steps: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.groups.push(StepGroup {
+            steps: steps.into_iter().map(Into::into).collect(),
+        });
+        self
+    }
+
+    /// Combine the flags from all steps, see [`CombiConf`]
+    pub fn combi(mut self, combi: CombiConf) -> Self {
+        self.combi = Some(combi);
+        self
+    }
+
+    /// Set the units the steps' thresholds are in, see [`Pipeline::units`]
+    pub fn units(mut self, units: impl Into<String>) -> Self {
+        self.units = Some(units.into());
+        self
+    }
+
+    /// Keep running the rest of the pipeline when a step fails, see
+    /// [`Pipeline::continue_on_error`]
+    pub fn continue_on_error(mut self, continue_on_error: bool) -> Self {
+        self.continue_on_error = continue_on_error;
+        self
+    }
+
+    /// Finish building the pipeline
+    ///
+    /// # Errors
+    ///
+    /// If the pipeline is invalid, see [`Pipeline::validate`]
+    pub fn build(self) -> Result<Pipeline, Error> {
+        Pipeline {
+            version: self.version,
+            description: self.description,
+            author: self.author,
+            steps: self.steps,
+            num_leading_required: 0,
+            num_trailing_required: 0,
+            combi: self.combi,
+            groups: self.groups,
+            units: self.units,
+            continue_on_error: self.continue_on_error,
+        }
+        .finish()
+    }
+}
+
+/// Flags that a step's failures can be mapped to
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum FlagOverride {
+    Pass,
+    Warn,
+    Inconclusive,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum CheckConf {
     SpecialValueCheck(SpecialValueCheckConf),
@@ -38,62 +344,364 @@ pub enum CheckConf {
     StepCheck(StepCheckConf),
     SpikeCheck(SpikeCheckConf),
     FlatlineCheck(FlatlineCheckConf),
+    PersistenceCheck(PersistenceCheckConf),
+    RocCheck(RocCheckConf),
+    CompletenessCheck(CompletenessCheckConf),
+    MadCheck(MadCheckConf),
     BuddyCheck(BuddyCheckConf),
+    IsolationCheck(IsolationCheckConf),
+    BuddyEventCheck(BuddyEventCheckConf),
+    DuplicateCheck(DuplicateCheckConf),
+    TimeShiftCheck(TimeShiftCheckConf),
     Sct(SctConf),
+    SctResistant(SctResistantConf),
     ModelConsistencyCheck(ModelConsistencyCheckConf),
+    FirstGuessCheck(FirstGuessCheckConf),
+    ClimatologyCheck(ClimatologyCheckConf),
+    SeasonalRangeCheck(SeasonalRangeCheckConf),
+    RadiationCheck(RadiationCheckConf),
+    MetadataCheck(MetadataCheckConf),
+    DewPointCheck(DewPointCheckConf),
     #[serde(skip)]
     Dummy,
 }
 
 impl CheckConf {
-    fn get_num_leading_trailing(&self) -> (u8, u8) {
+    pub(crate) fn get_num_leading_trailing(&self) -> (u8, u8) {
         match self {
             CheckConf::SpecialValueCheck(_)
             | CheckConf::RangeCheck(_)
             | CheckConf::RangeCheckDynamic(_)
             | CheckConf::BuddyCheck(_)
+            | CheckConf::IsolationCheck(_)
+            | CheckConf::BuddyEventCheck(_)
+            | CheckConf::DuplicateCheck(_)
+            | CheckConf::TimeShiftCheck(_)
             | CheckConf::Sct(_)
+            | CheckConf::SctResistant(_)
             | CheckConf::ModelConsistencyCheck(_)
+            | CheckConf::FirstGuessCheck(_)
+            | CheckConf::ClimatologyCheck(_)
+            | CheckConf::SeasonalRangeCheck(_)
+            | CheckConf::RadiationCheck(_)
+            | CheckConf::MetadataCheck(_)
+            | CheckConf::DewPointCheck(_)
             | CheckConf::Dummy => (0, 0),
             CheckConf::StepCheck(_) => (STEP_LEADING_PER_RUN, STEP_TRAILING_PER_RUN),
             CheckConf::SpikeCheck(_) => (SPIKE_LEADING_PER_RUN, SPIKE_TRAILING_PER_RUN),
             CheckConf::FlatlineCheck(conf) => (conf.max, 0),
+            CheckConf::PersistenceCheck(conf) => (conf.window, 0),
+            CheckConf::RocCheck(conf) => (conf.window, 0),
+            CheckConf::CompletenessCheck(conf) => (conf.window, 0),
+            CheckConf::MadCheck(conf) => (conf.window, conf.window),
+        }
+    }
+
+    /// Sanity-checks the check's parameters, returning the name of the first offending field and
+    /// what's wrong with it
+    fn validate(&self) -> Result<(), (&'static str, String)> {
+        fn require(
+            condition: bool,
+            field: &'static str,
+            reason: &str,
+        ) -> Result<(), (&'static str, String)> {
+            if condition {
+                Ok(())
+            } else {
+                Err((field, reason.to_string()))
+            }
+        }
+        const POSITIVE: &str = "must be positive";
+        const NON_EMPTY: &str = "must not be empty";
+
+        match self {
+            CheckConf::SpecialValueCheck(_)
+            | CheckConf::RangeCheckDynamic(_)
+            | CheckConf::ModelConsistencyCheck(_)
+            | CheckConf::FirstGuessCheck(_)
+            | CheckConf::RadiationCheck(_)
+            | CheckConf::DewPointCheck(_)
+            | CheckConf::Dummy => Ok(()),
+            CheckConf::RangeCheck(conf) => {
+                require(conf.min <= conf.max, "min", "must not exceed max")
+            }
+            CheckConf::StepCheck(conf) => require(conf.max > 0., "max", POSITIVE),
+            CheckConf::SpikeCheck(conf) => require(conf.max > 0., "max", POSITIVE),
+            CheckConf::FlatlineCheck(conf) => require(conf.max > 0, "max", POSITIVE),
+            CheckConf::PersistenceCheck(conf) => require(conf.window > 0, "window", POSITIVE),
+            CheckConf::RocCheck(conf) => {
+                require(conf.window > 0, "window", POSITIVE)?;
+                require(conf.max_per_hour > 0., "max_per_hour", POSITIVE)
+            }
+            CheckConf::CompletenessCheck(conf) => require(
+                conf.max_missing_fraction
+                    .is_none_or(|fraction| (0. ..=1.).contains(&fraction)),
+                "max_missing_fraction",
+                "must be between 0 and 1",
+            ),
+            CheckConf::MadCheck(conf) => {
+                require(conf.window > 0, "window", POSITIVE)?;
+                require(conf.k > 0., "k", POSITIVE)
+            }
+            CheckConf::BuddyCheck(conf) => {
+                require(!conf.radii.is_empty(), "radii", NON_EMPTY)?;
+                require(!conf.nums_min.is_empty(), "nums_min", NON_EMPTY)?;
+                require(
+                    conf.radii.len() == 1
+                        || conf.nums_min.len() == 1
+                        || conf.radii.len() == conf.nums_min.len(),
+                    "nums_min",
+                    "must have one element, or as many as radii",
+                )?;
+                require(
+                    conf.radii.iter().all(|radius| *radius > 0.),
+                    "radii",
+                    POSITIVE,
+                )?;
+                require(conf.threshold > 0., "threshold", POSITIVE)?;
+                require(conf.num_iterations > 0, "num_iterations", POSITIVE)
+            }
+            CheckConf::IsolationCheck(conf) => require(conf.radius > 0., "radius", POSITIVE),
+            CheckConf::BuddyEventCheck(conf) => {
+                require(conf.radius > 0., "radius", POSITIVE)?;
+                require(conf.threshold > 0., "threshold", POSITIVE)?;
+                require(conf.threshold <= 1., "threshold", "must not exceed 1")?;
+                require(conf.num_iterations > 0, "num_iterations", POSITIVE)
+            }
+            CheckConf::DuplicateCheck(conf) => {
+                require(conf.tolerance >= 0., "tolerance", "must not be negative")?;
+                require(conf.radius > 0., "radius", POSITIVE)
+            }
+            CheckConf::TimeShiftCheck(conf) => {
+                require(conf.radius > 0., "radius", POSITIVE)?;
+                require(conf.max_lag > 0, "max_lag", POSITIVE)
+            }
+            CheckConf::Sct(conf) => {
+                require(
+                    conf.num_min <= conf.num_max,
+                    "num_min",
+                    "must not exceed num_max",
+                )?;
+                require(
+                    conf.inner_radius <= conf.outer_radius,
+                    "inner_radius",
+                    "must not exceed outer_radius",
+                )?;
+                require(!conf.pos.is_empty(), "pos", NON_EMPTY)?;
+                require(!conf.neg.is_empty(), "neg", NON_EMPTY)?;
+                require(!conf.eps2.is_empty(), "eps2", NON_EMPTY)?;
+                require(
+                    conf.eps2
+                        .iter()
+                        .chain(conf.station_classes.iter().map(|class| &class.eps2))
+                        .all(|eps2| *eps2 > 0.),
+                    "eps2",
+                    POSITIVE,
+                )?;
+                require(conf.num_iterations > 0, "num_iterations", POSITIVE)
+            }
+            CheckConf::SctResistant(conf) => {
+                require(conf.num_min > 0, "num_min", POSITIVE)?;
+                require(
+                    conf.num_min <= conf.num_max,
+                    "num_min",
+                    "must not exceed num_max",
+                )?;
+                require(conf.outer_radius > 0., "outer_radius", POSITIVE)?;
+                require(conf.pos > 0., "pos", POSITIVE)?;
+                require(conf.neg > 0., "neg", POSITIVE)?;
+                // a zero spread would divide by zero when buddies all agree
+                require(conf.min_std > 0., "min_std", POSITIVE)?;
+                require(conf.num_iterations > 0, "num_iterations", POSITIVE)
+            }
+            CheckConf::ClimatologyCheck(ClimatologyCheckConf::Table { min, max }) => require(
+                min.iter().zip(max.iter()).all(|(min, max)| min <= max),
+                "min",
+                "must not exceed max",
+            ),
+            CheckConf::ClimatologyCheck(ClimatologyCheckConf::Source { .. }) => Ok(()),
+            CheckConf::SeasonalRangeCheck(conf) => {
+                require(!conf.bands.is_empty(), "bands", NON_EMPTY)?;
+                conf.bands.iter().try_for_each(|band| match band {
+                    SeasonalBand::Months { months, min, max } => {
+                        require(
+                            months.iter().all(|month| (1..=12).contains(month)),
+                            "bands",
+                            "months must be between 1 and 12",
+                        )?;
+                        require(min <= max, "bands", "min must not exceed max")
+                    }
+                    SeasonalBand::DayOfYear {
+                        start_day,
+                        end_day,
+                        min,
+                        max,
+                    } => {
+                        require(
+                            (1..=366).contains(start_day) && (1..=366).contains(end_day),
+                            "bands",
+                            "days must be between 1 and 366",
+                        )?;
+                        require(min <= max, "bands", "min must not exceed max")
+                    }
+                })
+            }
+            CheckConf::MetadataCheck(conf) => require(
+                conf.min_elev
+                    .zip(conf.max_elev)
+                    .is_none_or(|(min, max)| min <= max),
+                "min_elev",
+                "must not exceed max_elev",
+            ),
+        }
+    }
+
+    /// Name of the check, as used in pipeline files
+    pub fn name(&self) -> &'static str {
+        match self {
+            CheckConf::SpecialValueCheck(_) => "special_value_check",
+            CheckConf::RangeCheck(_) => "range_check",
+            CheckConf::RangeCheckDynamic(_) => "range_check_dynamic",
+            CheckConf::StepCheck(_) => "step_check",
+            CheckConf::SpikeCheck(_) => "spike_check",
+            CheckConf::FlatlineCheck(_) => "flatline_check",
+            CheckConf::PersistenceCheck(_) => "persistence_check",
+            CheckConf::RocCheck(_) => "roc_check",
+            CheckConf::CompletenessCheck(_) => "completeness_check",
+            CheckConf::MadCheck(_) => "mad_check",
+            CheckConf::BuddyCheck(_) => "buddy_check",
+            CheckConf::IsolationCheck(_) => "isolation_check",
+            CheckConf::BuddyEventCheck(_) => "buddy_event_check",
+            CheckConf::DuplicateCheck(_) => "duplicate_check",
+            CheckConf::TimeShiftCheck(_) => "time_shift_check",
+            CheckConf::Sct(_) => "sct",
+            CheckConf::SctResistant(_) => "sct_resistant",
+            CheckConf::ModelConsistencyCheck(_) => "model_consistency_check",
+            CheckConf::FirstGuessCheck(_) => "first_guess_check",
+            CheckConf::ClimatologyCheck(_) => "climatology_check",
+            CheckConf::SeasonalRangeCheck(_) => "seasonal_range_check",
+            CheckConf::RadiationCheck(_) => "radiation_check",
+            CheckConf::MetadataCheck(_) => "metadata_check",
+            CheckConf::DewPointCheck(_) => "dew_point_check",
+            CheckConf::Dummy => "dummy",
+        }
+    }
+
+    /// Data sources, paired with the extra_spec to pass to them, that this check needs data from
+    /// in addition to the data being QCed
+    pub(crate) fn get_backing_fetches(&self) -> Vec<(&str, Option<&str>)> {
+        match self {
+            CheckConf::RangeCheckDynamic(conf) => vec![
+                (conf.source.as_str(), Some(RANGE_DYNAMIC_MIN_SPEC)),
+                (conf.source.as_str(), Some(RANGE_DYNAMIC_MAX_SPEC)),
+            ],
+            CheckConf::ModelConsistencyCheck(conf) => {
+                vec![(conf.model_source.as_str(), Some(conf.model_args.as_str()))]
+            }
+            CheckConf::FirstGuessCheck(conf) => {
+                vec![(conf.source.as_str(), Some(conf.field.as_str()))]
+            }
+            CheckConf::ClimatologyCheck(ClimatologyCheckConf::Source { source }) => vec![
+                (source.as_str(), Some(RANGE_DYNAMIC_MIN_SPEC)),
+                (source.as_str(), Some(RANGE_DYNAMIC_MAX_SPEC)),
+            ],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Names of extra parameters, for the same stations and times as the data being QCed, that
+    /// this check needs
+    pub(crate) fn get_params(&self) -> Vec<&str> {
+        match self {
+            CheckConf::DewPointCheck(conf) => vec![conf.air_temperature_param.as_str()],
+            _ => Vec::new(),
         }
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+/// Flags values matching any of `special_values` as invalid
+///
+/// If `substitute` is set, it is proposed as the corrected value for flagged values
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
 pub struct SpecialValueCheckConf {
     pub special_values: Vec<f32>,
+    #[serde(default)]
+    pub substitute: Option<f32>,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
 pub struct RangeCheckConf {
     pub max: f32,
     pub min: f32,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+/// Range check with limits fetched from a data source, e.g. a climatology
+///
+/// The source is queried with the same space and time specs as the data being QCed, once with
+/// extra_spec "min" and once with "max", and should return series with identifiers matching
+/// those of the data being QCed
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
 pub struct RangeCheckDynamicConf {
     pub source: String,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
 pub struct StepCheckConf {
     pub max: f32,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
 pub struct SpikeCheckConf {
     pub max: f32,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
 pub struct FlatlineCheckConf {
     pub max: u8,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+/// Flags points where the standard deviation of the series over a window ending at the point is
+/// below `min_std`, which would suggest a stuck sensor
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct PersistenceCheckConf {
+    /// Number of points before the point being checked to include in the window
+    pub window: u8,
+    pub min_std: f32,
+}
+
+/// Flags points that have changed by more than `max_per_hour` per hour, on average, since the point
+/// `window` steps before them
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct RocCheckConf {
+    pub window: u8,
+    pub max_per_hour: f32,
+}
+
+/// Flags gaps in series as missing data
+///
+/// If `max_missing_fraction` is set, points are also flagged as failing if more than that
+/// fraction of the window made up of the point and the `window` points before it is missing
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct CompletenessCheckConf {
+    #[serde(default)]
+    pub window: u8,
+    pub max_missing_fraction: Option<f32>,
+}
+
+/// Flags points more than `k` median absolute deviations from the median of the `window` points
+/// either side of them
+///
+/// `min_mad` puts a floor under the median absolute deviation, so that points aren't flagged for
+/// tiny deviations from very smooth series
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct MadCheckConf {
+    pub window: u8,
+    pub k: f32,
+    #[serde(default)]
+    pub min_mad: f32,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
 pub struct BuddyCheckConf {
     pub radii: Vec<f32>,
     pub nums_min: Vec<u32>,
@@ -104,7 +712,62 @@ pub struct BuddyCheckConf {
     pub num_iterations: u32,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+/// Flags stations as isolated if they have fewer than `num_min` neighbours with data within
+/// `radius` metres, optionally only counting neighbours within `max_elev_diff` metres of elevation
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct IsolationCheckConf {
+    pub radius: f32,
+    pub num_min: u32,
+    pub max_elev_diff: Option<f32>,
+}
+
+/// Buddy check on whether an event (e.g. precipitation) occurred, rather than on values
+///
+/// An event is a value of at least `event_threshold`. Stations where less than `threshold` (as a
+/// fraction) of their neighbours within `radius` metres agree on whether an event occurred are
+/// flagged. Stations with fewer than `num_min` neighbours are flagged as isolated
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct BuddyEventCheckConf {
+    pub radius: f32,
+    pub num_min: u32,
+    pub event_threshold: f32,
+    pub threshold: f32,
+    pub max_elev_diff: Option<f32>,
+    pub num_iterations: u32,
+}
+
+/// Flags stations whose series are copies of another station's, to within `tolerance`, possibly
+/// shifted in time by up to `max_lag` steps
+///
+/// Series must share at least `min_overlap` points to be compared. Stations are only compared to
+/// others within `radius` metres of them, since every pair in range has to be compared
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct DuplicateCheckConf {
+    pub max_lag: u8,
+    pub tolerance: f32,
+    pub min_overlap: usize,
+    pub radius: f32,
+}
+
+/// Detects constant clock offsets by cross-correlating each series against the mean of its
+/// neighbours within `radius` metres, at lags of up to `max_lag` steps either way
+///
+/// A series is flagged if its correlation at the best lag beats the correlation at no lag by more
+/// than `min_improvement`. At least `min_overlap` points must be present in both the series and
+/// the reference to estimate a lag
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct TimeShiftCheckConf {
+    pub radius: f32,
+    pub max_lag: u8,
+    pub min_overlap: usize,
+    pub min_improvement: f32,
+}
+
+/// Configuration for the spatial consistency test
+///
+/// `pos`, `neg` and `eps2` apply to all stations, using their first element, except stations
+/// matching one of `station_classes`, which use that class's values instead
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
 pub struct SctConf {
     pub num_min: usize,
     pub num_max: usize,
@@ -119,15 +782,148 @@ pub struct SctConf {
     pub neg: Vec<f32>,
     pub eps2: Vec<f32>,
     pub obs_to_check: Option<Vec<bool>>,
+    #[serde(default)]
+    pub station_classes: Vec<SctStationClass>,
+}
+
+/// SCT parameters for a class of stations, such as those from a particular provider, identified
+/// by the prefix of their identifiers
+///
+/// If a station matches more than one class, the first one listed is used
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct SctStationClass {
+    pub identifier_prefix: String,
+    pub pos: f32,
+    pub neg: f32,
+    pub eps2: f32,
+}
+
+/// Configuration for a variant of SCT that is more robust to outliers among the neighbours
+///
+/// Each station is compared to a background estimated from up to `num_max` of its nearest
+/// neighbours within `outer_radius` metres. The background follows the vertical gradient of the
+/// neighbours if they span at least `min_elev_diff` metres of elevation. Deviations from it are
+/// scaled by the spread of the neighbours (at least `min_std`), and flagged if they are more than
+/// `pos` above or `neg` below it
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct SctResistantConf {
+    pub num_min: usize,
+    pub num_max: usize,
+    pub outer_radius: f32,
+    pub num_iterations: u32,
+    pub min_elev_diff: f32,
+    pub min_std: f32,
+    pub pos: f32,
+    pub neg: f32,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+/// Check of observations against a model field
+///
+/// The model source is queried with the same space and time specs as the data being QCed, with
+/// `model_args` as its extra_spec. It should return the model field as a set of gridpoints, which
+/// are interpolated to the locations of the observations
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
 pub struct ModelConsistencyCheckConf {
     pub model_source: String,
     pub model_args: String,
     pub threshold: f32,
 }
 
+/// Check of observations against a gridded first guess field, such as an NWP forecast or an
+/// analysis
+///
+/// The source is queried with the same space and time specs as the data being QCed, with `field`
+/// as its extra_spec, and should return the field as a set of gridpoints. These are adjusted to
+/// the elevation of each station using `elev_gradient` and interpolated to its location.
+/// Observations more than `pos` above or `neg` below the first guess are flagged
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct FirstGuessCheckConf {
+    pub source: String,
+    pub field: String,
+    /// Vertical gradient of the field, in units per metre (e.g. -0.0065 for air temperature)
+    pub elev_gradient: f32,
+    pub pos: f32,
+    pub neg: f32,
+    /// Extra tolerance added to `pos` and `neg` per metre of elevation difference between the
+    /// station and the grid
+    #[serde(default)]
+    pub elev_diff_tolerance: f32,
+}
+
+/// Range check with limits that depend on the calendar month
+///
+/// Limits can either be given directly as tables of 12 values, starting with January, that apply
+/// to all stations, or fetched from a data source. In the latter case the source is queried with
+/// extra_spec "min" and "max", and should return a series of 12 monthly limits per station, with
+/// identifiers matching those of the data being QCed
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum ClimatologyCheckConf {
+    Table { min: [f32; 12], max: [f32; 12] },
+    Source { source: String },
+}
+
+/// Range check with limits that depend on the time of year
+///
+/// Each band gives limits for either a set of calendar months (1-12), or a range of days of the
+/// year (1-366, inclusive, wrapping around new year if `start_day` is after `end_day`). The first
+/// band covering an observation's time is used, and observations not covered by any band are
+/// flagged Inconclusive
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct SeasonalRangeCheckConf {
+    pub bands: Vec<SeasonalBand>,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum SeasonalBand {
+    Months {
+        months: Vec<u32>,
+        min: f32,
+        max: f32,
+    },
+    DayOfYear {
+        start_day: u32,
+        end_day: u32,
+        min: f32,
+        max: f32,
+    },
+}
+
+/// Check of global radiation against the position of the sun
+///
+/// Flags observations above `night_max` while the sun is below the horizon, and observations
+/// above the physically possible limit for clear skies during the day
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct RadiationCheckConf {
+    pub night_max: f32,
+}
+
+/// Flags all observations from stations with implausible metadata as invalid
+///
+/// Stations need a latitude and longitude within the valid range, and an elevation within
+/// `[min_elev, max_elev]` if those are set. This should come before any spatial checks in a
+/// pipeline, so that badly located stations can be identified as the cause of any spatial
+/// inconsistencies
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct MetadataCheckConf {
+    pub min_elev: Option<f32>,
+    pub max_elev: Option<f32>,
+}
+
+/// Flags dew point observations that exceed the air temperature at the same station and time
+///
+/// The data being QCed is the dew point. Air temperature is fetched as an extra parameter from the
+/// same data source, using `air_temperature_param` as its extra_spec
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct DewPointCheckConf {
+    pub air_temperature_param: String,
+    /// How far the dew point may exceed the air temperature before being flagged, to allow for
+    /// measurement uncertainty
+    #[serde(default)]
+    pub tolerance: f32,
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     /// Generic IO error
@@ -136,12 +932,281 @@ pub enum Error {
     /// TOML deserialize error
     #[error("failed to deserialize toml: {0}")]
     TomlDeserialize(#[from] toml::de::Error),
+    /// JSON deserialize error
+    #[error("failed to deserialize json: {0}")]
+    JsonDeserialize(#[from] serde_json::Error),
     /// The directory contained something that wasn't a file
     #[error("the directory contained something that wasn't a file")]
     DirectoryStructure,
     /// Pipeline filename could not be parsed as a unicode string
     #[error("pipeline filename could not be parsed as a unicode string")]
     InvalidFilename,
+    /// A step depends on a step that isn't in the pipeline
+    #[error("step {step} depends on {dependency}, which is not in the pipeline")]
+    UnknownDependency {
+        /// Name of the step declaring the dependency
+        step: String,
+        /// Name of the missing dependency
+        dependency: String,
+    },
+    /// A group lists a step that doesn't exist or is already in another group, or steps that
+    /// depend on each other
+    #[error("group {group} {reason}")]
+    InvalidGroup {
+        /// Index of the group in the pipeline
+        group: usize,
+        /// What is wrong with the group
+        reason: String,
+    },
+    /// The dependencies between steps form a cycle
+    #[error("step {0} depends on itself, directly or indirectly")]
+    DependencyCycle(String),
+    /// Two steps have the same name, so results and dependencies can't tell them apart
+    #[error("more than one step is named {0}")]
+    DuplicateStep(String),
+    /// A step's parameter has a value the check can't run with
+    #[error("field {field} of step {step} {reason}")]
+    InvalidParameter {
+        /// Name of the step
+        step: String,
+        /// Name of the offending field in the step's check config
+        field: &'static str,
+        /// What's wrong with the field
+        reason: String,
+    },
+    /// The pipeline has no steps, so there would be nothing to run
+    #[error("pipeline has no steps")]
+    NoSteps,
+    /// The pipeline's units are not in the conversion table
+    #[error("unknown units {0}")]
+    UnknownUnits(String),
+    /// A `${VAR}` placeholder named a variable that wasn't provided or set in the environment
+    #[error("variable {0} is not set")]
+    UnresolvedVariable(String),
+    /// A `${` was not closed by a `}`
+    #[error("unterminated variable placeholder starting ${{{0}")]
+    UnterminatedVariable(String),
+    /// A route's time resolution couldn't be parsed
+    #[error("invalid time_resolution {0} in route")]
+    InvalidRoute(String),
+    /// A pipeline loaded from a file was invalid
+    #[error("invalid pipeline in file {file}: {source}")]
+    InvalidPipeline {
+        /// Name of the file the pipeline was loaded from
+        file: String,
+        /// What was wrong with it
+        source: Box<Error>,
+    },
+}
+
+impl Pipeline {
+    /// Start building a pipeline in code
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    /// Parse a pipeline from a TOML string, in the same format as pipeline files
+    ///
+    /// # Errors
+    ///
+    /// If the string could not be deserialized, or the pipeline is invalid
+    pub fn from_toml(s: &str) -> Result<Pipeline, Error> {
+        toml::from_str::<Pipeline>(s)?.finish()
+    }
+
+    /// Parse a pipeline from a JSON string, with the same structure as pipeline files
+    ///
+    /// # Errors
+    ///
+    /// If the string could not be deserialized, or the pipeline is invalid
+    pub fn from_json(s: &str) -> Result<Pipeline, Error> {
+        serde_json::from_str::<Pipeline>(s)?.finish()
+    }
+
+    /// Serialize the pipeline to a JSON string, with the same structure as pipeline files
+    pub fn to_json(&self) -> String {
+        // serialization can only fail on maps with non-string keys, which pipelines don't have
+        serde_json::to_string_pretty(self).expect("pipeline should serialize to JSON")
+    }
+
+    /// Validates a freshly deserialized or built pipeline, and fills in the fields derived from
+    /// its steps
+    pub(crate) fn finish(mut self) -> Result<Pipeline, Error> {
+        self.validate()?;
+        (self.num_leading_required, self.num_trailing_required) =
+            derive_num_leading_trailing(&self);
+        Ok(self)
+    }
+
+    /// Sanity-check the pipeline's configuration, so mistakes are caught at load time rather than
+    /// when running checks
+    ///
+    /// # Errors
+    ///
+    /// If the pipeline has no steps, a step's parameters are invalid, the dependencies between
+    /// steps are invalid (see [`dependency_levels`](Pipeline::dependency_levels)), or the
+    /// pipeline's units are unknown
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.steps.is_empty() {
+            return Err(Error::NoSteps);
+        }
+        if let Some(units) = &self.units {
+            if !units::is_known(units) {
+                return Err(Error::UnknownUnits(units.clone()));
+            }
+        }
+        for step in self.steps.iter() {
+            step.check
+                .validate()
+                .map_err(|(field, reason)| Error::InvalidParameter {
+                    step: step.name.clone(),
+                    field,
+                    reason,
+                })?;
+            if let (Some(min), Some(max)) = (step.filter.min_elevation, step.filter.max_elevation) {
+                if min > max {
+                    return Err(Error::InvalidParameter {
+                        step: step.name.clone(),
+                        field: "min_elevation",
+                        reason: format!("must not exceed max_elevation ({} > {})", min, max),
+                    });
+                }
+            }
+            if step.runner.backend == Backend::Grpc && step.runner.endpoint.is_none() {
+                return Err(Error::InvalidParameter {
+                    step: step.name.clone(),
+                    field: "endpoint",
+                    reason: "is required for the grpc backend".to_string(),
+                });
+            }
+        }
+        self.dependency_levels()?;
+
+        Ok(())
+    }
+
+    /// Indices of the steps each step depends on, through `depends_on`, `run_if`, or being in a
+    /// group after them
+    ///
+    /// # Errors
+    ///
+    /// If two steps have the same name, a step depends on a step that isn't in the pipeline, or
+    /// a group is invalid
+    pub fn dependencies(&self) -> Result<Vec<Vec<usize>>, Error> {
+        let mut indices: HashMap<&str, usize> = HashMap::with_capacity(self.steps.len());
+        for (i, step) in self.steps.iter().enumerate() {
+            if indices.insert(step.name.as_str(), i).is_some() {
+                return Err(Error::DuplicateStep(step.name.clone()));
+            }
+        }
+        let mut dependencies = self
+            .steps
+            .iter()
+            .map(|step| {
+                step.depends_on
+                    .iter()
+                    .chain(step.run_if.as_ref().map(|run_if| &run_if.step))
+                    .map(|dependency| {
+                        indices.get(dependency.as_str()).copied().ok_or_else(|| {
+                            Error::UnknownDependency {
+                                step: step.name.clone(),
+                                dependency: dependency.clone(),
+                            }
+                        })
+                    })
+                    .collect::<Result<Vec<usize>, Error>>()
+            })
+            .collect::<Result<Vec<Vec<usize>>, Error>>()?;
+
+        let mut grouped: HashSet<usize> = HashSet::new();
+        let mut previous_group: Vec<usize> = Vec::new();
+        for (group_index, group) in self.groups.iter().enumerate() {
+            let invalid = |reason: String| Error::InvalidGroup {
+                group: group_index,
+                reason,
+            };
+            // the group after an empty one would otherwise not wait for the one before it
+            if group.steps.is_empty() {
+                return Err(invalid("lists no steps".to_string()));
+            }
+            let members = group
+                .steps
+                .iter()
+                .map(|name| {
+                    let i = *indices
+                        .get(name.as_str())
+                        .ok_or_else(|| invalid(format!("lists unknown step {}", name)))?;
+                    if !grouped.insert(i) {
+                        return Err(invalid(format!("lists step {}, already in a group", name)));
+                    }
+                    Ok(i)
+                })
+                .collect::<Result<Vec<usize>, Error>>()?;
+
+            for i in members.iter() {
+                if let Some(dependency) = dependencies[*i].iter().find(|d| members.contains(d)) {
+                    return Err(invalid(format!(
+                        "lists step {} and its dependency {}",
+                        self.steps[*i].name, self.steps[*dependency].name
+                    )));
+                }
+                dependencies[*i].extend(previous_group.iter().copied());
+            }
+            previous_group = members;
+        }
+
+        Ok(dependencies)
+    }
+
+    /// Group the pipeline's steps into levels, such that each step only depends on steps in
+    /// earlier levels
+    ///
+    /// Levels hold indices into `steps`, in the order the steps are listed. Steps in the same level
+    /// are independent of each other, so can be run concurrently.
+    ///
+    /// # Errors
+    ///
+    /// If a step depends on a step that isn't in the pipeline, a group is invalid, or the
+    /// dependencies form a cycle
+    pub fn dependency_levels(&self) -> Result<Vec<Vec<usize>>, Error> {
+        let dependencies = self.dependencies()?;
+
+        let mut step_levels: Vec<Option<usize>> = vec![None; self.steps.len()];
+        let mut levels: Vec<Vec<usize>> = Vec::new();
+        let mut num_placed = 0;
+        while num_placed < self.steps.len() {
+            // steps whose dependencies have all been placed in earlier levels
+            let level: Vec<usize> = (0..self.steps.len())
+                .filter(|i| {
+                    step_levels[*i].is_none()
+                        && dependencies[*i]
+                            .iter()
+                            .all(|dependency| step_levels[*dependency].is_some())
+                })
+                .collect();
+            if level.is_empty() {
+                let stuck = step_levels.iter().position(Option::is_none).unwrap();
+                return Err(Error::DependencyCycle(self.steps[stuck].name.clone()));
+            }
+
+            for i in level.iter() {
+                step_levels[*i] = Some(levels.len());
+            }
+            num_placed += level.len();
+            levels.push(level);
+        }
+
+        Ok(levels)
+    }
+}
+
+/// JSON Schema describing pipeline files, for validating them in editors or CI without running
+/// rove
+///
+/// TOML pipeline files can be checked against this after converting them to JSON
+pub fn pipeline_schema() -> String {
+    // serializing a schema can't fail, it's all strings and maps
+    serde_json::to_string_pretty(&schemars::schema_for!(Pipeline)).unwrap()
 }
 
 /// Given a pipeline, derive the number of leading and trailing points per timeseries needed in
@@ -154,10 +1219,128 @@ pub fn derive_num_leading_trailing(pipeline: &Pipeline) -> (u8, u8) {
         .fold((0, 0), |acc, x| (acc.0.max(x.0), acc.1.max(x.1)))
 }
 
+/// Replaces `${VAR}` placeholders in `s` with the value of `VAR` from `vars`, or the environment
+///
+/// Comments are copied as they are, so placeholders in them don't need to resolve
+fn substitute_vars(s: &str, vars: &HashMap<String, String>) -> Result<String, Error> {
+    let mut out = String::with_capacity(s.len());
+    let mut open_string = None;
+    for line in s.split_inclusive('\n') {
+        let (code, comment) = match find_comment(line, &mut open_string) {
+            Some(start) => line.split_at(start),
+            None => (line, ""),
+        };
+        substitute_placeholders(code, vars, &mut out)?;
+        out.push_str(comment);
+    }
+
+    Ok(out)
+}
+
+/// Finds where the comment on a TOML `line` starts, if it has one, skipping over `#` in strings
+///
+/// `open_string` holds the delimiter of a multi-line string that is still open at the end of the
+/// line, to carry it over to the next one
+fn find_comment(line: &str, open_string: &mut Option<&'static [u8]>) -> Option<usize> {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if let Some(delimiter) = *open_string {
+            if bytes[i] == b'\\' && delimiter == b"\"\"\"" {
+                i += 2;
+            } else if bytes[i..].starts_with(delimiter) {
+                *open_string = None;
+                i += delimiter.len();
+            } else {
+                i += 1;
+            }
+            continue;
+        }
+
+        match bytes[i] {
+            b'#' => return Some(i),
+            quote @ (b'"' | b'\'') => {
+                let delimiter: &'static [u8] = if quote == b'"' { b"\"\"\"" } else { b"'''" };
+                if bytes[i..].starts_with(delimiter) {
+                    *open_string = Some(delimiter);
+                    i += delimiter.len();
+                    continue;
+                }
+
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote && bytes[i] != b'\n' {
+                    // only basic strings have escapes
+                    if bytes[i] == b'\\' && quote == b'"' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+
+    None
+}
+
+/// Replaces the `${VAR}` placeholders in `s`, appending the result to `out`
+fn substitute_placeholders(
+    s: &str,
+    vars: &HashMap<String, String>,
+    out: &mut String,
+) -> Result<(), Error> {
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = escaped;
+        } else if let Some(placeholder) = rest.strip_prefix("${") {
+            let end = placeholder.find('}').ok_or_else(|| {
+                Error::UnterminatedVariable(placeholder.chars().take(20).collect())
+            })?;
+            let var = &placeholder[..end];
+            let value = match vars.get(var) {
+                Some(value) => value.clone(),
+                None => {
+                    std::env::var(var).map_err(|_| Error::UnresolvedVariable(var.to_string()))?
+                }
+            };
+            out.push_str(&value);
+            rest = &placeholder[end + 1..];
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+
+    Ok(())
+}
+
 /// Given a directory containing toml files that each define a check pipeline, construct a hashmap
 /// of pipelines, where the keys are the pipelines' names (filename of the toml file that defines
 /// them, without the file extension)
+///
+/// Files with a `.json` extension are parsed as JSON instead, with the same structure.
+///
+/// `${VAR}` placeholders in the files, outside comments, are replaced by the environment variable
+/// `VAR`, see [`load_pipelines_with_vars`] to provide them some other way
 pub fn load_pipelines(path: impl AsRef<Path>) -> Result<HashMap<String, Pipeline>, Error> {
+    load_pipelines_with_vars(path, &HashMap::new())
+}
+
+/// Equivalent to [`load_pipelines`], but `${VAR}` placeholders are replaced by the value of `VAR`
+/// in `vars`, falling back to the environment if it isn't there
+///
+/// `$${` can be used for a literal `${`
+pub fn load_pipelines_with_vars(
+    path: impl AsRef<Path>,
+    vars: &HashMap<String, String>,
+) -> Result<HashMap<String, Pipeline>, Error> {
     std::fs::read_dir(path)?
         // transform dir entries into (String, Pipeline) pairs
         .map(|entry| {
@@ -166,20 +1349,26 @@ pub fn load_pipelines(path: impl AsRef<Path>) -> Result<HashMap<String, Pipeline
                 return Err(Error::DirectoryStructure);
             }
 
-            let name = entry
-                .file_name()
-                .to_str()
-                .ok_or(Error::InvalidFilename)?
-                .trim_end_matches(".toml")
-                .to_string();
+            let file_name = entry.file_name();
+            let file_name = file_name.to_str().ok_or(Error::InvalidFilename)?;
 
-            let mut pipeline = toml::from_str(&std::fs::read_to_string(entry.path())?)?;
-            (
-                pipeline.num_leading_required,
-                pipeline.num_trailing_required,
-            ) = derive_num_leading_trailing(&pipeline);
+            let contents = std::fs::read_to_string(entry.path())?;
+            let (name, pipeline) = match file_name.strip_suffix(".json") {
+                Some(name) => (
+                    name,
+                    substitute_vars(&contents, vars).and_then(|s| Pipeline::from_json(&s)),
+                ),
+                None => (
+                    file_name.trim_end_matches(".toml"),
+                    substitute_vars(&contents, vars).and_then(|s| Pipeline::from_toml(&s)),
+                ),
+            };
+            let pipeline = pipeline.map_err(|e| Error::InvalidPipeline {
+                file: file_name.to_string(),
+                source: Box::new(e),
+            })?;
 
-            Ok(Some((name, pipeline)))
+            Ok(Some((name.to_string(), pipeline)))
         })
         // remove `None`s
         .filter_map(Result::transpose)
@@ -187,6 +1376,74 @@ pub fn load_pipelines(path: impl AsRef<Path>) -> Result<HashMap<String, Pipeline
         .collect()
 }
 
+/// Maps parameters, and optionally time resolutions, to the names of the pipelines that should
+/// QC them, so callers don't need to know which pipeline to use for which data
+///
+/// Routes are tried in order, and routes without a `time_resolution` match any time resolution.
+/// They are usually loaded from a TOML file with [`load_routes`], like:
+///
+/// ```toml
+/// [[route]]
+/// parameter = "air_temperature"
+/// time_resolution = "PT1H"
+/// pipeline = "TA_PT1H"
+/// ```
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone, Default)]
+pub struct PipelineRoutes {
+    /// Routes, in the order they are tried
+    #[serde(rename = "route", default)]
+    pub routes: Vec<PipelineRoute>,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Clone)]
+pub struct PipelineRoute {
+    pub parameter: String,
+    /// ISO 8601 duration
+    pub time_resolution: Option<String>,
+    pub pipeline: String,
+}
+
+impl PipelineRoutes {
+    /// Find the name of the pipeline for a parameter at a time resolution, if any
+    pub fn find(&self, parameter: &str, time_resolution: RelativeDuration) -> Option<&str> {
+        self.routes
+            .iter()
+            .find(|route| {
+                route.parameter == parameter
+                    && route
+                        .time_resolution
+                        .as_ref()
+                        .is_none_or(|route_resolution| {
+                            RelativeDuration::parse_from_iso8601(route_resolution)
+                                .is_ok_and(|route_resolution| route_resolution == time_resolution)
+                        })
+            })
+            .map(|route| route.pipeline.as_str())
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        for time_resolution in self
+            .routes
+            .iter()
+            .filter_map(|route| route.time_resolution.as_ref())
+        {
+            RelativeDuration::parse_from_iso8601(time_resolution)
+                .map_err(|_| Error::InvalidRoute(time_resolution.clone()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Load a routing table from a TOML file, see [`PipelineRoutes`]
+///
+/// This should not be in the same directory as the pipelines passed to [`load_pipelines`], since
+/// it isn't a pipeline
+pub fn load_routes(path: impl AsRef<Path>) -> Result<PipelineRoutes, Error> {
+    let routes: PipelineRoutes = toml::from_str(&std::fs::read_to_string(path)?)?;
+    routes.validate()?;
+    Ok(routes)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -198,4 +1455,415 @@ mod test {
             .get("TA_PT1H")
             .unwrap();
     }
+
+    #[test]
+    fn test_deserialize_on_fail() {
+        let pipeline: Pipeline = toml::from_str(
+            r#"
+            [[step]]
+            name = "range_check"
+            on_fail = "warn"
+            [step.range_check]
+            min = -55
+            max = 50
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(pipeline.steps[0].on_fail, Some(FlagOverride::Warn));
+    }
+
+    #[test]
+    fn test_deserialize_combi() {
+        let pipeline: Pipeline = toml::from_str(
+            r#"
+            [combi]
+
+            [[step]]
+            name = "range_check"
+            [step.range_check]
+            min = -55
+            max = 50
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            pipeline.combi,
+            Some(CombiConf {
+                precedence: default_precedence()
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_names() {
+        for pipeline in load_pipelines("sample_pipelines/fresh").unwrap().values() {
+            for step in pipeline.steps.iter() {
+                // the names should match the keys serde uses for the checks' tables
+                let serde_json::Value::Object(map) = serde_json::to_value(&step.check).unwrap()
+                else {
+                    panic!("check should serialize to a table");
+                };
+                assert_eq!(map.keys().collect::<Vec<_>>(), vec![step.check.name()]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dependency_levels() {
+        let mut pipeline: Pipeline = toml::from_str(
+            r#"
+            [[step]]
+            name = "buddy_check"
+            depends_on = ["range_check", "step_check"]
+            [step.buddy_check]
+            radii = [5000.0]
+            nums_min = [2]
+            threshold = 2.0
+            max_elev_diff = 200.0
+            elev_gradient = 0.0
+            min_std = 1.0
+            num_iterations = 2
+
+            [[step]]
+            name = "range_check"
+            [step.range_check]
+            min = -55
+            max = 50
+
+            [[step]]
+            name = "step_check"
+            depends_on = ["range_check"]
+            [step.step_check]
+            max = 18.6
+
+            [[step]]
+            name = "flatline_check"
+            [step.flatline_check]
+            max = 10
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            pipeline.dependency_levels().unwrap(),
+            vec![vec![1, 3], vec![2], vec![0]]
+        );
+
+        pipeline.steps[1].depends_on = vec!["buddy_check".to_string()];
+        assert!(matches!(
+            pipeline.dependency_levels(),
+            Err(Error::DependencyCycle(_))
+        ));
+
+        pipeline.steps[1].depends_on = vec!["dip_check".to_string()];
+        assert!(matches!(
+            pipeline.dependency_levels(),
+            Err(Error::UnknownDependency { .. })
+        ));
+
+        pipeline.steps[1].depends_on = Vec::new();
+        pipeline.steps[3].name = "step_check".to_string();
+        assert!(matches!(
+            pipeline.dependency_levels(),
+            Err(Error::DuplicateStep(name)) if name == "step_check"
+        ));
+    }
+
+    #[test]
+    fn test_groups() {
+        let mut pipeline: Pipeline = toml::from_str(
+            r#"
+            [[step]]
+            name = "range_check"
+            [step.range_check]
+            min = -55
+            max = 50
+
+            [[step]]
+            name = "step_check"
+            [step.step_check]
+            max = 18.6
+
+            [[step]]
+            name = "spike_check"
+            [step.spike_check]
+            max = 18.6
+
+            [[step]]
+            name = "flatline_check"
+            [step.flatline_check]
+            max = 10
+
+            [[group]]
+            steps = ["range_check"]
+
+            [[group]]
+            steps = ["step_check", "spike_check"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            pipeline.dependency_levels().unwrap(),
+            vec![vec![0, 3], vec![1, 2]]
+        );
+
+        pipeline.steps[2].depends_on = vec!["step_check".to_string()];
+        assert!(matches!(
+            pipeline.dependency_levels(),
+            Err(Error::InvalidGroup { group: 1, .. })
+        ));
+
+        pipeline.steps[2].depends_on = Vec::new();
+        pipeline.groups[1].steps.push("range_check".to_string());
+        assert!(matches!(
+            pipeline.dependency_levels(),
+            Err(Error::InvalidGroup { group: 1, .. })
+        ));
+
+        pipeline.groups[1].steps = vec!["dip_check".to_string()];
+        assert!(matches!(
+            pipeline.dependency_levels(),
+            Err(Error::InvalidGroup { group: 1, .. })
+        ));
+
+        pipeline.groups[1].steps = Vec::new();
+        assert!(matches!(
+            pipeline.dependency_levels(),
+            Err(Error::InvalidGroup { group: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let pipeline: Pipeline = toml::from_str(
+            r#"
+            [[step]]
+            name = "range_check"
+            [step.range_check]
+            min = 50
+            max = -55
+            "#,
+        )
+        .unwrap();
+
+        match pipeline.validate() {
+            Err(Error::InvalidParameter { step, field, .. }) => {
+                assert_eq!(step, "range_check");
+                assert_eq!(field, "min");
+            }
+            other => panic!("expected InvalidParameter, got {:?}", other),
+        }
+
+        let pipeline: Pipeline = toml::from_str(
+            r#"
+            [[step]]
+            name = "range_check"
+            backend = "grpc"
+            [step.range_check]
+            min = -55
+            max = 50
+            "#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            pipeline.validate(),
+            Err(Error::InvalidParameter {
+                field: "endpoint",
+                ..
+            })
+        ));
+
+        let pipeline: Pipeline = toml::from_str(
+            r#"
+            [[step]]
+            name = "buddy_event_check"
+            [step.buddy_event_check]
+            radius = 5000.0
+            num_min = 2
+            event_threshold = 0.2
+            threshold = 1.5
+            num_iterations = 1
+            "#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            pipeline.validate(),
+            Err(Error::InvalidParameter {
+                field: "threshold",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_builder_and_json() {
+        let built = Pipeline::builder()
+            .step(PipelineStep::new(
+                "step_check",
+                CheckConf::StepCheck(StepCheckConf { max: 18.6 }),
+            ))
+            .step(PipelineStep::new(
+                "flatline_check",
+                CheckConf::FlatlineCheck(FlatlineCheckConf { max: 10 }),
+            ))
+            .build()
+            .unwrap();
+        assert_eq!(built.num_leading_required, 10);
+
+        let parsed = Pipeline::from_json(&serde_json::to_string(&built).unwrap()).unwrap();
+        assert_eq!(parsed, built);
+    }
+
+    #[test]
+    fn test_routes() {
+        let routes: PipelineRoutes = toml::from_str(
+            r#"
+            [[route]]
+            parameter = "air_temperature"
+            time_resolution = "PT1H"
+            pipeline = "TA_PT1H"
+
+            [[route]]
+            parameter = "air_temperature"
+            pipeline = "TA"
+            "#,
+        )
+        .unwrap();
+        routes.validate().unwrap();
+
+        assert_eq!(
+            routes.find("air_temperature", RelativeDuration::hours(1)),
+            Some("TA_PT1H")
+        );
+        assert_eq!(
+            routes.find("air_temperature", RelativeDuration::minutes(10)),
+            Some("TA")
+        );
+        assert_eq!(
+            routes.find("precipitation", RelativeDuration::hours(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_pipeline_schema() {
+        let schema: serde_json::Value = serde_json::from_str(&pipeline_schema()).unwrap();
+
+        assert!(schema["properties"]["step"].is_object());
+        assert!(schema["definitions"]["PipelineStep"].is_object());
+        assert!(schema["definitions"]["StepCheckConf"].is_object());
+    }
+
+    #[test]
+    fn test_step_filter() {
+        let pipeline = Pipeline::from_toml(
+            r#"
+            [[step]]
+            name = "sct"
+            only_providers = [1, 2]
+            only_station_types = ["automatic"]
+            max_elevation = 1000.0
+            [step.sct]
+            num_min = 5
+            num_max = 100
+            inner_radius = 50000.0
+            outer_radius = 150000.0
+            num_iterations = 5
+            num_min_prof = 20
+            min_elev_diff = 200.0
+            min_horizontal_scale = 10000.0
+            vertical_scale = 200.0
+            pos = [4.0]
+            neg = [8.0]
+            eps2 = [0.5]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            pipeline.steps[0].filter,
+            StepFilter {
+                only_providers: Some(vec![1, 2]),
+                only_station_types: Some(vec!["automatic".to_string()]),
+                min_elevation: None,
+                max_elevation: Some(1000.),
+            }
+        );
+        assert!(matches!(pipeline.steps[0].check, CheckConf::Sct(_)));
+
+        let station = |provider, station_type: Option<&str>| StationMetadata {
+            provider,
+            station_type: station_type.map(String::from),
+            ..Default::default()
+        };
+        let filter = &pipeline.steps[0].filter;
+        assert!(filter.applies_to(&station(Some(2), Some("automatic")), 10.));
+        assert!(!filter.applies_to(&station(Some(3), Some("automatic")), 10.));
+        assert!(!filter.applies_to(&station(None, Some("automatic")), 10.));
+        assert!(!filter.applies_to(&station(Some(2), Some("manual")), 10.));
+        assert!(!filter.applies_to(&station(Some(2), None), 10.));
+        assert!(!filter.applies_to(&station(Some(1), Some("automatic")), 1500.));
+
+        assert!(matches!(
+            Pipeline::from_toml(
+                r#"
+                [[step]]
+                name = "range_check"
+                min_elevation = 500.0
+                max_elevation = 100.0
+                [step.range_check]
+                min = 0.0
+                max = 1.0
+                "#,
+            ),
+            Err(Error::InvalidParameter {
+                field: "min_elevation",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_substitute_vars() {
+        let vars = HashMap::from([("MAX".to_string(), "18.6".to_string())]);
+
+        assert_eq!(
+            substitute_vars("max = ${MAX} # costs $5, $${MAX}", &vars).unwrap(),
+            "max = 18.6 # costs $5, $${MAX}"
+        );
+        assert_eq!(
+            substitute_vars("name = \"$${MAX}\"", &vars).unwrap(),
+            "name = \"${MAX}\""
+        );
+        // placeholders in comments are left alone, even if they don't resolve, but a # in a
+        // string doesn't start one
+        assert_eq!(
+            substitute_vars(
+                "# max = ${ROVE_TEST_UNSET_VARIABLE}\nmax = ${MAX} # not ${ROVE_TEST_UNSET_VARIABLE}\n",
+                &vars
+            )
+            .unwrap(),
+            "# max = ${ROVE_TEST_UNSET_VARIABLE}\nmax = 18.6 # not ${ROVE_TEST_UNSET_VARIABLE}\n"
+        );
+        assert_eq!(
+            substitute_vars(
+                "a = \"#${MAX}\\\"#\"\nb = '#${MAX}'\nc = \"\"\"\n#${MAX}\n\"\"\" # ${MAX}",
+                &vars
+            )
+            .unwrap(),
+            "a = \"#18.6\\\"#\"\nb = '#18.6'\nc = \"\"\"\n#18.6\n\"\"\" # ${MAX}"
+        );
+        assert!(matches!(
+            substitute_vars("max = ${ROVE_TEST_UNSET_VARIABLE}", &vars),
+            Err(Error::UnresolvedVariable(var)) if var == "ROVE_TEST_UNSET_VARIABLE"
+        ));
+        assert!(matches!(
+            substitute_vars("max = ${MAX", &vars),
+            Err(Error::UnterminatedVariable(_))
+        ));
+    }
 }
@@ -0,0 +1,344 @@
+use crate::{
+    data_switch::{DataCache, SpaceSpec, TimeSpec},
+    hooks::RunHooks,
+    results::QcResult,
+    runs::{RunState, RunStatus},
+    RunOptions,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Hash of what a run was asked to do, so that a key reused for a different request isn't
+/// answered with the first request's results
+pub(crate) type Fingerprint = u64;
+
+/// Hash the options of a run that affect its results
+fn hash_options(hasher: &mut DefaultHasher, pipeline: &str, options: &RunOptions) {
+    pipeline.hash(hasher);
+    options.continue_on_error.hash(hasher);
+    options.steps.hash(hasher);
+}
+
+fn hash_f32s(hasher: &mut DefaultHasher, values: &[f32]) {
+    values.len().hash(hasher);
+    for value in values {
+        value.to_bits().hash(hasher);
+    }
+}
+
+fn hash_series(hasher: &mut DefaultHasher, values: &[Option<f32>]) {
+    values.len().hash(hasher);
+    for value in values {
+        value.map(f32::to_bits).hash(hasher);
+    }
+}
+
+/// [`Fingerprint`] of a run fetching its data, see
+/// [`Scheduler::validate_direct_with_options`](crate::Scheduler::validate_direct_with_options)
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn fetch_fingerprint(
+    data_source: &str,
+    backing_sources: &[impl AsRef<str>],
+    time_spec: &TimeSpec,
+    space_spec: &SpaceSpec,
+    pipeline: &str,
+    extra_spec: Option<&str>,
+    options: &RunOptions,
+) -> Fingerprint {
+    let mut hasher = DefaultHasher::new();
+    data_source.hash(&mut hasher);
+    for source in backing_sources {
+        source.as_ref().hash(&mut hasher);
+    }
+    time_spec.timerange.hash(&mut hasher);
+    time_spec
+        .time_resolution
+        .format_to_iso8601()
+        .hash(&mut hasher);
+    time_spec.times.hash(&mut hasher);
+    match space_spec {
+        SpaceSpec::One(id) => (0u8, id).hash(&mut hasher),
+        SpaceSpec::Multiple(ids) => (1u8, ids).hash(&mut hasher),
+        SpaceSpec::Polygon(polygon) => {
+            2u8.hash(&mut hasher);
+            for point in polygon {
+                hash_f32s(&mut hasher, &[point.lat, point.lon]);
+            }
+        }
+        SpaceSpec::All => 3u8.hash(&mut hasher),
+    }
+    extra_spec.hash(&mut hasher);
+    hash_options(&mut hasher, pipeline, options);
+    hasher.finish()
+}
+
+/// [`Fingerprint`] of a run on data sent with it, see
+/// [`Scheduler::validate_data`](crate::Scheduler::validate_data)
+pub(crate) fn data_fingerprint(
+    data: &DataCache,
+    pipeline: &str,
+    options: &RunOptions,
+) -> Fingerprint {
+    let mut hasher = DefaultHasher::new();
+    for (id, values) in &data.data {
+        id.hash(&mut hasher);
+        hash_series(&mut hasher, values);
+    }
+    hash_f32s(&mut hasher, &data.rtree.lats);
+    hash_f32s(&mut hasher, &data.rtree.lons);
+    hash_f32s(&mut hasher, &data.rtree.elevs);
+    data.start_time.hash(&mut hasher);
+    data.period.format_to_iso8601().hash(&mut hasher);
+    data.num_leading_points.hash(&mut hasher);
+    data.num_trailing_points.hash(&mut hasher);
+    // sorted, as maps' order isn't
+    let mut params: Vec<_> = data.params.iter().collect();
+    params.sort_by_key(|(name, _)| *name);
+    for (name, param) in params {
+        name.hash(&mut hasher);
+        for values in param {
+            hash_series(&mut hasher, values);
+        }
+    }
+    format!("{:?}", data.metadata).hash(&mut hasher);
+    data.backing.hash(&mut hasher);
+    data.units.hash(&mut hasher);
+    let mut param_units: Vec<_> = data.param_units.iter().collect();
+    param_units.sort();
+    param_units.hash(&mut hasher);
+    hash_options(&mut hasher, pipeline, options);
+    hasher.finish()
+}
+
+#[derive(Debug)]
+struct Completed {
+    fingerprint: Fingerprint,
+    finished_at: Instant,
+    results: Vec<QcResult>,
+}
+
+#[derive(Debug, Default)]
+struct Runs {
+    completed: HashMap<String, Completed>,
+    // keys, oldest first
+    order: VecDeque<String>,
+}
+
+/// Results of recently completed runs, by the idempotency key they were started with, shared
+/// between clones of a scheduler, see
+/// [`Scheduler::with_idempotency`](crate::Scheduler::with_idempotency)
+#[derive(Debug, Clone)]
+pub(crate) struct ReplayCache {
+    ttl: Duration,
+    capacity: usize,
+    runs: Arc<Mutex<Runs>>,
+}
+
+impl ReplayCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        ReplayCache {
+            ttl,
+            capacity: capacity.max(1),
+            runs: Arc::new(Mutex::new(Runs::default())),
+        }
+    }
+
+    /// The completed runs, after forgetting the ones older than the ttl
+    fn runs(&self) -> MutexGuard<'_, Runs> {
+        // runs are never left inconsistent, so poisoning can be ignored
+        let mut runs = self.runs.lock().unwrap_or_else(PoisonError::into_inner);
+        let Runs { completed, order } = &mut *runs;
+        // runs are inserted in the order they finish, so the expired ones are at the front
+        while let Some(key) = order.front() {
+            if completed[key].finished_at.elapsed() < self.ttl {
+                break;
+            }
+            completed.remove(key);
+            order.pop_front();
+        }
+        runs
+    }
+
+    /// The fingerprint and results of the run completed with idempotency key `key`, if it was
+    /// recent enough
+    pub fn get(&self, key: &str) -> Option<(Fingerprint, Vec<QcResult>)> {
+        self.runs()
+            .completed
+            .get(key)
+            .map(|run| (run.fingerprint, run.results.clone()))
+    }
+
+    /// Remember the results of a run, forgetting the oldest run if the cache is full
+    fn insert(&self, key: String, fingerprint: Fingerprint, results: Vec<QcResult>) {
+        let mut runs = self.runs();
+        let Runs { completed, order } = &mut *runs;
+        if completed.remove(&key).is_some() {
+            order.retain(|other| *other != key);
+        }
+        while completed.len() >= self.capacity {
+            match order.pop_front() {
+                Some(oldest) => completed.remove(&oldest),
+                None => break,
+            };
+        }
+        order.push_back(key.clone());
+        completed.insert(
+            key,
+            Completed {
+                fingerprint,
+                finished_at: Instant::now(),
+                results,
+            },
+        );
+    }
+
+    /// Hooks that record the results of a run started with idempotency key `key`, for a request
+    /// with `fingerprint`, to be replayed if it completes cleanly
+    pub fn recorder(&self, key: String, fingerprint: Fingerprint) -> Recorder {
+        Recorder {
+            cache: self.clone(),
+            key,
+            fingerprint,
+            results: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+/// See [`ReplayCache::recorder`]
+pub(crate) struct Recorder {
+    cache: ReplayCache,
+    key: String,
+    fingerprint: Fingerprint,
+    results: Mutex<Vec<QcResult>>,
+}
+
+impl Recorder {
+    fn results(&self) -> MutexGuard<'_, Vec<QcResult>> {
+        self.results.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl RunHooks for Recorder {
+    fn on_step_complete(&self, result: &QcResult) {
+        self.results().push(result.clone());
+    }
+
+    fn on_run_complete(&self, status: &RunStatus) {
+        // runs that hit errors are worth retrying for real
+        if status.state == RunState::Done && status.error.is_none() {
+            let results = std::mem::take(&mut *self.results());
+            self.cache
+                .insert(self.key.clone(), self.fingerprint, results);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data_switch::Timestamp, runs::RunId};
+    use chrono::Utc;
+    use chronoutil::RelativeDuration;
+
+    #[test]
+    fn test_replay_cache() {
+        let cache = ReplayCache::new(Duration::from_secs(60), 2);
+        let status = |state, error: Option<&str>| RunStatus {
+            id: RunId::new_v4(),
+            pipeline: "TA_PT1H".to_string(),
+            state,
+            error: error.map(String::from),
+            started_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let result = QcResult {
+            test: "range_check".to_string(),
+            ..Default::default()
+        };
+
+        let recorder = cache.recorder("done".to_string(), 1);
+        recorder.on_step_complete(&result);
+        recorder.on_run_complete(&status(RunState::Done, None));
+        assert_eq!(cache.get("done"), Some((1, vec![result.clone()])));
+
+        for (key, state, error) in [
+            ("failed", RunState::Failed, Some("step failed")),
+            ("continued", RunState::Done, Some("step failed")),
+            ("cancelled", RunState::Cancelled, None),
+        ] {
+            let recorder = cache.recorder(key.to_string(), 1);
+            recorder.on_step_complete(&result);
+            recorder.on_run_complete(&status(state, error));
+            assert_eq!(cache.get(key), None);
+        }
+
+        // the oldest run is forgotten once the cache is full
+        for key in ["second", "third"] {
+            cache
+                .recorder(key.to_string(), 2)
+                .on_run_complete(&status(RunState::Done, None));
+        }
+        assert_eq!(cache.get("done"), None);
+        assert_eq!(cache.get("second"), Some((2, Vec::new())));
+        assert_eq!(cache.get("third"), Some((2, Vec::new())));
+
+        let cache = ReplayCache::new(Duration::ZERO, 2);
+        let recorder = cache.recorder("expired".to_string(), 1);
+        recorder.on_run_complete(&status(RunState::Done, None));
+        assert_eq!(cache.get("expired"), None);
+    }
+
+    #[test]
+    fn test_fingerprints() {
+        let time_spec = TimeSpec::new(Timestamp(0), Timestamp(3600), RelativeDuration::hours(1));
+        let options = RunOptions::default();
+        let fingerprint = |space_spec: &SpaceSpec, pipeline: &str, options: &RunOptions| {
+            fetch_fingerprint(
+                "frost",
+                &[] as &[&str],
+                &time_spec,
+                space_spec,
+                pipeline,
+                None,
+                options,
+            )
+        };
+
+        let first = fingerprint(&SpaceSpec::All, "TA_PT1H", &options);
+        // the run id and other options that don't affect the results don't matter
+        assert_eq!(
+            fingerprint(&SpaceSpec::All, "TA_PT1H", &RunOptions::default()),
+            first
+        );
+        assert_ne!(
+            fingerprint(&SpaceSpec::One("18700".to_string()), "TA_PT1H", &options),
+            first
+        );
+        assert_ne!(fingerprint(&SpaceSpec::All, "TA_PT10M", &options), first);
+
+        let data = |value| {
+            DataCache::new(
+                vec![60.],
+                vec![10.],
+                vec![100.],
+                Timestamp(0),
+                RelativeDuration::hours(1),
+                0,
+                0,
+                vec![("18700".to_string(), vec![Some(value)])],
+            )
+        };
+        assert_eq!(
+            data_fingerprint(&data(1.), "TA_PT1H", &options),
+            data_fingerprint(&data(1.), "TA_PT1H", &options)
+        );
+        assert_ne!(
+            data_fingerprint(&data(1.), "TA_PT1H", &options),
+            data_fingerprint(&data(2.), "TA_PT1H", &options)
+        );
+    }
+}
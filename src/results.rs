@@ -0,0 +1,269 @@
+use crate::{
+    data_switch::{StationMetadata, Timestamp},
+    pb,
+    runs::RunId,
+};
+use std::{collections::HashMap, time::Duration};
+
+/// Verdict of a check on one data point
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Flag {
+    /// The point passed the check
+    Pass,
+    /// The point failed the check
+    Fail,
+    /// The point is suspicious, but not bad enough to fail
+    Warn,
+    /// The check couldn't decide, e.g. because it wasn't run on this point
+    Inconclusive,
+    /// The point's value is impossible, e.g. not a number
+    Invalid,
+    /// The point has no value
+    DataMissing,
+    /// The point had too few neighbours for a spatial check to judge it
+    Isolated,
+}
+
+/// A check's verdict on one data point
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    /// Time of the point
+    pub time: Timestamp,
+    /// Data source defined identifier of the series the point is from
+    pub identifier: String,
+    /// The check's verdict
+    pub flag: Flag,
+    /// For checks that detect clock offsets, the number of timesteps this series is estimated to
+    /// lag behind its reference by
+    pub estimated_shift: Option<i32>,
+    /// For checks that can quantify it, how far the value was from what the check expected, in
+    /// units specific to the check. Positive if the value was higher than expected
+    pub score: Option<f32>,
+    /// For checks that can propose one, a suggested replacement for a flagged value
+    pub corrected_value: Option<f32>,
+}
+
+/// Metadata of a pipeline, so results can be traced back to the exact QC configuration
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PipelineMetadata {
+    /// Name the pipeline is registered under
+    pub name: String,
+    /// See [`Pipeline::version`](crate::Pipeline::version)
+    pub version: Option<String>,
+    /// See [`Pipeline::description`](crate::Pipeline::description)
+    pub description: Option<String>,
+    /// See [`Pipeline::author`](crate::Pipeline::author)
+    pub author: Option<String>,
+}
+
+/// Results of one step of a validation run, or the combined results of all its steps
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QcResult {
+    /// Name of the step, or of the combined results
+    pub test: String,
+    /// Flags for each data point
+    pub results: Vec<TestResult>,
+    /// The pipeline that produced these results
+    pub pipeline: PipelineMetadata,
+    /// Metadata of the stations in `results`, keyed by identifier, for the stations the data
+    /// source reported any for
+    pub stations: HashMap<String, StationMetadata>,
+    /// The data source the data was fetched from, where the run's data source was a fallback
+    /// chain
+    pub data_source: Option<String>,
+    /// Only set for runs that [continue on error](crate::RunOptions::continue_on_error). On a
+    /// step's results, why the step failed, in which case it has no results. On the combined
+    /// results, which steps' flags are missing from them
+    pub error: Option<String>,
+    /// The run these results are from
+    pub run_id: RunId,
+    /// How long the step took to run, or for the combined results, how long combining the
+    /// steps' flags took
+    pub duration: Option<Duration>,
+}
+
+impl From<pb::Flag> for Flag {
+    fn from(item: pb::Flag) -> Self {
+        match item {
+            pb::Flag::Pass => Flag::Pass,
+            pb::Flag::Fail => Flag::Fail,
+            pb::Flag::Warn => Flag::Warn,
+            pb::Flag::Inconclusive => Flag::Inconclusive,
+            pb::Flag::Invalid => Flag::Invalid,
+            pb::Flag::DataMissing => Flag::DataMissing,
+            pb::Flag::Isolated => Flag::Isolated,
+        }
+    }
+}
+
+impl From<Flag> for pb::Flag {
+    fn from(item: Flag) -> Self {
+        match item {
+            Flag::Pass => pb::Flag::Pass,
+            Flag::Fail => pb::Flag::Fail,
+            Flag::Warn => pb::Flag::Warn,
+            Flag::Inconclusive => pb::Flag::Inconclusive,
+            Flag::Invalid => pb::Flag::Invalid,
+            Flag::DataMissing => pb::Flag::DataMissing,
+            Flag::Isolated => pb::Flag::Isolated,
+        }
+    }
+}
+
+impl TryFrom<pb::TestResult> for TestResult {
+    type Error = String;
+
+    fn try_from(item: pb::TestResult) -> Result<Self, Self::Error> {
+        Ok(TestResult {
+            time: Timestamp(item.time.map_or(0, |time| time.seconds)),
+            flag: pb::Flag::from_i32(item.flag)
+                .ok_or_else(|| format!("unknown flag {}", item.flag))?
+                .into(),
+            identifier: item.identifier,
+            estimated_shift: item.estimated_shift,
+            score: item.score,
+            corrected_value: item.corrected_value,
+        })
+    }
+}
+
+impl From<TestResult> for pb::TestResult {
+    fn from(item: TestResult) -> Self {
+        pb::TestResult {
+            time: Some(prost_types::Timestamp {
+                seconds: item.time.0,
+                nanos: 0,
+            }),
+            identifier: item.identifier,
+            flag: pb::Flag::from(item.flag).into(),
+            estimated_shift: item.estimated_shift,
+            score: item.score,
+            corrected_value: item.corrected_value,
+        }
+    }
+}
+
+impl From<pb::PipelineMetadata> for PipelineMetadata {
+    fn from(item: pb::PipelineMetadata) -> Self {
+        PipelineMetadata {
+            name: item.name,
+            version: item.version,
+            description: item.description,
+            author: item.author,
+        }
+    }
+}
+
+impl From<PipelineMetadata> for pb::PipelineMetadata {
+    fn from(item: PipelineMetadata) -> Self {
+        pb::PipelineMetadata {
+            name: item.name,
+            version: item.version,
+            description: item.description,
+            author: item.author,
+        }
+    }
+}
+
+/// Only the fields a step sets are converted, the rest are left for the scheduler to fill in
+impl TryFrom<pb::ValidateResponse> for QcResult {
+    type Error = String;
+
+    fn try_from(item: pb::ValidateResponse) -> Result<Self, Self::Error> {
+        Ok(QcResult {
+            test: item.test,
+            results: item
+                .results
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            pipeline: item.pipeline.map(Into::into).unwrap_or_default(),
+            stations: item
+                .stations
+                .into_iter()
+                .map(|(identifier, metadata)| (identifier, metadata.into()))
+                .collect(),
+            data_source: item.data_source,
+            error: item.error,
+            run_id: item.run_id.parse().unwrap_or_default(),
+            // negative durations can't have been measured, so are dropped
+            duration: item.duration.and_then(|duration| {
+                Some(Duration::new(
+                    duration.seconds.try_into().ok()?,
+                    duration.nanos.try_into().ok()?,
+                ))
+            }),
+        })
+    }
+}
+
+impl From<QcResult> for pb::ValidateResponse {
+    fn from(item: QcResult) -> Self {
+        pb::ValidateResponse {
+            test: item.test,
+            results: item.results.into_iter().map(Into::into).collect(),
+            pipeline: Some(item.pipeline.into()),
+            explanation: None,
+            stations: item
+                .stations
+                .iter()
+                .map(|(identifier, metadata)| (identifier.clone(), metadata.into()))
+                .collect(),
+            data_source: item.data_source,
+            error: item.error,
+            run_id: item.run_id.to_string(),
+            duration: item.duration.map(|duration| prost_types::Duration {
+                seconds: duration.as_secs() as i64,
+                nanos: duration.subsec_nanos() as i32,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let result = QcResult {
+            test: "range_check".to_string(),
+            results: vec![TestResult {
+                time: Timestamp(3600),
+                identifier: "18700".to_string(),
+                flag: Flag::Fail,
+                estimated_shift: None,
+                score: Some(2.5),
+                corrected_value: None,
+            }],
+            pipeline: PipelineMetadata {
+                name: "TA_PT1H".to_string(),
+                version: Some("1".to_string()),
+                ..Default::default()
+            },
+            stations: HashMap::from([(
+                "18700".to_string(),
+                StationMetadata {
+                    provider: Some(1),
+                    ..Default::default()
+                },
+            )]),
+            run_id: RunId::new_v4(),
+            duration: Some(Duration::from_millis(1500)),
+            ..Default::default()
+        };
+
+        let response = pb::ValidateResponse::from(result.clone());
+        assert_eq!(response.results[0].flag, pb::Flag::Fail as i32);
+        assert_eq!(QcResult::try_from(response), Ok(result));
+
+        let unknown_flag = pb::TestResult {
+            flag: 100,
+            ..Default::default()
+        };
+        assert_eq!(
+            TestResult::try_from(unknown_flag),
+            Err("unknown flag 100".to_string())
+        );
+    }
+}
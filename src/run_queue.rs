@@ -0,0 +1,138 @@
+use crate::scheduler::Priority;
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    sync::{Arc, Mutex, PoisonError},
+};
+use tokio::sync::oneshot;
+
+#[derive(Debug)]
+struct State {
+    available: usize,
+    // ordered so the first is the highest priority, and the earliest of those
+    waiters: BTreeMap<(Reverse<Priority>, u64), oneshot::Sender<Permit>>,
+    next_waiter: u64,
+}
+
+/// A semaphore that hands out its permits by priority, then in order of arrival
+#[derive(Debug)]
+pub(crate) struct Queue {
+    state: Mutex<State>,
+}
+
+/// A slot in a [`Queue`], freed when dropped
+#[derive(Debug)]
+pub(crate) struct Permit {
+    // only None while a permit that couldn't be handed over is dropped
+    queue: Option<Arc<Queue>>,
+}
+
+impl Queue {
+    pub fn new(permits: usize) -> Arc<Self> {
+        Arc::new(Queue {
+            state: Mutex::new(State {
+                // a limit of 0 would block everything forever
+                available: permits.max(1),
+                waiters: BTreeMap::new(),
+                next_waiter: 0,
+            }),
+        })
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        // the state is never left inconsistent, so poisoning can be ignored
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wait for a slot, behind everything queued with the same or higher priority
+    pub async fn acquire(self: &Arc<Self>, priority: Priority) -> Permit {
+        let rx = {
+            let mut state = self.state();
+            if state.available > 0 {
+                state.available -= 1;
+                return Permit {
+                    queue: Some(self.clone()),
+                };
+            }
+
+            let (tx, rx) = oneshot::channel();
+            let id = state.next_waiter;
+            state.next_waiter += 1;
+            state.waiters.insert((Reverse(priority), id), tx);
+            rx
+        };
+
+        // waiters are only removed from the queue to be sent a permit, and if this future is
+        // dropped after that, the permit is dropped with the channel and passed on
+        rx.await
+            .expect("queue should hand waiters a permit before dropping them")
+    }
+
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state();
+        while let Some((_, tx)) = state.waiters.pop_first() {
+            match tx.send(Permit {
+                queue: Some(self.clone()),
+            }) {
+                Ok(()) => return,
+                // the waiter gave up, so this permit isn't released, but offered to the next
+                Err(mut permit) => {
+                    permit.queue = None;
+                }
+            }
+        }
+        state.available += 1;
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_queue() {
+        let queue = Queue::new(1);
+        let permit = queue.acquire(Priority::Normal).await;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        for (name, priority) in [
+            ("low", Priority::Low),
+            ("normal", Priority::Normal),
+            ("high", Priority::High),
+            ("abandoned", Priority::High),
+            ("high later", Priority::High),
+        ] {
+            let queue = queue.clone();
+            let tx = tx.clone();
+            let waiter = tokio::spawn(async move {
+                let _permit = queue.acquire(priority).await;
+                tx.send(name).unwrap();
+            });
+            if name == "abandoned" {
+                // give it time to join the queue before giving up
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                waiter.abort();
+            }
+        }
+        drop(tx);
+        // so every waiter is queued before the first is let through
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(permit);
+
+        let mut order = Vec::new();
+        while let Some(name) = rx.recv().await {
+            order.push(name);
+        }
+        assert_eq!(order, vec!["high", "high later", "normal", "low"]);
+        assert_eq!(queue.state().available, 1);
+    }
+}
@@ -0,0 +1,423 @@
+use crate::{
+    data_switch::{DataCache, StationMetadata, Timestamp},
+    harness::{self, BackingData},
+    pb::{
+        self, rove_runner_client::RoveRunnerClient, BackingSlice, DataSlice, ParamSeries,
+        RunStepRequest, Series, ValidateResponse,
+    },
+    pipeline::PipelineStep,
+};
+use chronoutil::RelativeDuration;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+};
+use tonic::{
+    metadata::MetadataValue,
+    transport::{Channel, Endpoint},
+    Request,
+};
+
+impl From<&StationMetadata> for pb::StationMetadata {
+    fn from(metadata: &StationMetadata) -> Self {
+        pb::StationMetadata {
+            provider: metadata.provider,
+            station_type: metadata.station_type.clone(),
+            sensor_height: metadata.sensor_height,
+            wigos_id: metadata.wigos_id.clone(),
+        }
+    }
+}
+
+impl From<pb::StationMetadata> for StationMetadata {
+    fn from(metadata: pb::StationMetadata) -> Self {
+        StationMetadata {
+            provider: metadata.provider,
+            station_type: metadata.station_type,
+            sensor_height: metadata.sensor_height,
+            wigos_id: metadata.wigos_id,
+        }
+    }
+}
+
+fn encode_series(
+    identifier: &str,
+    values: &[Option<f32>],
+    metadata: Option<&StationMetadata>,
+) -> Series {
+    Series {
+        identifier: identifier.to_string(),
+        values: values.iter().map(|value| value.unwrap_or(0.)).collect(),
+        present: values.iter().map(Option::is_some).collect(),
+        metadata: metadata
+            .filter(|metadata| !metadata.is_empty())
+            .map(Into::into),
+    }
+}
+
+fn decode_series(series: Series) -> Result<(String, Vec<Option<f32>>), String> {
+    if series.values.len() != series.present.len() {
+        return Err(format!(
+            "series {} has {} values but {} presence markers",
+            series.identifier,
+            series.values.len(),
+            series.present.len()
+        ));
+    }
+    let values = series
+        .values
+        .into_iter()
+        .zip(series.present)
+        .map(|(value, present)| present.then_some(value))
+        .collect();
+    Ok((series.identifier, values))
+}
+
+impl From<&DataCache> for DataSlice {
+    fn from(cache: &DataCache) -> Self {
+        DataSlice {
+            lats: cache.rtree.lats.clone(),
+            lons: cache.rtree.lons.clone(),
+            elevs: cache.rtree.elevs.clone(),
+            series: cache
+                .data
+                .iter()
+                .enumerate()
+                .map(|(i, (identifier, values))| {
+                    encode_series(identifier, values, Some(cache.station_metadata(i)))
+                })
+                .collect(),
+            start_time: Some(prost_types::Timestamp {
+                seconds: cache.start_time.0,
+                nanos: 0,
+            }),
+            period: cache.period.format_to_iso8601(),
+            num_leading_points: cache.num_leading_points.into(),
+            num_trailing_points: cache.num_trailing_points.into(),
+            params: cache
+                .params
+                .iter()
+                .map(|(name, param)| {
+                    (
+                        name.clone(),
+                        ParamSeries {
+                            series: cache
+                                .data
+                                .iter()
+                                .zip(param)
+                                .map(|((identifier, _), values)| {
+                                    encode_series(identifier, values, None)
+                                })
+                                .collect(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Check that the locations, series and parameters of a slice line up, and its series are long
+/// enough for its leading and trailing points, as checks index them assuming so, and slices can
+/// come from clients
+fn check_aligned(
+    lats: &[f32],
+    lons: &[f32],
+    elevs: &[f32],
+    data: &[(String, Vec<Option<f32>>)],
+    params: &HashMap<String, Vec<Vec<Option<f32>>>>,
+    num_leading_points: u8,
+    num_trailing_points: u8,
+) -> Result<(), String> {
+    if [lats.len(), lons.len(), elevs.len()] != [data.len(); 3] {
+        return Err(format!(
+            "{} series but {} lats, {} lons and {} elevs",
+            data.len(),
+            lats.len(),
+            lons.len(),
+            elevs.len()
+        ));
+    }
+    let series_len = data.first().map_or(0, |(_, values)| values.len());
+    if let Some((identifier, values)) = data.iter().find(|(_, values)| values.len() != series_len) {
+        return Err(format!(
+            "series {} has {} points, unlike the first series' {}",
+            identifier,
+            values.len(),
+            series_len
+        ));
+    }
+    if !data.is_empty()
+        && usize::from(num_leading_points) + usize::from(num_trailing_points) > series_len
+    {
+        return Err(format!(
+            "{} leading and {} trailing points don't fit in series of {} points",
+            num_leading_points, num_trailing_points, series_len
+        ));
+    }
+    for (name, param) in params {
+        if param.len() != data.len() || param.iter().any(|values| values.len() != series_len) {
+            return Err(format!(
+                "param {} isn't aligned with the series it's for",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+impl TryFrom<DataSlice> for DataCache {
+    type Error = String;
+
+    fn try_from(slice: DataSlice) -> Result<Self, Self::Error> {
+        let metadata = slice
+            .series
+            .iter()
+            .map(|series| series.metadata.clone().map(Into::into).unwrap_or_default())
+            .collect();
+        let data = slice
+            .series
+            .into_iter()
+            .map(decode_series)
+            .collect::<Result<Vec<_>, String>>()?;
+        let params = slice
+            .params
+            .into_iter()
+            .map(|(name, param)| {
+                Ok((
+                    name,
+                    param
+                        .series
+                        .into_iter()
+                        .map(|series| decode_series(series).map(|(_, values)| values))
+                        .collect::<Result<Vec<_>, String>>()?,
+                ))
+            })
+            .collect::<Result<HashMap<_, _>, String>>()?;
+        let num_leading_points = slice
+            .num_leading_points
+            .try_into()
+            .map_err(|_| "too many leading points")?;
+        let num_trailing_points = slice
+            .num_trailing_points
+            .try_into()
+            .map_err(|_| "too many trailing points")?;
+        check_aligned(
+            &slice.lats,
+            &slice.lons,
+            &slice.elevs,
+            &data,
+            &params,
+            num_leading_points,
+            num_trailing_points,
+        )?;
+
+        let mut cache = DataCache::new(
+            slice.lats,
+            slice.lons,
+            slice.elevs,
+            Timestamp(slice.start_time.ok_or("missing start_time")?.seconds),
+            RelativeDuration::parse_from_iso8601(&slice.period)
+                .map_err(|e| format!("invalid period: {}", e))?,
+            num_leading_points,
+            num_trailing_points,
+            data,
+        )
+        .with_metadata(metadata);
+        cache.params = params;
+
+        Ok(cache)
+    }
+}
+
+/// Encode a step and the data it runs on as a request to a runner service
+pub fn encode_request(
+    step: &PipelineStep,
+    cache: &DataCache,
+    backing_data: &BackingData,
+) -> RunStepRequest {
+    RunStepRequest {
+        // serialization can only fail on maps with non-string keys, which steps don't have
+        step: serde_json::to_string(step).expect("step should serialize to JSON"),
+        data: Some(cache.into()),
+        backing: backing_data
+            .iter()
+            .map(|((data_source, extra_spec), cache)| BackingSlice {
+                data_source: data_source.clone(),
+                extra_spec: extra_spec.clone(),
+                data: Some(cache.into()),
+            })
+            .collect(),
+    }
+}
+
+/// Decode a request from another rove instance into the step to run and its data
+pub fn decode_request(
+    request: RunStepRequest,
+) -> Result<(PipelineStep, DataCache, BackingData), String> {
+    let step: PipelineStep =
+        serde_json::from_str(&request.step).map_err(|e| format!("invalid step: {}", e))?;
+    let cache = request.data.ok_or("missing data")?.try_into()?;
+    let backing_data = request
+        .backing
+        .into_iter()
+        .map(|backing| {
+            Ok((
+                (backing.data_source, backing.extra_spec),
+                backing.data.ok_or("missing backing data")?.try_into()?,
+            ))
+        })
+        .collect::<Result<BackingData, String>>()?;
+
+    Ok((step, cache, backing_data))
+}
+
+/// Connections to the runner services that steps with `backend = "grpc"` are sent to
+///
+/// Each endpoint's connection is opened on first use, and shared by every step sent there after
+/// that. Clones share the connections.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RemoteRunners {
+    channels: Arc<Mutex<HashMap<String, Channel>>>,
+    // sent as a bearer token with each request, if set
+    token: Option<String>,
+}
+
+impl RemoteRunners {
+    pub(crate) fn new(token: Option<String>) -> Self {
+        RemoteRunners {
+            channels: Arc::default(),
+            token,
+        }
+    }
+
+    fn channel(&self, endpoint: &str) -> Result<Channel, tonic::transport::Error> {
+        // the lock is never held across anything that can panic, so poisoning can be ignored
+        let mut channels = self.channels.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(channel) = channels.get(endpoint) {
+            return Ok(channel.clone());
+        }
+        // channels reconnect by themselves if the connection drops, so can be kept for good
+        let channel = Endpoint::from_shared(endpoint.to_string())?.connect_lazy();
+        channels.insert(endpoint.to_string(), channel.clone());
+        Ok(channel)
+    }
+
+    /// Run a step on the runner service at `endpoint`
+    ///
+    /// Rove servers serve the runner service themselves if configured to, so any rove instance
+    /// can act as a runner for another. Must be called from within a tokio runtime.
+    pub(crate) async fn run(
+        &self,
+        endpoint: &str,
+        step: &PipelineStep,
+        cache: &DataCache,
+        backing_data: &BackingData,
+    ) -> Result<ValidateResponse, harness::Error> {
+        let remote_error =
+            |e: &dyn std::fmt::Display| harness::Error::Remote(endpoint.to_string(), e.to_string());
+
+        let mut client =
+            RoveRunnerClient::new(self.channel(endpoint).map_err(|e| remote_error(&e))?);
+        let mut request = Request::new(encode_request(step, cache, backing_data));
+        if let Some(token) = &self.token {
+            request.metadata_mut().insert(
+                "authorization",
+                MetadataValue::try_from(format!("Bearer {}", token))
+                    .map_err(|e| remote_error(&e))?,
+            );
+        }
+        let response = client
+            .run_step(request)
+            .await
+            .map_err(|e| remote_error(&e))?;
+
+        Ok(response.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{CheckConf, RangeCheckConf};
+
+    #[test]
+    fn test_round_trip() {
+        let mut cache = DataCache::new(
+            vec![60., 61.],
+            vec![10., 11.],
+            vec![100., 200.],
+            Timestamp(3600),
+            RelativeDuration::hours(1),
+            1,
+            0,
+            vec![
+                ("a".to_string(), vec![Some(1.), None]),
+                ("b".to_string(), vec![None, Some(f32::NAN)]),
+            ],
+        )
+        .with_metadata(vec![
+            StationMetadata {
+                provider: Some(1),
+                station_type: Some("automatic".to_string()),
+                sensor_height: Some(2.),
+                wigos_id: Some("0-578-0-18700".to_string()),
+            },
+            StationMetadata::default(),
+        ]);
+        cache.params.insert(
+            "wind".to_string(),
+            vec![vec![Some(3.), Some(4.)], vec![None, None]],
+        );
+        let backing_data = BackingData::from([(("model".to_string(), None), cache.clone())]);
+        let step = PipelineStep::new(
+            "range_check",
+            CheckConf::RangeCheck(RangeCheckConf { min: 0., max: 1. }),
+        );
+
+        let (decoded_step, decoded_cache, decoded_backing) =
+            decode_request(encode_request(&step, &cache, &backing_data)).unwrap();
+
+        assert_eq!(decoded_step, step);
+        for decoded in [
+            &decoded_cache,
+            &decoded_backing[&("model".to_string(), None)],
+        ] {
+            assert_eq!(decoded.data[0], cache.data[0]);
+            assert_eq!(decoded.data[1].1[0], None);
+            assert!(decoded.data[1].1[1].unwrap().is_nan());
+            assert_eq!(decoded.rtree.elevs, cache.rtree.elevs);
+            assert_eq!(decoded.start_time, cache.start_time);
+            assert_eq!(decoded.period, cache.period);
+            assert_eq!(decoded.num_leading_points, 1);
+            assert_eq!(decoded.metadata, cache.metadata);
+            assert_eq!(decoded.params, cache.params);
+        }
+    }
+
+    #[test]
+    fn test_check_aligned() {
+        let data = vec![
+            ("a".to_string(), vec![Some(1.), None]),
+            ("b".to_string(), vec![None, Some(2.)]),
+        ];
+        let check = |data: &[(String, Vec<Option<f32>>)], leading, trailing| {
+            check_aligned(
+                &[60., 61.],
+                &[10., 11.],
+                &[100., 200.],
+                data,
+                &HashMap::new(),
+                leading,
+                trailing,
+            )
+        };
+
+        assert!(check(&data, 1, 1).is_ok());
+        assert!(check(&data, 2, 1).is_err());
+        assert!(check(&data[..1], 0, 0).is_err());
+        let mut ragged = data.clone();
+        ragged[1].1.push(None);
+        assert!(check(&ragged, 0, 0).is_err());
+    }
+}
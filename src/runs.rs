@@ -0,0 +1,374 @@
+use crate::{hooks::Hooks, monitoring, results::QcResult};
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+use uuid::Uuid;
+
+/// Identifier of a validation run, see [`RunOptions`](crate::RunOptions)
+pub type RunId = Uuid;
+
+/// What a validation run is doing, see [`RunStatus`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunState {
+    /// Waiting for a slot in the scheduler's [run queue](crate::Scheduler::with_run_limits)
+    Queued,
+    /// Fetching data, for the whole run or its next chunk
+    Fetching,
+    /// Running the named steps
+    Running(Vec<String>),
+    /// Finished, with all results sent
+    Done,
+    /// Stopped by an error, see [`RunStatus::error`]
+    Failed,
+    /// Cancelled, or its results stopped being read
+    Cancelled,
+}
+
+impl RunState {
+    /// Whether the run has stopped
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            RunState::Done | RunState::Failed | RunState::Cancelled
+        )
+    }
+}
+
+/// The state of a validation run, see [`Scheduler::run_status`](crate::Scheduler::run_status)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunStatus {
+    /// Identifier of the run
+    pub id: RunId,
+    /// Name of the pipeline the run is running
+    pub pipeline: String,
+    /// What the run is doing
+    pub state: RunState,
+    /// The first error the run hit, if any. Runs that
+    /// [continue on error](crate::RunOptions::continue_on_error) can hit errors and still finish
+    /// as [`RunState::Done`]
+    pub error: Option<String>,
+    /// When the run was started
+    pub started_at: DateTime<Utc>,
+    /// When the run's state last changed
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+struct Runs {
+    capacity: usize,
+    statuses: HashMap<RunId, RunStatus>,
+    // oldest first
+    order: VecDeque<RunId>,
+}
+
+/// Bounded record of recent runs, shared between clones of a scheduler
+#[derive(Debug, Clone)]
+pub(crate) struct RunRegistry {
+    runs: Arc<Mutex<Runs>>,
+}
+
+impl RunRegistry {
+    pub fn new(capacity: usize) -> Self {
+        RunRegistry {
+            runs: Arc::new(Mutex::new(Runs {
+                capacity: capacity.max(1),
+                statuses: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+        }
+    }
+
+    fn runs(&self) -> MutexGuard<'_, Runs> {
+        // runs are never left inconsistent, so poisoning can be ignored
+        self.runs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Start recording a run, forgetting the oldest finished runs if the registry is full
+    ///
+    /// Runs in progress are never forgotten, so the registry grows past its capacity while
+    /// they fill it. A finished run with the same id is replaced. `hooks` are told about the
+    /// run's progress through the returned handle.
+    ///
+    /// Returns None, and records nothing, if a run with the same id is still in progress.
+    pub fn start(&self, id: RunId, pipeline: &str, hooks: Hooks) -> Option<RunHandle> {
+        let now = Utc::now();
+        let mut runs = self.runs();
+        match runs.statuses.get(&id) {
+            Some(status) if !status.state.is_finished() => return None,
+            Some(_) => {
+                runs.statuses.remove(&id);
+                runs.order.retain(|other| *other != id);
+            }
+            None => (),
+        }
+        while runs.statuses.len() >= runs.capacity {
+            let Runs {
+                statuses, order, ..
+            } = &mut *runs;
+            let Some(evicted) = order.iter().position(|id| statuses[id].state.is_finished()) else {
+                break;
+            };
+            if let Some(id) = order.remove(evicted) {
+                statuses.remove(&id);
+            }
+        }
+        let status = RunStatus {
+            id,
+            pipeline: pipeline.to_string(),
+            state: RunState::Queued,
+            error: None,
+            started_at: now,
+            updated_at: now,
+        };
+        runs.statuses.insert(id, status.clone());
+        runs.order.push_back(id);
+        // hooks may look at the registry themselves
+        drop(runs);
+        monitoring::run_started(pipeline);
+        hooks.run_start(&status);
+
+        Some(RunHandle {
+            id,
+            registry: self.clone(),
+            hooks,
+        })
+    }
+
+    pub fn get(&self, id: RunId) -> Option<RunStatus> {
+        self.runs().statuses.get(&id).cloned()
+    }
+
+    /// Every recorded run, oldest first
+    pub fn list(&self) -> Vec<RunStatus> {
+        let runs = self.runs();
+        runs.order
+            .iter()
+            .map(|id| runs.statuses[id].clone())
+            .collect()
+    }
+}
+
+/// Updates the recorded state of one run
+#[derive(Debug, Clone)]
+pub(crate) struct RunHandle {
+    pub id: RunId,
+    registry: RunRegistry,
+    hooks: Hooks,
+}
+
+impl RunHandle {
+    fn update(&self, f: impl FnOnce(&mut RunStatus)) {
+        let finished = {
+            let mut runs = self.registry.runs();
+            // the run may have been evicted to make room for newer ones
+            match runs.statuses.get_mut(&self.id) {
+                // a finished run's state is final
+                Some(status) if !status.state.is_finished() => {
+                    f(status);
+                    status.updated_at = Utc::now();
+                    status.state.is_finished().then(|| status.clone())
+                }
+                _ => None,
+            }
+        };
+        // hooks may look at the registry themselves
+        if let Some(status) = finished {
+            monitoring::run_finished(&status);
+            self.hooks.run_complete(&status);
+        }
+    }
+
+    pub fn set_state(&self, state: RunState) {
+        self.update(|status| status.state = state);
+    }
+
+    /// Record an error the run hit, which is only kept if it's the first
+    ///
+    /// If `fatal`, the run finishes as [`RunState::Failed`] rather than [`RunState::Done`].
+    pub fn record_error(&self, error: impl Display, fatal: bool) {
+        self.update(|status| {
+            status.error.get_or_insert_with(|| error.to_string());
+            if fatal {
+                status.state = RunState::Failed;
+            }
+        });
+    }
+
+    /// Mark the run as finished, as [`RunState::Cancelled`] if it didn't complete, otherwise as
+    /// [`RunState::Done`] unless it already failed
+    pub fn finish(&self, completed: bool) {
+        self.update(|status| {
+            status.state = match completed {
+                true => RunState::Done,
+                false => RunState::Cancelled,
+            }
+        });
+    }
+
+    /// Tell the run's hooks about results about to be sent
+    pub fn step_complete(&self, result: &QcResult) {
+        self.hooks.step_complete(result);
+    }
+
+    /// Finish the run as [`RunState::Cancelled`] if the returned guard is dropped before it is
+    /// [defused](AbandonGuard::defuse), for runs whose caller may stop waiting on them
+    pub fn abandon_on_drop(&self) -> AbandonGuard<'_> {
+        AbandonGuard {
+            status: self,
+            armed: true,
+        }
+    }
+}
+
+/// See [`RunHandle::abandon_on_drop`]
+#[derive(Debug)]
+pub(crate) struct AbandonGuard<'a> {
+    status: &'a RunHandle,
+    armed: bool,
+}
+
+impl AbandonGuard<'_> {
+    pub fn defuse(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for AbandonGuard<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.status.finish(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::RunHooks;
+
+    #[test]
+    fn test_registry() {
+        let registry = RunRegistry::new(2);
+        let first = registry
+            .start(Uuid::new_v4(), "first", Hooks::default())
+            .unwrap();
+        let second = registry
+            .start(Uuid::new_v4(), "second", Hooks::default())
+            .unwrap();
+
+        second.set_state(RunState::Running(vec!["range_check".to_string()]));
+        second.record_error("step failed", false);
+        second.record_error("another step failed", true);
+        // failed runs stay failed
+        second.finish(true);
+        let status = registry.get(second.id).unwrap();
+        assert_eq!(status.state, RunState::Failed);
+        assert_eq!(status.error.as_deref(), Some("step failed"));
+
+        // the oldest finished run is forgotten first, even if it's newer
+        let third = registry
+            .start(Uuid::new_v4(), "third", Hooks::default())
+            .unwrap();
+        assert!(registry.get(second.id).is_none());
+        assert_eq!(
+            registry
+                .list()
+                .iter()
+                .map(|status| status.pipeline.as_str())
+                .collect::<Vec<_>>(),
+            vec!["first", "third"]
+        );
+
+        // runs in progress are never forgotten, so the registry grows instead
+        let fourth = registry
+            .start(Uuid::new_v4(), "fourth", Hooks::default())
+            .unwrap();
+        assert_eq!(registry.list().len(), 3);
+        // an id can't be reused while its run is in progress
+        assert!(registry
+            .start(first.id, "again", Hooks::default())
+            .is_none());
+        assert_eq!(registry.get(first.id).unwrap().pipeline, "first");
+
+        // and shrinks back once they finish
+        first.finish(false);
+        third.finish(false);
+        assert_eq!(registry.get(third.id).unwrap().state, RunState::Cancelled);
+        let fifth = registry
+            .start(Uuid::new_v4(), "fifth", Hooks::default())
+            .unwrap();
+        assert!(registry.get(first.id).is_none());
+        assert!(registry.get(third.id).is_none());
+        assert!(registry.get(fourth.id).is_some());
+
+        fifth.abandon_on_drop().defuse();
+        assert_eq!(registry.get(fifth.id).unwrap().state, RunState::Queued);
+        drop(fifth.abandon_on_drop());
+        assert_eq!(registry.get(fifth.id).unwrap().state, RunState::Cancelled);
+
+        // a finished run's id can be reused
+        let again = registry.start(fifth.id, "again", Hooks::default()).unwrap();
+        assert_eq!(registry.get(again.id).unwrap().state, RunState::Queued);
+        assert_eq!(registry.list().len(), 2);
+    }
+
+    #[derive(Clone)]
+    struct Recorder {
+        registry: RunRegistry,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl RunHooks for Recorder {
+        fn on_run_start(&self, status: &RunStatus) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("start {}", status.pipeline));
+        }
+
+        fn on_step_complete(&self, result: &QcResult) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("step {}", result.test));
+        }
+
+        fn on_run_complete(&self, status: &RunStatus) {
+            // the registry isn't locked while hooks run
+            assert_eq!(self.registry.get(status.id).as_ref(), Some(status));
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("complete {:?}", status.state));
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let registry = RunRegistry::new(2);
+        let recorder = Recorder {
+            registry: registry.clone(),
+            events: Arc::new(Mutex::new(Vec::new())),
+        };
+        let mut hooks = Hooks::default();
+        hooks.push(Arc::new(recorder.clone()));
+
+        let run = registry.start(Uuid::new_v4(), "first", hooks).unwrap();
+        run.set_state(RunState::Fetching);
+        run.step_complete(&QcResult {
+            test: "range_check".to_string(),
+            ..Default::default()
+        });
+        run.record_error("step failed", true);
+        // only the first finish is reported
+        run.finish(true);
+
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec!["start first", "step range_check", "complete Failed"]
+        );
+    }
+}
@@ -1,13 +1,37 @@
 use crate::{
-    data_switch::{self, DataCache, DataSwitch, SpaceSpec, TimeSpec},
-    harness,
+    data_switch::{
+        self, ConnectorRequest, DataCache, DataSwitch, SpaceSpec, SpaceTile, StationMetadata,
+        Tiling, TimeSpec,
+    },
+    harness::{self, BackingData},
+    hooks::{Hooks, RunHooks},
+    monitoring,
     // TODO: rethink this dependency?
-    pb::ValidateResponse,
-    pipeline::Pipeline,
+    pb::{self, ExplainedStep, Explanation, PlannedFetch, ValidateResponse},
+    pipeline::{self, Pipeline, PipelineRoutes, PipelineStep, StepGroup},
+    replay::{self, Fingerprint, ReplayCache},
+    results::{PipelineMetadata, QcResult},
+    run_queue::{Permit, Queue},
+    runner::RemoteRunners,
+    runs::{RunHandle, RunId, RunRegistry, RunState, RunStatus},
+    units,
+};
+use rayon::prelude::*;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, PoisonError, RwLock, RwLockReadGuard,
+    },
+    time::{Duration, Instant},
 };
-use std::collections::HashMap;
 use thiserror::Error;
-use tokio::sync::mpsc::{channel, Receiver};
+use tokio::sync::{
+    mpsc::{channel, Receiver, Sender},
+    Semaphore,
+};
+use tokio_util::sync::CancellationToken;
 
 #[derive(Error, Debug)]
 #[non_exhaustive]
@@ -18,33 +42,776 @@ pub enum Error {
     InvalidArg(&'static str),
     #[error("data switch failed to find data: {0}")]
     DataSwitch(#[from] data_switch::Error),
+    #[error("failed to build thread pool: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    #[error("invalid pipeline: {0}")]
+    InvalidPipeline(#[from] pipeline::Error),
+    #[error("data could not be converted to the pipeline's units: {0}")]
+    Units(#[from] units::Error),
+    #[error("run was cancelled")]
+    Cancelled,
+    #[error("run did not finish before its deadline")]
+    DeadlineExceeded,
+    #[error(
+        "run would hold about {0} points in memory at once, over the limit of {1}, try a shorter \
+        timerange or a smaller area, or chunking the run"
+    )]
+    TooLarge(usize, usize),
+    #[error("idempotency key was already used for a different request")]
+    IdempotencyKeyReused,
+    #[error("run id {0} is already in use by a run in progress")]
+    RunIdInUse(RunId),
+}
+
+/// Run `future` to completion, unless `cancel` is cancelled or `deadline` passes first
+async fn until_cancelled<T>(
+    cancel: &CancellationToken,
+    deadline: Option<Instant>,
+    future: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let deadline = async {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        // so a run cancelled before it starts doesn't fetch anything
+        biased;
+        _ = cancel.cancelled() => Err(Error::Cancelled),
+        _ = deadline => Err(Error::DeadlineExceeded),
+        result = future => result,
+    }
+}
+
+/// Why the run should stop now, if it was cancelled or is past its deadline
+fn stop_reason(cancel: &CancellationToken, deadline: Option<Instant>) -> Option<Error> {
+    if cancel.is_cancelled() {
+        Some(Error::Cancelled)
+    } else if matches!(deadline, Some(deadline) if Instant::now() >= deadline) {
+        Some(Error::DeadlineExceeded)
+    } else {
+        None
+    }
+}
+
+/// Priority class of a validation run, see [`Scheduler::with_run_limits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// Runs that can wait, like reprocessing
+    Low,
+    /// Runs that don't say otherwise
+    #[default]
+    Normal,
+    /// Latency sensitive runs, like QC of freshly ingested data
+    High,
+}
+
+/// Limits on how much a scheduler does at once, see [`Scheduler::with_run_limits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RunLimits {
+    /// Maximum number of runs in progress at once, or no limit if None
+    pub max_concurrent_runs: Option<usize>,
+    /// Maximum number of runs fetching data at once, or no limit if None
+    pub max_concurrent_fetches: Option<usize>,
+}
+
+/// Options for a single validation run, see
+/// [`validate_direct_with_options`](Scheduler::validate_direct_with_options)
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Identifier of the run, which its status is recorded under, see
+    /// [`Scheduler::run_status`]. Defaults to a random UUID. Only one run in progress can have
+    /// a given id
+    pub run_id: RunId,
+    /// Where the run is queued, if the scheduler has [`RunLimits`]
+    pub priority: Priority,
+    /// Cancelling this aborts the run
+    pub cancel: CancellationToken,
+    /// Abort the run if it hasn't finished by then, failing it with [`Error::DeadlineExceeded`],
+    /// in the same way as cancelling it. Results sent before then are still valid
+    pub deadline: Option<Instant>,
+    /// Keep running the rest of the pipeline when a step fails, even if the pipeline doesn't
+    /// set [`continue_on_error`](Pipeline::continue_on_error) itself
+    pub continue_on_error: bool,
+    /// Only run the named steps of the pipeline, and the steps whose results they need to decide
+    /// what to QC, rather than all of them. The combined results aren't sent for such runs, as
+    /// they would be missing the other steps' flags
+    pub steps: Option<Vec<String>>,
+    /// Key identifying the request this run is for, so that if the scheduler
+    /// [replays runs](Scheduler::with_idempotency), a retry of a request that already
+    /// completed gets its results again rather than rerunning it
+    pub idempotency_key: Option<String>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            run_id: RunId::new_v4(),
+            priority: Priority::default(),
+            cancel: CancellationToken::default(),
+            deadline: None,
+            continue_on_error: false,
+            steps: None,
+            idempotency_key: None,
+        }
+    }
+}
+
+/// One validation run of a batch, see [`validate_batch`](Scheduler::validate_batch)
+///
+/// The fields are the arguments of
+/// [`validate_direct_with_options`](Scheduler::validate_direct_with_options).
+#[derive(Debug, Clone)]
+pub struct ValidateSpec {
+    pub data_source: String,
+    pub backing_sources: Vec<String>,
+    pub time_spec: TimeSpec,
+    pub space_spec: SpaceSpec,
+    pub pipeline: String,
+    pub extra_spec: Option<String>,
+    pub options: RunOptions,
+}
+
+/// Send the results of the run `start` starts down `tx`, each tagged with `index`, along with
+/// the error if it fails to start
+///
+/// The run is abandoned if `tx`'s receiver is dropped while it's queued or fetching, and
+/// cancelled through `cancel`, if there is one, if it's dropped afterwards, see
+/// [`Scheduler::disconnect_token`].
+pub(crate) async fn forward_tagged<E: From<Error>>(
+    index: usize,
+    start: impl Future<Output = Result<Receiver<Result<QcResult, Error>>, Error>>,
+    cancel: Option<CancellationToken>,
+    tx: Sender<(usize, Result<QcResult, E>)>,
+) {
+    let mut results = tokio::select! {
+        started = start => match started {
+            Ok(results) => results,
+            Err(e) => {
+                let _ = tx.send((index, Err(e.into()))).await;
+                return;
+            }
+        },
+        _ = tx.closed() => return,
+    };
+    let stop = || {
+        if let Some(cancel) = &cancel {
+            cancel.cancel();
+        }
+    };
+    loop {
+        let result = tokio::select! {
+            result = results.recv() => match result {
+                Some(result) => result,
+                None => return,
+            },
+            _ = tx.closed() => {
+                stop();
+                return;
+            }
+        };
+        if tx.send((index, result.map_err(Into::into))).await.is_err() {
+            stop();
+            return;
+        }
+    }
+}
+
+/// How to start a run, see [`Scheduler::start_or_replay`]
+enum Start {
+    /// Run it, with these hooks
+    Run(Hooks),
+    /// Send these results of a completed run with the same idempotency key again
+    Replay(Vec<QcResult>),
+}
+
+/// Forward `results` to a new receiver, and keep reading them once that receiver is dropped, so
+/// the run sending them isn't stopped by its caller going away
+fn read_unread(
+    mut results: Receiver<Result<QcResult, Error>>,
+) -> Receiver<Result<QcResult, Error>> {
+    let (tx, rx) = channel(1);
+    tokio::spawn(async move {
+        while let Some(result) = results.recv().await {
+            // once the receiver is dropped, results are only read to keep the run going
+            let _ = tx.send(result).await;
+        }
+    });
+    rx
+}
+
+/// Record how a run stopped because of `e`
+fn record_failure(status: &RunHandle, e: &Error) {
+    match e {
+        Error::Cancelled => status.finish(false),
+        e => status.record_error(e, true),
+    }
+}
+
+/// Wait for a slot in `queue`, if there is one, unless the run is cancelled first
+async fn enqueue(
+    queue: Option<&Arc<Queue>>,
+    options: &RunOptions,
+) -> Result<Option<Permit>, Error> {
+    match queue {
+        Some(queue) => {
+            until_cancelled(&options.cancel, options.deadline, async {
+                Ok(Some(queue.acquire(options.priority).await))
+            })
+            .await
+        }
+        None => Ok(None),
+    }
 }
 
 /// Receiver type for QC runs
 ///
 /// Holds information about test pipelines and data sources
+///
+/// Clones of a scheduler share its pipelines, so pipelines registered or removed through one
+/// handle are visible through all of them
 #[derive(Debug, Clone)]
-pub struct Scheduler<'a> {
-    // this is pub so that the server can determine the number of checks in a pipeline to size
-    // its channel with. can be made private if the server functionality is deprecated
-    #[allow(missing_docs)]
-    pub pipelines: HashMap<String, Pipeline>,
-    data_switch: DataSwitch<'a>,
+pub struct Scheduler {
+    // runs hold on to the Arc of the pipeline they started with, so swapping one out doesn't
+    // affect runs in flight
+    pipelines: Arc<RwLock<HashMap<String, Arc<Pipeline>>>>,
+    data_switch: DataSwitch,
+    // checks are run on rayon's global pool if this is None
+    thread_pool: Option<Arc<rayon::ThreadPool>>,
+    routes: PipelineRoutes,
+    // polygons are fetched and QCed in one go if this is None
+    tiling: Option<Tiling>,
+    // timeranges are fetched and QCed in one go if this is None
+    chunk_len: Option<u32>,
+    // fetched data is QCed in one go if this is None
+    result_chunk_len: Option<u32>,
+    // runs can hold any amount of data if this is None
+    max_points: Option<usize>,
+    // every step in a dependency level is run at once if this is None
+    max_concurrent_steps: Option<usize>,
+    // runs and fetches aren't queued if these are None
+    run_queue: Option<Arc<Queue>>,
+    fetch_queue: Option<Arc<Queue>>,
+    runs: RunRegistry,
+    hooks: Hooks,
+    // runs are never replayed if this is None
+    replays: Option<ReplayCache>,
+    remote_runners: RemoteRunners,
+}
+
+// number of runs a scheduler remembers by default
+const DEFAULT_RUN_HISTORY: usize = 1000;
+
+// number of runs of a batch in progress at once, so one huge batch doesn't flood the scheduler
+const MAX_CONCURRENT_BATCH_RUNS: usize = 16;
+
+/// A piece of a validation run, fetched and QCed on its own
+#[derive(Debug)]
+struct Chunk {
+    space_spec: SpaceSpec,
+    time_spec: TimeSpec,
+    // only set if the space spec was tiled
+    tile: Option<SpaceTile>,
 }
 
-impl<'a> Scheduler<'a> {
+impl Scheduler {
     /// Instantiate a new scheduler
-    pub fn new(pipelines: HashMap<String, Pipeline>, data_switch: DataSwitch<'a>) -> Self {
+    pub fn new(pipelines: HashMap<String, Pipeline>, data_switch: DataSwitch) -> Self {
         Scheduler {
-            pipelines,
+            pipelines: Arc::new(RwLock::new(
+                pipelines
+                    .into_iter()
+                    .map(|(name, pipeline)| (name, Arc::new(pipeline)))
+                    .collect(),
+            )),
             data_switch,
+            thread_pool: None,
+            routes: PipelineRoutes::default(),
+            tiling: None,
+            chunk_len: None,
+            result_chunk_len: None,
+            max_points: None,
+            max_concurrent_steps: None,
+            run_queue: None,
+            fetch_queue: None,
+            runs: RunRegistry::new(DEFAULT_RUN_HISTORY),
+            hooks: Hooks::default(),
+            replays: None,
+            remote_runners: RemoteRunners::default(),
         }
     }
 
-    fn schedule_tests(
+    /// Set the routing table used by
+    /// [`validate_for_parameter`](Scheduler::validate_for_parameter) to pick pipelines
+    pub fn with_routes(mut self, routes: PipelineRoutes) -> Self {
+        self.routes = routes;
+        self
+    }
+
+    /// Limit the number of threads used to run checks to `num_threads`
+    ///
+    /// By default, checks share rayon's global thread pool, which has one thread per CPU. Either
+    /// way, runs wait on their checks from tokio's blocking threads, so heavy checks like SCT
+    /// don't hold up the async workers serving other requests.
+    ///
+    /// # Errors
+    ///
+    /// If the thread pool could not be created
+    pub fn with_num_threads(mut self, num_threads: usize) -> Result<Self, Error> {
+        self.thread_pool = Some(Arc::new(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()?,
+        ));
+        Ok(self)
+    }
+
+    /// Fetch and QC requests for polygons one tile at a time, according to `tiling`
+    ///
+    /// This bounds the memory used by requests covering large areas, at the cost of fetching
+    /// the series in each tile's margin more than once. Responses are sent per tile, so clients
+    /// receive several responses for each step.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArg`] if `tiling` isn't [valid](Tiling::is_valid)
+    pub fn with_tiling(mut self, tiling: Tiling) -> Result<Self, Error> {
+        if !tiling.is_valid() {
+            return Err(Error::InvalidArg(
+                "tile_size must be positive, and margin must not be negative",
+            ));
+        }
+        self.tiling = Some(tiling);
+        Ok(self)
+    }
+
+    /// Fetch and QC requests with more than `chunk_len` points per series in chunks of at most
+    /// `chunk_len` points
+    ///
+    /// This keeps fetches for long reprocessing windows from timing out or using too much memory.
+    /// Each chunk is fetched with the leading and trailing points the pipeline needs, so chunks
+    /// overlap by that many points and timeseries checks see the same context they would without
+    /// chunking. Responses are sent per chunk, so clients receive several responses for each
+    /// step.
+    pub fn with_chunk_len(mut self, chunk_len: u32) -> Self {
+        self.chunk_len = Some(chunk_len);
+        self
+    }
+
+    /// QC fetched data, and send the results, in chunks of at most `result_chunk_len` points per
+    /// series
+    ///
+    /// Unlike [`with_chunk_len`](Scheduler::with_chunk_len), this doesn't split up fetches, only
+    /// what's QCed at once, so results for the start of a long timerange are sent long before
+    /// the end of it is QCed, without fetching the context around each chunk again. Clients
+    /// receive several responses for each step, one per chunk, in time order. If both are set,
+    /// each fetched chunk is QCed in chunks of its own.
+    pub fn with_result_chunk_len(mut self, result_chunk_len: u32) -> Self {
+        self.result_chunk_len = Some(result_chunk_len);
+        self
+    }
+
+    /// Reject runs that would hold more than `max_points` points in memory at once
+    ///
+    /// Points are counted across every series, including context, backing series and extra
+    /// parameters. Runs of a known number of series, like [`SpaceSpec::One`] and
+    /// [`SpaceSpec::Multiple`], are estimated and rejected before anything is fetched. Rejected
+    /// runs fail with [`Error::TooLarge`].
+    ///
+    /// How many series a [`SpaceSpec::Polygon`] or [`SpaceSpec::All`] covers isn't known until
+    /// it's fetched, so those runs are only rejected once their data is in memory, before it's
+    /// QCed, which takes several times more memory than the data itself. The limit doesn't bound
+    /// the fetch itself, so services taking such runs over large areas should enable
+    /// [tiling](Scheduler::with_tiling), which splits polygons into tiles, and
+    /// [chunking](Scheduler::with_chunk_len), which bounds the timerange fetched at once. The
+    /// limit then applies to each tile or chunk.
+    pub fn with_max_points(mut self, max_points: usize) -> Self {
+        self.max_points = Some(max_points);
+        self
+    }
+
+    fn check_size(&self, num_points: usize) -> Result<(), Error> {
+        match self.max_points {
+            Some(max_points) if num_points > max_points => {
+                Err(Error::TooLarge(num_points, max_points))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Run at most `max_concurrent_steps` of a pipeline's steps at once
+    ///
+    /// Steps that don't depend on each other are run concurrently, so a pipeline of several
+    /// independent checks takes about as long as its slowest check. Each running step holds its
+    /// own results, and parallelises over series internally, so this bounds the memory and
+    /// threads a run of a wide pipeline can take. By default there's no limit. A limit of 0 is
+    /// treated as 1.
+    pub fn with_max_concurrent_steps(mut self, max_concurrent_steps: usize) -> Self {
+        self.max_concurrent_steps = Some(max_concurrent_steps.max(1));
+        self
+    }
+
+    /// Queue runs and their fetches beyond the limits in `limits`
+    ///
+    /// Queued runs are started in order of [`Priority`], then in the order they arrived, so
+    /// large low priority runs, like reprocessing, don't hold up small latency sensitive ones.
+    /// A run holds its slot from when it starts fetching until its last results are sent, and
+    /// a fetch slot while it fetches each chunk. Runs already in progress aren't interrupted for
+    /// higher priority ones. Clones of the scheduler share its queues.
+    pub fn with_run_limits(mut self, limits: RunLimits) -> Self {
+        self.run_queue = limits.max_concurrent_runs.map(Queue::new);
+        self.fetch_queue = limits.max_concurrent_fetches.map(Queue::new);
+        self
+    }
+
+    /// Remember the status of the last `capacity` runs, rather than the last 1000
+    ///
+    /// Once the limit is reached, finished runs are forgotten, oldest first. Runs still in
+    /// progress are never forgotten, so the history grows past the limit while they fill it. A
+    /// capacity of 0 is treated as 1. Clones of the scheduler share its run history.
+    pub fn with_run_history(mut self, capacity: usize) -> Self {
+        self.runs = RunRegistry::new(capacity);
+        self
+    }
+
+    /// Call `hooks` as runs start, their steps complete, and they finish
+    ///
+    /// Can be called more than once, in which case hooks are called in the order they were
+    /// registered. Only runs started after this are reported to the hooks.
+    pub fn with_hooks(mut self, hooks: impl RunHooks + 'static) -> Self {
+        self.hooks.push(Arc::new(hooks));
+        self
+    }
+
+    /// Send `runner_token` to the runner services that steps with `backend = "grpc"` are run on
+    ///
+    /// The token is sent as `Bearer <runner_token>` in the requests' `authorization` metadata,
+    /// as rove servers serving the runner service expect, see
+    /// [`ServerConfig::runner_token`](crate::ServerConfig::runner_token).
+    pub fn with_runner_token(mut self, runner_token: String) -> Self {
+        self.remote_runners = RemoteRunners::new(Some(runner_token));
+        self
+    }
+
+    /// Whether the scheduler is ready to run pipelines, meaning it has at least one, and every
+    /// data source is healthy, see [`DataConnector::check_health`]
+    ///
+    /// [`DataConnector::check_health`]: crate::data_switch::DataConnector::check_health
+    pub async fn is_ready(&self) -> bool {
+        if !self.has_pipelines() {
+            return false;
+        }
+        let unhealthy = self.data_switch.check_health().await;
+        for (source, e) in &unhealthy {
+            tracing::warn!(%source, %e, "Data source is unhealthy.");
+        }
+        unhealthy.is_empty()
+    }
+
+    /// Whether the scheduler has any pipelines to run, the part of
+    /// [`is_ready`](Scheduler::is_ready) that doesn't depend on the data sources
+    pub(crate) fn has_pipelines(&self) -> bool {
+        let has_pipelines = !self.pipelines().is_empty();
+        if !has_pipelines {
+            tracing::debug!("Not ready, no pipelines are loaded.");
+        }
+        has_pipelines
+    }
+
+    /// Remember the results of up to `capacity` runs with an
+    /// [idempotency key](RunOptions::idempotency_key) for `ttl` after they complete, and replay
+    /// them to later runs with the same key
+    ///
+    /// This saves rerunning whole pipelines when a client retries a request that completed, but
+    /// whose results it didn't get, e.g. because of a network blip. Such runs carry on after
+    /// their receiver is dropped, and aren't cancelled when the server's clients disconnect, so
+    /// there is something to replay. Only runs that finish with no errors are remembered, and
+    /// runs with the same key as one still in progress are run again. A replay is recorded as a
+    /// run of its own, which finishes straight away, with its [`run_id`](QcResult::run_id) set
+    /// on the replayed results. Hooks aren't called for replays, as they run nothing.
+    ///
+    /// A key reused for a different request, whether it fetches different data, sends
+    /// different data, or runs a different pipeline or steps, fails with
+    /// [`Error::IdempotencyKeyReused`] while the first request's results are remembered.
+    ///
+    /// The results are held in memory until they expire, or the oldest are forgotten to make
+    /// room for newer ones, so `ttl` should be kept short, just long enough to cover clients'
+    /// retries. A capacity of 0 is treated as 1. Clones of the scheduler share the remembered
+    /// results.
+    pub fn with_idempotency(mut self, ttl: Duration, capacity: usize) -> Self {
+        self.replays = Some(ReplayCache::new(ttl, capacity));
+        self
+    }
+
+    /// The status of the run with identifier `id`, if it is still remembered
+    pub fn run_status(&self, id: RunId) -> Option<RunStatus> {
+        self.runs.get(id)
+    }
+
+    /// The status of every remembered run, oldest first
+    pub fn list_runs(&self) -> Vec<RunStatus> {
+        self.runs.list()
+    }
+
+    fn pipelines(&self) -> RwLockReadGuard<'_, HashMap<String, Arc<Pipeline>>> {
+        // the lock is never held across anything that can panic, so poisoning can be ignored
+        self.pipelines
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Add a pipeline to the scheduler under `name`, replacing any pipeline already registered
+    /// under that name, and returning it
+    ///
+    /// The pipeline is validated before it is registered. Runs already in progress keep using
+    /// the pipeline they started with.
+    ///
+    /// # Errors
+    ///
+    /// If the pipeline is invalid, in which case the registered pipelines are left unchanged
+    pub fn register_pipeline(
+        &self,
+        name: impl Into<String>,
         pipeline: Pipeline,
+    ) -> Result<Option<Arc<Pipeline>>, Error> {
+        Ok(self.insert_pipeline(name, pipeline.finish()?))
+    }
+
+    /// [`register_pipeline`](Scheduler::register_pipeline), for a pipeline that has already
+    /// been validated and had its derived fields filled in, like one parsed from TOML or JSON
+    pub(crate) fn insert_pipeline(
+        &self,
+        name: impl Into<String>,
+        pipeline: Pipeline,
+    ) -> Option<Arc<Pipeline>> {
+        self.pipelines
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.into(), Arc::new(pipeline))
+    }
+
+    /// Remove the pipeline registered under `name`, returning it if there was one
+    ///
+    /// Runs already in progress keep using the pipeline they started with.
+    pub fn remove_pipeline(&self, name: &str) -> Option<Arc<Pipeline>> {
+        self.pipelines
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(name)
+    }
+
+    /// Run a pipeline's steps on some data, sending each step's results, then the combined
+    /// results, down `tx` as they are ready
+    ///
+    /// Blocks until the pipeline is finished. Returns false if the receiver was dropped or `cancel`
+    /// was cancelled before then, as there's no point running anything else for it. Steps
+    /// already running when the run is cancelled are finished, but no more are started.
+    ///
+    /// If `continue_on_error` is set, steps that fail are sent as responses with their
+    /// [`error`](QcResult::error) set, rather than as errors, so that the receiver can
+    /// carry on reading the other steps' results. The combined results are then sent even if
+    /// some steps failed, with the failed steps listed in their error.
+    #[allow(clippy::too_many_arguments)]
+    fn run_pipeline(
+        pipeline: &Pipeline,
+        levels: &[Vec<usize>],
+        metadata: &PipelineMetadata,
+        data: &DataCache,
+        backing_data: &BackingData,
+        thread_pool: Option<&rayon::ThreadPool>,
+        max_concurrent_steps: Option<usize>,
+        runtime: &tokio::runtime::Handle,
+        remote_runners: &RemoteRunners,
+        tx: &Sender<Result<QcResult, Error>>,
+        cancel: &CancellationToken,
+        deadline: Option<Instant>,
+        continue_on_error: bool,
+        status: &RunHandle,
+    ) -> bool {
+        let stations: HashMap<String, StationMetadata> = data
+            .data
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !data.is_backing(*i) && !data.station_metadata(*i).is_empty())
+            .map(|(i, (identifier, _))| (identifier.clone(), data.station_metadata(i).clone()))
+            .collect();
+
+        // kept for conditional steps, and to be combined at the end
+        let mut responses: Vec<ValidateResponse> = Vec::new();
+        let mut failed_steps: Vec<&str> = Vec::new();
+
+        let stop = || match stop_reason(cancel, deadline) {
+            Some(e) => {
+                record_failure(status, &e);
+                // if this fails the receiver was dropped, and there's nobody left to tell
+                let _ = tx.blocking_send(Err(e));
+                true
+            }
+            None => false,
+        };
+
+        // steps in a level are independent, so are run concurrently, up to the limit
+        for level in levels {
+            if stop() {
+                return false;
+            }
+            status.set_state(RunState::Running(
+                level
+                    .iter()
+                    .map(|i| pipeline.steps[*i].name.clone())
+                    .collect(),
+            ));
+
+            let run = || {
+                run_level(
+                    level,
+                    max_concurrent_steps,
+                    || stop_reason(cancel, deadline).is_some(),
+                    |i| {
+                        let step = &pipeline.steps[i];
+                        let condition_results = step.run_if.as_ref().and_then(|run_if| {
+                            responses
+                                .iter()
+                                .find(|response| response.test == run_if.step)
+                        });
+                        let started = Instant::now();
+                        let result = match step.runner.remote_endpoint() {
+                            None => harness::run_test_if(
+                                step,
+                                data,
+                                backing_data,
+                                condition_results,
+                                harness::run_test,
+                            ),
+                            Some(endpoint) => harness::run_test_if(
+                                step,
+                                data,
+                                backing_data,
+                                condition_results,
+                                |step, data, backing_data| {
+                                    runtime.block_on(remote_runners.run(
+                                        endpoint,
+                                        step,
+                                        data,
+                                        backing_data,
+                                    ))
+                                },
+                            ),
+                        };
+                        (result, started.elapsed())
+                    },
+                )
+            };
+            let results = match thread_pool {
+                Some(thread_pool) => thread_pool.install(run),
+                None => run(),
+            };
+
+            // steps left unstarted when the run was stopped have no result
+            let stopped = results.iter().any(Option::is_none);
+            for (i, result) in level.iter().zip(results) {
+                let Some((result, duration)) = result else {
+                    continue;
+                };
+                let step_name = pipeline.steps[*i].name.as_str();
+                // remote runners may send flags this version doesn't know
+                let result = result.and_then(|response| {
+                    let qc_result = QcResult::try_from(response.clone())
+                        .map_err(harness::Error::InvalidResults)?;
+                    responses.push(response);
+                    Ok(qc_result)
+                });
+                monitoring::step_finished(&metadata.name, step_name, duration, result.is_err());
+                if let Err(e) = &result {
+                    failed_steps.push(step_name);
+                    status.record_error(e, !continue_on_error);
+                }
+                // error responses aren't kept, so steps conditional on a failed step fail too
+                let mut result = match result {
+                    Err(e) if continue_on_error => Ok(QcResult {
+                        test: step_name.to_string(),
+                        error: Some(e.to_string()),
+                        ..Default::default()
+                    }),
+                    result => result,
+                };
+                if let Ok(qc_result) = &mut result {
+                    qc_result.duration = Some(duration);
+                    qc_result.pipeline = metadata.clone();
+                    qc_result.stations = stations.clone();
+                    qc_result.data_source = data.source.clone();
+                    qc_result.run_id = status.id;
+                    monitoring::flags_sent(qc_result);
+                    status.step_complete(qc_result);
+                }
+
+                match tx.blocking_send(result.map_err(Error::Runner)) {
+                    Ok(_) => {
+                        // item (server response) was queued to be send to client
+                    }
+                    Err(_item) => {
+                        // output_stream was build from rx and both are dropped
+                        return false;
+                    }
+                }
+            }
+            if stopped && stop() {
+                return false;
+            }
+        }
+
+        // combined flags would be misleading if some steps are missing, unless the receiver is
+        // told which. Steps that weren't selected aren't worth telling about, and pipelines
+        // narrowed down to some of their steps have no combi
+        if let (Some(combi), true) = (
+            &pipeline.combi,
+            failed_steps.is_empty() || continue_on_error,
+        ) {
+            let started = Instant::now();
+            // the flags were all checked when their steps' results were converted
+            let mut qc_result = match QcResult::try_from(harness::combine(&responses, combi)) {
+                Ok(qc_result) => qc_result,
+                Err(e) => {
+                    let e = Error::Runner(harness::Error::InvalidResults(e));
+                    status.record_error(&e, true);
+                    return tx.blocking_send(Err(e)).is_ok();
+                }
+            };
+            if !failed_steps.is_empty() {
+                qc_result.error = Some(format!(
+                    "combined without the flags of failed steps: {}",
+                    failed_steps.join(", ")
+                ));
+            }
+            qc_result.duration = Some(started.elapsed());
+            qc_result.pipeline = metadata.clone();
+            qc_result.stations = stations;
+            qc_result.data_source = data.source.clone();
+            qc_result.run_id = status.id;
+            monitoring::flags_sent(&qc_result);
+            status.step_complete(&qc_result);
+            return tx.blocking_send(Ok(qc_result)).is_ok();
+        }
+
+        true
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn schedule_tests(
+        pipeline: Arc<Pipeline>,
+        levels: Vec<Vec<usize>>,
+        metadata: PipelineMetadata,
         data: DataCache,
-    ) -> Receiver<Result<ValidateResponse, Error>> {
+        backing_data: BackingData,
+        thread_pool: Option<Arc<rayon::ThreadPool>>,
+        max_concurrent_steps: Option<usize>,
+        result_chunk_len: Option<u32>,
+        remote_runners: RemoteRunners,
+        options: RunOptions,
+        run_permit: Option<Permit>,
+        status: RunHandle,
+    ) -> Receiver<Result<QcResult, Error>> {
         // spawn and channel are required if you want handle "disconnect" functionality
         // the `out_stream` will not be polled after client disconnect
         // TODO: Should we keep this channel or just return everything together?
@@ -53,24 +820,303 @@ impl<'a> Scheduler<'a> {
         // convinced of its utility. Since we won't run the combi check to generate end user flags
         // until the full pipeline is finished, it doesn't seem like the individual flags have any
         // use before that point.
-        let (tx, rx) = channel(pipeline.steps.len());
-        tokio::spawn(async move {
-            for step in pipeline.steps.iter() {
-                let result = harness::run_test(step, &data);
+        let (tx, rx) = channel(pipeline.steps.len() + 1);
+        // remote steps are run from rayon's threads, outside the runtime, so need a handle to it
+        let runtime = tokio::runtime::Handle::current();
+        // checks are CPU-bound, and parallelise over series internally, so they're kept off the
+        // async workers
+        tokio::task::spawn_blocking(move || {
+            // held until the run is finished
+            let _run_permit = run_permit;
+            monitoring::data_fetched(&metadata.name, &data);
+            // if this fails the receiver was dropped, and there's nobody left to tell
+            let completed = run_windows(
+                result_chunk_len,
+                &data,
+                &backing_data,
+                |data, backing_data| {
+                    Scheduler::run_pipeline(
+                        &pipeline,
+                        &levels,
+                        &metadata,
+                        data,
+                        backing_data,
+                        thread_pool.as_deref(),
+                        max_concurrent_steps,
+                        &runtime,
+                        &remote_runners,
+                        &tx,
+                        &options.cancel,
+                        options.deadline,
+                        pipeline.continue_on_error || options.continue_on_error,
+                        &status,
+                    )
+                },
+            );
+            status.finish(completed);
+        });
 
-                match tx.send(result.map_err(Error::Runner)).await {
-                    Ok(_) => {
-                        // item (server response) was queued to be send to client
+        rx
+    }
+
+    /// Like [`schedule_tests`](Scheduler::schedule_tests), but fetching and QCing one chunk at a
+    /// time, so only one chunk's data is held in memory at once
+    ///
+    /// Each chunk's results are sent as soon as its pipeline finishes, so clients receive one set
+    /// of responses per chunk.
+    #[allow(clippy::too_many_arguments)]
+    async fn schedule_chunks(
+        &self,
+        pipeline: Arc<Pipeline>,
+        levels: Vec<Vec<usize>>,
+        metadata: PipelineMetadata,
+        data_source: &str,
+        backing_sources: Vec<String>,
+        chunks: Vec<Chunk>,
+        extra_spec: Option<&str>,
+        options: RunOptions,
+        run_permit: Option<Permit>,
+        status: RunHandle,
+    ) -> Result<Receiver<Result<QcResult, Error>>, Error> {
+        let (tx, rx) = channel(pipeline.steps.len() + 1);
+        let mut chunks = chunks.into_iter();
+
+        // the first chunk is fetched up front, so that failures that will affect every chunk, like
+        // an unknown data source, are returned directly
+        let mut next = match chunks.next() {
+            Some(chunk) => {
+                let fetched = until_cancelled(
+                    &options.cancel,
+                    options.deadline,
+                    self.fetch_run_data(
+                        &pipeline,
+                        data_source,
+                        &backing_sources,
+                        &chunk.time_spec,
+                        &chunk.space_spec,
+                        extra_spec,
+                        options.priority,
+                    ),
+                )
+                .await?;
+                Some((chunk, fetched))
+            }
+            None => None,
+        };
+
+        let scheduler = self.clone();
+        let data_source = data_source.to_string();
+        let extra_spec = extra_spec.map(String::from);
+        let runtime = tokio::runtime::Handle::current();
+        tokio::spawn(async move {
+            // held until the last chunk is finished
+            let _run_permit = run_permit;
+            let completed = async {
+                while let Some((chunk, (mut data, backing_data))) = next {
+                    // series outside the tile are only there as neighbours for the ones inside it
+                    if let Some(tile) = &chunk.tile {
+                        data.backing = (0..data.data.len())
+                            .map(|i| {
+                                data.is_backing(i)
+                                    || !tile.owns(data.rtree.lats[i], data.rtree.lons[i])
+                            })
+                            .collect();
                     }
-                    Err(_item) => {
-                        // output_stream was build from rx and both are dropped
-                        break;
+
+                    if (0..data.data.len()).any(|i| !data.is_backing(i)) {
+                        let pipeline = pipeline.clone();
+                        let levels = levels.clone();
+                        let metadata = metadata.clone();
+                        let thread_pool = scheduler.thread_pool.clone();
+                        let max_concurrent_steps = scheduler.max_concurrent_steps;
+                        let result_chunk_len = scheduler.result_chunk_len;
+                        let runtime = runtime.clone();
+                        let remote_runners = scheduler.remote_runners.clone();
+                        let tx = tx.clone();
+                        let cancel = options.cancel.clone();
+                        let deadline = options.deadline;
+                        let continue_on_error = options.continue_on_error;
+                        let status = status.clone();
+                        let finished = tokio::task::spawn_blocking(move || {
+                            monitoring::data_fetched(&metadata.name, &data);
+                            run_windows(
+                                result_chunk_len,
+                                &data,
+                                &backing_data,
+                                |data, backing_data| {
+                                    Scheduler::run_pipeline(
+                                        &pipeline,
+                                        &levels,
+                                        &metadata,
+                                        data,
+                                        backing_data,
+                                        thread_pool.as_deref(),
+                                        max_concurrent_steps,
+                                        &runtime,
+                                        &remote_runners,
+                                        &tx,
+                                        &cancel,
+                                        deadline,
+                                        pipeline.continue_on_error || continue_on_error,
+                                        &status,
+                                    )
+                                },
+                            )
+                        })
+                        .await
+                        .unwrap_or(false);
+                        if !finished {
+                            return false;
+                        }
                     }
+
+                    next = match chunks.next() {
+                        Some(chunk) => {
+                            status.set_state(RunState::Fetching);
+                            match until_cancelled(
+                                &options.cancel,
+                                options.deadline,
+                                scheduler.fetch_run_data(
+                                    &pipeline,
+                                    &data_source,
+                                    &backing_sources,
+                                    &chunk.time_spec,
+                                    &chunk.space_spec,
+                                    extra_spec.as_deref(),
+                                    options.priority,
+                                ),
+                            )
+                            .await
+                            {
+                                Ok(fetched) => Some((chunk, fetched)),
+                                Err(e) => {
+                                    record_failure(&status, &e);
+                                    // if this fails the receiver was dropped, and there's nobody
+                                    // left to tell
+                                    let _ = tx.send(Err(e)).await;
+                                    return false;
+                                }
+                            }
+                        }
+                        None => None,
+                    };
                 }
+                true
             }
+            .await;
+            status.finish(completed);
         });
 
-        rx
+        Ok(rx)
+    }
+
+    /// Fetch the data to be QCed, with its backing series and extra parameters, and any backing
+    /// data needed by the pipeline's checks
+    #[allow(clippy::too_many_arguments)]
+    async fn fetch_run_data(
+        &self,
+        pipeline: &Pipeline,
+        data_source: &str,
+        backing_sources: &[impl AsRef<str>],
+        time_spec: &TimeSpec,
+        space_spec: &SpaceSpec,
+        extra_spec: Option<&str>,
+        priority: Priority,
+    ) -> Result<(DataCache, BackingData), Error> {
+        let _fetch_permit = match &self.fetch_queue {
+            Some(fetch_queue) => Some(fetch_queue.acquire(priority).await),
+            None => None,
+        };
+        let (params, backing_fetches) = plan_fetches(pipeline);
+
+        let data = match self
+            .data_switch
+            .fetch_data(
+                data_source,
+                space_spec,
+                time_spec,
+                pipeline.num_leading_required,
+                pipeline.num_trailing_required,
+                extra_spec,
+                &params,
+            )
+            .await
+        {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::error!(%e);
+                return Err(Error::DataSwitch(e));
+            }
+        };
+
+        let mut backing_series = Vec::with_capacity(backing_sources.len());
+        for source in backing_sources {
+            match self
+                .data_switch
+                .fetch_data(
+                    source.as_ref(),
+                    space_spec,
+                    time_spec,
+                    pipeline.num_leading_required,
+                    pipeline.num_trailing_required,
+                    extra_spec,
+                    &[],
+                )
+                .await
+            {
+                Ok(series) => backing_series.push((source.as_ref().to_string(), series)),
+                Err(e) => {
+                    tracing::error!(%e);
+                    return Err(Error::DataSwitch(e));
+                }
+            }
+        }
+
+        let mut backing_data = BackingData::new();
+        for (source, backing_extra_spec) in backing_fetches {
+            let backing_cache = match self
+                .data_switch
+                .fetch_data(
+                    source,
+                    space_spec,
+                    time_spec,
+                    pipeline.num_leading_required,
+                    pipeline.num_trailing_required,
+                    backing_extra_spec,
+                    &[],
+                )
+                .await
+            {
+                Ok(data) => data,
+                Err(e) => {
+                    tracing::error!(%e);
+                    return Err(Error::DataSwitch(e));
+                }
+            };
+            backing_data.insert(
+                (source.to_string(), backing_extra_spec.map(String::from)),
+                backing_cache,
+            );
+        }
+
+        self.check_size(
+            num_points(&data)
+                + backing_series
+                    .iter()
+                    .map(|(_, series)| num_points(series))
+                    .sum::<usize>()
+                + backing_data.values().map(num_points).sum::<usize>(),
+        )?;
+
+        // merging series rebuilds the spatial index, and converting units touches every point, so
+        // for large fetches these are kept off the async workers too
+        let units = pipeline.units.clone();
+        tokio::task::spawn_blocking(move || {
+            prepare_run_data(data, backing_series, backing_data, units.as_deref())
+        })
+        .await
+        // the task is never aborted, so this only fails if it panicked
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
     }
 
     /// Run a set of QC tests on some data
@@ -79,7 +1125,9 @@ impl<'a> Scheduler<'a> {
     /// [`DataSwitch`](data_switch::DataSwitch).
     /// `backing_sources` a list of keys similar to `data_source`, but data
     /// from these will only be used to QC data from `data_source` and will not
-    /// themselves be QCed.
+    /// themselves be QCed. Their series are fetched with the same specs as
+    /// `data_source`, and added to its data, so that e.g. spatial checks of
+    /// sparse networks can use them as neighbours.
     /// `time_spec` and `space_spec` narrow down what data to QC, more info
     /// on what these mean and how to construct them can be found on their
     /// own doc pages.
@@ -89,54 +1137,1139 @@ impl<'a> Scheduler<'a> {
     /// `extra_spec` is an extra identifier that gets passed to the relevant
     /// DataConnector. The format of `extra_spec` is connector-specific.
     ///
+    /// If the scheduler has a [`Tiling`](data_switch::Tiling) and `space_spec`
+    /// is a polygon, the polygon is fetched and QCed one tile at a time, and
+    /// responses are sent for each tile in turn. Likewise, if the scheduler
+    /// has a chunk length and the timerange is longer than it, the timerange
+    /// is fetched and QCed one chunk at a time. If `time_spec` has
+    /// [`times`](TimeSpec::times), each run of consecutive times is fetched
+    /// and QCed separately, so gaps between them aren't fetched.
+    ///
     /// # Errors
     ///
     /// Returned from the function if:
     /// - The pipeline named by in the `test_pipeline` argument is not recognized
     ///   by the system
-    /// - The data_source string did not have a matching entry in the
-    ///   Scheduler's DataSwitch
+    /// - The dependencies between the pipeline's steps are invalid
+    /// - `time_spec` has times, but none of them are within its timerange
+    /// - The data_source string, or a data source needed by one of the checks
+    ///   in the pipeline, did not have a matching entry in the Scheduler's
+    ///   DataSwitch
     ///
     /// In the the returned channel if:
     /// - The test harness encounters an error on during one of the QC tests.
     ///   This will also result in the channel being closed
+    /// - Fetching data for a tile or chunk other than the first fails
     pub async fn validate_direct(
         &self,
         data_source: impl AsRef<str>,
-        // TODO: we should actually use these
-        _backing_sources: &[impl AsRef<str>],
+        backing_sources: &[impl AsRef<str>],
         time_spec: &TimeSpec,
         space_spec: &SpaceSpec,
         // TODO: should we allow specifying multiple pipelines per call?
         test_pipeline: impl AsRef<str>,
         extra_spec: Option<&str>,
-    ) -> Result<Receiver<Result<ValidateResponse, Error>>, Error> {
-        let pipeline = self
-            .pipelines
-            .get(test_pipeline.as_ref())
-            .ok_or(Error::InvalidArg("pipeline not recognised"))?;
+    ) -> Result<Receiver<Result<QcResult, Error>>, Error> {
+        self.validate_direct_with_options(
+            data_source,
+            backing_sources,
+            time_spec,
+            space_spec,
+            test_pipeline,
+            extra_spec,
+            RunOptions::default(),
+        )
+        .await
+    }
 
-        let data = match self
-            .data_switch
-            .fetch_data(
+    /// Like [`validate_direct`](Scheduler::validate_direct), with options for the run
+    ///
+    /// If the scheduler has [`RunLimits`], the run waits in the queue according to the
+    /// options' [`Priority`] before it starts fetching.
+    ///
+    /// The run can be aborted by cancelling the options' token. Fetches in progress when the run
+    /// is cancelled are abandoned, and no further steps or chunks are started, so the run's data
+    /// is freed promptly. Steps already running are finished first, as checks can't be
+    /// interrupted. Cancelling the run while it is queued or fetching its first chunk returns
+    /// [`Error::Cancelled`], afterwards it is sent down the channel, which is then closed.
+    ///
+    /// Dropping the returned receiver also stops the run, but only once it next tries to send
+    /// results, unless the run is [recorded to be replayed](Scheduler::with_idempotency).
+    ///
+    /// A run past its options' [`deadline`](RunOptions::deadline) is stopped the same way as a
+    /// cancelled one, but with [`Error::DeadlineExceeded`], so whatever results it sent before
+    /// then are followed by that error.
+    ///
+    /// The run's status is recorded under the options' [`run_id`](RunOptions::run_id) from when
+    /// this is called, and can be looked up with [`run_status`](Scheduler::run_status) while
+    /// the run is queued or fetching, before this returns. Every response from the run has its
+    /// [`run_id`](QcResult::run_id) set to it as well.
+    ///
+    /// # Errors
+    ///
+    /// As [`validate_direct`](Scheduler::validate_direct), or [`Error::Cancelled`] or
+    /// [`Error::DeadlineExceeded`], or [`Error::InvalidArg`] if the options select steps that
+    /// aren't in the pipeline, or [`Error::RunIdInUse`] if another run with the options'
+    /// [`run_id`](RunOptions::run_id) is still in progress
+    #[allow(clippy::too_many_arguments)]
+    pub async fn validate_direct_with_options(
+        &self,
+        data_source: impl AsRef<str>,
+        backing_sources: &[impl AsRef<str>],
+        time_spec: &TimeSpec,
+        space_spec: &SpaceSpec,
+        test_pipeline: impl AsRef<str>,
+        extra_spec: Option<&str>,
+        options: RunOptions,
+    ) -> Result<Receiver<Result<QcResult, Error>>, Error> {
+        let start = self.start_or_replay(&options, || {
+            replay::fetch_fingerprint(
                 data_source.as_ref(),
+                backing_sources,
+                time_spec,
                 space_spec,
+                test_pipeline.as_ref(),
+                extra_spec,
+                &options,
+            )
+        })?;
+        let hooks = match start {
+            Start::Run(hooks) => hooks,
+            Start::Replay(results) => {
+                return self.replay(test_pipeline.as_ref(), options.run_id, results)
+            }
+        };
+        let recorded = self.records(&options);
+
+        let status = self
+            .runs
+            .start(options.run_id, test_pipeline.as_ref(), hooks)
+            .ok_or(Error::RunIdInUse(options.run_id))?;
+        // the caller can drop this future while the run is queued or fetching
+        let abandon_guard = status.abandon_on_drop();
+        let result = self
+            .start_run(
+                data_source.as_ref(),
+                backing_sources,
                 time_spec,
-                pipeline.num_leading_required,
-                pipeline.num_trailing_required,
+                space_spec,
+                test_pipeline.as_ref(),
                 extra_spec,
+                options,
+                status.clone(),
             )
-            .await
+            .await;
+        abandon_guard.defuse();
+        if let Err(e) = &result {
+            record_failure(&status, e);
+        }
+        result.map(|results| match recorded {
+            true => read_unread(results),
+            false => results,
+        })
+    }
+
+    /// Like [`validate_direct_with_options`](Scheduler::validate_direct_with_options), but QCing
+    /// `data` the caller already has, rather than fetching it through the data switch
+    ///
+    /// Meant for ingestors that have the data in hand, and can't republish it somewhere a
+    /// connector could fetch it from first. The data must be in the pipeline's units, and
+    /// include as many leading and trailing points as the pipeline, or the steps selected in
+    /// `options`, [need](Pipeline::num_leading_required), which can be gaps if there's no data
+    /// for them.
+    /// Steps that need backing sources fail, as there's nothing to fetch them from. The run is
+    /// neither chunked nor tiled, but its results are still split per
+    /// [`with_result_chunk_len`](Scheduler::with_result_chunk_len).
+    ///
+    /// # Errors
+    ///
+    /// As [`validate_direct_with_options`](Scheduler::validate_direct_with_options), apart from
+    /// the data switch's errors, or [`Error::InvalidArg`] if `data` has too few leading or
+    /// trailing points
+    pub async fn validate_data(
+        &self,
+        data: DataCache,
+        test_pipeline: impl AsRef<str>,
+        options: RunOptions,
+    ) -> Result<Receiver<Result<QcResult, Error>>, Error> {
+        let start = self.start_or_replay(&options, || {
+            replay::data_fingerprint(&data, test_pipeline.as_ref(), &options)
+        })?;
+        let hooks = match start {
+            Start::Run(hooks) => hooks,
+            Start::Replay(results) => {
+                return self.replay(test_pipeline.as_ref(), options.run_id, results)
+            }
+        };
+        let recorded = self.records(&options);
+
+        let status = self
+            .runs
+            .start(options.run_id, test_pipeline.as_ref(), hooks)
+            .ok_or(Error::RunIdInUse(options.run_id))?;
+        let abandon_guard = status.abandon_on_drop();
+        let result = self
+            .start_run_on_data(data, test_pipeline.as_ref(), options, status.clone())
+            .await;
+        abandon_guard.defuse();
+        if let Err(e) = &result {
+            record_failure(&status, e);
+        }
+        result.map(|results| match recorded {
+            true => read_unread(results),
+            false => results,
+        })
+    }
+
+    /// The body of [`validate_data`](Scheduler::validate_data), once the run is recorded
+    async fn start_run_on_data(
+        &self,
+        data: DataCache,
+        test_pipeline: &str,
+        options: RunOptions,
+        status: RunHandle,
+    ) -> Result<Receiver<Result<QcResult, Error>>, Error> {
+        let pipeline = match &options.steps {
+            Some(steps) => Arc::new(select_steps(&self.get_pipeline(test_pipeline)?, steps)?),
+            None => self.get_pipeline(test_pipeline)?,
+        };
+        let levels = pipeline.dependency_levels()?;
+        let metadata = pipeline_metadata(test_pipeline, &pipeline);
+
+        if data.num_leading_points < pipeline.num_leading_required
+            || data.num_trailing_points < pipeline.num_trailing_required
         {
-            Ok(data) => data,
-            Err(e) => {
-                tracing::error!(%e);
-                return Err(Error::DataSwitch(e));
+            return Err(Error::InvalidArg(
+                "data has fewer leading or trailing points than the pipeline needs",
+            ));
+        }
+        self.check_size(data.data.iter().map(|(_, values)| values.len()).sum())?;
+        let run_permit = enqueue(self.run_queue.as_ref(), &options).await?;
+
+        Ok(Scheduler::schedule_tests(
+            pipeline,
+            levels,
+            metadata,
+            data,
+            BackingData::new(),
+            self.thread_pool.clone(),
+            self.max_concurrent_steps,
+            self.result_chunk_len,
+            self.remote_runners.clone(),
+            options,
+            run_permit,
+            status,
+        ))
+    }
+
+    /// Run several validations at once, sending all their results down one channel, each tagged
+    /// with the index of the spec in `specs` it's from
+    ///
+    /// Each spec is run as if it were passed to
+    /// [`validate_direct_with_options`](Scheduler::validate_direct_with_options), including
+    /// waiting in the queue if the scheduler has [`RunLimits`], so a large batch doesn't run
+    /// any more at once than the same runs started separately would. Either way, at most 16 of
+    /// a batch's specs are in progress at once, and the rest are started, in order, as those
+    /// finish. A spec that fails, whether before it starts or partway through, has its error sent
+    /// tagged with its index, and sends nothing further, while the others carry on. The channel
+    /// is closed once every run has finished.
+    ///
+    /// Dropping the returned receiver cancels every run still queued or running, apart from
+    /// those [recorded to be replayed](Scheduler::with_idempotency) that have started running.
+    ///
+    /// Must be called from within a tokio runtime, as the runs are spawned onto it.
+    pub fn validate_batch(
+        &self,
+        specs: Vec<ValidateSpec>,
+    ) -> Receiver<(usize, Result<QcResult, Error>)> {
+        let (tx, rx) = channel(specs.len().clamp(1, MAX_CONCURRENT_BATCH_RUNS));
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_BATCH_RUNS));
+            for (i, spec) in specs.into_iter().enumerate() {
+                let permit = tokio::select! {
+                    // the semaphore is never closed
+                    permit = permits.clone().acquire_owned() => permit.unwrap(),
+                    // the rest are abandoned along with the receiver
+                    _ = tx.closed() => return,
+                };
+                let scheduler = scheduler.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    let cancel = scheduler.disconnect_token(&spec.options);
+                    let start = scheduler.validate_direct_with_options(
+                        &spec.data_source,
+                        &spec.backing_sources,
+                        &spec.time_spec,
+                        &spec.space_spec,
+                        &spec.pipeline,
+                        spec.extra_spec.as_deref(),
+                        spec.options,
+                    );
+                    forward_tagged(i, start, cancel, tx).await;
+                });
+            }
+        });
+        rx
+    }
+
+    /// Whether a run with `options` has its results recorded to be
+    /// [replayed](Scheduler::with_idempotency)
+    fn records(&self, options: &RunOptions) -> bool {
+        self.replays.is_some() && options.idempotency_key.is_some()
+    }
+
+    /// The token to cancel a run with `options` through when its caller goes away, or None if
+    /// the run should finish regardless, as it's [recorded](Scheduler::with_idempotency) for the
+    /// caller's retry to get its results
+    pub(crate) fn disconnect_token(&self, options: &RunOptions) -> Option<CancellationToken> {
+        (!self.records(options)).then(|| options.cancel.clone())
+    }
+
+    /// How to start a run with `options`, for a request with the [`Fingerprint`] `fingerprint`
+    /// returns, which is only computed if the scheduler
+    /// [remembers runs](Scheduler::with_idempotency)
+    ///
+    /// # Errors
+    ///
+    /// [`Error::IdempotencyKeyReused`] if a completed run with the same key was for a different
+    /// request
+    fn start_or_replay(
+        &self,
+        options: &RunOptions,
+        fingerprint: impl FnOnce() -> Fingerprint,
+    ) -> Result<Start, Error> {
+        let mut hooks = self.hooks.clone();
+        if let (Some(replays), Some(key)) = (&self.replays, &options.idempotency_key) {
+            let fingerprint = fingerprint();
+            if let Some((completed_fingerprint, results)) = replays.get(key) {
+                if completed_fingerprint != fingerprint {
+                    return Err(Error::IdempotencyKeyReused);
+                }
+                tracing::debug!(%key, "replaying results of a completed run");
+                return Ok(Start::Replay(results));
             }
+            hooks.push(Arc::new(replays.recorder(key.clone(), fingerprint)));
+        }
+        Ok(Start::Run(hooks))
+    }
+
+    /// Send the results of a completed run again, as run `run_id`
+    fn replay(
+        &self,
+        test_pipeline: &str,
+        run_id: RunId,
+        results: Vec<QcResult>,
+    ) -> Result<Receiver<Result<QcResult, Error>>, Error> {
+        let status = self
+            .runs
+            .start(run_id, test_pipeline, Hooks::default())
+            .ok_or(Error::RunIdInUse(run_id))?;
+        let (tx, rx) = channel(results.len().max(1));
+        for mut result in results {
+            result.run_id = run_id;
+            tx.try_send(Ok(result))
+                .expect("channel should have room for every result");
+        }
+        status.finish(true);
+        Ok(rx)
+    }
+
+    /// The body of [`validate_direct_with_options`](Scheduler::validate_direct_with_options),
+    /// once the run is recorded
+    #[allow(clippy::too_many_arguments)]
+    async fn start_run(
+        &self,
+        data_source: &str,
+        backing_sources: &[impl AsRef<str>],
+        time_spec: &TimeSpec,
+        space_spec: &SpaceSpec,
+        test_pipeline: &str,
+        extra_spec: Option<&str>,
+        options: RunOptions,
+        status: RunHandle,
+    ) -> Result<Receiver<Result<QcResult, Error>>, Error> {
+        let pipeline = match &options.steps {
+            Some(steps) => Arc::new(select_steps(&self.get_pipeline(test_pipeline)?, steps)?),
+            None => self.get_pipeline(test_pipeline)?,
+        };
+        let levels = pipeline.dependency_levels()?;
+        let metadata = pipeline_metadata(test_pipeline.as_ref(), &pipeline);
+
+        let mut chunks = self.split_run(time_spec, space_spec)?;
+        // the rest are checked once they're fetched
+        let num_series = match space_spec {
+            SpaceSpec::One(_) => Some(1),
+            SpaceSpec::Multiple(data_ids) => Some(data_ids.len()),
+            SpaceSpec::Polygon(_) | SpaceSpec::All => None,
         };
+        if let Some(num_series) = num_series {
+            let num_points_per_series = chunks
+                .iter()
+                .map(|chunk| chunk.time_spec.num_points())
+                .max()
+                .unwrap_or(0)
+                + pipeline.num_leading_required as usize
+                + pipeline.num_trailing_required as usize;
+            self.check_size(num_series * num_points_per_series)?;
+        }
+        let run_permit = enqueue(self.run_queue.as_ref(), &options).await?;
+        status.set_state(RunState::Fetching);
+
+        if chunks.len() > 1 || chunks[0].tile.is_some() {
+            return self
+                .schedule_chunks(
+                    pipeline,
+                    levels,
+                    metadata,
+                    data_source.as_ref(),
+                    backing_sources
+                        .iter()
+                        .map(|source| source.as_ref().to_string())
+                        .collect(),
+                    chunks,
+                    extra_spec,
+                    options,
+                    run_permit,
+                    status,
+                )
+                .await;
+        }
+
+        // there's only one, which may be narrower than time_spec if it has times
+        let chunk = chunks.remove(0);
+        let (data, backing_data) = until_cancelled(
+            &options.cancel,
+            options.deadline,
+            self.fetch_run_data(
+                &pipeline,
+                data_source.as_ref(),
+                backing_sources,
+                &chunk.time_spec,
+                &chunk.space_spec,
+                extra_spec,
+                options.priority,
+            ),
+        )
+        .await?;
+
+        Ok(Scheduler::schedule_tests(
+            pipeline,
+            levels,
+            metadata,
+            data,
+            backing_data,
+            self.thread_pool.clone(),
+            self.max_concurrent_steps,
+            self.result_chunk_len,
+            self.remote_runners.clone(),
+            options,
+            run_permit,
+            status,
+        ))
+    }
+
+    /// Split a run into the chunks it is fetched and QCed in, according to the scheduler's
+    /// chunking and tiling, which is just the one chunk if it isn't split
+    ///
+    /// Chunks are ordered by time first, so results for earlier times come out first.
+    fn split_run(&self, time_spec: &TimeSpec, space_spec: &SpaceSpec) -> Result<Vec<Chunk>, Error> {
+        if !time_spec.has_positive_resolution() {
+            return Err(Error::InvalidArg("time_resolution must be positive"));
+        }
+        let time_specs: Vec<TimeSpec> = time_spec
+            .runs()
+            .into_iter()
+            .flat_map(|run| match self.chunk_len {
+                Some(chunk_len) => run.chunks(chunk_len),
+                None => vec![run],
+            })
+            .collect();
+        if time_specs.is_empty() {
+            return Err(Error::InvalidArg("no times to QC within the timerange"));
+        }
+
+        let tiles = self.tiling.and_then(|tiling| space_spec.tiles(tiling));
+        let chunks: Vec<Chunk> = time_specs
+            .into_iter()
+            .flat_map(|time_spec| match &tiles {
+                Some(tiles) => tiles
+                    .iter()
+                    .map(|tile| Chunk {
+                        space_spec: SpaceSpec::Polygon(tile.fetch.clone()),
+                        time_spec: time_spec.clone(),
+                        tile: Some(tile.clone()),
+                    })
+                    .collect(),
+                None => vec![Chunk {
+                    space_spec: space_spec.clone(),
+                    time_spec,
+                    tile: None,
+                }],
+            })
+            .collect();
+        // e.g. a polygon with no vertices, which has no tiles
+        if chunks.is_empty() {
+            return Err(Error::InvalidArg("no space to QC within the space_spec"));
+        }
+
+        Ok(chunks)
+    }
+
+    /// The requests [`validate_direct`](Scheduler::validate_direct) would make of the data
+    /// switch's connectors with the same arguments, in the order it would make them, without
+    /// making any
+    ///
+    /// Meant for finding out why a connector returns no data, by showing exactly what it is
+    /// asked for, including the leading and trailing points the pipeline needs. Runs split into
+    /// chunks make a set of requests for each chunk.
+    ///
+    /// # Errors
+    ///
+    /// If the pipeline named by `test_pipeline` is not recognized by the system, one of the data
+    /// sources has no connector, or there are no times to QC within the timerange
+    pub fn fetch_plan(
+        &self,
+        data_source: impl AsRef<str>,
+        backing_sources: &[impl AsRef<str>],
+        time_spec: &TimeSpec,
+        space_spec: &SpaceSpec,
+        test_pipeline: impl AsRef<str>,
+        extra_spec: Option<&str>,
+    ) -> Result<Vec<ConnectorRequest>, Error> {
+        let pipeline = self.get_pipeline(test_pipeline.as_ref())?;
+        let (params, backing_fetches) = plan_fetches(&pipeline);
+
+        let mut requests = Vec::new();
+        // in the same order as fetch_run_data
+        for chunk in self.split_run(time_spec, space_spec)? {
+            let plan = |source: &str, extra_spec: Option<&str>, params: &[&str]| {
+                self.data_switch.plan_fetch(
+                    source,
+                    &chunk.space_spec,
+                    &chunk.time_spec,
+                    pipeline.num_leading_required,
+                    pipeline.num_trailing_required,
+                    extra_spec,
+                    params,
+                )
+            };
+            requests.extend(plan(data_source.as_ref(), extra_spec, &params)?);
+            for source in backing_sources {
+                requests.extend(plan(source.as_ref(), extra_spec, &[])?);
+            }
+            for (source, backing_extra_spec) in &backing_fetches {
+                requests.extend(plan(source, *backing_extra_spec, &[])?);
+            }
+        }
+        Ok(requests)
+    }
+
+    /// Describe what [`validate_direct`](Scheduler::validate_direct) would do with the same
+    /// arguments, without fetching any data or running any checks
+    ///
+    /// # Errors
+    ///
+    /// As [`fetch_plan`](Scheduler::fetch_plan), or if the dependencies between the pipeline's
+    /// steps are invalid
+    pub fn explain(
+        &self,
+        data_source: impl AsRef<str>,
+        backing_sources: &[impl AsRef<str>],
+        time_spec: &TimeSpec,
+        space_spec: &SpaceSpec,
+        test_pipeline: impl AsRef<str>,
+        extra_spec: Option<&str>,
+    ) -> Result<Explanation, Error> {
+        let pipeline = self.get_pipeline(test_pipeline.as_ref())?;
+        let pipeline = pipeline.as_ref();
+        let levels = pipeline.dependency_levels()?;
+        let dependencies = &pipeline.dependencies()?;
+        let (params, backing_fetches) = plan_fetches(pipeline);
+
+        let steps = levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level, indices)| {
+                indices.iter().map(move |i| {
+                    let step = &pipeline.steps[*i];
+                    ExplainedStep {
+                        name: step.name.clone(),
+                        check: step.check.name().to_string(),
+                        level: level as u32,
+                        depends_on: dependencies[*i]
+                            .iter()
+                            .map(|dependency| pipeline.steps[*dependency].name.clone())
+                            .collect(),
+                    }
+                })
+            })
+            .collect();
+
+        let fetches = std::iter::once(PlannedFetch {
+            data_source: data_source.as_ref().to_string(),
+            extra_spec: extra_spec.map(String::from),
+            params: params.into_iter().map(String::from).collect(),
+            backing: false,
+        })
+        .chain(backing_sources.iter().map(|source| PlannedFetch {
+            data_source: source.as_ref().to_string(),
+            extra_spec: extra_spec.map(String::from),
+            params: Vec::new(),
+            backing: true,
+        }))
+        .chain(
+            backing_fetches
+                .into_iter()
+                .map(|(source, backing_extra_spec)| PlannedFetch {
+                    data_source: source.to_string(),
+                    extra_spec: backing_extra_spec.map(String::from),
+                    params: Vec::new(),
+                    backing: true,
+                }),
+        )
+        .collect();
+
+        let requests = self
+            .fetch_plan(
+                data_source,
+                backing_sources,
+                time_spec,
+                space_spec,
+                test_pipeline,
+                extra_spec,
+            )?
+            .into_iter()
+            .map(|request| pb::ConnectorRequest {
+                data_source: request.data_source,
+                space_spec: describe_space_spec(&request.space_spec),
+                start_time: Some(prost_types::Timestamp {
+                    seconds: request.timerange.start.0,
+                    nanos: 0,
+                }),
+                end_time: Some(prost_types::Timestamp {
+                    seconds: request.timerange.end.0,
+                    nanos: 0,
+                }),
+                time_resolution: request.time_resolution.format_to_iso8601(),
+                num_leading_points: request.num_leading_points.into(),
+                num_trailing_points: request.num_trailing_points.into(),
+                fetch_start_time: Some(prost_types::Timestamp {
+                    seconds: request.fetch_timerange.start.0,
+                    nanos: 0,
+                }),
+                fetch_end_time: Some(prost_types::Timestamp {
+                    seconds: request.fetch_timerange.end.0,
+                    nanos: 0,
+                }),
+                extra_spec: request.extra_spec,
+                params: request.params,
+            })
+            .collect();
+
+        Ok(Explanation {
+            steps,
+            num_leading_points: pipeline.num_leading_required.into(),
+            num_trailing_points: pipeline.num_trailing_required.into(),
+            fetches,
+            start_time: Some(prost_types::Timestamp {
+                seconds: time_spec.timerange.start.0,
+                nanos: 0,
+            }),
+            end_time: Some(prost_types::Timestamp {
+                seconds: time_spec.timerange.end.0,
+                nanos: 0,
+            }),
+            time_resolution: time_spec.time_resolution.format_to_iso8601(),
+            space_spec: describe_space_spec(space_spec),
+            requests,
+        })
+    }
+
+    /// Metadata of a pipeline, for attaching to its responses
+    ///
+    /// # Errors
+    ///
+    /// If the pipeline is not recognized by the system
+    pub fn pipeline_metadata(&self, test_pipeline: &str) -> Result<PipelineMetadata, Error> {
+        Ok(pipeline_metadata(
+            test_pipeline,
+            self.get_pipeline(test_pipeline)?.as_ref(),
+        ))
+    }
+
+    /// Metadata of every pipeline registered with the scheduler, sorted by name
+    pub fn list_pipelines(&self) -> Vec<PipelineMetadata> {
+        let mut pipelines: Vec<PipelineMetadata> = self
+            .pipelines()
+            .iter()
+            .map(|(name, pipeline)| pipeline_metadata(name, pipeline))
+            .collect();
+        pipelines.sort_by(|a, b| a.name.cmp(&b.name));
+        pipelines
+    }
+
+    /// Look up a pipeline registered with the scheduler by name
+    ///
+    /// # Errors
+    ///
+    /// If the pipeline isn't recognised
+    pub fn get_pipeline(&self, name: &str) -> Result<Arc<Pipeline>, Error> {
+        self.pipelines()
+            .get(name)
+            .cloned()
+            .ok_or(Error::InvalidArg("pipeline not recognised"))
+    }
+
+    /// Run the pipeline routed to for `parameter` at the time resolution of `time_spec` on some
+    /// data
+    ///
+    /// Equivalent to [`validate_direct`](Scheduler::validate_direct), with the pipeline looked up
+    /// in the scheduler's routing table.
+    ///
+    /// # Errors
+    ///
+    /// As for `validate_direct`, and if no route matches the parameter and time resolution
+    pub async fn validate_for_parameter(
+        &self,
+        data_source: impl AsRef<str>,
+        backing_sources: &[impl AsRef<str>],
+        time_spec: &TimeSpec,
+        space_spec: &SpaceSpec,
+        parameter: impl AsRef<str>,
+        extra_spec: Option<&str>,
+    ) -> Result<Receiver<Result<QcResult, Error>>, Error> {
+        let pipeline = self
+            .routes
+            .find(parameter.as_ref(), time_spec.time_resolution)
+            .ok_or(Error::InvalidArg("no pipeline routed for parameter"))?;
+
+        self.validate_direct(
+            data_source,
+            backing_sources,
+            time_spec,
+            space_spec,
+            pipeline,
+            extra_spec,
+        )
+        .await
+    }
+}
+
+fn pipeline_metadata(name: &str, pipeline: &Pipeline) -> PipelineMetadata {
+    PipelineMetadata {
+        name: name.to_string(),
+        version: pipeline.version.clone(),
+        description: pipeline.description.clone(),
+        author: pipeline.author.clone(),
+    }
+}
+
+/// Human readable description of a space spec
+fn describe_space_spec(space_spec: &SpaceSpec) -> String {
+    match space_spec {
+        SpaceSpec::One(data_id) => format!("one series: {}", data_id),
+        SpaceSpec::Multiple(data_ids) => {
+            format!("{} series: {}", data_ids.len(), data_ids.join(", "))
+        }
+        SpaceSpec::Polygon(polygon) => {
+            format!("polygon with {} vertices", polygon.len())
+        }
+        SpaceSpec::All => "all".to_string(),
+    }
+}
+
+/// Merge backing series into the data being QCed, and convert everything to the pipeline's
+/// units, if it has any
+fn prepare_run_data(
+    mut data: DataCache,
+    backing_series: Vec<(String, DataCache)>,
+    mut backing_data: BackingData,
+    units: Option<&str>,
+) -> Result<(DataCache, BackingData), Error> {
+    for (source, mut series) in backing_series {
+        // converted before they're added, as adding them only converts them to the data's units,
+        // which it may not report
+        if let Some(units) = units {
+            if let Err(e) = series.convert_units(units) {
+                return Err(Error::DataSwitch(data_switch::Error::BackingUnits(
+                    source, e,
+                )));
+            }
+        }
+        if let Err(e) = data.add_backing(source, series) {
+            tracing::error!(%e);
+            return Err(Error::DataSwitch(e));
+        }
+    }
+
+    // so pipelines tuned for one unit don't misflag data reported in another
+    if let Some(units) = units {
+        data.convert_units(units)?;
+        // backing data may be of other quantities, like a model's precipitation used to QC
+        // temperature, which are left as they are
+        for backing_cache in backing_data.values_mut() {
+            match backing_cache.convert_units(units) {
+                Ok(()) | Err(units::Error::Incompatible(..)) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    Ok((data, backing_data))
+}
+
+/// Number of points in a cache, across its series and their extra parameters
+fn num_points(cache: &DataCache) -> usize {
+    let series_len = cache.data.first().map_or(0, |ts| ts.1.len());
+    cache.data.len() * series_len * (1 + cache.params.len())
+}
+
+/// Run `run_pipeline` on `data` in consecutive windows of at most `window_len` points per series,
+/// or all at once if `window_len` is None, stopping early if it returns false
+///
+/// Backing data is windowed the same way, as it's fetched with the same timerange and context.
+fn run_windows(
+    window_len: Option<u32>,
+    data: &DataCache,
+    backing_data: &BackingData,
+    mut run_pipeline: impl FnMut(&DataCache, &BackingData) -> bool,
+) -> bool {
+    let window_len = match window_len {
+        Some(window_len) if (window_len as usize) < data.num_qc_points() => window_len as usize,
+        _ => return run_pipeline(data, backing_data),
+    };
+
+    let mut backing_windows: Vec<_> = backing_data
+        .iter()
+        .map(|(key, cache)| (key, cache.windows(window_len).into_iter()))
+        .collect();
+    for window in data.windows(window_len) {
+        let backing_window = backing_windows
+            .iter_mut()
+            .filter_map(|(key, windows)| Some(((*key).clone(), windows.next()?)))
+            .collect();
+        if !run_pipeline(&window, &backing_window) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Run `run_step` on each of the steps of a dependency level, at most `max_concurrent` at once,
+/// starting the next step in `level` as soon as a running one finishes
+///
+/// Steps aren't started once `stopped` returns true, and have no result. The rest's results are
+/// in the same order as `level`.
+fn run_level<T: Send>(
+    level: &[usize],
+    max_concurrent: Option<usize>,
+    stopped: impl Fn() -> bool + Sync,
+    run_step: impl Fn(usize) -> T + Sync,
+) -> Vec<Option<T>> {
+    let next = AtomicUsize::new(0);
+    let num_lanes = max_concurrent
+        .unwrap_or(level.len())
+        .clamp(1, level.len().max(1));
+    let finished: Vec<(usize, T)> = (0..num_lanes)
+        .into_par_iter()
+        .flat_map_iter(|_| {
+            // each lane takes the next step waiting to start, until there are none left
+            std::iter::from_fn(|| {
+                let position = next.fetch_add(1, Ordering::Relaxed);
+                (position < level.len() && !stopped())
+                    .then(|| (position, run_step(level[position])))
+            })
+        })
+        .collect();
+
+    let mut results: Vec<Option<T>> = level.iter().map(|_| None).collect();
+    for (position, result) in finished {
+        results[position] = Some(result);
+    }
+    results
+}
+
+/// Narrow a pipeline down to the steps named in `selected`, and the steps whose results they need
+/// to decide what to QC
+///
+/// Its data and backing data are then fetched only for those steps, with the leading and
+/// trailing points they need. Steps only run after those of their dependencies that were
+/// selected, groups keep their order, minus the steps that weren't selected, and combined flags
+/// are only kept if every step was.
+fn select_steps(pipeline: &Pipeline, selected: &[String]) -> Result<Pipeline, Error> {
+    if selected.is_empty() {
+        return Err(Error::InvalidArg("no steps selected"));
+    }
+    let position = |name: &str| pipeline.steps.iter().position(|step| step.name == name);
+
+    let mut keep = vec![false; pipeline.steps.len()];
+    let mut to_keep = selected
+        .iter()
+        .map(|name| position(name).ok_or(Error::InvalidArg("selected step not in pipeline")))
+        .collect::<Result<Vec<usize>, Error>>()?;
+    while let Some(i) = to_keep.pop() {
+        if std::mem::replace(&mut keep[i], true) {
+            continue;
+        }
+        // the pipeline was validated, so conditions only name steps in it
+        if let Some(condition) = pipeline.steps[i].run_if.as_ref() {
+            to_keep.extend(position(&condition.step));
+        }
+    }
+
+    let mut steps: Vec<PipelineStep> = pipeline
+        .steps
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(step, _)| step.clone())
+        .collect();
+    // dependencies only order steps, so ones that aren't run have nothing to wait for
+    let names: Vec<String> = steps.iter().map(|step| step.name.clone()).collect();
+    for step in steps.iter_mut() {
+        step.depends_on
+            .retain(|dependency| names.contains(dependency));
+    }
+    let groups = pipeline
+        .groups
+        .iter()
+        .map(|group| StepGroup {
+            steps: group
+                .steps
+                .iter()
+                .filter(|name| names.contains(name))
+                .cloned()
+                .collect(),
+        })
+        .filter(|group| !group.steps.is_empty())
+        .collect();
+    let combi = if steps.len() == pipeline.steps.len() {
+        pipeline.combi.clone()
+    } else {
+        None
+    };
+
+    let mut narrowed = Pipeline {
+        version: pipeline.version.clone(),
+        description: pipeline.description.clone(),
+        author: pipeline.author.clone(),
+        steps,
+        num_leading_required: 0,
+        num_trailing_required: 0,
+        combi,
+        groups,
+        units: pipeline.units.clone(),
+        continue_on_error: pipeline.continue_on_error,
+    };
+    (
+        narrowed.num_leading_required,
+        narrowed.num_trailing_required,
+    ) = pipeline::derive_num_leading_trailing(&narrowed);
+    Ok(narrowed)
+}
+
+/// The parameters to fetch alongside the data being QCed, and the (data source, extra_spec) pairs
+/// to fetch backing data from, to run a pipeline
+fn plan_fetches(pipeline: &Pipeline) -> (Vec<&str>, Vec<(&str, Option<&str>)>) {
+    let mut params: Vec<&str> = pipeline
+        .steps
+        .iter()
+        .flat_map(|step| step.check.get_params())
+        .collect();
+    params.sort_unstable();
+    params.dedup();
+
+    let mut backing_fetches: Vec<(&str, Option<&str>)> = Vec::new();
+    for fetch in pipeline
+        .steps
+        .iter()
+        .flat_map(|step| step.check.get_backing_fetches())
+    {
+        if !backing_fetches.contains(&fetch) {
+            backing_fetches.push(fetch);
+        }
+    }
+
+    (params, backing_fetches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_switch::Timestamp;
+    use chronoutil::RelativeDuration;
+    use std::sync::{atomic::AtomicBool, mpsc, Mutex};
+
+    #[test]
+    fn test_run_level() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let level = [3, 1, 4, 5];
+
+        // the first step only finishes once the third has, which needs the third to start as
+        // soon as the second finishes, rather than with the first
+        let (tx, rx) = mpsc::channel();
+        let (tx, rx) = (Mutex::new(tx), Mutex::new(rx));
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let results = pool.install(|| {
+            run_level(
+                &level,
+                Some(2),
+                || false,
+                |i| {
+                    let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now_running, Ordering::SeqCst);
+                    match i {
+                        3 => assert!(rx
+                            .lock()
+                            .unwrap()
+                            .recv_timeout(Duration::from_secs(10))
+                            .is_ok()),
+                        4 => tx.lock().unwrap().send(()).unwrap(),
+                        _ => (),
+                    }
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    i * 10
+                },
+            )
+        });
+        assert_eq!(results, vec![Some(30), Some(10), Some(40), Some(50)]);
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+
+        // nothing more is started once it's stopped
+        let stopped = AtomicBool::new(false);
+        let results = pool.install(|| {
+            run_level(
+                &level,
+                Some(1),
+                || stopped.load(Ordering::SeqCst),
+                |i| {
+                    stopped.store(i == 1, Ordering::SeqCst);
+                    i
+                },
+            )
+        });
+        assert_eq!(results, vec![Some(3), Some(1), None, None]);
+    }
+
+    #[tokio::test]
+    async fn test_tiling_empty_polygon() {
+        let pipeline = Pipeline::builder()
+            .step(PipelineStep::new(
+                "range_check",
+                pipeline::CheckConf::RangeCheck(pipeline::RangeCheckConf { min: 0., max: 1. }),
+            ))
+            .build()
+            .unwrap();
+        let scheduler = Scheduler::new(
+            HashMap::from([("test".to_string(), pipeline)]),
+            DataSwitch::new(HashMap::<String, _>::new()),
+        )
+        .with_tiling(Tiling {
+            tile_size: 1.,
+            margin: 0.,
+        })
+        .unwrap();
+
+        // an empty polygon has no tiles, so nothing to run
+        assert!(matches!(
+            scheduler
+                .validate_direct(
+                    "test",
+                    &[] as &[&str],
+                    &TimeSpec::new(Timestamp(0), Timestamp(3600), RelativeDuration::hours(1)),
+                    &SpaceSpec::Polygon(Vec::new()),
+                    "test",
+                    None,
+                )
+                .await,
+            Err(Error::InvalidArg(_))
+        ));
+    }
+
+    #[test]
+    fn test_select_steps() {
+        let pipeline = Pipeline::from_toml(
+            r#"
+            [[step]]
+            name = "range_check"
+            [step.range_check]
+            min = -55
+            max = 50
+
+            [[step]]
+            name = "step_check"
+            depends_on = ["range_check"]
+            [step.step_check]
+            max = 18.6
+            "#,
+        )
+        .unwrap();
+
+        // the dependency isn't selected, so isn't waited for
+        let narrowed = select_steps(&pipeline, &["step_check".to_string()]).unwrap();
+        assert_eq!(narrowed.steps.len(), 1);
+        assert!(narrowed.steps[0].depends_on.is_empty());
+        assert_eq!(narrowed.dependency_levels().unwrap(), vec![vec![0]]);
+
+        let narrowed = select_steps(
+            &pipeline,
+            &["step_check".to_string(), "range_check".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            narrowed.dependency_levels().unwrap(),
+            vec![vec![0], vec![1]]
+        );
+    }
+
+    #[test]
+    fn test_prepare_run_data() {
+        let cache = |identifier: &str, values: Vec<Option<f32>>| {
+            DataCache::new(
+                vec![60.],
+                vec![10.],
+                vec![0.],
+                Timestamp(0),
+                RelativeDuration::hours(1),
+                0,
+                0,
+                vec![(identifier.to_string(), values)],
+            )
+        };
+
+        // backing series are converted even if the data doesn't report its units
+        let (data, _) = prepare_run_data(
+            cache("a", vec![Some(1.), None]),
+            vec![(
+                "other".to_string(),
+                cache("b", vec![Some(32.), Some(212.)]).with_units("degF"),
+            )],
+            BackingData::new(),
+            Some("degC"),
+        )
+        .unwrap();
+        assert_eq!(data.units, None);
+        assert_eq!(data.data[0].1, vec![Some(1.), None]);
+        assert!(data.data[1].1[0].unwrap().abs() < 1e-4);
+        assert!((data.data[1].1[1].unwrap() - 100.).abs() < 1e-3);
 
-        // TODO: can probably get rid of this clone if we get rid of the channels in
-        // schedule_tests
-        Ok(Scheduler::schedule_tests(pipeline.clone(), data))
+        assert!(matches!(
+            prepare_run_data(
+                cache("a", vec![Some(1.), None]),
+                vec![(
+                    "rain".to_string(),
+                    cache("b", vec![Some(1.), None]).with_units("mm"),
+                )],
+                BackingData::new(),
+                Some("degC"),
+            ),
+            Err(Error::DataSwitch(data_switch::Error::BackingUnits(..)))
+        ));
     }
 }
@@ -1,22 +1,86 @@
 use crate::{
-    data_switch::{DataSwitch, GeoPoint, SpaceSpec, TimeSpec, Timerange, Timestamp},
+    client_limits::{ClientLimitLayer, ClientLimits},
+    data_switch::{
+        self, DataCache, DataSwitch, GeoPoint, SpaceSpec, TimeSpec, Timerange, Timestamp,
+    },
+    harness,
     pb::{
         self,
+        rove_admin_server::{RoveAdmin, RoveAdminServer},
+        rove_runner_server::{RoveRunner, RoveRunnerServer},
         rove_server::{Rove, RoveServer},
-        ValidateRequest, ValidateResponse,
+        GetPipelineRequest, GetPipelineResponse, GetRunRequest, ListPipelinesResponse,
+        ListRunsResponse, PipelineFormat, PipelineMetadata, RegisterPipelineRequest,
+        RemovePipelineRequest, RequestError, RunStepRequest, ValidateBatchRequest,
+        ValidateBatchResponse, ValidateDataRequest, ValidateDataResponse, ValidateRequest,
+        ValidateResponse,
     },
     pipeline::Pipeline,
-    scheduler::{self, Scheduler},
+    results::QcResult,
+    runner,
+    runs::{RunId, RunState, RunStatus},
+    scheduler::{self, forward_tagged, Priority, RunOptions, Scheduler, ValidateSpec},
+    shutdown::{self, DrainLayer},
+    transport::{MessageLimitLayer, TransportConfig},
 };
 use chronoutil::RelativeDuration;
 use futures::Stream;
-use std::{collections::HashMap, net::SocketAddr, pin::Pin};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    pin::Pin,
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc::channel;
-use tokio_stream::wrappers::{ReceiverStream, UnixListenerStream};
-use tonic::{transport::Server, Request, Response, Status};
+use tokio_stream::{
+    wrappers::{ReceiverStream, UnixListenerStream},
+    StreamExt,
+};
+use tokio_util::sync::CancellationToken;
+use tonic::{
+    metadata::{MetadataMap, MetadataValue},
+    transport::Server,
+    Request, Response, Status, Streaming,
+};
+use tonic_health::{server::HealthReporter, ServingStatus};
 
 type ResponseStream = Pin<Box<dyn Stream<Item = Result<ValidateResponse, Status>> + Send>>;
 
+/// Configuration of the gRPC server, see [`start_server_with_config`]
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    /// If set, the admin API is served too, which allows pipelines to be registered and removed
+    /// at runtime. Requests to it must carry this token in their `authorization` metadata, as
+    /// `Bearer <admin_token>`. Validation requests must carry it too to ask for
+    /// [high priority](Priority::High), so that only trusted clients can jump the queue. Without
+    /// an admin token, no validation request can ask for high priority
+    pub admin_token: Option<String>,
+    /// If set, the runner service is served too, which lets other rove instances run steps with
+    /// `backend = "grpc"` on this server. Requests to it must carry this token in their
+    /// `authorization` metadata, as `Bearer <runner_token>`, which the other instances send if
+    /// given it with [`Scheduler::with_runner_token`]
+    pub runner_token: Option<String>,
+    /// If set, the server only accepts TLS connections, using this config's certificate and
+    /// key, rather than plaintext ones
+    #[cfg(feature = "tls")]
+    pub tls: Option<tonic::transport::ServerTlsConfig>,
+    /// Limits on how much each client can ask of the server. Unlimited by default
+    pub client_limits: ClientLimits,
+    /// If set, the server also shuts down when this is cancelled, as well as on SIGTERM or
+    /// SIGINT, for applications that embed the server and shut down some other way
+    pub shutdown: Option<CancellationToken>,
+    /// How long to wait for requests in flight to finish when shutting down, before giving up on
+    /// them. Waits as long as they take if None
+    pub shutdown_grace_period: Option<Duration>,
+    /// Tuning of the HTTP/2 transport, like message size limits and keepalives
+    pub transport: TransportConfig,
+    /// If true, the health service also reports the server as `NOT_SERVING` while any data
+    /// source fails its [health check](crate::data_switch::DataConnector::check_health). Off by
+    /// default, as a single unhealthy source would then take the server out of a load
+    /// balancer's rotation, even for pipelines that don't use it
+    pub check_data_source_health: bool,
+}
+
 #[derive(Debug)]
 enum ListenerType {
     Addr(SocketAddr),
@@ -30,16 +94,264 @@ impl From<scheduler::Error> for Status {
                 Status::invalid_argument(format!("invalid argument: {}", s))
             }
             scheduler::Error::Runner(e) => Status::aborted(format!("failed to run test: {}", e)),
+            scheduler::Error::DataSwitch(e @ data_switch::Error::Timeout(..)) => {
+                Status::deadline_exceeded(format!("data switch failed to fetch data: {}", e))
+            }
             scheduler::Error::DataSwitch(e) => {
                 Status::not_found(format!("data switch failed to find data: {}", e))
             }
+            scheduler::Error::ThreadPool(e) => {
+                Status::internal(format!("failed to build thread pool: {}", e))
+            }
+            scheduler::Error::InvalidPipeline(e) => {
+                Status::internal(format!("invalid pipeline: {}", e))
+            }
+            scheduler::Error::Units(e) => Status::failed_precondition(format!(
+                "data could not be converted to the pipeline's units: {}",
+                e
+            )),
+            scheduler::Error::Cancelled => Status::cancelled("run was cancelled"),
+            scheduler::Error::DeadlineExceeded => {
+                Status::deadline_exceeded("run did not finish before its deadline")
+            }
+            e @ scheduler::Error::TooLarge(..) => Status::resource_exhausted(e.to_string()),
+            e @ scheduler::Error::IdempotencyKeyReused => {
+                Status::failed_precondition(e.to_string())
+            }
+            e @ scheduler::Error::RunIdInUse(_) => Status::already_exists(e.to_string()),
+        }
+    }
+}
+
+impl From<RunStatus> for pb::RunStatus {
+    fn from(item: RunStatus) -> Self {
+        let (state, steps) = match item.state {
+            RunState::Queued => (pb::RunState::Queued, Vec::new()),
+            RunState::Fetching => (pb::RunState::Fetching, Vec::new()),
+            RunState::Running(steps) => (pb::RunState::Running, steps),
+            RunState::Done => (pb::RunState::Done, Vec::new()),
+            RunState::Failed => (pb::RunState::Failed, Vec::new()),
+            RunState::Cancelled => (pb::RunState::Cancelled, Vec::new()),
+        };
+        pb::RunStatus {
+            run_id: item.id.to_string(),
+            pipeline: item.pipeline,
+            state: state.into(),
+            steps,
+            error: item.error,
+            started_at: Some(prost_types::Timestamp {
+                seconds: item.started_at.timestamp(),
+                nanos: item.started_at.timestamp_subsec_nanos() as i32,
+            }),
+            updated_at: Some(prost_types::Timestamp {
+                seconds: item.updated_at.timestamp(),
+                nanos: item.updated_at.timestamp_subsec_nanos() as i32,
+            }),
         }
     }
 }
 
+#[allow(clippy::result_large_err)]
+fn parse_run_id(run_id: &str) -> Result<RunId, Status> {
+    run_id
+        .parse()
+        .map_err(|e| Status::invalid_argument(format!("invalid run_id: {}", e)))
+}
+
+/// The timeout a client set on its call, from the `grpc-timeout` header, if it set one
+///
+/// See <https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#requests> for the format.
+fn parse_grpc_timeout(metadata: &MetadataMap) -> Result<Option<Duration>, Status> {
+    let timeout = match metadata.get("grpc-timeout") {
+        Some(timeout) => timeout,
+        None => return Ok(None),
+    };
+    let invalid = || Status::invalid_argument("invalid grpc-timeout header");
+    let timeout = timeout.to_str().map_err(|_| invalid())?;
+    // up to 8 digits, then the unit
+    if timeout.len() < 2 || timeout.len() > 9 {
+        return Err(invalid());
+    }
+    let (value, unit) = timeout.split_at(timeout.len() - 1);
+    // parse would also take a leading +
+    if !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let value: u64 = value.parse().map_err(|_| invalid())?;
+    Ok(Some(match unit {
+        "H" => Duration::from_secs(value * 60 * 60),
+        "M" => Duration::from_secs(value * 60),
+        "S" => Duration::from_secs(value),
+        "m" => Duration::from_millis(value),
+        "u" => Duration::from_micros(value),
+        "n" => Duration::from_nanos(value),
+        _ => return Err(invalid()),
+    }))
+}
+
+/// Marks requests that may ask for high priority, see [`ServerConfig::admin_token`]
+#[derive(Debug, Clone, Copy)]
+struct HighPriorityAllowed;
+
+fn parse_priority(priority: i32, high_allowed: bool) -> Result<Priority, Status> {
+    match pb::Priority::from_i32(priority) {
+        Some(pb::Priority::Normal) => Ok(Priority::Normal),
+        Some(pb::Priority::High) if high_allowed => Ok(Priority::High),
+        Some(pb::Priority::High) => Err(Status::permission_denied(
+            "high priority needs the admin token",
+        )),
+        Some(pb::Priority::Low) => Ok(Priority::Low),
+        None => Err(Status::invalid_argument("unrecognised priority")),
+    }
+}
+
+/// The run a validate request asks for, which is stopped at `deadline` if set, and may only be
+/// high priority if `high_allowed`
+fn validate_spec(
+    req: ValidateRequest,
+    deadline: Option<Instant>,
+    high_allowed: bool,
+) -> Result<ValidateSpec, Status> {
+    let time_spec = TimeSpec {
+        timerange: Timerange {
+            start: Timestamp(
+                req.start_time
+                    .as_ref()
+                    .ok_or(Status::invalid_argument("invalid timestamp for start_time"))?
+                    .seconds,
+            ),
+            end: Timestamp(
+                req.end_time
+                    .as_ref()
+                    .ok_or(Status::invalid_argument("invalid timestamp for start_time"))?
+                    .seconds,
+            ),
+        },
+        time_resolution: RelativeDuration::parse_from_iso8601(&req.time_resolution)
+            .map_err(|e| Status::invalid_argument(format!("invalid time_resolution: {}", e)))?,
+        times: (!req.times.is_empty()).then(|| {
+            req.times
+                .iter()
+                .map(|time| Timestamp(time.seconds))
+                .collect()
+        }),
+    };
+
+    // TODO: implementing From<pb::validate_request::SpaceSpec> for SpaceSpec
+    // would make this much neater
+    let space_spec = match req
+        .space_spec
+        .ok_or(Status::invalid_argument("missing space_spec"))?
+    {
+        pb::validate_request::SpaceSpec::One(station_id) => SpaceSpec::One(station_id),
+        pb::validate_request::SpaceSpec::Multiple(series_ids) => {
+            if series_ids.ids.is_empty() {
+                return Err(Status::invalid_argument(
+                    "space_spec multiple must list at least one series",
+                ));
+            }
+            SpaceSpec::Multiple(series_ids.ids)
+        }
+        pb::validate_request::SpaceSpec::Polygon(pb_polygon) => {
+            if pb_polygon.polygon.is_empty() {
+                return Err(Status::invalid_argument(
+                    "space_spec polygon must have at least one vertex",
+                ));
+            }
+            SpaceSpec::Polygon(
+                pb_polygon
+                    .polygon
+                    .into_iter()
+                    .map(|point| GeoPoint {
+                        lat: point.lat,
+                        lon: point.lon,
+                    })
+                    .collect::<Vec<GeoPoint>>(),
+            )
+        }
+        pb::validate_request::SpaceSpec::All(_) => SpaceSpec::All,
+    };
+
+    let priority = parse_priority(req.priority, high_allowed)?;
+    let run_id = match &req.run_id {
+        Some(run_id) => parse_run_id(run_id)?,
+        None => RunId::new_v4(),
+    };
+
+    Ok(ValidateSpec {
+        data_source: req.data_source,
+        backing_sources: req.backing_sources,
+        time_spec,
+        space_spec,
+        pipeline: req.pipeline,
+        extra_spec: req.extra_spec,
+        options: RunOptions {
+            run_id,
+            priority,
+            cancel: CancellationToken::new(),
+            deadline,
+            continue_on_error: req.continue_on_error,
+            steps: (!req.steps.is_empty()).then_some(req.steps),
+            idempotency_key: req.idempotency_key,
+        },
+    })
+}
+
+/// The data a validate data request asks to QC, its pipeline, and the options for the run, which
+/// is stopped at `deadline` if set, and may only be high priority if `high_allowed`
+fn data_run(
+    req: ValidateDataRequest,
+    deadline: Option<Instant>,
+    high_allowed: bool,
+) -> Result<(DataCache, String, RunOptions), Status> {
+    let data = DataCache::try_from(req.data.ok_or(Status::invalid_argument("missing data"))?)
+        .map_err(|e| Status::invalid_argument(format!("invalid data: {}", e)))?;
+    let run_id = match &req.run_id {
+        Some(run_id) => parse_run_id(run_id)?,
+        None => RunId::new_v4(),
+    };
+
+    Ok((
+        data,
+        req.pipeline,
+        RunOptions {
+            run_id,
+            priority: parse_priority(req.priority, high_allowed)?,
+            cancel: CancellationToken::new(),
+            deadline,
+            continue_on_error: req.continue_on_error,
+            steps: (!req.steps.is_empty()).then_some(req.steps),
+            idempotency_key: req.idempotency_key,
+        },
+    ))
+}
+
+/// A response to one request of a batch or stream, or why it failed
+fn tagged_response(
+    result: Result<QcResult, Status>,
+) -> (Option<ValidateResponse>, Option<RequestError>) {
+    match result {
+        Ok(result) => (Some(result.into()), None),
+        Err(status) => (
+            None,
+            Some(RequestError {
+                code: status.code() as i32,
+                message: status.message().to_string(),
+            }),
+        ),
+    }
+}
+
+// responses buffered for a client streaming data to validate, across all its requests
+const VALIDATE_DATA_BUFFER: usize = 64;
+
 #[tonic::async_trait]
-impl Rove for Scheduler<'static> {
+impl Rove for Scheduler {
     type ValidateStream = ResponseStream;
+    type ValidateBatchStream =
+        Pin<Box<dyn Stream<Item = Result<ValidateBatchResponse, Status>> + Send>>;
+    type ValidateDataStream =
+        Pin<Box<dyn Stream<Item = Result<ValidateDataResponse, Status>> + Send>>;
 
     #[tracing::instrument]
     async fn validate(
@@ -48,69 +360,92 @@ impl Rove for Scheduler<'static> {
     ) -> Result<Response<Self::ValidateStream>, Status> {
         tracing::debug!("Got a request: {:?}", request);
 
+        // the client gives up on the call after this, so the run may as well stop too
+        let deadline = parse_grpc_timeout(request.metadata())?
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+        let high_allowed = request.extensions().get::<HighPriorityAllowed>().is_some();
         let req = request.into_inner();
+        let dry_run = req.dry_run;
+        let spec = validate_spec(req, deadline, high_allowed)?;
 
-        let time_spec = TimeSpec {
-            timerange: Timerange {
-                start: Timestamp(
-                    req.start_time
-                        .as_ref()
-                        .ok_or(Status::invalid_argument("invalid timestamp for start_time"))?
-                        .seconds,
-                ),
-                end: Timestamp(
-                    req.end_time
-                        .as_ref()
-                        .ok_or(Status::invalid_argument("invalid timestamp for start_time"))?
-                        .seconds,
+        if dry_run {
+            let explanation = self
+                .explain(
+                    &spec.data_source,
+                    &spec.backing_sources,
+                    &spec.time_spec,
+                    &spec.space_spec,
+                    &spec.pipeline,
+                    spec.extra_spec.as_deref(),
+                )
+                .map_err(Into::<Status>::into)?;
+            let response = ValidateResponse {
+                test: String::from("explain"),
+                results: Vec::new(),
+                pipeline: Some(
+                    self.pipeline_metadata(&spec.pipeline)
+                        .map_err(Into::<Status>::into)?
+                        .into(),
                 ),
-            },
-            time_resolution: RelativeDuration::parse_from_iso8601(&req.time_resolution)
-                .map_err(|e| Status::invalid_argument(format!("invalid time_resolution: {}", e)))?,
-        };
+                explanation: Some(explanation),
+                ..Default::default()
+            };
+            return Ok(Response::new(
+                Box::pin(tokio_stream::once(Ok(response))) as Self::ValidateStream
+            ));
+        }
 
-        // TODO: implementing From<pb::validate_request::SpaceSpec> for SpaceSpec
-        // would make this much neater
-        let space_spec = match req.space_spec.unwrap() {
-            pb::validate_request::SpaceSpec::One(station_id) => SpaceSpec::One(station_id),
-            pb::validate_request::SpaceSpec::Polygon(pb_polygon) => SpaceSpec::Polygon(
-                pb_polygon
-                    .polygon
-                    .into_iter()
-                    .map(|point| GeoPoint {
-                        lat: point.lat,
-                        lon: point.lon,
-                    })
-                    .collect::<Vec<GeoPoint>>(),
-            ),
-            pb::validate_request::SpaceSpec::All(_) => SpaceSpec::All,
-        };
+        let pipeline_len = self
+            .get_pipeline(&spec.pipeline)
+            .map_err(Into::<Status>::into)?
+            .steps
+            .len();
 
+        let run_id = spec.options.run_id;
+        // if the client disconnects before the first fetch is done, this future is dropped, which
+        // abandons the fetch without needing the token. Runs recorded for replay have none, so
+        // they finish for the client's retry
+        let cancel = self.disconnect_token(&spec.options);
         let mut rx = self
-            .validate_direct(
-                req.data_source,
-                &req.backing_sources,
-                &time_spec,
-                &space_spec,
-                &req.pipeline,
-                req.extra_spec.as_deref(),
+            .validate_direct_with_options(
+                spec.data_source,
+                &spec.backing_sources,
+                &spec.time_spec,
+                &spec.space_spec,
+                &spec.pipeline,
+                spec.extra_spec.as_deref(),
+                spec.options,
             )
             .await
             .map_err(Into::<Status>::into)?;
 
-        // this unwrap is fine because validate_direct already checked the hashmap entry exists
-        let pipeline_len = self.pipelines.get(&req.pipeline).unwrap().steps.len();
-
         // TODO: remove this channel chaining once async iterators drop
         let (tx_final, rx_final) = channel(pipeline_len);
         tokio::spawn(async move {
-            while let Some(i) = rx.recv().await {
-                match tx_final.send(i.map_err(|e| e.into())).await {
+            loop {
+                let i = tokio::select! {
+                    i = rx.recv() => match i {
+                        Some(i) => i,
+                        None => break,
+                    },
+                    // the client disconnected, so stop fetching and QCing for it now rather
+                    // than when the next results are ready
+                    _ = tx_final.closed() => {
+                        if let Some(cancel) = &cancel {
+                            cancel.cancel();
+                        }
+                        break;
+                    }
+                };
+                match tx_final.send(i.map(Into::into).map_err(Into::into)).await {
                     Ok(_) => {
                         // item (server response) was queued to be send to client
                     }
                     Err(_item) => {
                         // output_stream was build from rx and both are dropped
+                        if let Some(cancel) = &cancel {
+                            cancel.cancel();
+                        }
                         break;
                     }
                 };
@@ -118,49 +453,422 @@ impl Rove for Scheduler<'static> {
         });
 
         let output_stream = ReceiverStream::new(rx_final);
+        let mut response = Response::new(Box::pin(output_stream) as Self::ValidateStream);
+        response.metadata_mut().insert(
+            "rove-run-id",
+            MetadataValue::try_from(run_id.to_string())
+                .expect("uuids should be valid metadata values"),
+        );
+        Ok(response)
+    }
+
+    #[tracing::instrument]
+    async fn validate_batch(
+        &self,
+        request: Request<ValidateBatchRequest>,
+    ) -> Result<Response<Self::ValidateBatchStream>, Status> {
+        tracing::debug!("Got a request: {:?}", request);
+
+        let deadline = parse_grpc_timeout(request.metadata())?
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+        let high_allowed = request.extensions().get::<HighPriorityAllowed>().is_some();
+        let req = request.into_inner();
+        if req.requests.is_empty() {
+            return Err(Status::invalid_argument(
+                "batch must have at least one request",
+            ));
+        }
+        let specs = req
+            .requests
+            .into_iter()
+            .enumerate()
+            .map(|(i, req)| {
+                if req.dry_run {
+                    return Err(Status::invalid_argument(format!(
+                        "request {}: dry runs aren't supported in batches",
+                        i
+                    )));
+                }
+                validate_spec(req, deadline, high_allowed).map_err(|status| {
+                    Status::new(
+                        status.code(),
+                        format!("request {}: {}", i, status.message()),
+                    )
+                })
+            })
+            .collect::<Result<Vec<ValidateSpec>, Status>>()?;
+
+        // when the client disconnects, the stream and so the receiver are dropped, which
+        // cancels the runs
+        let output_stream =
+            ReceiverStream::new(self.validate_batch(specs)).map(|(index, result)| {
+                let (response, error) = tagged_response(result.map_err(Into::into));
+                Ok(ValidateBatchResponse {
+                    index: index as u32,
+                    response,
+                    error,
+                })
+            });
+        Ok(Response::new(
+            Box::pin(output_stream) as Self::ValidateBatchStream
+        ))
+    }
+
+    #[tracing::instrument]
+    async fn validate_data(
+        &self,
+        request: Request<Streaming<ValidateDataRequest>>,
+    ) -> Result<Response<Self::ValidateDataStream>, Status> {
+        tracing::debug!("Got a request: {:?}", request);
+
+        let deadline = parse_grpc_timeout(request.metadata())?
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+        let high_allowed = request.extensions().get::<HighPriorityAllowed>().is_some();
+        let mut requests = request.into_inner();
+
+        let (tx, rx) = channel(VALIDATE_DATA_BUFFER);
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            let mut index = 0;
+            loop {
+                let req = tokio::select! {
+                    req = requests.message() => match req {
+                        Ok(Some(req)) => req,
+                        Ok(None) => break,
+                        Err(status) => {
+                            // the runs already started still finish
+                            tracing::debug!(%status, "Request stream broke.");
+                            break;
+                        }
+                    },
+                    // the client disconnected, which the runs find out for themselves
+                    _ = tx.closed() => break,
+                };
+
+                match data_run(req, deadline, high_allowed) {
+                    Ok((data, pipeline, options)) => {
+                        let scheduler = scheduler.clone();
+                        let tx = tx.clone();
+                        tokio::spawn(async move {
+                            let cancel = scheduler.disconnect_token(&options);
+                            let start = scheduler.validate_data(data, &pipeline, options);
+                            forward_tagged(index, start, cancel, tx).await;
+                        });
+                    }
+                    Err(status) => {
+                        if tx.send((index, Err(status))).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                index += 1;
+            }
+        });
+
+        // when the client disconnects, the stream and so the receiver are dropped, which
+        // cancels the runs
+        let output_stream = ReceiverStream::new(rx).map(|(index, result)| {
+            let (response, error) = tagged_response(result);
+            Ok(ValidateDataResponse {
+                index: index as u32,
+                response,
+                error,
+            })
+        });
+        Ok(Response::new(
+            Box::pin(output_stream) as Self::ValidateDataStream
+        ))
+    }
+
+    #[tracing::instrument]
+    async fn list_pipelines(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<ListPipelinesResponse>, Status> {
+        Ok(Response::new(ListPipelinesResponse {
+            pipelines: self.list_pipelines().into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    #[tracing::instrument]
+    async fn get_pipeline(
+        &self,
+        request: Request<GetPipelineRequest>,
+    ) -> Result<Response<GetPipelineResponse>, Status> {
+        let name = request.into_inner().name;
+        let pipeline = self.get_pipeline(&name).map_err(Into::<Status>::into)?;
+
+        Ok(Response::new(GetPipelineResponse {
+            metadata: Some(
+                self.pipeline_metadata(&name)
+                    .map_err(Into::<Status>::into)?
+                    .into(),
+            ),
+            config: pipeline.to_json(),
+        }))
+    }
+
+    #[tracing::instrument]
+    async fn get_run(
+        &self,
+        request: Request<GetRunRequest>,
+    ) -> Result<Response<pb::RunStatus>, Status> {
+        let run_id = request.into_inner().run_id;
+
+        let status = self
+            .run_status(parse_run_id(&run_id)?)
+            .ok_or(Status::not_found(format!("run {} not recognised", run_id)))?;
+
+        Ok(Response::new(status.into()))
+    }
+
+    #[tracing::instrument]
+    async fn list_runs(&self, _request: Request<()>) -> Result<Response<ListRunsResponse>, Status> {
+        Ok(Response::new(ListRunsResponse {
+            runs: self.list_runs().into_iter().map(Into::into).collect(),
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl RoveAdmin for Scheduler {
+    #[tracing::instrument]
+    async fn register_pipeline(
+        &self,
+        request: Request<RegisterPipelineRequest>,
+    ) -> Result<Response<PipelineMetadata>, Status> {
+        let req = request.into_inner();
+
+        let pipeline = match PipelineFormat::from_i32(req.format) {
+            Some(PipelineFormat::Toml) => Pipeline::from_toml(&req.config),
+            Some(PipelineFormat::Json) => Pipeline::from_json(&req.config),
+            None => return Err(Status::invalid_argument("unrecognised pipeline format")),
+        }
+        .map_err(|e| Status::invalid_argument(format!("invalid pipeline: {}", e)))?;
+
+        // parsing already validated the pipeline
+        self.insert_pipeline(req.name.clone(), pipeline);
+        tracing::info!(message = "Registered pipeline.", name = %req.name);
+
         Ok(Response::new(
-            Box::pin(output_stream) as Self::ValidateStream
+            self.pipeline_metadata(&req.name)
+                .map_err(Into::<Status>::into)?
+                .into(),
         ))
     }
+
+    #[tracing::instrument]
+    async fn remove_pipeline(
+        &self,
+        request: Request<RemovePipelineRequest>,
+    ) -> Result<Response<()>, Status> {
+        let name = request.into_inner().name;
+
+        self.remove_pipeline(&name)
+            .ok_or(Status::not_found(format!(
+                "pipeline {} not recognised",
+                name
+            )))?;
+        tracing::info!(message = "Removed pipeline.", %name);
+
+        Ok(Response::new(()))
+    }
+}
+
+#[tonic::async_trait]
+impl RoveRunner for Scheduler {
+    #[tracing::instrument(skip(request))]
+    async fn run_step(
+        &self,
+        request: Request<RunStepRequest>,
+    ) -> Result<Response<ValidateResponse>, Status> {
+        let (step, cache, backing_data) = runner::decode_request(request.into_inner())
+            .map_err(|e| Status::invalid_argument(format!("invalid run step request: {}", e)))?;
+        tracing::debug!(message = "Running step for a remote scheduler.", step = %step.name);
+
+        // checks are CPU-bound, so are kept off the async workers
+        let response =
+            tokio::task::spawn_blocking(move || harness::run_test(&step, &cache, &backing_data))
+                .await
+                .map_err(|e| Status::internal(format!("runner task failed: {}", e)))?
+                .map_err(|e| Status::aborted(format!("failed to run test: {}", e)))?;
+
+        Ok(Response::new(response))
+    }
+}
+
+/// Interceptor rejecting requests that don't carry `token` as a bearer token, `kind` being what
+/// the token is for, e.g. "admin"
+// the signature is dictated by tonic's Interceptor trait
+#[allow(clippy::result_large_err)]
+fn check_token(
+    token: String,
+    kind: &'static str,
+) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+    let expected = format!("Bearer {}", token);
+    move |request: Request<()>| match request.metadata().get("authorization") {
+        Some(value) if constant_time_eq(value.as_bytes(), expected.as_bytes()) => Ok(request),
+        _ => Err(Status::unauthenticated(format!(
+            "missing or invalid {} token",
+            kind
+        ))),
+    }
+}
+
+/// Interceptor marking requests that carry `admin_token` as a bearer token as allowed to ask for
+/// high priority. Other requests are let through unmarked
+// the signature is dictated by tonic's Interceptor trait
+#[allow(clippy::result_large_err)]
+fn allow_high_priority(
+    admin_token: Option<String>,
+) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+    let expected = admin_token.map(|admin_token| format!("Bearer {}", admin_token));
+    move |mut request: Request<()>| {
+        let authorized = match (&expected, request.metadata().get("authorization")) {
+            (Some(expected), Some(value)) => {
+                constant_time_eq(value.as_bytes(), expected.as_bytes())
+            }
+            _ => false,
+        };
+        if authorized {
+            request.extensions_mut().insert(HighPriorityAllowed);
+        }
+        Ok(request)
+    }
+}
+
+/// Compare two byte strings in time that depends only on their lengths, so timing the
+/// comparison doesn't reveal how much of a secret was guessed right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && std::hint::black_box(a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b))) == 0
 }
 
 async fn start_server_inner(
     listener: ListenerType,
-    data_switch: DataSwitch<'static>,
-    pipelines: HashMap<String, Pipeline>,
+    rove_service: Scheduler,
+    config: ServerConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let rove_service = Scheduler::new(pipelines, data_switch);
+    let rove_server = RoveServer::with_interceptor(
+        rove_service.clone(),
+        allow_high_priority(config.admin_token.clone()),
+    );
+    // the admin service shares the scheduler's pipelines, so changes are visible to the
+    // main service
+    let admin_service = config.admin_token.map(|admin_token| {
+        RoveAdminServer::with_interceptor(rove_service.clone(), check_token(admin_token, "admin"))
+    });
+    let runner_service = config.runner_token.map(|runner_token| {
+        RoveRunnerServer::with_interceptor(
+            rove_service.clone(),
+            check_token(runner_token, "runner"),
+        )
+    });
+
+    let builder = config.transport.apply(Server::builder());
+    #[cfg(feature = "tls")]
+    let builder = match config.tls {
+        Some(tls) => builder.tls_config(tls)?,
+        None => builder,
+    };
+    let shutdown = config.shutdown.unwrap_or_default();
+    let signal_task = tokio::spawn(shutdown::cancel_on_signal(shutdown.clone())?);
+    let mut builder = builder
+        .layer(DrainLayer::new(shutdown.clone()))
+        .layer(MessageLimitLayer::new(&config.transport))
+        .layer(ClientLimitLayer::new(config.client_limits));
 
-    match listener {
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    // not ready until the first check says otherwise
+    health_reporter
+        .set_not_serving::<RoveServer<Scheduler>>()
+        .await;
+    health_reporter
+        .set_service_status("", ServingStatus::NotServing)
+        .await;
+    let health_task = tokio::spawn(report_health(
+        rove_service,
+        health_reporter,
+        config.check_data_source_health,
+    ));
+
+    let result = match listener {
         ListenerType::Addr(addr) => {
             tracing::info!(message = "Starting server.", %addr);
 
-            Server::builder()
+            let serve = builder
                 .trace_fn(|_| tracing::info_span!("helloworld_server"))
-                .add_service(RoveServer::new(rove_service))
-                .serve(addr)
-                .await?;
+                .add_service(health_service)
+                .add_service(rove_server)
+                .add_optional_service(runner_service)
+                .add_optional_service(admin_service)
+                .serve_with_shutdown(addr, shutdown.clone().cancelled_owned());
+            shutdown::drain(serve, &shutdown, config.shutdown_grace_period).await
         }
         ListenerType::UnixListener(stream) => {
-            Server::builder()
-                .add_service(RoveServer::new(rove_service))
-                .serve_with_incoming(stream)
-                .await?;
+            let serve = builder
+                .add_service(health_service)
+                .add_service(rove_server)
+                .add_optional_service(runner_service)
+                .add_optional_service(admin_service)
+                .serve_with_incoming_shutdown(stream, shutdown.clone().cancelled_owned());
+            shutdown::drain(serve, &shutdown, config.shutdown_grace_period).await
         }
-    }
+    };
+    health_task.abort();
+    signal_task.abort();
+    result?;
 
     Ok(())
 }
 
+// how often the server rechecks whether it's ready, for its health service
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Keep the health service's status of the Rove service, and of the server as a whole, up to
+/// date with whether `scheduler` has pipelines to run, and if `check_data_sources`, whether its
+/// data sources are healthy
+async fn report_health(
+    scheduler: Scheduler,
+    mut reporter: HealthReporter,
+    check_data_sources: bool,
+) {
+    let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let ready = match check_data_sources {
+            true => scheduler.is_ready().await,
+            false => scheduler.has_pipelines(),
+        };
+        let status = match ready {
+            true => {
+                reporter.set_serving::<RoveServer<Scheduler>>().await;
+                ServingStatus::Serving
+            }
+            false => {
+                reporter.set_not_serving::<RoveServer<Scheduler>>().await;
+                ServingStatus::NotServing
+            }
+        };
+        reporter.set_service_status("", status).await;
+    }
+}
+
 /// Equivalent to `start_server`, but using a unix listener instead of listening
 /// on a socket, to enable more deterministic integration testing.
 #[doc(hidden)]
 pub async fn start_server_unix_listener(
     stream: UnixListenerStream,
-    data_switch: DataSwitch<'static>,
+    data_switch: DataSwitch,
     pipelines: HashMap<String, Pipeline>,
+    config: ServerConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    start_server_inner(ListenerType::UnixListener(stream), data_switch, pipelines).await
+    start_server_inner(
+        ListenerType::UnixListener(stream),
+        Scheduler::new(pipelines, data_switch),
+        config,
+    )
+    .await
 }
 
 /// Starts up a gRPC server to process QC run requests
@@ -168,10 +876,168 @@ pub async fn start_server_unix_listener(
 /// Takes a [socket address](std::net::SocketAddr) to listen on, a
 /// [data switch](DataSwitch) to provide access to data sources, and a hashmap
 /// of pipelines of checks that can be run on data, keyed by their names.
+///
+/// The server also serves the standard
+/// [gRPC health checking service](https://github.com/grpc/grpc/blob/master/doc/health-checking.md),
+/// for readiness probes. Both the `rove.Rove` service and the server as a whole are reported as
+/// `NOT_SERVING` while the server has no pipelines, and `SERVING` otherwise. Data sources'
+/// health can be taken into account too, see
+/// [`ServerConfig::check_data_source_health`].
+///
+/// On SIGTERM or SIGINT, the server shuts down gracefully: it stops accepting new requests,
+/// refusing them with `UNAVAILABLE`, and returns once the validation runs in flight have sent
+/// all their results.
 pub async fn start_server(
     addr: SocketAddr,
-    data_switch: DataSwitch<'static>,
+    data_switch: DataSwitch,
+    pipelines: HashMap<String, Pipeline>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    start_server_inner(
+        ListenerType::Addr(addr),
+        Scheduler::new(pipelines, data_switch),
+        ServerConfig::default(),
+    )
+    .await
+}
+
+/// Equivalent to [`start_server`], but also serving the admin API, which allows pipelines to be
+/// registered and removed at runtime
+///
+/// Requests to the admin API must carry `admin_token` in their `authorization` metadata, as
+/// `Bearer <admin_token>`.
+pub async fn start_server_with_admin(
+    addr: SocketAddr,
+    data_switch: DataSwitch,
+    pipelines: HashMap<String, Pipeline>,
+    admin_token: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    start_server_with_config(
+        addr,
+        data_switch,
+        pipelines,
+        ServerConfig {
+            admin_token: Some(admin_token),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Equivalent to [`start_server`], but configured by `config`, e.g. to serve the admin API, to
+/// serve over TLS, or to shut down on a signal of the application's own
+///
+/// TLS needs the `tls` feature.
+pub async fn start_server_with_config(
+    addr: SocketAddr,
+    data_switch: DataSwitch,
     pipelines: HashMap<String, Pipeline>,
+    config: ServerConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    start_server_inner(ListenerType::Addr(addr), data_switch, pipelines).await
+    start_server_inner(
+        ListenerType::Addr(addr),
+        Scheduler::new(pipelines, data_switch),
+        config,
+    )
+    .await
+}
+
+/// Equivalent to [`start_server_with_config`], but serving an existing [`Scheduler`]
+///
+/// This lets the scheduler be configured beyond its defaults, and shared with whatever else the
+/// application runs on it, like [periodic jobs](crate::periodic), so they all see the same
+/// pipelines and share its limits.
+pub async fn start_server_with_scheduler(
+    addr: SocketAddr,
+    scheduler: Scheduler,
+    config: ServerConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    start_server_inner(ListenerType::Addr(addr), scheduler, config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"Bearer hunter2", b"Bearer hunter2"));
+        assert!(!constant_time_eq(b"Bearer hunter3", b"Bearer hunter2"));
+        assert!(!constant_time_eq(b"Bearer hunter", b"Bearer hunter2"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn test_allow_high_priority() {
+        let high_allowed = |admin_token: Option<&str>, authorization: Option<&str>| {
+            let mut request = Request::new(());
+            if let Some(authorization) = authorization {
+                request
+                    .metadata_mut()
+                    .insert("authorization", authorization.parse().unwrap());
+            }
+            let request = allow_high_priority(admin_token.map(String::from))(request).unwrap();
+            request.extensions().get::<HighPriorityAllowed>().is_some()
+        };
+        assert!(high_allowed(Some("hunter2"), Some("Bearer hunter2")));
+        assert!(!high_allowed(Some("hunter2"), Some("Bearer hunter3")));
+        assert!(!high_allowed(Some("hunter2"), None));
+        assert!(!high_allowed(None, Some("Bearer hunter2")));
+
+        let high = pb::Priority::High as i32;
+        assert_eq!(parse_priority(high, true).unwrap(), Priority::High);
+        assert_eq!(
+            parse_priority(high, false).unwrap_err().code(),
+            tonic::Code::PermissionDenied
+        );
+        assert_eq!(
+            parse_priority(pb::Priority::Low as i32, false).unwrap(),
+            Priority::Low
+        );
+    }
+
+    #[test]
+    fn test_parse_grpc_timeout() {
+        let parse = |timeout: Option<&str>| {
+            let mut metadata = MetadataMap::new();
+            if let Some(timeout) = timeout {
+                metadata.insert("grpc-timeout", timeout.parse().unwrap());
+            }
+            parse_grpc_timeout(&metadata)
+        };
+
+        assert_eq!(parse(None).unwrap(), None);
+        for (timeout, expected) in [
+            ("2H", Duration::from_secs(2 * 60 * 60)),
+            ("3M", Duration::from_secs(3 * 60)),
+            ("5S", Duration::from_secs(5)),
+            ("250m", Duration::from_millis(250)),
+            ("100u", Duration::from_micros(100)),
+            ("7n", Duration::from_nanos(7)),
+            ("0S", Duration::ZERO),
+            ("99999999H", Duration::from_secs(99999999 * 60 * 60)),
+        ] {
+            assert_eq!(parse(Some(timeout)).unwrap(), Some(expected), "{}", timeout);
+        }
+
+        for timeout in [
+            "",
+            "S",
+            "5",
+            "5s",
+            "5x",
+            "-5S",
+            "+5S",
+            "5.5S",
+            " 5S",
+            // more than 8 digits
+            "123456789S",
+        ] {
+            assert_eq!(
+                parse(Some(timeout)).unwrap_err().code(),
+                tonic::Code::InvalidArgument,
+                "{}",
+                timeout
+            );
+        }
+    }
 }
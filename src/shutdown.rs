@@ -0,0 +1,212 @@
+use crate::transport::status_trailers;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use tonic::{
+    body::BoxBody,
+    codegen::{
+        http::{HeaderMap, Request, Response},
+        Body, Bytes,
+    },
+    Status,
+};
+use tower::{Layer, Service};
+
+/// Cancel `shutdown` when the process gets SIGTERM or SIGINT
+///
+/// The signal handlers are installed straight away, so signals that arrive before the returned
+/// future is first polled aren't missed.
+pub(crate) fn cancel_on_signal(
+    shutdown: CancellationToken,
+) -> std::io::Result<impl Future<Output = ()>> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    Ok(async move {
+        tokio::select! {
+            _ = sigterm.recv() => tracing::info!("Got SIGTERM."),
+            _ = sigint.recv() => tracing::info!("Got SIGINT."),
+            // shut down some other way, so there's nothing left to do
+            _ = shutdown.cancelled() => return,
+        }
+        shutdown.cancel();
+    })
+}
+
+/// Wait for `serve` to finish, which it does by draining once `shutdown` is cancelled. If
+/// draining takes longer than `grace_period`, stop waiting for the requests still in flight
+pub(crate) async fn drain<E>(
+    serve: impl Future<Output = Result<(), E>>,
+    shutdown: &CancellationToken,
+    grace_period: Option<Duration>,
+) -> Result<(), E> {
+    tokio::pin!(serve);
+    tokio::select! {
+        result = &mut serve => return result,
+        _ = shutdown.cancelled() => {}
+    }
+
+    tracing::info!("Shutting down, waiting for requests in flight to finish.");
+    match grace_period {
+        None => serve.await,
+        Some(grace_period) => match tokio::time::timeout(grace_period, serve).await {
+            Ok(result) => result,
+            Err(_) => {
+                tracing::warn!(
+                    ?grace_period,
+                    "Requests were still in flight at the end of the shutdown grace period."
+                );
+                Ok(())
+            }
+        },
+    }
+}
+
+const HEALTH_WATCH_PATH: &str = "/grpc.health.v1.Health/Watch";
+
+/// Tower layer that refuses new requests once `shutdown` is cancelled, with `UNAVAILABLE`, so
+/// clients retry them on another server while the requests in flight are drained
+///
+/// Health watches never finish by themselves, so they are ended with `UNAVAILABLE` too, rather
+/// than holding up the drain.
+#[derive(Debug, Clone)]
+pub(crate) struct DrainLayer {
+    shutdown: CancellationToken,
+}
+
+impl DrainLayer {
+    pub fn new(shutdown: CancellationToken) -> Self {
+        DrainLayer { shutdown }
+    }
+}
+
+impl<S> Layer<S> for DrainLayer {
+    type Service = Drain<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Drain {
+            inner,
+            shutdown: self.shutdown.clone(),
+        }
+    }
+}
+
+/// See [`DrainLayer`]
+#[derive(Debug, Clone)]
+pub(crate) struct Drain<S> {
+    inner: S,
+    shutdown: CancellationToken,
+}
+
+fn shutting_down() -> Status {
+    Status::unavailable("server is shutting down")
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for Drain<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        if self.shutdown.is_cancelled() {
+            return Box::pin(std::future::ready(Ok(shutting_down().to_http())));
+        }
+
+        let is_watch = request.uri().path() == HEALTH_WATCH_PATH;
+        let response = self.inner.call(request);
+        if !is_watch {
+            return Box::pin(response);
+        }
+        let shutdown = self.shutdown.clone();
+        Box::pin(async move {
+            Ok(response.await?.map(|body| {
+                DrainBody {
+                    inner: body,
+                    shutdown: Box::pin(shutdown.cancelled_owned()),
+                    shut_down: false,
+                }
+                .boxed_unsync()
+            }))
+        })
+    }
+}
+
+/// A response body that ends, with `UNAVAILABLE`, once the server starts shutting down
+struct DrainBody {
+    inner: BoxBody,
+    shutdown: Pin<Box<WaitForCancellationFutureOwned>>,
+    shut_down: bool,
+}
+
+impl Body for DrainBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if !self.shut_down && self.shutdown.as_mut().poll(cx).is_ready() {
+            self.shut_down = true;
+        }
+        if self.shut_down {
+            return Poll::Ready(None);
+        }
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        if self.shut_down {
+            return Poll::Ready(Ok(Some(status_trailers(shutting_down()))));
+        }
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        !self.shut_down && self.inner.is_end_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain() {
+        let shutdown = CancellationToken::new();
+
+        // finishes by itself
+        assert_eq!(
+            drain(async { Ok::<_, ()>(()) }, &shutdown, None).await,
+            Ok(())
+        );
+
+        // stops waiting once the grace period runs out
+        shutdown.cancel();
+        let result = tokio::time::timeout(
+            Duration::from_secs(1),
+            drain(
+                std::future::pending::<Result<(), ()>>(),
+                &shutdown,
+                Some(Duration::from_millis(10)),
+            ),
+        )
+        .await;
+        assert_eq!(result, Ok(Ok(())));
+    }
+}
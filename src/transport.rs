@@ -0,0 +1,325 @@
+use futures::StreamExt;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tonic::{
+    body::BoxBody,
+    codegen::{
+        http::{header::CONTENT_TYPE, HeaderMap, Request, Response},
+        Body as HttpBody, Bytes,
+    },
+    transport::{Body, Server},
+    Status,
+};
+use tower::{Layer, Service};
+
+/// Tuning of the server's HTTP/2 transport, see
+/// [`ServerConfig::transport`](crate::ServerConfig::transport)
+///
+/// The defaults are tonic's own.
+#[derive(Debug, Clone)]
+pub struct TransportConfig {
+    /// Largest message, in bytes as sent over the wire, clients may send, or no limit if None.
+    /// Requests with larger messages fail with `RESOURCE_EXHAUSTED`
+    pub max_decoding_message_size: Option<usize>,
+    /// Largest message, in bytes as sent over the wire, the server may send, or no limit if
+    /// None. Responses stop with `RESOURCE_EXHAUSTED` at the first larger message
+    ///
+    /// Most gRPC clients refuse messages over 4MiB by default, which results for large spatial
+    /// runs can go over. Setting this to the clients' limit gives them a clearer error, though
+    /// the lasting fix is to raise their limit, or split results with
+    /// [tiling](crate::Scheduler::with_tiling).
+    pub max_encoding_message_size: Option<usize>,
+    /// Whether to set `TCP_NODELAY` on accepted connections
+    pub tcp_nodelay: bool,
+    /// How long connections sit idle before TCP keepalive probes are sent, or no probes if None
+    pub tcp_keepalive: Option<Duration>,
+    /// How often to ping connections with HTTP/2 pings, or no pings if None
+    pub http2_keepalive_interval: Option<Duration>,
+    /// How long to wait for HTTP/2 pings to be acknowledged before closing the connection, or
+    /// tonic's default of 20 seconds if None
+    pub http2_keepalive_timeout: Option<Duration>,
+    /// Maximum number of concurrent HTTP/2 streams, and so requests, per connection clients are
+    /// told they may open, or no limit if None
+    pub max_concurrent_streams: Option<u32>,
+    /// Maximum number of requests per connection handled at once, or no limit if None. Further
+    /// requests wait their turn, unlike [`max_concurrent_streams`](Self::max_concurrent_streams)
+    pub concurrency_limit_per_connection: Option<usize>,
+    /// HTTP/2 stream-level flow control window, in bytes, or the HTTP/2 default if None
+    pub initial_stream_window_size: Option<u32>,
+    /// HTTP/2 connection-level flow control window, in bytes, or the HTTP/2 default if None
+    pub initial_connection_window_size: Option<u32>,
+    /// Maximum HTTP/2 frame size, in bytes, or the HTTP/2 default if None
+    pub max_frame_size: Option<u32>,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        TransportConfig {
+            max_decoding_message_size: None,
+            max_encoding_message_size: None,
+            tcp_nodelay: true,
+            tcp_keepalive: None,
+            http2_keepalive_interval: None,
+            http2_keepalive_timeout: None,
+            max_concurrent_streams: None,
+            concurrency_limit_per_connection: None,
+            initial_stream_window_size: None,
+            initial_connection_window_size: None,
+            max_frame_size: None,
+        }
+    }
+}
+
+impl TransportConfig {
+    /// Apply the settings tonic has builder methods for to `builder`. Message sizes need a
+    /// [`MessageLimitLayer`] on top
+    pub(crate) fn apply<L>(&self, builder: Server<L>) -> Server<L> {
+        let mut builder = builder
+            .tcp_nodelay(self.tcp_nodelay)
+            .tcp_keepalive(self.tcp_keepalive)
+            .http2_keepalive_interval(self.http2_keepalive_interval)
+            .http2_keepalive_timeout(self.http2_keepalive_timeout)
+            .max_concurrent_streams(self.max_concurrent_streams)
+            .initial_stream_window_size(self.initial_stream_window_size)
+            .initial_connection_window_size(self.initial_connection_window_size)
+            .max_frame_size(self.max_frame_size);
+        if let Some(limit) = self.concurrency_limit_per_connection {
+            builder = builder.concurrency_limit_per_connection(limit);
+        }
+        builder
+    }
+}
+
+/// The trailers that end a response with `status`
+pub(crate) fn status_trailers(status: Status) -> HeaderMap {
+    // the status's own headers, without the ones only meant for the start of a response
+    let mut trailers = status.to_http().into_parts().0.headers;
+    trailers.remove(CONTENT_TYPE);
+    trailers
+}
+
+// length of the prefix before each gRPC message, a compression flag then a u32 length
+const PREFIX_LEN: usize = 5;
+
+/// Follows the gRPC messages in a body as it's streamed, to check their lengths without
+/// waiting for the whole message
+#[derive(Debug)]
+struct Messages {
+    max_len: usize,
+    prefix: [u8; PREFIX_LEN],
+    prefix_read: usize,
+    // of the current message
+    remaining: usize,
+}
+
+impl Messages {
+    fn new(max_len: usize) -> Self {
+        Messages {
+            max_len,
+            prefix: [0; PREFIX_LEN],
+            prefix_read: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Read the next `chunk` of the body, returning the length of the first message that starts
+    /// in it and is over the limit, if any
+    fn check(&mut self, mut chunk: &[u8]) -> Result<(), usize> {
+        while !chunk.is_empty() {
+            if self.remaining > 0 {
+                let read = self.remaining.min(chunk.len());
+                self.remaining -= read;
+                chunk = &chunk[read..];
+                continue;
+            }
+
+            let read = (PREFIX_LEN - self.prefix_read).min(chunk.len());
+            self.prefix[self.prefix_read..self.prefix_read + read].copy_from_slice(&chunk[..read]);
+            self.prefix_read += read;
+            chunk = &chunk[read..];
+            if self.prefix_read == PREFIX_LEN {
+                self.prefix_read = 0;
+                let len = u32::from_be_bytes([
+                    self.prefix[1],
+                    self.prefix[2],
+                    self.prefix[3],
+                    self.prefix[4],
+                ]) as usize;
+                if len > self.max_len {
+                    return Err(len);
+                }
+                self.remaining = len;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn too_large(direction: &str, len: usize, max_len: usize) -> Status {
+    Status::resource_exhausted(format!(
+        "{} message of {} bytes is over the limit of {} bytes",
+        direction, len, max_len
+    ))
+}
+
+/// Tower layer enforcing [`TransportConfig`]'s message size limits, which tonic doesn't have
+/// settings for
+#[derive(Debug, Clone)]
+pub(crate) struct MessageLimitLayer {
+    max_decoding_message_size: Option<usize>,
+    max_encoding_message_size: Option<usize>,
+}
+
+impl MessageLimitLayer {
+    pub fn new(config: &TransportConfig) -> Self {
+        MessageLimitLayer {
+            max_decoding_message_size: config.max_decoding_message_size,
+            max_encoding_message_size: config.max_encoding_message_size,
+        }
+    }
+}
+
+impl<S> Layer<S> for MessageLimitLayer {
+    type Service = MessageLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MessageLimit {
+            inner,
+            max_decoding_message_size: self.max_decoding_message_size,
+            max_encoding_message_size: self.max_encoding_message_size,
+        }
+    }
+}
+
+/// See [`MessageLimitLayer`]
+#[derive(Debug, Clone)]
+pub(crate) struct MessageLimit<S> {
+    inner: S,
+    max_decoding_message_size: Option<usize>,
+    max_encoding_message_size: Option<usize>,
+}
+
+impl<S> Service<Request<Body>> for MessageLimit<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let request = match self.max_decoding_message_size {
+            Some(max_len) => request.map(|body| {
+                let mut messages = Messages::new(max_len);
+                // the error reaches the service's handler, which fails the request with it
+                Body::wrap_stream(body.map(move |chunk| {
+                    let chunk = chunk?;
+                    messages
+                        .check(&chunk)
+                        .map_err(|len| too_large("request", len, max_len))?;
+                    Ok::<_, Box<dyn std::error::Error + Send + Sync>>(chunk)
+                }))
+            }),
+            None => request,
+        };
+
+        let response = self.inner.call(request);
+        let max_len = match self.max_encoding_message_size {
+            Some(max_len) => max_len,
+            None => return Box::pin(response),
+        };
+        Box::pin(async move {
+            Ok(response.await?.map(|body| {
+                LimitedBody {
+                    inner: body,
+                    messages: Messages::new(max_len),
+                    failed: false,
+                    error: None,
+                }
+                .boxed_unsync()
+            }))
+        })
+    }
+}
+
+/// A response body that ends with `RESOURCE_EXHAUSTED` at the first message over the limit
+struct LimitedBody {
+    inner: BoxBody,
+    messages: Messages,
+    failed: bool,
+    error: Option<Status>,
+}
+
+impl HttpBody for LimitedBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.failed {
+            return Poll::Ready(None);
+        }
+        let data = match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(data))) => data,
+            other => return other,
+        };
+        if let Err(len) = self.messages.check(&data) {
+            let status = too_large("response", len, self.messages.max_len);
+            tracing::warn!(%status, "Ended response with a message over the size limit.");
+            self.failed = true;
+            self.error = Some(status);
+            return Poll::Ready(None);
+        }
+        Poll::Ready(Some(Ok(data)))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        if self.failed {
+            return Poll::Ready(Ok(self.error.take().map(status_trailers)));
+        }
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        !self.failed && self.inner.is_end_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(len: u32) -> Vec<u8> {
+        let mut message = vec![0];
+        message.extend(len.to_be_bytes());
+        message.extend(vec![1; len as usize]);
+        message
+    }
+
+    #[test]
+    fn test_messages() {
+        let mut messages = Messages::new(4);
+
+        // messages split across chunks, including in their prefix
+        let body = [message(4), message(0), message(3)].concat();
+        for chunk in body.chunks(3) {
+            assert_eq!(messages.check(chunk), Ok(()));
+        }
+
+        assert_eq!(messages.check(&message(5)), Err(5));
+    }
+}
@@ -0,0 +1,130 @@
+//! Conversions between the units data sources report data in
+//!
+//! Units are identified by the strings used by [Frost](https://frost.met.no), e.g. `degC`, `mm`
+//! and `m/s`, with a few aliases. Each unit is a linear transformation of a base unit of its
+//! quantity, so any two units of the same quantity can be converted between.
+
+use thiserror::Error;
+
+/// Error type for unit conversions
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The unit is not in the conversion table
+    #[error("unknown unit `{0}`")]
+    UnknownUnit(String),
+    /// The units measure different quantities
+    #[error("cannot convert from `{0}` to `{1}`, they measure different quantities")]
+    Incompatible(String, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quantity {
+    Temperature,
+    Length,
+    Pressure,
+    Speed,
+    Fraction,
+}
+
+/// Quantity a unit measures, and the scale and offset taking a value in it to the base unit of
+/// that quantity
+fn lookup(unit: &str) -> Option<(Quantity, f64, f64)> {
+    use Quantity::*;
+
+    Some(match unit {
+        "degC" | "°C" | "Cel" => (Temperature, 1., 0.),
+        // tenths of a degree, as some sources store temperatures as integers
+        "0.1degC" => (Temperature, 0.1, 0.),
+        "K" => (Temperature, 1., -273.15),
+        "degF" | "°F" => (Temperature, 5. / 9., -32. * 5. / 9.),
+        "mm" => (Length, 1., 0.),
+        "0.1mm" => (Length, 0.1, 0.),
+        "cm" => (Length, 10., 0.),
+        "m" => (Length, 1000., 0.),
+        "hPa" => (Pressure, 1., 0.),
+        "Pa" => (Pressure, 0.01, 0.),
+        "kPa" => (Pressure, 10., 0.),
+        "m/s" => (Speed, 1., 0.),
+        "km/h" => (Speed, 1. / 3.6, 0.),
+        "knots" | "kn" => (Speed, 1852. / 3600., 0.),
+        "percent" | "%" => (Fraction, 1., 0.),
+        "1" => (Fraction, 100., 0.),
+        _ => return None,
+    })
+}
+
+/// Whether `unit` is in the conversion table
+pub fn is_known(unit: &str) -> bool {
+    lookup(unit).is_some()
+}
+
+/// A linear conversion between two units, see [`conversion`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conversion {
+    scale: f64,
+    offset: f64,
+}
+
+impl Conversion {
+    /// Convert a value
+    pub fn apply(&self, value: f32) -> f32 {
+        (f64::from(value) * self.scale + self.offset) as f32
+    }
+
+    /// Whether the conversion leaves values unchanged
+    pub fn is_identity(&self) -> bool {
+        self.scale == 1. && self.offset == 0.
+    }
+}
+
+/// The conversion from values in unit `from` to unit `to`
+///
+/// # Errors
+///
+/// If either unit is unknown, or they measure different quantities
+pub fn conversion(from: &str, to: &str) -> Result<Conversion, Error> {
+    let (from_quantity, from_scale, from_offset) =
+        lookup(from).ok_or_else(|| Error::UnknownUnit(from.to_string()))?;
+    let (to_quantity, to_scale, to_offset) =
+        lookup(to).ok_or_else(|| Error::UnknownUnit(to.to_string()))?;
+    if from_quantity != to_quantity {
+        return Err(Error::Incompatible(from.to_string(), to.to_string()));
+    }
+
+    // through the base unit: base = value * from_scale + from_offset, and
+    // converted = (base - to_offset) / to_scale
+    Ok(Conversion {
+        scale: from_scale / to_scale,
+        offset: (from_offset - to_offset) / to_scale,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(value: f32, from: &str, to: &str) -> f32 {
+        conversion(from, to).unwrap().apply(value)
+    }
+
+    #[test]
+    fn test_conversion() {
+        assert!((convert(273.15, "K", "degC") - 0.).abs() < 1e-4);
+        assert!((convert(20., "degC", "K") - 293.15).abs() < 1e-4);
+        assert!((convert(212., "degF", "K") - 373.15).abs() < 1e-3);
+        assert_eq!(convert(215., "0.1degC", "degC"), 21.5);
+        assert_eq!(convert(1.5, "m", "mm"), 1500.);
+        assert!((convert(36., "km/h", "m/s") - 10.).abs() < 1e-5);
+        assert!(conversion("degC", "degC").unwrap().is_identity());
+
+        assert_eq!(
+            conversion("degC", "mm"),
+            Err(Error::Incompatible("degC".to_string(), "mm".to_string()))
+        );
+        assert_eq!(
+            conversion("furlongs", "m"),
+            Err(Error::UnknownUnit("furlongs".to_string()))
+        );
+    }
+}
@@ -1,15 +1,34 @@
+use chronoutil::RelativeDuration;
 use core::future::Future;
-use pb::{rove_client::RoveClient, validate_request::SpaceSpec, Flag, ValidateRequest};
+use pb::{
+    rove_admin_client::RoveAdminClient, rove_client::RoveClient, validate_request::SpaceSpec,
+    DataSlice, Flag, GetPipelineRequest, PipelineFormat, RegisterPipelineRequest,
+    RemovePipelineRequest, Series, ValidateBatchRequest, ValidateDataRequest, ValidateRequest,
+};
 use rove::{
-    data_switch::{DataConnector, DataSwitch},
-    dev_utils::{construct_hardcoded_pipeline, TestDataSource},
-    start_server_unix_listener, Pipeline,
+    data_switch::{
+        self, DataCache, DataConnector, DataSwitch, MemoryConnector, Observation, TimeSpec,
+        Timestamp,
+    },
+    dev_utils::{
+        assert_fixture, construct_hardcoded_pipeline, run_fixture, Fixture, TestDataSource,
+    },
+    start_server_unix_listener, start_server_with_config, Pipeline, RunOptions, RunState,
+    Scheduler, ServerConfig, TransportConfig,
+};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use std::{collections::HashMap, sync::Arc};
 use tempfile::NamedTempFile;
 use tokio::net::{UnixListener, UnixStream};
 use tokio_stream::{wrappers::UnixListenerStream, StreamExt};
+use tokio_util::sync::CancellationToken;
 use tonic::transport::{Channel, Endpoint};
+use tonic_health::proto::{
+    health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest,
+};
 use tower::service_fn;
 
 mod pb {
@@ -18,18 +37,44 @@ mod pb {
 
 const DATA_LEN_SINGLE: usize = 3;
 const DATA_LEN_SPATIAL: usize = 1000;
+const ADMIN_TOKEN: &str = "test_token";
+const RUNNER_TOKEN: &str = "test_runner_token";
 
 pub async fn set_up_rove(
-    data_switch: DataSwitch<'static>,
+    data_switch: DataSwitch,
     pipelines: HashMap<String, Pipeline>,
-) -> (impl Future<Output = ()>, RoveClient<Channel>) {
+) -> (
+    impl Future<Output = ()>,
+    RoveClient<Channel>,
+    RoveAdminClient<Channel>,
+) {
+    let (coordinator_future, coordinator_channel) = set_up_rove_channel(
+        data_switch,
+        pipelines,
+        ServerConfig {
+            admin_token: Some(ADMIN_TOKEN.to_string()),
+            ..Default::default()
+        },
+    )
+    .await;
+    let client = RoveClient::new(coordinator_channel.clone());
+    let admin_client = RoveAdminClient::new(coordinator_channel);
+
+    (coordinator_future, client, admin_client)
+}
+
+pub async fn set_up_rove_channel(
+    data_switch: DataSwitch,
+    pipelines: HashMap<String, Pipeline>,
+    config: ServerConfig,
+) -> (impl Future<Output = ()>, Channel) {
     let coordintor_socket = NamedTempFile::new().unwrap();
     let coordintor_socket = Arc::new(coordintor_socket.into_temp_path());
     std::fs::remove_file(&*coordintor_socket).unwrap();
     let coordintor_uds = UnixListener::bind(&*coordintor_socket).unwrap();
     let coordintor_stream = UnixListenerStream::new(coordintor_uds);
     let coordinator_future = async {
-        start_server_unix_listener(coordintor_stream, data_switch, pipelines)
+        start_server_unix_listener(coordintor_stream, data_switch, pipelines, config)
             .await
             .unwrap();
     };
@@ -42,9 +87,8 @@ pub async fn set_up_rove(
         }))
         .await
         .unwrap();
-    let client = RoveClient::new(coordinator_channel);
 
-    (coordinator_future, client)
+    (coordinator_future, coordinator_channel)
 }
 
 // TODO: we should probably just use one of the sample pipelines here once we have the checks
@@ -53,27 +97,26 @@ pub async fn set_up_rove(
 async fn integration_test_hardcoded_pipeline() {
     let data_switch = DataSwitch::new(HashMap::from([(
         "test",
-        &TestDataSource {
+        Arc::new(TestDataSource {
             data_len_single: DATA_LEN_SINGLE,
             data_len_series: 1,
             data_len_spatial: DATA_LEN_SPATIAL,
-        } as &dyn DataConnector,
+        }) as Arc<dyn DataConnector + Send + Sync>,
     )]));
 
-    let (coordinator_future, mut client) =
+    let (coordinator_future, mut client, _) =
         set_up_rove(data_switch, construct_hardcoded_pipeline()).await;
 
     let requests_future = async {
         let mut stream = client
             .validate(ValidateRequest {
                 data_source: String::from("test"),
-                backing_sources: vec![],
                 start_time: Some(prost_types::Timestamp::default()),
                 end_time: Some(prost_types::Timestamp::default()),
                 time_resolution: String::from("PT5M"),
                 space_spec: Some(SpaceSpec::All(())),
                 pipeline: String::from("hardcoded"),
-                extra_spec: None,
+                ..Default::default()
             })
             .await
             .unwrap()
@@ -85,6 +128,8 @@ async fn integration_test_hardcoded_pipeline() {
         let mut sct_recv_count = 0;
         while let Some(recv) = stream.next().await {
             let inner = recv.unwrap();
+            assert_eq!(inner.pipeline.unwrap().name, "hardcoded");
+            assert!(inner.duration.is_some());
             match inner.test.as_ref() {
                 "spike_check" => {
                     spike_recv_count += 1;
@@ -119,3 +164,1311 @@ async fn integration_test_hardcoded_pipeline() {
         _ = requests_future => (),
     }
 }
+
+#[tokio::test]
+async fn integration_test_batch() {
+    let data_switch = DataSwitch::new(HashMap::from([(
+        "test",
+        Arc::new(TestDataSource {
+            data_len_single: DATA_LEN_SINGLE,
+            data_len_series: 1,
+            data_len_spatial: DATA_LEN_SPATIAL,
+        }) as Arc<dyn DataConnector + Send + Sync>,
+    )]));
+
+    let (coordinator_future, mut client, _) =
+        set_up_rove(data_switch, construct_hardcoded_pipeline()).await;
+
+    let requests_future = async {
+        let request = ValidateRequest {
+            data_source: String::from("test"),
+            start_time: Some(prost_types::Timestamp::default()),
+            end_time: Some(prost_types::Timestamp::default()),
+            time_resolution: String::from("PT5M"),
+            space_spec: Some(SpaceSpec::All(())),
+            pipeline: String::from("hardcoded"),
+            ..Default::default()
+        };
+        let mut stream = client
+            .validate_batch(ValidateBatchRequest {
+                requests: vec![
+                    request.clone(),
+                    ValidateRequest {
+                        data_source: String::from("missing"),
+                        ..request.clone()
+                    },
+                    request,
+                ],
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        let mut recv_counts = [0; 3];
+        while let Some(recv) = stream.next().await {
+            let inner = recv.unwrap();
+            let index = inner.index as usize;
+            if index == 1 {
+                assert!(inner.response.is_none());
+                assert!(inner.error.is_some());
+            } else {
+                assert!(inner.error.is_none());
+                assert_eq!(inner.response.unwrap().pipeline.unwrap().name, "hardcoded");
+            }
+            recv_counts[index] += 1;
+        }
+        // one response per step, or the error, which ends the failed request alone
+        assert_eq!(recv_counts, [4, 1, 4]);
+    };
+
+    tokio::select! {
+        _ = coordinator_future => panic!("coordinator returned first"),
+        _ = requests_future => (),
+    }
+}
+
+#[tokio::test]
+async fn integration_test_validate_data() {
+    // nothing is fetched, so the data switch doesn't need any sources
+    let (coordinator_future, mut client, _) = set_up_rove(
+        DataSwitch::new(HashMap::<String, Arc<dyn DataConnector + Send + Sync>>::new()),
+        construct_hardcoded_pipeline(),
+    )
+    .await;
+
+    let data = DataSlice {
+        lats: (0..DATA_LEN_SPATIAL)
+            .map(|i| ((i as f32).powi(2) * 0.001) % 3.)
+            .collect(),
+        lons: (0..DATA_LEN_SPATIAL)
+            .map(|i| ((i as f32 + 1.).powi(2) * 0.001) % 3.)
+            .collect(),
+        elevs: vec![1.; DATA_LEN_SPATIAL],
+        series: (0..DATA_LEN_SPATIAL)
+            .map(|i| Series {
+                identifier: i.to_string(),
+                values: vec![1.; 5],
+                present: vec![true; 5],
+                metadata: None,
+            })
+            .collect(),
+        start_time: Some(prost_types::Timestamp::default()),
+        period: String::from("PT5M"),
+        num_leading_points: 2,
+        num_trailing_points: 2,
+        params: HashMap::new(),
+    };
+    let request = ValidateDataRequest {
+        pipeline: String::from("hardcoded"),
+        data: Some(data.clone()),
+        ..Default::default()
+    };
+    let requests = vec![
+        request.clone(),
+        ValidateDataRequest {
+            data: Some(DataSlice {
+                lats: Vec::new(),
+                ..data
+            }),
+            ..request
+        },
+    ];
+
+    let requests_future = async {
+        let mut stream = client
+            .validate_data(tokio_stream::iter(requests))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let mut recv_counts = [0; 2];
+        while let Some(recv) = stream.next().await {
+            let inner = recv.unwrap();
+            let index = inner.index as usize;
+            if index == 1 {
+                let error = inner.error.unwrap();
+                assert_eq!(error.code, tonic::Code::InvalidArgument as i32);
+                assert!(error.message.starts_with("invalid data"));
+            } else {
+                let response = inner.response.unwrap();
+                assert_eq!(response.pipeline.unwrap().name, "hardcoded");
+                assert!(response
+                    .results
+                    .iter()
+                    .all(|result| result.flag != Flag::Invalid as i32));
+            }
+            recv_counts[index] += 1;
+        }
+        assert_eq!(recv_counts, [4, 1]);
+    };
+
+    tokio::select! {
+        _ = coordinator_future => panic!("coordinator returned first"),
+        _ = requests_future => (),
+    }
+}
+
+#[tokio::test]
+async fn integration_test_max_concurrent_steps() {
+    let data_switch = DataSwitch::new(HashMap::from([(
+        "test",
+        Arc::new(TestDataSource {
+            data_len_single: DATA_LEN_SINGLE,
+            data_len_series: 1,
+            data_len_spatial: DATA_LEN_SPATIAL,
+        }) as Arc<dyn DataConnector + Send + Sync>,
+    )]));
+    let scheduler =
+        Scheduler::new(construct_hardcoded_pipeline(), data_switch).with_max_concurrent_steps(1);
+
+    let mut rx = scheduler
+        .validate_direct(
+            "test",
+            &[] as &[&str],
+            &TimeSpec::new(Timestamp(0), Timestamp(0), RelativeDuration::minutes(5)),
+            &data_switch::SpaceSpec::All,
+            "hardcoded",
+            None,
+        )
+        .await
+        .unwrap();
+
+    // the steps are independent, so one at a time they come out in pipeline order
+    let mut tests = Vec::new();
+    while let Some(response) = rx.recv().await {
+        tests.push(response.unwrap().test);
+    }
+    assert_eq!(
+        tests,
+        vec!["step_check", "spike_check", "buddy_check", "sct"]
+    );
+}
+
+#[tokio::test]
+async fn integration_test_cancellation() {
+    let data_switch = DataSwitch::new(HashMap::from([(
+        "test",
+        Arc::new(TestDataSource {
+            data_len_single: DATA_LEN_SINGLE,
+            data_len_series: 1,
+            data_len_spatial: DATA_LEN_SPATIAL,
+        }) as Arc<dyn DataConnector + Send + Sync>,
+    )]));
+    let scheduler = Scheduler::new(construct_hardcoded_pipeline(), data_switch);
+    let cancel = CancellationToken::new();
+    cancel.cancel();
+    let options = RunOptions {
+        cancel,
+        ..Default::default()
+    };
+    let run_id = options.run_id;
+
+    let reused = scheduler
+        .validate_direct_with_options(
+            "test",
+            &[] as &[&str],
+            &TimeSpec::new(Timestamp(0), Timestamp(0), RelativeDuration::minutes(5)),
+            &data_switch::SpaceSpec::All,
+            "hardcoded",
+            None,
+            options,
+        )
+        .await;
+
+    assert_eq!(result.unwrap_err().to_string(), "run was cancelled");
+    assert_eq!(
+        scheduler.run_status(run_id).unwrap().state,
+        RunState::Cancelled
+    );
+}
+
+#[tokio::test]
+async fn integration_test_deadline() {
+    let data_switch = DataSwitch::new(HashMap::from([(
+        "test",
+        Arc::new(TestDataSource {
+            data_len_single: DATA_LEN_SINGLE,
+            data_len_series: 1,
+            data_len_spatial: DATA_LEN_SPATIAL,
+        }) as Arc<dyn DataConnector + Send + Sync>,
+    )]));
+    let scheduler = Scheduler::new(construct_hardcoded_pipeline(), data_switch);
+    let options = RunOptions {
+        deadline: Some(Instant::now()),
+        ..Default::default()
+    };
+    let run_id = options.run_id;
+
+    let reused = scheduler
+        .validate_direct_with_options(
+            "test",
+            &[] as &[&str],
+            &TimeSpec::new(Timestamp(0), Timestamp(0), RelativeDuration::minutes(5)),
+            &data_switch::SpaceSpec::All,
+            "hardcoded",
+            None,
+            options,
+        )
+        .await;
+
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "run did not finish before its deadline"
+    );
+    let status = scheduler.run_status(run_id).unwrap();
+    assert_eq!(status.state, RunState::Failed);
+    assert_eq!(
+        status.error.as_deref(),
+        Some("run did not finish before its deadline")
+    );
+}
+
+#[tokio::test]
+async fn integration_test_continue_on_error() {
+    let memory = MemoryConnector::new();
+    memory.insert_observations((0..3).map(|i| Observation {
+        series_id: "18700".to_string(),
+        lat: 59.94,
+        lon: 10.72,
+        elev: 94.,
+        time: Timestamp(i * 3600),
+        value: Some(i as f32 * 6.),
+    }));
+    let pipeline = Pipeline::from_toml(
+        r#"
+        [combi]
+
+        [[step]]
+        name = "remote_range_check"
+        backend = "grpc"
+        # nothing listens here, so this step always fails
+        endpoint = "http://127.0.0.1:1"
+        [step.range_check]
+        min = 0.0
+        max = 10.0
+
+        [[step]]
+        name = "range_check"
+        [step.range_check]
+        min = 0.0
+        max = 10.0
+        "#,
+    )
+    .unwrap();
+    let scheduler = Scheduler::new(
+        HashMap::from([("remote".to_string(), pipeline)]),
+        DataSwitch::new([(
+            "memory",
+            Arc::new(memory) as Arc<dyn DataConnector + Send + Sync>,
+        )]),
+    );
+
+    let options = RunOptions {
+        continue_on_error: true,
+        ..Default::default()
+    };
+    let run_id = options.run_id;
+    let mut rx = scheduler
+        .validate_direct_with_options(
+            "memory",
+            &[] as &[&str],
+            &TimeSpec::new(Timestamp(0), Timestamp(7200), RelativeDuration::hours(1)),
+            &data_switch::SpaceSpec::All,
+            "remote",
+            None,
+            options,
+        )
+        .await
+        .unwrap();
+
+    let mut responses = Vec::new();
+    while let Some(response) = rx.recv().await {
+        responses.push(response.unwrap());
+    }
+    assert_eq!(responses.len(), 3);
+    assert_eq!(responses[0].test, "remote_range_check");
+    assert!(responses[0].error.is_some());
+    assert!(responses[0].results.is_empty());
+    assert_eq!(responses[1].test, "range_check");
+    assert_eq!(responses[1].error, None);
+    assert_eq!(responses[1].results.len(), 3);
+    // the combined flags say what they're missing
+    assert_eq!(
+        responses[2].error.as_deref(),
+        Some("combined without the flags of failed steps: remote_range_check")
+    );
+    assert_eq!(responses[2].results.len(), 3);
+    assert!(responses.iter().all(|response| response.run_id == run_id));
+
+    // the run finished despite the failed step, which it remembers
+    let status = scheduler.run_status(run_id).unwrap();
+    assert_eq!(status.state, RunState::Done);
+    assert!(status.error.is_some());
+    assert_eq!(scheduler.list_runs(), vec![status]);
+}
+
+#[tokio::test]
+async fn integration_test_selected_steps() {
+    let memory = MemoryConnector::new();
+    memory.insert_observations((0..3).map(|i| Observation {
+        series_id: "18700".to_string(),
+        lat: 59.94,
+        lon: 10.72,
+        elev: 94.,
+        time: Timestamp(i * 3600),
+        value: Some(i as f32 * 6.),
+    }));
+    let pipeline = Pipeline::from_toml(
+        r#"
+        [combi]
+
+        [[step]]
+        name = "wide_range_check"
+        [step.range_check]
+        min = 0.0
+        max = 100.0
+
+        [[step]]
+        name = "narrow_range_check"
+        run_if = { step = "wide_range_check", flag = "pass" }
+        [step.range_check]
+        min = 0.0
+        max = 10.0
+
+        [[step]]
+        name = "flatline_check"
+        [step.flatline_check]
+        max = 2
+        "#,
+    )
+    .unwrap();
+    let scheduler = Scheduler::new(
+        HashMap::from([("ranges".to_string(), pipeline)]),
+        DataSwitch::new([(
+            "memory",
+            Arc::new(memory) as Arc<dyn DataConnector + Send + Sync>,
+        )]),
+    );
+    let time_spec = TimeSpec::new(Timestamp(0), Timestamp(7200), RelativeDuration::hours(1));
+    let space_spec = data_switch::SpaceSpec::All;
+    let run = |steps: Vec<&str>| {
+        scheduler.validate_direct_with_options(
+            "memory",
+            &[] as &[&str],
+            &time_spec,
+            &space_spec,
+            "ranges",
+            None,
+            RunOptions {
+                steps: Some(steps.into_iter().map(String::from).collect()),
+                ..Default::default()
+            },
+        )
+    };
+
+    let mut rx = run(vec!["narrow_range_check"]).await.unwrap();
+    let mut tests = Vec::new();
+    while let Some(response) = rx.recv().await {
+        tests.push(response.unwrap().test);
+    }
+    // the step it's conditional on is run too, but nothing else, and nothing is combined
+    assert_eq!(tests, vec!["wide_range_check", "narrow_range_check"]);
+
+    assert_eq!(
+        run(vec!["sct"]).await.unwrap_err().to_string(),
+        "invalid argument: selected step not in pipeline"
+    );
+
+    // the range checks need no leading points, even though the flatline check does
+    let data = DataCache::new(
+        vec![59.94],
+        vec![10.72],
+        vec![94.],
+        Timestamp(0),
+        RelativeDuration::hours(1),
+        0,
+        0,
+        vec![("18700".to_string(), vec![Some(1.), Some(2.), Some(3.)])],
+    );
+    let mut rx = scheduler
+        .validate_data(
+            data,
+            "ranges",
+            RunOptions {
+                steps: Some(vec!["narrow_range_check".to_string()]),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    let mut tests = Vec::new();
+    while let Some(response) = rx.recv().await {
+        tests.push(response.unwrap().test);
+    }
+    assert_eq!(tests, vec!["wide_range_check", "narrow_range_check"]);
+}
+
+#[tokio::test]
+async fn integration_test_idempotency() {
+    let memory = MemoryConnector::new();
+    memory.insert_observations((0..3).map(|i| Observation {
+        series_id: "18700".to_string(),
+        lat: 59.94,
+        lon: 10.72,
+        elev: 94.,
+        time: Timestamp(i * 3600),
+        value: Some(i as f32 * 6.),
+    }));
+    let pipeline = Pipeline::from_toml(
+        r#"
+        [[step]]
+        name = "range_check"
+        [step.range_check]
+        min = 0.0
+        max = 10.0
+        "#,
+    )
+    .unwrap();
+    let scheduler = Scheduler::new(
+        HashMap::from([("range".to_string(), pipeline)]),
+        DataSwitch::new([(
+            "memory",
+            Arc::new(memory.clone()) as Arc<dyn DataConnector + Send + Sync>,
+        )]),
+    )
+    .with_idempotency(Duration::from_secs(60), 100);
+    let time_spec = TimeSpec::new(Timestamp(0), Timestamp(7200), RelativeDuration::hours(1));
+    let space_spec = data_switch::SpaceSpec::All;
+    let run = |idempotency_key: &str| {
+        let options = RunOptions {
+            idempotency_key: Some(idempotency_key.to_string()),
+            ..Default::default()
+        };
+        let run_id = options.run_id;
+        let scheduler = scheduler.clone();
+        let time_spec = time_spec.clone();
+        let space_spec = space_spec.clone();
+        async move {
+            let mut rx = scheduler
+                .validate_direct_with_options(
+                    "memory",
+                    &[] as &[&str],
+                    &time_spec,
+                    &space_spec,
+                    "range",
+                    None,
+                    options,
+                )
+                .await
+                .unwrap();
+            let mut results = Vec::new();
+            while let Some(response) = rx.recv().await {
+                let response = response.unwrap();
+                assert_eq!(response.run_id, run_id);
+                results.push(response.results);
+            }
+            assert_eq!(scheduler.run_status(run_id).unwrap().state, RunState::Done);
+            results
+        }
+    };
+
+    let first = run("first").await;
+
+    // runs whose receiver is dropped still finish, so they can be replayed
+    let options = RunOptions {
+        idempotency_key: Some("dropped".to_string()),
+        ..Default::default()
+    };
+    let dropped_id = options.run_id;
+    drop(
+        scheduler
+            .validate_direct_with_options(
+                "memory",
+                &[] as &[&str],
+                &time_spec,
+                &space_spec,
+                "range",
+                None,
+                options,
+            )
+            .await
+            .unwrap(),
+    );
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !scheduler
+            .run_status(dropped_id)
+            .unwrap()
+            .state
+            .is_finished()
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        scheduler.run_status(dropped_id).unwrap().state,
+        RunState::Done
+    );
+
+    // a rerun would flag this
+    memory.insert_observations([Observation {
+        series_id: "18700".to_string(),
+        lat: 59.94,
+        lon: 10.72,
+        elev: 94.,
+        time: Timestamp(0),
+        value: Some(50.),
+    }]);
+    assert_eq!(run("first").await, first);
+    assert_eq!(run("dropped").await, first);
+    assert_ne!(run("second").await, first);
+
+    // a key can't be reused for a different request
+    let reused = scheduler
+        .validate_direct_with_options(
+            "memory",
+            &[] as &[&str],
+            &TimeSpec::new(Timestamp(0), Timestamp(3600), RelativeDuration::hours(1)),
+            &space_spec,
+            "range",
+            None,
+            RunOptions {
+                idempotency_key: Some("first".to_string()),
+                ..Default::default()
+            },
+        )
+        .await;
+    assert_eq!(
+        reused.unwrap_err().to_string(),
+        "idempotency key was already used for a different request"
+    );
+}
+
+#[tokio::test]
+async fn integration_test_result_chunks() {
+    let memory = MemoryConnector::new();
+    memory.insert_observations((0..5).map(|i| Observation {
+        series_id: "18700".to_string(),
+        lat: 59.94,
+        lon: 10.72,
+        elev: 94.,
+        time: Timestamp(i * 3600),
+        value: Some(i as f32),
+    }));
+    let pipeline = Pipeline::from_toml(
+        r#"
+        [combi]
+
+        [[step]]
+        name = "range_check"
+        [step.range_check]
+        min = 0.0
+        max = 10.0
+        "#,
+    )
+    .unwrap();
+    let scheduler = Scheduler::new(
+        HashMap::from([("range".to_string(), pipeline)]),
+        DataSwitch::new([(
+            "memory",
+            Arc::new(memory) as Arc<dyn DataConnector + Send + Sync>,
+        )]),
+    )
+    .with_result_chunk_len(2);
+
+    let mut rx = scheduler
+        .validate_direct(
+            "memory",
+            &[] as &[&str],
+            &TimeSpec::new(
+                Timestamp(0),
+                Timestamp(4 * 3600),
+                RelativeDuration::hours(1),
+            ),
+            &data_switch::SpaceSpec::All,
+            "range",
+            None,
+        )
+        .await
+        .unwrap();
+
+    let mut responses = Vec::new();
+    while let Some(response) = rx.recv().await {
+        let response = response.unwrap();
+        responses.push((
+            response.test,
+            response
+                .results
+                .iter()
+                .map(|result| result.time.0 / 3600)
+                .collect::<Vec<_>>(),
+        ));
+    }
+    // each chunk's results, combined ones included, are sent before the next chunk is QCed
+    assert_eq!(
+        responses,
+        vec![
+            ("range_check".to_string(), vec![0, 1]),
+            ("combi".to_string(), vec![0, 1]),
+            ("range_check".to_string(), vec![2, 3]),
+            ("combi".to_string(), vec![2, 3]),
+            ("range_check".to_string(), vec![4]),
+            ("combi".to_string(), vec![4]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn integration_test_max_points() {
+    let memory = MemoryConnector::new();
+    memory.insert_observations((0..5).flat_map(|i| {
+        ["18700", "18701"].map(|series_id| Observation {
+            series_id: series_id.to_string(),
+            lat: 59.94,
+            lon: 10.72,
+            elev: 94.,
+            time: Timestamp(i * 3600),
+            value: Some(i as f32),
+        })
+    }));
+    let pipeline = Pipeline::from_toml(
+        r#"
+        [[step]]
+        name = "range_check"
+        [step.range_check]
+        min = 0.0
+        max = 10.0
+        "#,
+    )
+    .unwrap();
+    let scheduler = Scheduler::new(
+        HashMap::from([("range".to_string(), pipeline)]),
+        DataSwitch::new([(
+            "memory",
+            Arc::new(memory) as Arc<dyn DataConnector + Send + Sync>,
+        )]),
+    )
+    .with_max_points(8);
+    let time_spec = TimeSpec::new(
+        Timestamp(0),
+        Timestamp(4 * 3600),
+        RelativeDuration::hours(1),
+    );
+
+    let result = |space_spec: data_switch::SpaceSpec| {
+        let scheduler = scheduler.clone();
+        let time_spec = time_spec.clone();
+        async move {
+            scheduler
+                .validate_direct(
+                    "memory",
+                    &[] as &[&str],
+                    &time_spec,
+                    &space_spec,
+                    "range",
+                    None,
+                )
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+    };
+
+    // one series fits
+    assert_eq!(
+        result(data_switch::SpaceSpec::One("18700".to_string())).await,
+        Ok(())
+    );
+    // two are rejected before fetching, and all of them once they're fetched
+    for space_spec in [
+        data_switch::SpaceSpec::Multiple(vec!["18700".to_string(), "18701".to_string()]),
+        data_switch::SpaceSpec::All,
+    ] {
+        assert!(result(space_spec)
+            .await
+            .unwrap_err()
+            .starts_with("run would hold about 10 points in memory at once, over the limit of 8"));
+    }
+}
+
+#[tokio::test]
+async fn integration_test_health() {
+    let data_switch = DataSwitch::new(HashMap::from([(
+        "test",
+        Arc::new(TestDataSource {
+            data_len_single: DATA_LEN_SINGLE,
+            data_len_series: 1,
+            data_len_spatial: DATA_LEN_SPATIAL,
+        }) as Arc<dyn DataConnector + Send + Sync>,
+    )]));
+
+    // without pipelines, there's nothing to serve
+    assert!(
+        !Scheduler::new(HashMap::new(), data_switch.clone())
+            .is_ready()
+            .await
+    );
+
+    let (coordinator_future, channel) = set_up_rove_channel(
+        data_switch,
+        construct_hardcoded_pipeline(),
+        ServerConfig::default(),
+    )
+    .await;
+    let mut client = HealthClient::new(channel);
+
+    let requests_future = async {
+        let mut statuses = client
+            .watch(HealthCheckRequest {
+                service: "rove.Rove".to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        // the server starts out not serving, until its first check
+        while let Some(status) = statuses.next().await {
+            if status.unwrap().status == ServingStatus::Serving as i32 {
+                return;
+            }
+        }
+        panic!("server never started serving");
+    };
+
+    tokio::select! {
+        _ = coordinator_future => panic!("coordinator returned first"),
+        _ = requests_future => (),
+    }
+}
+
+#[tokio::test]
+async fn integration_test_shutdown() {
+    let data_switch = DataSwitch::new(HashMap::from([(
+        "test",
+        Arc::new(TestDataSource {
+            data_len_single: DATA_LEN_SINGLE,
+            data_len_series: 1,
+            data_len_spatial: DATA_LEN_SPATIAL,
+        }) as Arc<dyn DataConnector + Send + Sync>,
+    )]));
+    let shutdown = CancellationToken::new();
+
+    let (coordinator_future, channel) = set_up_rove_channel(
+        data_switch,
+        construct_hardcoded_pipeline(),
+        ServerConfig {
+            shutdown: Some(shutdown.clone()),
+            ..Default::default()
+        },
+    )
+    .await;
+    let mut client = RoveClient::new(channel);
+    let request = ValidateRequest {
+        data_source: String::from("test"),
+        start_time: Some(prost_types::Timestamp::default()),
+        end_time: Some(prost_types::Timestamp::default()),
+        time_resolution: String::from("PT5M"),
+        space_spec: Some(SpaceSpec::All(())),
+        pipeline: String::from("hardcoded"),
+        ..Default::default()
+    };
+
+    let requests_future = async {
+        let mut stream = client.validate(request.clone()).await.unwrap().into_inner();
+        shutdown.cancel();
+
+        // the run in flight still sends all its results
+        let mut recv_count = 0;
+        while let Some(recv) = stream.next().await {
+            recv.unwrap();
+            recv_count += 1;
+        }
+        assert_eq!(recv_count, 4);
+
+        // but new runs are refused
+        assert!(client.validate(request).await.is_err());
+    };
+
+    // the coordinator returns once it has drained
+    tokio::time::timeout(Duration::from_secs(10), async {
+        tokio::join!(coordinator_future, requests_future)
+    })
+    .await
+    .expect("server should finish shutting down");
+}
+
+#[tokio::test]
+async fn integration_test_message_limits() {
+    let request = ValidateRequest {
+        data_source: String::from("test"),
+        start_time: Some(prost_types::Timestamp::default()),
+        end_time: Some(prost_types::Timestamp::default()),
+        time_resolution: String::from("PT5M"),
+        space_spec: Some(SpaceSpec::All(())),
+        pipeline: String::from("hardcoded"),
+        ..Default::default()
+    };
+
+    for (transport, request_fails) in [
+        (
+            TransportConfig {
+                max_decoding_message_size: Some(8),
+                ..Default::default()
+            },
+            true,
+        ),
+        (
+            // smaller than the results for DATA_LEN_SPATIAL stations
+            TransportConfig {
+                max_encoding_message_size: Some(100),
+                ..Default::default()
+            },
+            false,
+        ),
+    ] {
+        let data_switch = DataSwitch::new(HashMap::from([(
+            "test",
+            Arc::new(TestDataSource {
+                data_len_single: DATA_LEN_SINGLE,
+                data_len_series: 1,
+                data_len_spatial: DATA_LEN_SPATIAL,
+            }) as Arc<dyn DataConnector + Send + Sync>,
+        )]));
+        let (coordinator_future, channel) = set_up_rove_channel(
+            data_switch,
+            construct_hardcoded_pipeline(),
+            ServerConfig {
+                transport,
+                ..Default::default()
+            },
+        )
+        .await;
+        let mut client = RoveClient::new(channel);
+
+        let requests_future = async {
+            let status = match client.validate(request.clone()).await {
+                Err(status) => {
+                    assert!(request_fails);
+                    status
+                }
+                Ok(response) => {
+                    assert!(!request_fails);
+                    response.into_inner().next().await.unwrap().unwrap_err()
+                }
+            };
+            assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        };
+
+        tokio::select! {
+            _ = coordinator_future => panic!("coordinator returned first"),
+            _ = requests_future => (),
+        }
+    }
+}
+
+#[tokio::test]
+async fn integration_test_dry_run() {
+    let data_switch = DataSwitch::new(HashMap::from([(
+        "test",
+        Arc::new(TestDataSource {
+            data_len_single: DATA_LEN_SINGLE,
+            data_len_series: 1,
+            data_len_spatial: DATA_LEN_SPATIAL,
+        }) as Arc<dyn DataConnector + Send + Sync>,
+    )]));
+
+    let (coordinator_future, mut client, _) =
+        set_up_rove(data_switch, construct_hardcoded_pipeline()).await;
+
+    let requests_future = async {
+        let responses: Vec<_> = client
+            .validate(ValidateRequest {
+                data_source: String::from("test"),
+                start_time: Some(prost_types::Timestamp::default()),
+                end_time: Some(prost_types::Timestamp::default()),
+                time_resolution: String::from("PT5M"),
+                space_spec: Some(SpaceSpec::All(())),
+                pipeline: String::from("hardcoded"),
+                dry_run: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .collect()
+            .await;
+
+        assert_eq!(responses.len(), 1);
+        let explanation = responses[0].as_ref().unwrap().explanation.clone().unwrap();
+        let checks: Vec<&str> = explanation
+            .steps
+            .iter()
+            .map(|step| step.check.as_str())
+            .collect();
+        assert_eq!(
+            checks,
+            vec!["step_check", "spike_check", "buddy_check", "sct"]
+        );
+        assert_eq!(explanation.num_leading_points, 1);
+        assert_eq!(explanation.num_trailing_points, 1);
+        assert_eq!(explanation.fetches.len(), 1);
+        assert_eq!(explanation.requests.len(), 1);
+        let request = &explanation.requests[0];
+        assert_eq!(request.data_source, "test");
+        assert_eq!(request.fetch_start_time.as_ref().unwrap().seconds, -300);
+        assert_eq!(request.fetch_end_time.as_ref().unwrap().seconds, 300);
+    };
+
+    tokio::select! {
+        _ = coordinator_future => panic!("coordinator returned first"),
+        _ = requests_future => (),
+    }
+}
+
+#[tokio::test]
+async fn integration_test_get_pipeline() {
+    let data_switch = DataSwitch::new(HashMap::from([(
+        "test",
+        Arc::new(TestDataSource {
+            data_len_single: DATA_LEN_SINGLE,
+            data_len_series: 1,
+            data_len_spatial: DATA_LEN_SPATIAL,
+        }) as Arc<dyn DataConnector + Send + Sync>,
+    )]));
+
+    let pipelines = construct_hardcoded_pipeline();
+    let expected = pipelines.get("hardcoded").unwrap().clone();
+    let (coordinator_future, mut client, _) = set_up_rove(data_switch, pipelines).await;
+
+    let requests_future = async {
+        let list = client.list_pipelines(()).await.unwrap().into_inner();
+        assert_eq!(list.pipelines.len(), 1);
+        assert_eq!(list.pipelines[0].name, "hardcoded");
+
+        let response = client
+            .get_pipeline(GetPipelineRequest {
+                name: String::from("hardcoded"),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.metadata.unwrap().name, "hardcoded");
+        assert_eq!(Pipeline::from_json(&response.config).unwrap(), expected);
+
+        let status = client
+            .get_pipeline(GetPipelineRequest {
+                name: String::from("missing"),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    };
+
+    tokio::select! {
+        _ = coordinator_future => panic!("coordinator returned first"),
+        _ = requests_future => (),
+    }
+}
+
+fn admin_request<T>(message: T, token: &str) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request.metadata_mut().insert(
+        "authorization",
+        format!("Bearer {}", token).parse().unwrap(),
+    );
+    request
+}
+
+#[tokio::test]
+async fn integration_test_admin() {
+    let data_switch = DataSwitch::new(HashMap::from([(
+        "test",
+        Arc::new(TestDataSource {
+            data_len_single: DATA_LEN_SINGLE,
+            data_len_series: 1,
+            data_len_spatial: DATA_LEN_SPATIAL,
+        }) as Arc<dyn DataConnector + Send + Sync>,
+    )]));
+
+    let (coordinator_future, mut client, mut admin_client) =
+        set_up_rove(data_switch, construct_hardcoded_pipeline()).await;
+
+    let requests_future = async {
+        let register = RegisterPipelineRequest {
+            name: String::from("runtime"),
+            config: String::from(
+                r#"
+                version = "1"
+
+                [[step]]
+                name = "step_check"
+                [step.step_check]
+                max = 3.0
+                "#,
+            ),
+            format: PipelineFormat::Toml.into(),
+        };
+
+        let status = admin_client
+            .register_pipeline(admin_request(register.clone(), "wrong_token"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        let status = admin_client
+            .register_pipeline(admin_request(
+                RegisterPipelineRequest {
+                    config: String::from("[[step]]\nname = \"unknown\""),
+                    ..register.clone()
+                },
+                ADMIN_TOKEN,
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        // there would be nothing to run
+        let status = admin_client
+            .register_pipeline(admin_request(
+                RegisterPipelineRequest {
+                    config: String::from("version = \"1\"\nstep = []"),
+                    ..register.clone()
+                },
+                ADMIN_TOKEN,
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let metadata = admin_client
+            .register_pipeline(admin_request(register, ADMIN_TOKEN))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(metadata.name, "runtime");
+        assert_eq!(metadata.version.as_deref(), Some("1"));
+
+        let responses: Vec<_> = client
+            .validate(ValidateRequest {
+                data_source: String::from("test"),
+                start_time: Some(prost_types::Timestamp::default()),
+                end_time: Some(prost_types::Timestamp::default()),
+                time_resolution: String::from("PT5M"),
+                space_spec: Some(SpaceSpec::One(String::from("single"))),
+                pipeline: String::from("runtime"),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .collect()
+            .await;
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].as_ref().unwrap().test, "step_check");
+
+        admin_client
+            .remove_pipeline(admin_request(
+                RemovePipelineRequest {
+                    name: String::from("runtime"),
+                },
+                ADMIN_TOKEN,
+            ))
+            .await
+            .unwrap();
+        let list = client.list_pipelines(()).await.unwrap().into_inner();
+        assert_eq!(list.pipelines.len(), 1);
+        assert_eq!(list.pipelines[0].name, "hardcoded");
+    };
+
+    tokio::select! {
+        _ = coordinator_future => panic!("coordinator returned first"),
+        _ = requests_future => (),
+    }
+}
+
+#[tokio::test]
+async fn integration_test_fixture() {
+    assert_fixture(
+        r#"
+        [[step]]
+        name = "range_check"
+        [step.range_check]
+        min = 0.0
+        max = 10.0
+
+        [[step]]
+        name = "step_check"
+        [step.step_check]
+        max = 3.0
+        "#,
+        r#"
+        {
+            "start_time": 1700000000,
+            "period": "PT1H",
+            "num_leading_points": 1,
+            "series": [
+                {"identifier": "a", "lat": 59.9, "lon": 10.7, "values": [1.0, 2.0, 9.0, null, 11.0]},
+                {"identifier": "b", "lat": 60.4, "lon": 5.3, "values": [5.0, 5.0, 5.0, 5.0, 5.0]}
+            ]
+        }
+        "#,
+        r#"
+        {
+            "range_check": {
+                "a": ["pass", "pass", "data_missing", "fail"],
+                "b": ["pass", "pass", "pass", "pass"]
+            },
+            "step_check": {
+                "a": ["pass", "warn", "data_missing", "data_missing"],
+                "b": ["pass", "pass", "pass", "pass"]
+            }
+        }
+        "#,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn integration_test_fixture_lengths() {
+    let pipeline = Pipeline::from_toml(
+        r#"
+        [[step]]
+        name = "range_check"
+        [step.range_check]
+        min = 0.0
+        max = 10.0
+        "#,
+    )
+    .unwrap();
+
+    for series in [
+        // fewer points than the leading and trailing ones
+        r#"[{"identifier": "a", "lat": 59.9, "lon": 10.7, "values": [1.0]}]"#,
+        // ragged
+        r#"[
+            {"identifier": "a", "lat": 59.9, "lon": 10.7, "values": [1.0, 2.0, 3.0]},
+            {"identifier": "b", "lat": 60.4, "lon": 5.3, "values": [1.0, 2.0]}
+        ]"#,
+    ] {
+        let fixture = Fixture::from_json(&format!(
+            r#"{{
+                "start_time": 1700000000,
+                "period": "PT1H",
+                "num_leading_points": 1,
+                "num_trailing_points": 1,
+                "series": {}
+            }}"#,
+            series
+        ))
+        .unwrap();
+        assert!(run_fixture(&pipeline, &fixture).await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn integration_test_remote_runner() {
+    // the runner has to be reachable by URI, so listens on TCP rather than a unix socket
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    tokio::spawn(async move {
+        start_server_with_config(
+            addr,
+            DataSwitch::new(HashMap::<String, _>::new()),
+            HashMap::new(),
+            ServerConfig {
+                runner_token: Some(RUNNER_TOKEN.to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    });
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(addr).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let pipeline = Pipeline::from_toml(&format!(
+        r#"
+        [[step]]
+        name = "range_check"
+        backend = "grpc"
+        endpoint = "http://{}"
+        max_elevation = 1000.0
+        [step.range_check]
+        min = 0.0
+        max = 10.0
+        "#,
+        addr
+    ))
+    .unwrap();
+    let fixture = Fixture::from_json(
+        r#"
+        {
+            "start_time": 1700000000,
+            "period": "PT1H",
+            "series": [
+                {"identifier": "a", "lat": 59.9, "lon": 10.7, "values": [1.0, null, 11.0]},
+                {"identifier": "b", "lat": 61.6, "lon": 8.3, "elev": 2469.0, "values": [20.0, 5.0, 5.0]}
+            ]
+        }
+        "#,
+    )
+    .unwrap();
+    let scheduler = Scheduler::new(
+        HashMap::from([("fixture".to_string(), pipeline)]),
+        DataSwitch::new(HashMap::from([(
+            "fixture",
+            Arc::new(fixture) as Arc<dyn DataConnector + Send + Sync>,
+        )])),
+    );
+    let run = |scheduler: Scheduler| async move {
+        let mut rx = scheduler
+            .validate_direct(
+                "fixture",
+                &[] as &[&str],
+                &TimeSpec::new(
+                    Timestamp(1700000000),
+                    Timestamp(1700007200),
+                    RelativeDuration::hours(1),
+                ),
+                &data_switch::SpaceSpec::All,
+                "fixture",
+                None,
+            )
+            .await
+            .unwrap();
+        let mut responses = Vec::new();
+        while let Some(response) = rx.recv().await {
+            responses.push(response);
+        }
+        responses
+    };
+
+    // the runner refuses steps sent without its token
+    assert!(run(scheduler.clone()).await.iter().any(Result::is_err));
+
+    let range_check = run(scheduler.with_runner_token(RUNNER_TOKEN.to_string()))
+        .await
+        .into_iter()
+        .map(Result::unwrap)
+        .find(|response| response.test == "range_check")
+        .unwrap();
+    let flags = |identifier: &str| {
+        range_check
+            .results
+            .iter()
+            .filter(|result| result.identifier == identifier)
+            .map(|result| result.flag)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        flags("a"),
+        vec![rove::Flag::Pass, rove::Flag::DataMissing, rove::Flag::Fail]
+    );
+    assert_eq!(flags("b"), vec![rove::Flag::Inconclusive; 3]);
+}